@@ -0,0 +1,80 @@
+//! Hit rates of the cache eviction policies under a zipfian load, run with `cargo bench -p ursa-gateway`.
+
+use std::{sync::Arc, time::UNIX_EPOCH};
+
+use tlrfu::{CacheEvictionPolicy, Tlrfu};
+
+// the gateway is a binary, its cache is built into the bench instead
+#[allow(dead_code)]
+#[path = "../src/cache/lru.rs"]
+mod lru;
+#[allow(dead_code)]
+#[path = "../src/cache/tlrfu.rs"]
+mod tlrfu;
+mod util {
+    pub mod timer {
+        pub use std::time::SystemTime;
+
+        pub fn now() -> SystemTime {
+            SystemTime::now()
+        }
+    }
+}
+
+const KEYS: usize = 1000;
+const CACHED: u64 = 100;
+const REQUESTS: usize = 20_000;
+
+/// Hits of `REQUESTS` gets over `KEYS` keys drawn from a zipfian distribution,
+/// inserting the misses in a cache holding `CACHED` of them.
+async fn zipf_hits(policy: CacheEvictionPolicy) -> usize {
+    let weights: Vec<f64> = (1..=KEYS).map(|rank| 1.0 / rank as f64).collect();
+    let total: f64 = weights.iter().sum();
+    let cdf: Vec<f64> = weights
+        .iter()
+        .scan(0.0, |acc, weight| {
+            *acc += weight / total;
+            Some(*acc)
+        })
+        .collect();
+
+    let mut cache = Tlrfu::<Vec<u8>>::new(CACHED, 1_000_000_000).with_policy(policy);
+    let start = UNIX_EPOCH.elapsed().unwrap().as_nanos();
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d; // xorshift, fixed seed
+    let mut hits = 0;
+    for i in 0..REQUESTS {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let sample = (state >> 11) as f64 / (1u64 << 53) as f64;
+        let key = cdf
+            .partition_point(|p| *p < sample)
+            .min(cdf.len() - 1)
+            .to_string();
+        if cache.get(&key).await.unwrap().is_some() {
+            hits += 1;
+        } else {
+            // distinct ttls, as inserted one per nanosecond
+            cache
+                .insert_until(key, Arc::new(vec![0]), start + i as u128)
+                .await
+                .unwrap();
+        }
+    }
+    hits
+}
+
+#[tokio::main]
+async fn main() {
+    for policy in [
+        CacheEvictionPolicy::Lfu,
+        CacheEvictionPolicy::Lru,
+        CacheEvictionPolicy::TtlOnly,
+    ] {
+        let hits = zipf_hits(policy).await;
+        println!(
+            "{policy:?}: {hits} hits of {REQUESTS} ({:.1}%)",
+            hits as f64 * 100.0 / REQUESTS as f64
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use axum::{
+    headers::{authorization::Bearer, Authorization},
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, TypedHeader,
+};
+use subtle::ConstantTimeEq;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::config::GatewayConfig;
+
+/// Reject admin requests without the admin token as bearer token, once one is configured.
+pub async fn authorize<B>(
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Extension(config): Extension<Arc<RwLock<GatewayConfig>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let authorized = match (&config.read().await.admin_server.token, auth) {
+        (None, _) => true,
+        (Some(token), Some(TypedHeader(auth))) => {
+            bool::from(token.as_bytes().ct_eq(auth.token().as_bytes()))
+        }
+        (Some(_), None) => false,
+    };
+    if !authorized {
+        warn!(
+            "Rejected admin request {} {}, invalid admin token",
+            req.method(),
+            req.uri()
+        );
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token").into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::header};
+    use tokio::sync::mpsc::unbounded_channel;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::app, worker::cache::Cache};
+
+    async fn request(
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        config: &Arc<RwLock<GatewayConfig>>,
+    ) -> StatusCode {
+        let (tx, _rx) = unbounded_channel();
+        let cache = Arc::new(RwLock::new(Cache::new(200_000_000, 0, tx, 1024, 1024)));
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let body = if method == "PUT" {
+            Body::from(r#"{"enabled":false}"#)
+        } else {
+            Body::empty()
+        };
+        let req = req
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .unwrap();
+        app(Arc::clone(config), cache)
+            .oneshot(req)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn every_route_authorized() {
+        let config = Arc::new(RwLock::new(GatewayConfig::default()));
+        // without a token the routes are open
+        assert_eq!(
+            request("GET", "/config", None, &config).await,
+            StatusCode::OK
+        );
+
+        config.write().await.admin_server.token = Some("token".into());
+        let routes = [
+            ("GET", "/config"),
+            ("POST", "/purge-cache"),
+            ("GET", "/cache/stats"),
+            ("DELETE", "/cache/not-a-cid"),
+            ("GET", "/maintenance"),
+            ("PUT", "/maintenance"),
+        ];
+        for (method, uri) in routes {
+            for token in [None, Some("other")] {
+                assert_eq!(
+                    request(method, uri, token, &config).await,
+                    StatusCode::UNAUTHORIZED,
+                    "{method} {uri}"
+                );
+            }
+        }
+        for (method, uri) in [
+            ("GET", "/config"),
+            ("POST", "/purge-cache"),
+            ("GET", "/cache/stats"),
+            ("GET", "/maintenance"),
+            ("PUT", "/maintenance"),
+        ] {
+            assert_eq!(
+                request(method, uri, Some("token"), &config).await,
+                StatusCode::OK,
+                "{method} {uri}"
+            );
+        }
+    }
+}
@@ -1,3 +1,4 @@
+mod auth;
 mod route;
 
 use std::{
@@ -8,11 +9,17 @@ use std::{
 use anyhow::{Context, Result};
 use axum::{
     extract::Extension,
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, post, put},
     Router,
 };
 use axum_server::Handle;
-use route::api::v1::{get::get_config_handler, post::purge_cache_handler};
+use route::api::v1::{
+    delete::purge_cid_handler,
+    get::{get_cache_stats_handler, get_config_handler, get_maintenance_handler},
+    post::purge_cache_handler,
+    put::put_maintenance_handler,
+};
 use tokio::{
     select, spawn,
     sync::{broadcast::Receiver, RwLock},
@@ -31,7 +38,7 @@ pub async fn start<Cache: AdminCache>(
 ) -> Result<()> {
     let config_reader = Arc::clone(&config);
     let GatewayConfig {
-        admin_server: AdminConfig { addr, port },
+        admin_server: AdminConfig { addr, port, .. },
         ..
     } = &(*config_reader.read().await);
 
@@ -41,11 +48,7 @@ pub async fn start<Cache: AdminCache>(
         *port,
     ));
 
-    let app = Router::new()
-        .route("/config", get(get_config_handler))
-        .route("/purge-cache", post(purge_cache_handler::<Cache>))
-        .layer(Extension(config))
-        .layer(Extension(cache));
+    let app = app(config, cache);
 
     info!("Admin server listening on {addr}");
 
@@ -61,6 +64,23 @@ pub async fn start<Cache: AdminCache>(
     Ok(())
 }
 
+/// The admin routes, every one authorized by the admin token once it is configured.
+fn app<Cache: AdminCache>(config: Arc<RwLock<GatewayConfig>>, cache: Arc<RwLock<Cache>>) -> Router {
+    Router::new()
+        .route("/config", get(get_config_handler))
+        .route("/purge-cache", post(purge_cache_handler::<Cache>))
+        .route("/cache/stats", get(get_cache_stats_handler::<Cache>))
+        .route("/cache/:cid", delete(purge_cid_handler::<Cache>))
+        .route(
+            "/maintenance",
+            get(get_maintenance_handler).put(put_maintenance_handler),
+        )
+        // added after the authorization, the extensions are inserted before it runs
+        .layer(middleware::from_fn(auth::authorize))
+        .layer(Extension(config))
+        .layer(Extension(cache))
+}
+
 async fn graceful_shutdown(handle: Handle, mut shutdown_rx: Receiver<()>) {
     select! {
         _ = shutdown_rx.recv() => {
@@ -0,0 +1,165 @@
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::anyhow;
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use hyper::StatusCode;
+use libipld::Cid;
+use serde_json::json;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::{config::GatewayConfig, worker::cache::admin::AdminCache};
+
+/// Remove every entry of a cid from memory and disk, answering whether any was cached.
+/// Disabled without an admin token, the admin router checks it.
+pub async fn purge_cid_handler<Cache: AdminCache>(
+    Path(cid): Path<String>,
+    Extension(config): Extension<Arc<RwLock<GatewayConfig>>>,
+    Extension(cache): Extension<Arc<RwLock<Cache>>>,
+) -> Response {
+    if config.read().await.admin_server.token.is_none() {
+        return (
+            StatusCode::FORBIDDEN,
+            "Purging a cid is disabled, no admin token is configured",
+        )
+            .into_response();
+    }
+    let Ok(cid) = Cid::from_str(&cid) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid cid string, cannot parse {cid} to CID"),
+        )
+            .into_response();
+    };
+    // the lock is released before the worker takes it to remove the entry
+    let purged = cache.read().await.purge_cid(cid);
+    let removed = match purged {
+        Ok(rx) => rx
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("Purge command dropped"))),
+        Err(e) => Err(e),
+    };
+    match removed {
+        Ok(removed) => {
+            info!("Purged cid {cid} from the cache, removed: {removed}");
+            Json(json!({ "removed": removed })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to purge cid {cid}: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to purge cid").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use bytes::Bytes;
+    use hyper::{client::Client, header};
+    use hyper_tls::HttpsConnector;
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        admin::app,
+        resolver::Resolver,
+        worker::{
+            self,
+            cache::{key::CacheKey, server::ServerCache, worker::WorkerCache, Cache},
+        },
+    };
+
+    const CID: &str = "bafkreihwcrnsi2tqozwq22k4vl7flutu43jlxgb3tenewysm2xvfuej5i4";
+
+    async fn purge(
+        cid: &str,
+        token: Option<&str>,
+        config: &Arc<RwLock<GatewayConfig>>,
+        cache: &Arc<RwLock<Cache>>,
+    ) -> (StatusCode, Bytes) {
+        let mut req = Request::delete(format!("/cache/{cid}"));
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let response = app(Arc::clone(config), Arc::clone(cache))
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        (
+            status,
+            hyper::body::to_bytes(response.into_body()).await.unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn purge_cid() {
+        let (tx, rx) = unbounded_channel();
+        let cache = Arc::new(RwLock::new(Cache::new(200_000_000, 0, tx, 1024, 1024)));
+        let resolver = Resolver::new(vec![], Client::builder().build(HttpsConnector::new()));
+        let (signal_tx, _signal_rx) = channel(1);
+        let (shutdown_tx, shutdown_rx) = channel(1);
+        let worker = worker::start(
+            rx,
+            Arc::clone(&cache),
+            Arc::new(resolver),
+            0,
+            signal_tx,
+            shutdown_rx,
+        );
+
+        let config = Arc::new(RwLock::new(GatewayConfig::default()));
+        let (status, _) = purge(CID, Some("token"), &config, &cache).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        config.write().await.admin_server.token = Some("token".into());
+
+        let keys = [
+            CacheKey::new(CID),
+            CacheKey::new(CID).with_query(Some("v=1"), &["v".to_string()]),
+            CacheKey {
+                path: Some("a".into()),
+                encoding: Some("gzip".into()),
+                ..CacheKey::new(CID)
+            },
+        ];
+        let data = Arc::new(Bytes::from_static(b"data"));
+        for key in &keys {
+            cache
+                .write()
+                .await
+                .insert(key.to_string(), Arc::clone(&data))
+                .await
+                .unwrap();
+            assert!(cache.read().await.cached(key).unwrap().is_some());
+        }
+
+        for token in [None, Some("other")] {
+            let (status, _) = purge(CID, token, &config, &cache).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+        assert!(cache.read().await.cached(&keys[0]).unwrap().is_some());
+
+        let (status, body) = purge(CID, Some("token"), &config, &cache).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "removed": true }).to_string());
+        // every variant is a miss once purged
+        for key in &keys {
+            assert!(cache.read().await.cached(key).unwrap().is_none(), "{key}");
+        }
+
+        let (status, body) = purge(CID, Some("token"), &config, &cache).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "removed": false }).to_string());
+
+        let (status, _) = purge("not-a-cid", Some("token"), &config, &cache).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        shutdown_tx.send(()).await.unwrap();
+        worker.await.unwrap();
+    }
+}
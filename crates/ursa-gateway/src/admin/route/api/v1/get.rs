@@ -4,10 +4,33 @@ use axum::{Extension, Json};
 use serde_json::{json, Value};
 use tokio::sync::RwLock;
 
-use crate::config::GatewayConfig;
+use super::put::Maintenance;
+use crate::{
+    config::GatewayConfig,
+    worker::cache::{admin::AdminCache, stats::CacheStatsSnapshot},
+};
 
+/// The config, without the admin token.
 pub async fn get_config_handler(
     Extension(config): Extension<Arc<RwLock<GatewayConfig>>>,
 ) -> Json<Value> {
-    Json(json!(&(*config.read().await)))
+    let mut config = json!(&(*config.read().await));
+    if let Some(token) = config.pointer_mut("/admin_server/token") {
+        *token = Value::Null;
+    }
+    Json(config)
+}
+
+pub async fn get_maintenance_handler(
+    Extension(config): Extension<Arc<RwLock<GatewayConfig>>>,
+) -> Json<Maintenance> {
+    Json(Maintenance {
+        enabled: config.read().await.server.maintenance,
+    })
+}
+
+pub async fn get_cache_stats_handler<Cache: AdminCache>(
+    Extension(cache): Extension<Arc<RwLock<Cache>>>,
+) -> Json<CacheStatsSnapshot> {
+    Json(cache.read().await.stats())
 }
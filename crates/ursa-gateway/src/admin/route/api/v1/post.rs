@@ -6,9 +6,38 @@ use tokio::sync::RwLock;
 
 use crate::worker::cache::admin::AdminCache;
 
+/// Empty every tier of the cache, answering once the disk tier is purged too.
 pub async fn purge_cache_handler<Cache: AdminCache>(
     Extension(cache): Extension<Arc<RwLock<Cache>>>,
 ) -> StatusCode {
-    cache.write().await.purge();
+    // the lock is released before the files are removed
+    let purge_disk = cache.write().await.purge();
+    purge_disk.await;
     StatusCode::OK
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::worker::cache::{disk::DiskCache, Cache};
+
+    #[tokio::test]
+    async fn purge_disk() {
+        let dir = std::env::temp_dir().join(format!("ursa-gateway-purge-{}", std::process::id()));
+        let disk = DiskCache::open(&dir, 1024).await.unwrap();
+        disk.write("a", b"data", u128::MAX, disk.generation())
+            .await
+            .unwrap();
+        let (tx, _) = unbounded_channel();
+        let cache = Cache::new(1024, 0, tx, 1024, 1024).with_disk_cache(disk);
+        let cache = Arc::new(RwLock::new(cache));
+
+        let status = purge_cache_handler(Extension(cache)).await;
+        assert_eq!(status, StatusCode::OK);
+        // the files are gone once answered
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        tokio::fs::remove_dir(&dir).await.unwrap();
+    }
+}
@@ -1 +1,26 @@
+use std::sync::Arc;
 
+use axum::{Extension, Json};
+use hyper::StatusCode;
+use metrics::gauge;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::config::GatewayConfig;
+
+/// Whether the gateway only serves cached content.
+#[derive(Deserialize, Serialize)]
+pub struct Maintenance {
+    pub enabled: bool,
+}
+
+pub async fn put_maintenance_handler(
+    Extension(config): Extension<Arc<RwLock<GatewayConfig>>>,
+    Json(Maintenance { enabled }): Json<Maintenance>,
+) -> StatusCode {
+    config.write().await.server.maintenance = enabled;
+    gauge!("gateway_maintenance_mode", if enabled { 1.0 } else { 0.0 });
+    info!("Maintenance mode enabled: {enabled}");
+    StatusCode::OK
+}
@@ -5,6 +5,7 @@ use std::{
 };
 
 use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
 
 use super::lru::Lru;
 use crate::util::timer::now;
@@ -16,13 +17,33 @@ struct Data<T: ByteSize> {
     ttl: u128,
 }
 
+/// Key, value and ttl of an entry evicted for the size of the cache.
+pub type Evicted<T> = (Arc<String>, Arc<T>, u128);
+
+/// Entry evicted once an insert exceeds the size of the cache.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheEvictionPolicy {
+    /// the least recently used entry
+    Lru,
+    /// the least recently used entry of the lowest frequency
+    #[default]
+    Lfu,
+    /// the entry expiring first, entries are not renewed on get
+    #[serde(rename = "ttl-only")]
+    TtlOnly,
+}
+
 pub struct Tlrfu<T: ByteSize> {
     store: HashMap<Arc<String>, Data<T>>,
     freq: BTreeMap<usize, Lru<usize, Arc<String>>>, // shrinkable
     ttl: BTreeMap<u128, Arc<String>>,
     used_size: u64,
     max_size: u64,
+    low_water: u64,  // evict down to
+    high_water: u64, // start evicting past
     ttl_buf: u128,
+    policy: CacheEvictionPolicy,
 }
 
 pub trait ByteSize {
@@ -37,16 +58,41 @@ impl<T: ByteSize> Tlrfu<T> {
             ttl: BTreeMap::new(),
             used_size: 0,
             max_size,
+            low_water: max_size,
+            high_water: max_size,
             ttl_buf,
+            policy: CacheEvictionPolicy::default(),
         }
     }
 
+    pub fn with_policy(mut self, policy: CacheEvictionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Evict in batches: once an insert crosses `high_water`, entries are evicted
+    /// until the cache is down to `low_water`. Both are capped to `max_size`.
+    pub fn with_watermarks(mut self, low_water: u64, high_water: u64) -> Self {
+        self.high_water = high_water.min(self.max_size);
+        self.low_water = low_water.min(self.high_water);
+        self
+    }
+
     pub fn contains(&self, k: &String) -> bool {
         self.store.contains_key(k)
     }
 
+    /// Number of cached entries.
+    pub fn count(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn used_size(&self) -> u64 {
+        self.used_size
+    }
+
     fn is_size_exceeded(&self, bytes: u64) -> bool {
-        self.used_size + bytes > self.max_size
+        self.used_size + bytes > self.high_water
     }
 
     pub fn dirty_get(&self, k: &String) -> Option<&Arc<T>> {
@@ -55,6 +101,9 @@ impl<T: ByteSize> Tlrfu<T> {
 
     pub async fn get(&mut self, k: &String) -> Result<Option<&Arc<T>>> {
         if let Some(data) = self.store.get_mut(k) {
+            if self.policy == CacheEvictionPolicy::TtlOnly {
+                return Ok(Some(&data.value));
+            }
             let lru = self
                 .freq
                 .get_mut(&data.freq)
@@ -66,7 +115,9 @@ impl<T: ByteSize> Tlrfu<T> {
                 )
             })?;
             lru.is_empty().then(|| self.freq.remove(&data.freq));
-            data.freq += 1;
+            if self.policy == CacheEvictionPolicy::Lfu {
+                data.freq += 1;
+            }
             let lru = self.freq.entry(data.freq).or_insert_with(|| Lru::new(None));
             let lru_k = lru
                 .get_tail_key()
@@ -92,26 +143,97 @@ impl<T: ByteSize> Tlrfu<T> {
         }
     }
 
-    pub async fn insert(&mut self, k: String, v: Arc<T>) -> Result<()> {
+    /// Evict an entry according to the eviction policy, returning it.
+    async fn evict(&mut self) -> Result<Evicted<T>> {
+        match self.policy {
+            // without frequency bumps, every entry is in the lowest frequency
+            CacheEvictionPolicy::Lru | CacheEvictionPolicy::Lfu => self.evict_lowest_freq().await,
+            CacheEvictionPolicy::TtlOnly => self.evict_first_expiring().await,
+        }
+    }
+
+    /// Evict the least recently used entry of the lowest frequency.
+    async fn evict_lowest_freq(&mut self) -> Result<Evicted<T>> {
+        let (&freq, lru) = self
+            .freq
+            .iter_mut()
+            .next()
+            .context("[TLRFU]: Freq is empty while deleting. Maybe size too big?")?;
+        let key = lru
+            .remove_head()
+            .await?
+            .with_context(|| format!("[LRU]: Failed to get deleted head key at freq: {freq}"))?;
+        let data = self
+            .store
+            .remove(key.as_ref())
+            .with_context(|| format!("[TLRFU]: Key {key} not found at store while deleting"))?;
+        lru.is_empty().then(|| self.freq.remove(&freq));
+        self.used_size -= data.value.len() as u64;
+        self.ttl.remove(&data.ttl);
+        Ok((key, data.value, data.ttl))
+    }
+
+    /// Evict the entry with the earliest ttl.
+    async fn evict_first_expiring(&mut self) -> Result<Evicted<T>> {
+        let &ttl = self
+            .ttl
+            .keys()
+            .next()
+            .context("[TLRFU]: Ttl is empty while deleting. Maybe size too big?")?;
+        let key = self
+            .ttl
+            .remove(&ttl)
+            .with_context(|| format!("[TLRFU]: Key not found when delete ttl: {ttl}"))?;
+        let data = self
+            .store
+            .remove(key.as_ref())
+            .with_context(|| format!("[TLRFU]: Key {key} not found at store while deleting"))?;
+        let lru = self
+            .freq
+            .get_mut(&data.freq)
+            .with_context(|| format!("[TLRFU]: Key: {key} not found at freq {}", data.freq))?;
+        lru.remove(&data.lru_k).await.with_context(|| {
+            format!(
+                "[TLRFU]: Failed to remove LRU key: {} not found at freq {}",
+                data.lru_k, data.freq
+            )
+        })?;
+        lru.is_empty().then(|| self.freq.remove(&data.freq));
+        self.used_size -= data.value.len() as u64;
+        Ok((key, data.value, data.ttl))
+    }
+
+    /// Insert an entry, returning the entries evicted to make room for it.
+    pub async fn insert(&mut self, k: String, v: Arc<T>) -> Result<Vec<Evicted<T>>> {
+        let ttl = now()
+            .duration_since(UNIX_EPOCH)
+            .context("Failed to get system time from unix epoch")?
+            .as_nanos()
+            + self.ttl_buf;
+        self.insert_until(k, v, ttl).await
+    }
+
+    /// Insert an entry expiring at `ttl` nanoseconds since the unix epoch,
+    /// capped to the ttl buffer.
+    pub async fn insert_until(
+        &mut self,
+        k: String,
+        v: Arc<T>,
+        ttl: u128,
+    ) -> Result<Vec<Evicted<T>>> {
         if self.contains(&k) {
             bail!("[TLRFU]: Key {k:?} existed while inserting");
         }
-        while self.is_size_exceeded(v.len() as u64) {
-            let (&freq, lru) = self
-                .freq
-                .iter_mut()
-                .next()
-                .context("[TLRFU]: Freq is empty while deleting. Maybe size too big?")?;
-            let key = lru.remove_head().await?.with_context(|| {
-                format!("[LRU]: Failed to get deleted head key at freq: {freq}")
-            })?;
-            let data = self
-                .store
-                .remove(key.as_ref())
-                .with_context(|| format!("[TLRFU]: Key {key} not found at store while deleting"))?;
-            lru.is_empty().then(|| self.freq.remove(&freq));
-            self.used_size -= data.value.len() as u64;
-            self.ttl.remove(&data.ttl);
+        let bytes = v.len() as u64;
+        let mut evicted = vec![];
+        if self.is_size_exceeded(bytes) {
+            while self.used_size + bytes > self.low_water {
+                if self.freq.is_empty() && !self.is_size_exceeded(bytes) {
+                    // larger than the low-water mark but fits under the high-water mark
+                    break;
+                }
+                evicted.push(self.evict().await?);
+            }
         }
         let key = Arc::new(k);
         let lru = self.freq.entry(1).or_insert_with(|| Lru::new(None));
@@ -123,11 +245,13 @@ impl<T: ByteSize> Tlrfu<T> {
             format!("[LRU]: Failed to insert LRU with key: {lru_k}, value: {key}")
         })?;
         self.used_size += v.len() as u64; // MAX = 2^64-1 bytes
-        let ttl = now()
-            .duration_since(UNIX_EPOCH)
-            .context("Failed to get system time from unix epoch")?
-            .as_nanos()
-            + self.ttl_buf;
+        let ttl = ttl.min(
+            now()
+                .duration_since(UNIX_EPOCH)
+                .context("Failed to get system time from unix epoch")?
+                .as_nanos()
+                + self.ttl_buf,
+        );
         self.store.insert(
             Arc::clone(&key),
             Data {
@@ -138,7 +262,7 @@ impl<T: ByteSize> Tlrfu<T> {
             },
         );
         self.ttl.insert(ttl, key);
-        Ok(())
+        Ok(evicted)
     }
 
     pub async fn process_ttl_clean_up(&mut self) -> Result<usize> {
@@ -157,27 +281,46 @@ impl<T: ByteSize> Tlrfu<T> {
             {
                 return Ok(count);
             }
-            let data = self
-                .store
-                .remove(key.as_ref())
+            let key = Arc::clone(key);
+            self.remove(&key)
+                .await?
                 .with_context(|| format!("[TLRFU]: Key {key} not found at store while deleting"))?;
-            let lru = self
-                .freq
-                .get_mut(&data.freq)
-                .with_context(|| format!("[TLRFU]: Key: {key} not found at freq {}", data.freq))?;
-            lru.remove(&data.lru_k).await.with_context(|| {
-                format!(
-                    "[TLRFU]: Failed to remove LRU key: {} not found at freq {}",
-                    data.lru_k, data.freq
-                )
-            })?;
-            lru.is_empty().then(|| self.freq.remove(&data.freq));
-            self.used_size -= data.value.len() as u64;
-            self.ttl.remove(&data.ttl);
             count += 1;
         }
     }
 
+    /// Remove a single entry, `None` if it isn't cached.
+    pub async fn remove(&mut self, k: &String) -> Result<Option<Arc<T>>> {
+        let Some(data) = self.store.remove(k) else {
+            return Ok(None);
+        };
+        let lru = self
+            .freq
+            .get_mut(&data.freq)
+            .with_context(|| format!("[TLRFU]: Key: {k} not found at freq {}", data.freq))?;
+        lru.remove(&data.lru_k).await.with_context(|| {
+            format!(
+                "[TLRFU]: Failed to remove LRU key: {} not found at freq {}",
+                data.lru_k, data.freq
+            )
+        })?;
+        lru.is_empty().then(|| self.freq.remove(&data.freq));
+        self.used_size -= data.value.len() as u64;
+        self.ttl.remove(&data.ttl);
+        Ok(Some(data.value))
+    }
+
+    /// Cached entries with their frequency and ttl, least frequently used first.
+    pub fn entries(&self) -> Vec<(&String, &Arc<T>, usize, u128)> {
+        let mut entries: Vec<_> = self
+            .store
+            .iter()
+            .map(|(k, data)| (k.as_ref(), &data.value, data.freq, data.ttl))
+            .collect();
+        entries.sort_by_key(|(_, _, freq, _)| *freq);
+        entries
+    }
+
     pub fn purge(&mut self) {
         self.store = HashMap::new();
         self.freq = BTreeMap::new();
@@ -272,6 +415,26 @@ mod tests {
         assert_eq!(cache.used_size, 2);
     }
 
+    #[tokio::test]
+    async fn remove_one() {
+        let mut cache = Tlrfu::<Vec<u8>>::new(200_000_000, 0);
+        cache.insert("a".into(), Arc::new(vec![0])).await.unwrap();
+        cache
+            .insert("b".into(), Arc::new(vec![1, 1]))
+            .await
+            .unwrap();
+
+        let removed = cache.remove(&"a".to_string()).await.unwrap().unwrap();
+        assert_eq!(removed.as_ref(), &[0]);
+        assert!(cache.remove(&"a".to_string()).await.unwrap().is_none());
+
+        assert!(!cache.contains(&"a".to_string()));
+        assert_eq!(cache.store.len(), 1);
+        assert_eq!(cache.freq.get(&1).unwrap()._len(), 1);
+        assert_eq!(cache.ttl.len(), 1);
+        assert_eq!(cache.used_size, 2);
+    }
+
     #[tokio::test]
     async fn get_empty() {
         let mut cache = Tlrfu::<Vec<u8>>::new(200_000_000, 0);
@@ -370,11 +533,14 @@ mod tests {
         let mut cache = Tlrfu::<Vec<u8>>::new(2, 0);
         cache.insert("a".into(), Arc::new(vec![0])).await.unwrap();
         cache.insert("b".into(), Arc::new(vec![1])).await.unwrap();
-        cache.insert("c".into(), Arc::new(vec![2])).await.unwrap();
+        let evicted = cache.insert("c".into(), Arc::new(vec![2])).await.unwrap();
 
         assert_eq!(cache.store.len(), 2);
 
         assert!(cache.store.get(&"a".to_string()).is_none());
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].0.as_str(), "a");
+        assert_eq!(evicted[0].1.as_ref(), &[0]);
 
         let data = cache.store.get(&"b".to_string()).unwrap();
         assert_eq!(data.value.as_ref(), &[1]);
@@ -472,6 +638,49 @@ mod tests {
         assert_eq!(cache.used_size, 3);
     }
 
+    #[tokio::test]
+    async fn insert_exceed_high_water_evicts_to_low_water() {
+        let mut cache = Tlrfu::<Vec<u8>>::new(10, 0).with_watermarks(4, 10);
+        for k in ["a", "b", "c", "d", "e"] {
+            cache.insert(k.into(), Arc::new(vec![0, 1])).await.unwrap();
+        }
+        assert_eq!(cache.used_size, 10);
+
+        // crossing the high-water mark evicts a batch down to the low-water mark
+        cache
+            .insert("f".into(), Arc::new(vec![0, 1]))
+            .await
+            .unwrap();
+        assert_eq!(cache.store.len(), 2);
+        assert!(cache.store.get(&"e".to_string()).is_some());
+        assert!(cache.store.get(&"f".to_string()).is_some());
+        assert_eq!(cache.used_size, 4);
+
+        // no eviction until the high-water mark is crossed again
+        for k in ["g", "h", "i"] {
+            cache.insert(k.into(), Arc::new(vec![0, 1])).await.unwrap();
+        }
+        assert_eq!(cache.store.len(), 5);
+        assert_eq!(cache.used_size, 10);
+    }
+
+    #[tokio::test]
+    async fn insert_above_low_water_under_high_water() {
+        let mut cache = Tlrfu::<Vec<u8>>::new(10, 0).with_watermarks(2, 10);
+        cache
+            .insert("a".into(), Arc::new(vec![0; 8]))
+            .await
+            .unwrap();
+        cache
+            .insert("b".into(), Arc::new(vec![0; 5]))
+            .await
+            .unwrap();
+
+        assert_eq!(cache.store.len(), 1);
+        assert!(cache.store.get(&"b".to_string()).is_some());
+        assert_eq!(cache.used_size, 5);
+    }
+
     #[tokio::test]
     async fn process_ttl_clean_up_successfully() {
         let mut cache = Tlrfu::<Vec<u8>>::new(3, 1_000_000_000);
@@ -546,4 +755,42 @@ mod tests {
 
         assert_eq!(cache.ttl.len(), 1);
     }
+
+    #[tokio::test]
+    async fn lru_evicts_least_recently_used() {
+        let mut cache = Tlrfu::<Vec<u8>>::new(2, 0).with_policy(CacheEvictionPolicy::Lru);
+        cache.insert("a".into(), Arc::new(vec![0])).await.unwrap();
+        cache.get(&"a".into()).await.unwrap().unwrap();
+        cache.get(&"a".into()).await.unwrap().unwrap();
+        cache.insert("b".into(), Arc::new(vec![1])).await.unwrap();
+        cache.get(&"b".into()).await.unwrap().unwrap();
+        cache.insert("c".into(), Arc::new(vec![2])).await.unwrap();
+
+        assert!(!cache.contains(&"a".into()));
+        assert!(cache.contains(&"b".into()));
+        assert!(cache.contains(&"c".into()));
+        assert_eq!(cache.freq.len(), 1);
+        assert_eq!(cache.used_size, 2);
+    }
+
+    #[tokio::test]
+    async fn ttl_only_evicts_first_expiring() {
+        let mut cache =
+            Tlrfu::<Vec<u8>>::new(2, 1_000_000_000).with_policy(CacheEvictionPolicy::TtlOnly);
+        cache.insert("a".into(), Arc::new(vec![0])).await.unwrap();
+        let ttl = cache.store.get(&"a".to_string()).unwrap().ttl;
+        cache
+            .insert_until("b".into(), Arc::new(vec![1]), ttl - 1)
+            .await
+            .unwrap();
+        cache.get(&"a".into()).await.unwrap().unwrap();
+        cache.insert("c".into(), Arc::new(vec![2])).await.unwrap();
+
+        assert!(cache.contains(&"a".into()));
+        assert!(!cache.contains(&"b".into()));
+        assert!(cache.contains(&"c".into()));
+        assert_eq!(cache.store.get(&"a".to_string()).unwrap().ttl, ttl);
+        assert_eq!(cache.ttl.len(), 2);
+        assert_eq!(cache.used_size, 2);
+    }
 }
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use clap::{Args, Parser, Subcommand};
 use tracing::Level;
 
-use crate::config::DEFAULT_URSA_GATEWAY_CONFIG_PATH;
+use crate::config::{RouterKind, DEFAULT_URSA_GATEWAY_CONFIG_PATH};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)] // Read from `Cargo.toml`
@@ -42,6 +42,9 @@ pub struct DaemonCmdOpts {
     /// concurrency limit
     #[arg(long)]
     pub concurrency_limit: Option<u32>,
+    /// max simultaneous connections
+    #[arg(long)]
+    pub max_connections: Option<u32>,
     /// tls cert path
     #[arg(long)]
     pub tls_cert_path: Option<PathBuf>,
@@ -57,6 +60,24 @@ pub struct DaemonCmdOpts {
     /// cache control max size response
     #[arg(long)]
     pub cache_control_max_size: Option<u64>,
+    /// send a sha-256 digest of the content
+    #[arg(long)]
+    pub digest_header: Option<bool>,
+    /// send the x-ipfs-roots header
+    #[arg(long)]
+    pub roots_header: Option<bool>,
+    /// fetch uncached content on HEAD requests
+    #[arg(long)]
+    pub head_fetch: Option<bool>,
+    /// accepted cid multicodecs, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub allowed_codecs: Option<Vec<u64>>,
+    /// accepted cid multihash codes, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub allowed_hashes: Option<Vec<u64>>,
+    /// only serve cached content
+    #[arg(long)]
+    pub maintenance: Option<bool>,
     /// admin port
     #[arg(long)]
     pub admin_port: Option<u16>,
@@ -66,13 +87,55 @@ pub struct DaemonCmdOpts {
     /// indexer cid url
     #[arg(long)]
     pub indexer_cid_url: Option<String>,
+    /// resolve content through peers only, bypassing the indexer
+    #[arg(long)]
+    pub peers_only: Option<bool>,
+    /// peer node http addresses, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub peers: Option<Vec<String>>,
+    /// content routers asked in order, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub routers: Option<Vec<RouterKind>>,
+    /// upstream requests made for a single content
+    #[arg(long)]
+    pub max_attempts: Option<usize>,
+    /// check fetched content against its cid
+    #[arg(long)]
+    pub verify_content: Option<bool>,
+    /// indexer keep-warm ping interval (ms, 0 = disabled)
+    #[arg(long)]
+    pub keep_warm_interval: Option<u64>,
+    /// indexer connections kept pooled by the keep-warm ping
+    #[arg(long)]
+    pub keep_warm_connections: Option<usize>,
     /// max cache size (bytes)
     #[arg(long)]
     pub max_cache_size: Option<u64>,
     /// cache ttl (ms)
     #[arg(long)]
     pub ttl_buf: Option<u64>,
+    /// cache size to start evicting at (bytes)
+    #[arg(long)]
+    pub cache_high_water: Option<u64>,
+    /// cache size to evict down to (bytes)
+    #[arg(long)]
+    pub cache_low_water: Option<u64>,
+    /// cache persistence path
+    #[arg(long)]
+    pub cache_persist_path: Option<PathBuf>,
+    /// serve completed fetches while they are cached (ms, 0 = disabled)
+    #[arg(long)]
+    pub cache_dedup_window: Option<u64>,
     /// ttl cache interval (ms)
     #[arg(long)]
     pub ttl_cache_interval: Option<u64>,
+    /// per-worker shutdown timeout (ms)
+    #[arg(long)]
+    pub shutdown_timeout: Option<u64>,
+    /// max workers joined concurrently on shutdown (0 = unbounded)
+    #[arg(long)]
+    pub shutdown_concurrency: Option<usize>,
+    /// max fetches resolved concurrently (0 = unbounded)
+    #[arg(long)]
+    pub max_concurrent_fetches: Option<usize>,
 }
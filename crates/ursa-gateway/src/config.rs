@@ -5,10 +5,11 @@ use std::{
 };
 
 use anyhow::{Context, Result};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use tracing::{info, Level};
 
-use crate::cli::DaemonCmdOpts;
+use crate::{cache::CacheEvictionPolicy, cli::DaemonCmdOpts};
 
 pub const DEFAULT_URSA_GATEWAY_PATH: &str = ".ursa/gateway";
 pub const DEFAULT_URSA_GATEWAY_CONFIG_PATH: &str = ".ursa/gateway/config.toml";
@@ -58,33 +59,191 @@ pub struct ServerConfig {
     pub addr: String,
     pub request_timeout: u64,
     pub concurrency_limit: u32,
+    pub max_connections: u32,
     pub cert_path: PathBuf,
     pub key_path: PathBuf,
     pub stream_buf: u64,
     pub cache_control_max_age: u64,
     pub cache_control_max_size: u64,
+    /// send a sha-256 `Digest` of the content, as a header when its length is known and at
+    /// most 4 MiB, and as a trailer when it isn't known. Larger content is sent without one
+    pub digest_header: bool,
+    /// send the requested cid and the roots of the car in `X-Ipfs-Roots`
+    pub roots_header: bool,
+    /// fetch and cache uncached content on HEAD requests, instead of only resolving its size
+    pub head_fetch: bool,
+    /// accepted cid multicodecs, empty accepts all
+    pub allowed_codecs: Vec<u64>,
+    /// accepted cid multihash codes, empty accepts all
+    pub allowed_hashes: Vec<u64>,
+    /// only serve cached content, answering cache misses with `maintenance_status`,
+    /// toggled at runtime through the admin server
+    #[serde(default)]
+    pub maintenance: bool,
+    /// status of the cache misses in maintenance mode
+    #[serde(default = "ServerConfig::default_maintenance_status")]
+    pub maintenance_status: u16,
+    /// message of the cache misses in maintenance mode
+    #[serde(default = "ServerConfig::default_maintenance_message")]
+    pub maintenance_message: String,
+    /// list the directories without an `index.html` as html or json, per the `Accept` header,
+    /// instead of answering 404
+    #[serde(default)]
+    pub directory_listing: bool,
+    /// exposition format of `/metrics`
+    #[serde(default)]
+    pub metrics_format: MetricsFormat,
+    /// query params that change the content, kept in the cache key and sent to the providers,
+    /// every other param is ignored by the cache and the resolution
+    #[serde(default)]
+    pub cache_key_params: Vec<String>,
+    /// bound of the `x-ursa-timeout-ms` header, overriding `request_timeout` for a request,
+    /// at least `request_timeout`
+    #[serde(default = "ServerConfig::default_max_request_timeout")]
+    pub max_request_timeout: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsFormat {
+    #[default]
+    Prometheus,
+    /// with the trace ids of the request latency samples as exemplars
+    OpenMetrics,
+}
+
+impl ServerConfig {
+    fn default_maintenance_status() -> u16 {
+        503
+    }
+    fn default_max_request_timeout() -> u64 {
+        30_000 // 30s
+    }
+    fn default_maintenance_message() -> String {
+        "The gateway is under maintenance, only cached content is served".into()
+    }
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct AdminConfig {
     pub port: u16,
     pub addr: String,
+    /// bearer token authorizing every admin route, `DELETE /cache/:cid` is disabled without one
+    #[serde(default)]
+    pub token: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct IndexerConfig {
     pub cid_url: String,
+    /// indexers asked in order by the indexer router when `cid_url` fails or times out
+    #[serde(default)]
+    pub fallback_cid_urls: Vec<String>,
+    /// timeout (ms) of each router lookup, after which the next router is asked, 0 disables it
+    #[serde(default = "IndexerConfig::default_lookup_timeout")]
+    pub lookup_timeout: u64,
+    /// keep the providers found by a router lookup for this long (ms), 0 disables it
+    #[serde(default = "IndexerConfig::default_resolver_cache_ttl")]
+    pub resolver_cache_ttl: u64,
+    /// keep the content reported missing by a router for this long (ms), 0 disables it
+    #[serde(default = "IndexerConfig::default_resolver_negative_cache_ttl")]
+    pub resolver_negative_cache_ttl: u64,
+    /// bypass the indexer, resolving content through `peers` only, same as `routers = ["peers"]`
+    pub peers_only: bool,
+    /// node http addresses queried in order by the peers router
+    pub peers: Vec<String>,
+    /// content routers asked for the providers of a content, in order
+    #[serde(default = "IndexerConfig::default_routers")]
+    pub routers: Vec<RouterKind>,
+    /// router lookups and provider requests made for a single content, across all routers
+    #[serde(default = "IndexerConfig::default_max_attempts")]
+    pub max_attempts: usize,
+    /// check the fetched content against its cid while it is served, aborting it on a mismatch
+    #[serde(default = "IndexerConfig::default_verify_content")]
+    pub verify_content: bool,
+    /// ping the indexer at this interval (ms) to keep connections pooled, 0 disables it
+    #[serde(default = "IndexerConfig::default_keep_warm_interval")]
+    pub keep_warm_interval: u64,
+    /// connections kept pooled by the keep-warm ping
+    #[serde(default = "IndexerConfig::default_keep_warm_connections")]
+    pub keep_warm_connections: usize,
+}
+
+impl IndexerConfig {
+    fn default_routers() -> Vec<RouterKind> {
+        vec![RouterKind::Indexer]
+    }
+    fn default_max_attempts() -> usize {
+        8
+    }
+    fn default_lookup_timeout() -> u64 {
+        2_000 // 2s
+    }
+    fn default_resolver_cache_ttl() -> u64 {
+        60_000 // 1min
+    }
+    fn default_resolver_negative_cache_ttl() -> u64 {
+        5_000 // 5s
+    }
+    fn default_verify_content() -> bool {
+        true
+    }
+    fn default_keep_warm_interval() -> u64 {
+        0 // disabled
+    }
+    fn default_keep_warm_connections() -> usize {
+        2
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RouterKind {
+    /// providers announced to the indexer
+    Indexer,
+    /// the nodes listed in `peers`
+    Peers,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct CacheConfig {
     pub max_size: u64,
     pub ttl_buf: u64,
+    /// start evicting once the cache grows past this size, defaults to `max_size`
+    pub high_water: Option<u64>,
+    /// evict down to this size in a single batch, defaults to `max_size`
+    pub low_water: Option<u64>,
+    /// persist the cache entries here on shutdown and restore them on startup
+    pub persist_path: Option<PathBuf>,
+    /// serve a completed fetch for this long (ms) while it is being cached, 0 disables it
+    #[serde(default = "CacheConfig::default_dedup_window")]
+    pub dedup_window: u64,
+    /// entry evicted once the cache is full: lru, lfu or ttl-only
+    #[serde(default)]
+    pub eviction_policy: CacheEvictionPolicy,
+    /// keep the entries evicted from memory as files here, checked on a miss before fetching
+    pub disk_cache_dir: Option<PathBuf>,
+    /// size of the disk tier, the first written files are removed past it
+    #[serde(default = "CacheConfig::default_disk_cache_max_bytes")]
+    pub disk_cache_max_bytes: u64,
+}
+
+impl CacheConfig {
+    fn default_dedup_window() -> u64 {
+        1_000
+    }
+    fn default_disk_cache_max_bytes() -> u64 {
+        10_000_000_000 // 10GB
+    }
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct WorkerConfig {
     pub ttl_cache_interval: u64,
+    pub shutdown_timeout: u64,
+    pub shutdown_concurrency: usize,
+    /// fetches resolved concurrently, queued fetches are served round-robin across clients
+    pub max_concurrent_fetches: usize,
 }
 
 impl Default for GatewayConfig {
@@ -96,6 +255,7 @@ impl Default for GatewayConfig {
                 port: 443,
                 request_timeout: 5_000, // 5s
                 concurrency_limit: 100_000,
+                max_connections: 10_000,
                 cert_path: PathBuf::from(env!("HOME"))
                     .join(DEFAULT_URSA_GATEWAY_PATH)
                     .join("cert.pem"),
@@ -105,20 +265,54 @@ impl Default for GatewayConfig {
                 stream_buf: 2_000_000,                 // 2MB
                 cache_control_max_age: 604_800,        // one week
                 cache_control_max_size: 1_000_000_000, // 1GB
+                digest_header: false,
+                roots_header: false,
+                head_fetch: false,
+                allowed_codecs: vec![],
+                allowed_hashes: vec![],
+                maintenance: false,
+                maintenance_status: ServerConfig::default_maintenance_status(),
+                maintenance_message: ServerConfig::default_maintenance_message(),
+                directory_listing: false,
+                metrics_format: MetricsFormat::Prometheus,
+                cache_key_params: vec![],
+                max_request_timeout: ServerConfig::default_max_request_timeout(),
             },
             admin_server: AdminConfig {
                 addr: "0.0.0.0".into(),
                 port: 5001,
+                token: None,
             },
             indexer: IndexerConfig {
                 cid_url: "https://cid.contact/cid".into(),
+                fallback_cid_urls: vec![],
+                lookup_timeout: IndexerConfig::default_lookup_timeout(),
+                resolver_cache_ttl: IndexerConfig::default_resolver_cache_ttl(),
+                resolver_negative_cache_ttl: IndexerConfig::default_resolver_negative_cache_ttl(),
+                peers_only: false,
+                peers: vec![],
+                routers: IndexerConfig::default_routers(),
+                max_attempts: IndexerConfig::default_max_attempts(),
+                verify_content: IndexerConfig::default_verify_content(),
+                keep_warm_interval: IndexerConfig::default_keep_warm_interval(),
+                keep_warm_connections: IndexerConfig::default_keep_warm_connections(),
             },
             cache: CacheConfig {
                 max_size: 200_000_000,  // 200MB
                 ttl_buf: 5 * 60 * 1000, // 5 mins
+                high_water: None,
+                low_water: None,
+                persist_path: None,
+                dedup_window: CacheConfig::default_dedup_window(),
+                eviction_policy: CacheEvictionPolicy::default(),
+                disk_cache_dir: None,
+                disk_cache_max_bytes: CacheConfig::default_disk_cache_max_bytes(),
             },
             worker: WorkerConfig {
                 ttl_cache_interval: 5 * 60 * 1000, // 5 mins
+                shutdown_timeout: 30_000,          // 30s
+                shutdown_concurrency: 0,           // unbounded
+                max_concurrent_fetches: 0,         // unbounded
             },
         }
     }
@@ -144,6 +338,9 @@ impl GatewayConfig {
         if let Some(concurrency_limit) = config.concurrency_limit {
             self.server.concurrency_limit = concurrency_limit;
         }
+        if let Some(max_connections) = config.max_connections {
+            self.server.max_connections = max_connections;
+        }
         if let Some(tls_cert_path) = config.tls_cert_path {
             self.server.cert_path = tls_cert_path;
         }
@@ -156,6 +353,24 @@ impl GatewayConfig {
         if let Some(cache_control_max_age) = config.cache_control_max_age {
             self.server.cache_control_max_age = cache_control_max_age;
         }
+        if let Some(digest_header) = config.digest_header {
+            self.server.digest_header = digest_header;
+        }
+        if let Some(roots_header) = config.roots_header {
+            self.server.roots_header = roots_header;
+        }
+        if let Some(head_fetch) = config.head_fetch {
+            self.server.head_fetch = head_fetch;
+        }
+        if let Some(allowed_codecs) = config.allowed_codecs {
+            self.server.allowed_codecs = allowed_codecs;
+        }
+        if let Some(allowed_hashes) = config.allowed_hashes {
+            self.server.allowed_hashes = allowed_hashes;
+        }
+        if let Some(maintenance) = config.maintenance {
+            self.server.maintenance = maintenance;
+        }
         if let Some(port) = config.admin_port {
             self.admin_server.port = port;
         }
@@ -165,17 +380,59 @@ impl GatewayConfig {
         if let Some(indexer_cid_url) = config.indexer_cid_url {
             self.indexer.cid_url = indexer_cid_url;
         }
+        if let Some(peers_only) = config.peers_only {
+            self.indexer.peers_only = peers_only;
+        }
+        if let Some(peers) = config.peers {
+            self.indexer.peers = peers;
+        }
+        if let Some(routers) = config.routers {
+            self.indexer.routers = routers;
+        }
+        if let Some(max_attempts) = config.max_attempts {
+            self.indexer.max_attempts = max_attempts;
+        }
+        if let Some(verify_content) = config.verify_content {
+            self.indexer.verify_content = verify_content;
+        }
+        if let Some(keep_warm_interval) = config.keep_warm_interval {
+            self.indexer.keep_warm_interval = keep_warm_interval;
+        }
+        if let Some(keep_warm_connections) = config.keep_warm_connections {
+            self.indexer.keep_warm_connections = keep_warm_connections;
+        }
         if let Some(max_cache_size) = config.max_cache_size {
             self.cache.max_size = max_cache_size;
         }
         if let Some(ttl_buf) = config.ttl_buf {
             self.cache.ttl_buf = ttl_buf;
         }
+        if let Some(cache_high_water) = config.cache_high_water {
+            self.cache.high_water = Some(cache_high_water);
+        }
+        if let Some(cache_low_water) = config.cache_low_water {
+            self.cache.low_water = Some(cache_low_water);
+        }
+        if let Some(cache_persist_path) = config.cache_persist_path {
+            self.cache.persist_path = Some(cache_persist_path);
+        }
+        if let Some(cache_dedup_window) = config.cache_dedup_window {
+            self.cache.dedup_window = cache_dedup_window;
+        }
         if let Some(ttl_cache_interval) = config.ttl_cache_interval {
             self.worker.ttl_cache_interval = ttl_cache_interval;
         }
         if let Some(cache_control_max_size) = config.cache_control_max_size {
             self.server.cache_control_max_size = cache_control_max_size;
         }
+        if let Some(shutdown_timeout) = config.shutdown_timeout {
+            self.worker.shutdown_timeout = shutdown_timeout;
+        }
+        if let Some(shutdown_concurrency) = config.shutdown_concurrency {
+            self.worker.shutdown_concurrency = shutdown_concurrency;
+        }
+        if let Some(max_concurrent_fetches) = config.max_concurrent_fetches {
+            self.worker.max_concurrent_fetches = max_concurrent_fetches;
+        }
     }
 }
@@ -7,15 +7,19 @@ mod server;
 mod util;
 mod worker;
 
-use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+use std::{iter::once, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use cli::{Cli, Commands};
-use config::{init_config, load_config};
+use config::{init_config, load_config, RouterKind};
+use futures::{stream, StreamExt};
 use hyper::Body;
 use hyper_tls::HttpsConnector;
-use resolver::Resolver;
+use resolver::{
+    router::{ContentRouter, IndexerRouter, StaticRouter},
+    Resolver,
+};
 use tokio::{
     select,
     signal::{
@@ -30,9 +34,9 @@ use tokio::{
     },
     task::JoinHandle,
 };
-use tracing::{error, info, info_span, Instrument, Level};
+use tracing::{error, info, info_span, warn, Instrument, Level};
 use ursa_telemetry::TelemetryConfig;
-use worker::cache::Cache;
+use worker::cache::{disk::DiskCache, Cache};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -68,20 +72,98 @@ async fn main() -> Result<()> {
             gateway_config.merge_daemon_opts(opts);
 
             let ttl_cache_interval = gateway_config.worker.ttl_cache_interval;
+            let shutdown_timeout = Duration::from_millis(gateway_config.worker.shutdown_timeout);
+            let shutdown_concurrency = gateway_config.worker.shutdown_concurrency;
+            let max_concurrent_fetches = gateway_config.worker.max_concurrent_fetches;
+            let cache_persist_path = gateway_config.cache.persist_path.clone();
 
-            let resolver = Arc::new(Resolver::new(
-                String::from(&gateway_config.indexer.cid_url),
-                hyper::Client::builder().build::<_, Body>(HttpsConnector::new()),
-            ));
+            let client = hyper::Client::builder().build::<_, Body>(HttpsConnector::new());
+            let indexer = &gateway_config.indexer;
+            let router_kinds = if indexer.peers_only {
+                vec![RouterKind::Peers]
+            } else {
+                indexer.routers.clone()
+            };
+            if router_kinds.is_empty() {
+                bail!("At least one content router is required");
+            }
+            if indexer.max_attempts == 0 {
+                bail!("max_attempts must be greater than 0");
+            }
+            let mut routers: Vec<Box<dyn ContentRouter>> = Vec::new();
+            for kind in router_kinds {
+                match kind {
+                    // the fallback indexers are asked right after the primary one
+                    RouterKind::Indexer => {
+                        for cid_url in once(&indexer.cid_url).chain(&indexer.fallback_cid_urls) {
+                            routers.push(Box::new(IndexerRouter::new(
+                                cid_url.clone(),
+                                client.clone(),
+                            )));
+                        }
+                    }
+                    RouterKind::Peers => {
+                        if indexer.peers.is_empty() {
+                            bail!("The peers router requires at least one peer");
+                        }
+                        info!("Resolving content through peers: {:?}", indexer.peers);
+                        routers.push(Box::new(StaticRouter::new(indexer.peers.clone())));
+                    }
+                }
+            }
+            let keep_warm = (indexer.keep_warm_interval != 0).then(|| {
+                (
+                    IndexerRouter::new(indexer.cid_url.clone(), client.clone()),
+                    Duration::from_millis(indexer.keep_warm_interval),
+                    indexer.keep_warm_connections,
+                )
+            });
+            let mut resolver =
+                Resolver::new(routers, client).with_max_attempts(indexer.max_attempts);
+            if indexer.verify_content {
+                resolver = resolver.with_verify();
+            }
+            if indexer.lookup_timeout != 0 {
+                resolver =
+                    resolver.with_lookup_timeout(Duration::from_millis(indexer.lookup_timeout));
+            }
+            if indexer.resolver_cache_ttl != 0 || indexer.resolver_negative_cache_ttl != 0 {
+                resolver = resolver.with_cache(
+                    Duration::from_millis(indexer.resolver_cache_ttl),
+                    Duration::from_millis(indexer.resolver_negative_cache_ttl),
+                );
+            }
+            let resolver = Arc::new(resolver);
 
             let (worker_tx, worker_rx) = mpsc::unbounded_channel();
-            let cache = Arc::new(RwLock::new(Cache::new(
-                gateway_config.cache.max_size,
+            let max_cache_size = gateway_config.cache.max_size;
+            let mut cache = Cache::new(
+                max_cache_size,
                 gateway_config.cache.ttl_buf as u128 * 1_000_000, // ms to ns
                 worker_tx.clone(),                                // cache command producer
                 gateway_config.server.stream_buf,
                 gateway_config.server.cache_control_max_size,
-            )));
+            )
+            .with_watermarks(
+                gateway_config.cache.low_water.unwrap_or(max_cache_size),
+                gateway_config.cache.high_water.unwrap_or(max_cache_size),
+            )
+            .with_dedup_window(Duration::from_millis(gateway_config.cache.dedup_window))
+            .with_eviction_policy(gateway_config.cache.eviction_policy);
+            if let Some(dir) = &gateway_config.cache.disk_cache_dir {
+                let disk = DiskCache::open(dir, gateway_config.cache.disk_cache_max_bytes)
+                    .await
+                    .with_context(|| format!("Failed to open disk cache at: {dir:?}"))?;
+                cache = cache.with_disk_cache(disk);
+            }
+            let cache = Arc::new(RwLock::new(cache));
+            if let Some(path) = &cache_persist_path {
+                match cache.write().await.restore(path).await {
+                    Ok(count) => info!("Restored {count} cache entries from: {path:?}"),
+                    Err(e) => warn!("Failed to restore cache: {e:?}"),
+                }
+            }
+            let persist_cache = Arc::clone(&cache);
             let server_cache = Arc::clone(&cache);
             let admin_cache = Arc::clone(&server_cache);
 
@@ -152,15 +234,42 @@ async fn main() -> Result<()> {
                 )
             };
 
+            let keep_warm_worker = keep_warm.map(|(indexer, interval, connections)| {
+                let mut shutdown_rx = shutdown_tx.subscribe();
+                let worker = async move {
+                    info!("[Keep Warm Worker]: Interval: {interval:?}, connections: {connections}");
+                    loop {
+                        select! {
+                            _ = tokio::time::sleep(interval) => {
+                                indexer.ping(connections).await;
+                            },
+                            _ = shutdown_rx.recv() => {
+                                break;
+                            }
+                        }
+                    }
+                    info!("Keep warm worker stopped");
+                };
+                spawn(worker.instrument(info_span!("Keep warm worker")))
+            });
+
             // main worker to stop last
             let (main_worker, main_shutdown_tx, mut worker_signal_rx) = {
                 let (signal_tx, signal_rx) = mpsc::channel(1);
                 let (main_shutdown_tx, shutdown_rx) = mpsc::channel(1);
-                let worker = worker::start(worker_rx, cache, resolver, signal_tx, shutdown_rx);
+                let worker = worker::start(
+                    worker_rx,
+                    cache,
+                    resolver,
+                    max_concurrent_fetches,
+                    signal_tx,
+                    shutdown_rx,
+                );
                 (worker, main_shutdown_tx, signal_rx)
             };
 
-            let workers = vec![server_worker, admin_worker, ttl_cache_worker];
+            let mut workers = vec![server_worker, admin_worker, ttl_cache_worker];
+            workers.extend(keep_warm_worker);
 
             #[cfg(unix)]
             let terminate = async {
@@ -174,12 +283,18 @@ async fn main() -> Result<()> {
             let terminate = std::future::pending::<()>();
 
             select! {
-                _ = ctrl_c() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker).await,
-                _ = terminate => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker).await,
-                _ = server_worker_signal_rx.recv() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker).await,
-                _ = admin_worker_signal_rx.recv() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker).await,
-                _ = ttl_cache_worker_signal_rx.recv() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker).await,
-                _ = worker_signal_rx.recv() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker).await
+                _ = ctrl_c() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker, shutdown_timeout, shutdown_concurrency).await,
+                _ = terminate => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker, shutdown_timeout, shutdown_concurrency).await,
+                _ = server_worker_signal_rx.recv() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker, shutdown_timeout, shutdown_concurrency).await,
+                _ = admin_worker_signal_rx.recv() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker, shutdown_timeout, shutdown_concurrency).await,
+                _ = ttl_cache_worker_signal_rx.recv() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker, shutdown_timeout, shutdown_concurrency).await,
+                _ = worker_signal_rx.recv() => graceful_shutdown(shutdown_tx, workers, main_shutdown_tx, main_worker, shutdown_timeout, shutdown_concurrency).await
+            }
+            if let Some(path) = &cache_persist_path {
+                match persist_cache.read().await.persist(path).await {
+                    Ok(count) => info!("Persisted {count} cache entries to: {path:?}"),
+                    Err(e) => error!("Failed to persist cache: {e:?}"),
+                }
             }
             info!("Gateway shut down successfully")
         }
@@ -193,17 +308,33 @@ async fn graceful_shutdown(
     workers: Vec<JoinHandle<()>>,
     main_shutdown_tx: mpsc::Sender<()>,
     main_worker: JoinHandle<()>,
+    timeout: Duration,
+    concurrency: usize,
 ) {
     info!("Gateway shutting down...");
     shutdown_tx
         .send(())
         .expect("Send shutdown signal successfully");
-    for worker in workers {
-        worker.await.expect("Worker to shut down successfully");
-    }
+    // join the workers concurrently so a slow worker doesn't delay the others
+    stream::iter(workers)
+        .for_each_concurrent((concurrency > 0).then_some(concurrency), |worker| {
+            join_worker(worker, timeout)
+        })
+        .await;
+    // main worker to stop last
     main_shutdown_tx
         .send(())
         .await
         .expect("Send shutdown signal successfully");
-    main_worker.await.expect("Worker to shut down successfully");
+    join_worker(main_worker, timeout).await;
+}
+
+async fn join_worker(mut worker: JoinHandle<()>, timeout: Duration) {
+    match tokio::time::timeout(timeout, &mut worker).await {
+        Ok(result) => result.expect("Worker to shut down successfully"),
+        Err(_) => {
+            warn!("Worker did not shut down within {timeout:?}, aborting");
+            worker.abort();
+        }
+    }
 }
@@ -0,0 +1,289 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::StatusCode;
+use metrics::increment_counter;
+use tokio::sync::OnceCell;
+
+use super::router::Providers;
+use crate::util::error::Error;
+
+/// Most lookups kept, the oldest are evicted past this.
+const MAX_ENTRIES: usize = 10_000;
+
+struct Resolution {
+    result: Result<Providers, Error>,
+    at: Instant,
+}
+
+/// Provider lookups of the routers, kept for a ttl. Concurrent lookups of the same
+/// content share a single router request.
+pub struct ResolutionCache {
+    ttl: Duration,
+    /// ttl of the lookups of content the router doesn't know of
+    negative_ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+type Key = (usize, String);
+
+/// Lookups keyed by the index of the router and the cid, with the order they were
+/// made in so the oldest are evicted first.
+#[derive(Default)]
+struct Entries {
+    lookups: HashMap<Key, (u64, Arc<OnceCell<Resolution>>)>,
+    /// sequence number and key of the lookups, oldest first. Keys looked up again
+    /// or removed since are left behind with an outdated sequence number
+    order: VecDeque<(u64, Key)>,
+    next: u64,
+}
+
+impl ResolutionCache {
+    pub fn new(ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            ttl,
+            negative_ttl,
+            entries: Default::default(),
+        }
+    }
+
+    /// How long `result` is served from the cache, only the providers and the
+    /// content reported missing are kept, other failures are retried right away.
+    fn ttl(&self, result: &Result<Providers, Error>) -> Duration {
+        match result {
+            Ok(_) => self.ttl,
+            Err(Error::Upstream(StatusCode::NOT_FOUND, _)) => self.negative_ttl,
+            Err(_) => Duration::ZERO,
+        }
+    }
+
+    fn expired(&self, resolution: &Resolution) -> bool {
+        resolution.at.elapsed() >= self.ttl(&resolution.result)
+    }
+
+    /// The cached lookup of `cid` by the router at `router`, or the result of `lookup`.
+    pub async fn get_or_lookup<F, Fut>(
+        &self,
+        router: usize,
+        cid: &str,
+        lookup: F,
+    ) -> Result<Providers, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Providers, Error>>,
+    {
+        let key = (router, cid.to_string());
+        let cell = {
+            let mut entries = self.entries.lock().unwrap();
+            // pending lookups are joined
+            match entries
+                .lookups
+                .get(&key)
+                .filter(|(_, cell)| cell.get().map_or(true, |r| !self.expired(r)))
+            {
+                Some((_, cell)) => {
+                    if cell.initialized() {
+                        increment_counter!("gateway_resolution_cache_hits");
+                    }
+                    Arc::clone(cell)
+                }
+                None => {
+                    self.evict(&mut entries);
+                    let cell = Arc::new(OnceCell::new());
+                    let seq = entries.next;
+                    entries.next += 1;
+                    entries.order.push_back((seq, key.clone()));
+                    entries
+                        .lookups
+                        .insert(key.clone(), (seq, Arc::clone(&cell)));
+                    cell
+                }
+            }
+        };
+        let resolution = cell
+            .get_or_init(|| async {
+                Resolution {
+                    result: lookup().await,
+                    at: Instant::now(),
+                }
+            })
+            .await;
+        if self.ttl(&resolution.result).is_zero() {
+            let mut entries = self.entries.lock().unwrap();
+            if entries
+                .lookups
+                .get(&key)
+                .map_or(false, |(_, entry)| Arc::ptr_eq(entry, &cell))
+            {
+                entries.lookups.remove(&key);
+            }
+        }
+        resolution.result.clone()
+    }
+
+    /// Drop the oldest lookups while they are expired or there are too many, making
+    /// room for a new one.
+    fn evict(&self, entries: &mut Entries) {
+        while let Some((seq, key)) = entries.order.front() {
+            let current = entries.lookups.get(key).filter(|(s, _)| s == seq);
+            let stale = current.map_or(true, |(_, cell)| {
+                cell.get().map_or(false, |r| self.expired(r))
+            });
+            if !stale && entries.order.len() < MAX_ENTRIES {
+                break;
+            }
+            let (seq, key) = entries.order.pop_front().unwrap();
+            if entries.lookups.get(&key).map_or(false, |(s, _)| *s == seq) {
+                entries.lookups.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::future::join_all;
+    use tokio::time::sleep;
+
+    use super::*;
+
+    /// Lookup counting its calls, answering `result` after a while.
+    async fn lookup(
+        calls: &AtomicUsize,
+        result: Result<Providers, Error>,
+    ) -> Result<Providers, Error> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        sleep(Duration::from_millis(20)).await;
+        result
+    }
+
+    fn found() -> Result<Providers, Error> {
+        Ok(Providers {
+            addresses: vec!["http://127.0.0.1:4069".into()],
+            size: Some(4),
+        })
+    }
+
+    fn not_found() -> Result<Providers, Error> {
+        Err(Error::Upstream(StatusCode::NOT_FOUND, "not found".into()))
+    }
+
+    #[tokio::test]
+    async fn concurrent_lookups_coalesce() {
+        let cache = ResolutionCache::new(Duration::from_secs(60), Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+        let results =
+            join_all((0..10).map(|_| cache.get_or_lookup(0, "cid", || lookup(&calls, found()))))
+                .await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        for result in results {
+            assert_eq!(result.unwrap().size, Some(4));
+        }
+
+        // other cids and routers are looked up on their own
+        cache
+            .get_or_lookup(0, "other", || lookup(&calls, found()))
+            .await
+            .unwrap();
+        cache
+            .get_or_lookup(1, "cid", || lookup(&calls, found()))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn entries_expire() {
+        let cache = ResolutionCache::new(Duration::from_millis(300), Duration::from_millis(50));
+        let calls = AtomicUsize::new(0);
+        for _ in 0..2 {
+            cache
+                .get_or_lookup(0, "cid", || lookup(&calls, found()))
+                .await
+                .unwrap();
+            cache
+                .get_or_lookup(0, "missing", || lookup(&calls, not_found()))
+                .await
+                .unwrap_err();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // the negative entry expires first
+        sleep(Duration::from_millis(60)).await;
+        cache
+            .get_or_lookup(0, "cid", || lookup(&calls, found()))
+            .await
+            .unwrap();
+        cache
+            .get_or_lookup(0, "missing", || lookup(&calls, not_found()))
+            .await
+            .unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        sleep(Duration::from_millis(300)).await;
+        cache
+            .get_or_lookup(0, "cid", || lookup(&calls, found()))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn oldest_entries_evicted() {
+        let cache = ResolutionCache::new(Duration::from_secs(60), Duration::from_millis(50));
+        let calls = AtomicUsize::new(0);
+        let counted = || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { found() }
+        };
+        for i in 0..=MAX_ENTRIES {
+            cache
+                .get_or_lookup(0, &i.to_string(), counted)
+                .await
+                .unwrap();
+        }
+        assert_eq!(cache.entries.lock().unwrap().lookups.len(), MAX_ENTRIES);
+
+        // the first lookup made room for the last one
+        cache.get_or_lookup(0, "1", counted).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), MAX_ENTRIES + 1);
+        cache.get_or_lookup(0, "0", counted).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), MAX_ENTRIES + 2);
+
+        // expired entries are evicted once the oldest
+        let cache = ResolutionCache::new(Duration::from_secs(60), Duration::from_millis(50));
+        cache
+            .get_or_lookup(0, "missing", || lookup(&calls, not_found()))
+            .await
+            .unwrap_err();
+        sleep(Duration::from_millis(60)).await;
+        cache
+            .get_or_lookup(0, "cid", || lookup(&calls, found()))
+            .await
+            .unwrap();
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.lookups.len(), 1);
+        assert_eq!(entries.order.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failures_not_cached() {
+        let cache = ResolutionCache::new(Duration::from_secs(60), Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+        for _ in 0..2 {
+            cache
+                .get_or_lookup(0, "cid", || {
+                    lookup(&calls, Err(Error::Internal("unreachable".into())))
+                })
+                .await
+                .unwrap_err();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,33 +1,72 @@
+pub mod cache;
 pub mod model;
+pub mod router;
+pub mod verify;
 
-use anyhow::{anyhow, Context};
-use axum::{
-    body::Body,
-    http::response::{Parts, Response},
-};
+use std::{str::FromStr, time::Duration};
+
+use axum::{body::Body, http::response::Response};
 use hyper::{
-    body::to_bytes,
+    body::HttpBody,
     client::{self, HttpConnector},
-    StatusCode, Uri,
+    header, StatusCode, Uri,
 };
 use hyper_tls::HttpsConnector;
-use libp2p::multiaddr::Protocol;
-use model::IndexerResponse;
-use serde_json::from_slice;
-use tracing::{debug, error, info, warn};
+use libipld::Cid;
+use metrics::increment_counter;
+use tokio::time::timeout;
+use tracing::{debug, error, warn};
 
 use crate::{
-    resolver::model::{Metadata, ProviderResult},
-    util::error::Error,
+    resolver::{
+        cache::ResolutionCache,
+        router::{ContentRouter, Providers},
+        verify::{verified_body, CarVerifier},
+    },
+    util::error::{Error, FetchFailure},
 };
 
-const FLEEK_NETWORK_FILTER: &[u8] = b"FleekNetwork";
-
 type Client = client::Client<HttpsConnector<HttpConnector>, Body>;
 
 pub struct Resolver {
-    indexer_cid_url: String,
+    /// Routers asked for the providers of a content, in order.
+    routers: Vec<Box<dyn ContentRouter>>,
     client: Client,
+    max_attempts: usize,
+    /// Give up on a router lookup past this, asking the next router.
+    lookup_timeout: Option<Duration>,
+    /// Lookups kept for a while, shared by the concurrent requests of a content.
+    cache: Option<ResolutionCache>,
+    /// Check the fetched content against the cid while it is served.
+    verify: bool,
+}
+
+/// Upstream requests left to resolve a single content, shared by the
+/// router lookups and the provider failover so retries can't compound.
+struct RetryBudget {
+    max: usize,
+    used: usize,
+}
+
+impl RetryBudget {
+    fn new(max: usize) -> Self {
+        Self { max, used: 0 }
+    }
+
+    /// Take an attempt, failing once the budget is exhausted.
+    fn attempt(&mut self, cid: &str) -> Result<(), Error> {
+        if self.used == self.max {
+            warn!("Retry budget of {} attempts exhausted for {cid}", self.max);
+            increment_counter!("gateway_retry_budget_exhausted");
+            return Err(Error::Fetch {
+                reason: FetchFailure::RetryBudgetExhausted,
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: format!("Retry budget of {} attempts exhausted", self.max),
+            });
+        }
+        self.used += 1;
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -37,139 +76,145 @@ pub struct NodeResponse {
 }
 
 impl Resolver {
-    pub fn new(indexer_cid_url: String, client: Client) -> Self {
+    pub fn new(routers: Vec<Box<dyn ContentRouter>>, client: Client) -> Self {
         Self {
-            indexer_cid_url,
+            routers,
             client,
+            max_attempts: usize::MAX,
+            lookup_timeout: None,
+            cache: None,
+            verify: false,
+        }
+    }
+
+    /// Reject content not matching its cid. Providers serving another content are failed
+    /// over, a mismatch found once the content is being served aborts it.
+    pub fn with_verify(self) -> Self {
+        Self {
+            verify: true,
+            ..self
+        }
+    }
+
+    /// Bound the router lookups and provider requests made for a single content.
+    pub fn with_max_attempts(self, max_attempts: usize) -> Self {
+        Self {
+            max_attempts,
+            ..self
         }
     }
 
-    pub async fn resolve_content(&self, cid: &str) -> Result<NodeResponse, Error> {
-        let endpoint = format!("{}/{cid}", self.indexer_cid_url);
+    /// Bound each router lookup, a router too slow to answer counts as failed.
+    pub fn with_lookup_timeout(self, lookup_timeout: Duration) -> Self {
+        Self {
+            lookup_timeout: Some(lookup_timeout),
+            ..self
+        }
+    }
 
-        let uri = endpoint.parse::<Uri>().map_err(|e| {
-            error!("Error parsed uri: {endpoint} {e:?}");
-            anyhow!("Error parsed uri: {endpoint}")
-        })?;
+    /// Keep the providers found for `ttl`, and the content not found for `negative_ttl`.
+    pub fn with_cache(self, ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            cache: Some(ResolutionCache::new(ttl, negative_ttl)),
+            ..self
+        }
+    }
 
-        let body = match self
-            .client
-            .get(uri)
-            .await
-            .map_err(|e| {
-                error!("Error requested indexer: {endpoint} {e:?}");
-                anyhow!("Error requested indexer: {endpoint}")
-            })?
-            .into_parts()
-        {
-            (
-                Parts {
-                    status: StatusCode::OK,
-                    ..
-                },
-                body,
-            ) => body,
-            (parts, body) => {
-                error!("Error requested indexer {endpoint} with parts {parts:?} and body {body:?}");
-                return Err(Error::Upstream(
-                    parts.status,
-                    format!("Error requested indexer: {endpoint}"),
-                ));
+    /// Providers of `cid` found by the router at `index`.
+    async fn find_providers(&self, index: usize, cid: &str) -> Result<Providers, Error> {
+        let router = self.routers[index].as_ref();
+        match &self.cache {
+            Some(cache) => {
+                cache
+                    .get_or_lookup(index, cid, || self.lookup(router, cid))
+                    .await
             }
-        };
+            None => self.lookup(router, cid).await,
+        }
+    }
 
-        let bytes = to_bytes(body).await.map_err(|e| {
-            error!("Error read data from indexer: {endpoint} {e:?}");
-            anyhow!("Error read data from indexer {endpoint}")
-        })?;
-
-        let indexer_response: IndexerResponse = from_slice(&bytes).map_err(|e| {
-            error!("Error parsed indexer response from indexer: {endpoint} {e:?}");
-            anyhow!("Error parsed indexer response from indexer: {endpoint}")
-        })?;
-
-        debug!("Received indexer response for {cid}: {indexer_response:?}");
-
-        let providers: Vec<(&ProviderResult, Metadata)> = indexer_response
-            .multihash_results
-            .first()
-            .context("Indexer result did not contain a multi-hash result")?
-            .provider_results
-            .iter()
-            .filter_map(|provider| {
-                let metadata_bytes = match base64::decode(&provider.metadata) {
-                    Ok(b) => b,
-                    Err(e) => {
-                        error!("Failed to decode metadata {e:?}");
-                        return None;
-                    }
-                };
-                let metadata = match bincode::deserialize::<Metadata>(&metadata_bytes) {
-                    Ok(b) => b,
-                    Err(e) => {
-                        error!("Failed to deserialize metadata {e:?}");
-                        return None;
-                    }
-                };
-                if metadata.data == FLEEK_NETWORK_FILTER {
-                    return Some((provider, metadata));
-                }
-                warn!("Invalid data in metadata {:?}", metadata.data);
-                None
+    async fn lookup(&self, router: &dyn ContentRouter, cid: &str) -> Result<Providers, Error> {
+        let Some(lookup_timeout) = self.lookup_timeout else {
+            return router.find_providers(cid).await;
+        };
+        timeout(lookup_timeout, router.find_providers(cid))
+            .await
+            .unwrap_or_else(|_| {
+                warn!("Router lookup of {cid} timed out after {lookup_timeout:?}");
+                Err(Error::Internal(format!(
+                    "Router lookup timed out after {lookup_timeout:?}"
+                )))
             })
-            .collect();
-
-        // TODO:
-        // cherry-pick closest node
-        let (provider, metadata) = providers
-            .first() // FIXME: temporary
-            .context("Multi-hash result did not contain a provider")?;
-
-        info!("File size received {}", metadata.size);
-
-        let provider_addresses: Vec<String> = provider
-            .provider
-            .addrs
-            .iter()
-            .map(|m_addr| {
-                let (mut protocol, mut host, mut port) =
-                    (String::from("http"), String::new(), String::new());
-                for addr in m_addr.into_iter() {
-                    match addr {
-                        Protocol::Ip6(ip) => {
-                            host = ip.to_string();
-                        }
-                        Protocol::Ip4(ip) => {
-                            host = ip.to_string();
-                        }
-                        Protocol::Tcp(p) => {
-                            port = p.to_string();
-                        }
-                        Protocol::Https => {
-                            protocol = "https".to_string();
-                        }
-                        _ => {}
-                    };
+    }
+
+    /// Fetch the content from the providers of the first router whose providers serve it,
+    /// with the `query` string of the provider requests.
+    pub async fn resolve_content(
+        &self,
+        cid: &str,
+        query: Option<&str>,
+    ) -> Result<NodeResponse, Error> {
+        let mut budget = RetryBudget::new(self.max_attempts);
+        let mut last_error = None;
+        for index in 0..self.routers.len() {
+            budget.attempt(cid)?;
+            let result = match self.find_providers(index, cid).await {
+                Ok(providers) => self
+                    .fetch(
+                        &providers.addresses,
+                        providers.size,
+                        cid,
+                        query,
+                        &mut budget,
+                    )
+                    .await
+                    .map_err(|e| e.with_reason(FetchFailure::ProvidersUnreachable)),
+                Err(e) => Err(e.with_reason(FetchFailure::NoProviders)),
+            };
+            match result {
+                Ok(resp) => return Ok(resp),
+                Err(e) => {
+                    debug!("Failed to resolve {cid} through a router: {e:?}");
+                    last_error = Some(e);
                 }
-                (
-                    format!("{protocol}://{host}:{port}"),
-                    host.is_empty() || port.is_empty(),
-                )
-            })
-            .filter(|(_, incomplete)| !incomplete)
-            .map(|(addr, _)| addr)
-            .collect();
-
-        if provider_addresses.is_empty() {
-            return Err(Error::Internal(
-                "Failed to get a valid address for provider".to_string(),
-            ));
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            Error::Internal("Failed to get data".to_string()).with_reason(FetchFailure::NoProviders)
+        }))
+    }
+
+    /// Size of the content without fetching it, `None` if only known once fetched.
+    pub async fn resolve_size(&self, cid: &str) -> Result<Option<u64>, Error> {
+        let mut budget = RetryBudget::new(self.max_attempts);
+        let mut last_error = None;
+        for index in 0..self.routers.len() {
+            budget.attempt(cid)?;
+            match self.find_providers(index, cid).await {
+                Ok(providers) => return Ok(providers.size),
+                Err(e) => last_error = Some(e),
+            }
         }
 
-        debug!("Provider addresses to query: {provider_addresses:?}");
+        Err(last_error.unwrap_or_else(|| Error::Internal("Failed to get data".to_string())))
+    }
 
-        for addr in provider_addresses.into_iter() {
-            let endpoint = format!("{addr}/ursa/v0/{cid}");
+    /// Request the content from the first of `providers` serving it.
+    async fn fetch(
+        &self,
+        providers: &[String],
+        size: Option<u64>,
+        cid: &str,
+        query: Option<&str>,
+        budget: &mut RetryBudget,
+    ) -> Result<NodeResponse, Error> {
+        let mut last_error = None;
+        for addr in providers {
+            let endpoint = match query {
+                Some(query) => format!("{addr}/ursa/v0/{cid}?{query}"),
+                None => format!("{addr}/ursa/v0/{cid}"),
+            };
             let uri = match endpoint.parse::<Uri>() {
                 Ok(uri) => uri,
                 Err(e) => {
@@ -177,17 +222,378 @@ impl Resolver {
                     continue;
                 }
             };
+            budget.attempt(cid)?;
             match self.client.get(uri).await {
+                Ok(resp) if resp.status() == StatusCode::OK => {
+                    // without a size from the router it is only known if the node sends it,
+                    // content of unknown size is never cached
+                    let size = size
+                        .or_else(|| {
+                            resp.headers()
+                                .get(header::CONTENT_LENGTH)
+                                .and_then(|len| len.to_str().ok()?.parse().ok())
+                        })
+                        .unwrap_or(u64::MAX);
+                    if !self.verify {
+                        return Ok(NodeResponse { resp, size });
+                    }
+                    match verified(resp, cid).await {
+                        Ok(resp) => return Ok(NodeResponse { resp, size }),
+                        Err(e) => {
+                            warn!("Provider {addr} served invalid content for {cid}: {e:?}");
+                            last_error = Some(e);
+                        }
+                    }
+                }
                 Ok(resp) => {
-                    return Ok(NodeResponse {
-                        resp,
-                        size: metadata.size,
-                    })
+                    debug!("Provider {addr} responded {} for {cid}", resp.status());
+                    last_error = Some(Error::Upstream(
+                        resp.status(),
+                        format!("Error requested provider: {endpoint}"),
+                    ));
                 }
                 Err(e) => error!("Error querying the node provider: {endpoint:?} {e:?}"),
             };
         }
 
-        Err(Error::Internal("Failed to get data".to_string()))
+        Err(last_error.unwrap_or_else(|| Error::Internal("Failed to get data".to_string())))
+    }
+}
+
+/// The content of `resp`, checked against `cid` while it is streamed. The car header and
+/// first block are checked before returning, failing if they don't match.
+async fn verified(resp: Response<Body>, cid: &str) -> Result<Response<Body>, Error> {
+    let parsed = Cid::from_str(cid)
+        .map_err(|e| Error::Internal(format!("Failed to parse cid {cid}: {e}")))?;
+    let mismatch = |e: String| {
+        increment_counter!("gateway_content_mismatch");
+        Error::Fetch {
+            reason: FetchFailure::VerificationFailed,
+            status: StatusCode::BAD_GATEWAY,
+            message: format!("Content doesn't match {cid}: {e}"),
+        }
+    };
+    let (parts, mut body) = resp.into_parts();
+    let mut verifier = CarVerifier::new(parsed);
+    let mut prefix = vec![];
+    while !verifier.started() {
+        match body.data().await {
+            Some(Ok(chunk)) => prefix.push(verifier.push(&chunk).map_err(mismatch)?),
+            Some(Err(e)) => {
+                return Err(Error::Internal(format!(
+                    "Failed to read provider response: {e}"
+                )))
+            }
+            None => {
+                verifier.finish().map_err(mismatch)?;
+                break;
+            }
+        }
+    }
+    Ok(Response::from_parts(
+        parts,
+        verified_body(prefix, body, verifier),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use axum::{routing::get, Router, Server};
+    use futures::stream;
+    use fvm_ipld_car::CarHeader;
+    use hyper_tls::HttpsConnector;
+    use libipld::multihash::{Code, MultihashDigest};
+
+    use super::*;
+    use crate::resolver::router::{IndexerRouter, StaticRouter};
+
+    struct Failing;
+
+    #[async_trait]
+    impl ContentRouter for Failing {
+        async fn find_providers(&self, _cid: &str) -> Result<Providers, Error> {
+            Err(Error::Internal("no providers".to_string()))
+        }
+    }
+
+    struct Known(u64);
+
+    #[async_trait]
+    impl ContentRouter for Known {
+        async fn find_providers(&self, _cid: &str) -> Result<Providers, Error> {
+            Ok(Providers {
+                addresses: vec![],
+                size: Some(self.0),
+            })
+        }
+    }
+
+    fn resolver(routers: Vec<Box<dyn ContentRouter>>) -> Resolver {
+        Resolver::new(
+            routers,
+            client::Client::builder().build(HttpsConnector::new()),
+        )
+    }
+
+    #[tokio::test]
+    async fn routers_in_order() {
+        let size = resolver(vec![Box::new(Known(1)), Box::new(Known(2))])
+            .resolve_size("cid")
+            .await
+            .unwrap();
+        assert_eq!(size, Some(1));
+
+        let size = resolver(vec![
+            Box::new(Failing),
+            Box::new(StaticRouter::new(vec!["http://127.0.0.1:4069".into()])),
+        ])
+        .resolve_size("cid")
+        .await
+        .unwrap();
+        assert_eq!(size, None);
+
+        assert!(resolver(vec![Box::new(Failing)])
+            .resolve_size("cid")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn retry_budget() {
+        let routers = || -> Vec<Box<dyn ContentRouter>> {
+            vec![Box::new(Failing), Box::new(Failing), Box::new(Known(1))]
+        };
+        let size = resolver(routers())
+            .with_max_attempts(3)
+            .resolve_size("cid")
+            .await
+            .unwrap();
+        assert_eq!(size, Some(1));
+
+        let error = resolver(routers())
+            .with_max_attempts(2)
+            .resolve_size("cid")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Fetch {
+                reason: FetchFailure::RetryBudgetExhausted,
+                ..
+            }
+        ));
+    }
+
+    /// Car of a single raw block, claiming to be `cid`.
+    async fn car(cid: Cid, data: &[u8]) -> Vec<u8> {
+        let mut car = Vec::new();
+        let header = CarHeader {
+            roots: vec![cid],
+            version: 1,
+        };
+        header
+            .write_stream_async(&mut car, &mut stream::iter(vec![(cid, data.to_vec())]))
+            .await
+            .unwrap();
+        car
+    }
+
+    fn serve(app: Router) -> String {
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        format!("http://{addr}")
+    }
+
+    /// Provider serving `car` for every cid.
+    fn provider(car: Vec<u8>) -> String {
+        serve(Router::new().route(
+            "/ursa/v0/:cid",
+            get(move || {
+                let car = car.clone();
+                async move { car }
+            }),
+        ))
+    }
+
+    /// Indexer finding a provider of `size` bytes for every cid.
+    fn healthy_indexer(size: u64) -> String {
+        // bincode of the `Metadata` of the provider
+        let mut metadata = 0u128.to_le_bytes().to_vec();
+        metadata.extend(size.to_le_bytes());
+        metadata.extend((b"FleekNetwork".len() as u64).to_le_bytes());
+        metadata.extend(b"FleekNetwork");
+        let response = serde_json::json!({
+            "MultihashResults": [{
+                "Multihash": "",
+                "ProviderResults": [{
+                    "ContextID": "",
+                    "Metadata": base64::encode(metadata),
+                    "Provider": {"ID": "", "Addrs": ["/ip4/127.0.0.1/tcp/4069"]},
+                }],
+            }],
+        })
+        .to_string();
+        let url = serve(Router::new().route(
+            "/cid/:cid",
+            get(move || {
+                let response = response.clone();
+                async move { response }
+            }),
+        ));
+        format!("{url}/cid")
+    }
+
+    fn indexers(urls: &[String]) -> Vec<Box<dyn ContentRouter>> {
+        let client = client::Client::builder().build(HttpsConnector::new());
+        urls.iter()
+            .map(|url| -> Box<dyn ContentRouter> {
+                Box::new(IndexerRouter::new(url.clone(), client.clone()))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn indexer_failover() {
+        let failing = serve(Router::new().route(
+            "/cid/:cid",
+            get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+        ));
+        let routers = indexers(&[
+            format!("{failing}/cid"),
+            "http://127.0.0.1:1/cid".into(),
+            healthy_indexer(42),
+        ]);
+        let size = resolver(routers).resolve_size("cid").await.unwrap();
+        assert_eq!(size, Some(42));
+
+        // the failed lookups count against the retry budget
+        let routers = indexers(&[format!("{failing}/cid"), healthy_indexer(42)]);
+        let error = resolver(routers)
+            .with_max_attempts(1)
+            .resolve_size("cid")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Fetch {
+                reason: FetchFailure::RetryBudgetExhausted,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn indexer_lookup_timeout() {
+        let slow = serve(Router::new().route(
+            "/cid/:cid",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                StatusCode::INTERNAL_SERVER_ERROR
+            }),
+        ));
+        let routers = indexers(&[format!("{slow}/cid"), healthy_indexer(42)]);
+        let size = timeout(
+            Duration::from_secs(5),
+            resolver(routers)
+                .with_lookup_timeout(Duration::from_millis(100))
+                .resolve_size("cid"),
+        )
+        .await
+        .expect("the slow indexer to be timed out")
+        .unwrap();
+        assert_eq!(size, Some(42));
+    }
+
+    #[tokio::test]
+    async fn content_mismatch() {
+        let cid = Cid::new_v1(0x55, Code::Sha2_256.digest(b"data"));
+        let wrong = provider(car(cid, b"wrong").await);
+        let right = provider(car(cid, b"data").await);
+
+        let resp = resolver(vec![Box::new(StaticRouter::new(vec![
+            wrong.clone(),
+            right,
+        ]))])
+        .with_verify()
+        .resolve_content(&cid.to_string(), None)
+        .await
+        .unwrap();
+        assert_eq!(resp.size, car(cid, b"data").await.len() as u64);
+
+        let error = resolver(vec![Box::new(StaticRouter::new(vec![wrong]))])
+            .with_verify()
+            .resolve_content(&cid.to_string(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Fetch {
+                reason: FetchFailure::VerificationFailed,
+                status: StatusCode::BAD_GATEWAY,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn verified_while_streamed() {
+        let cid = Cid::new_v1(0x55, Code::Sha2_256.digest(&[1; 4096]));
+        let other = Cid::new_v1(0x55, Code::Sha2_256.digest(b"other"));
+        let blocks = |last: &[u8]| vec![(cid, vec![1; 4096]), (other, last.to_vec())];
+        let mut valid = Vec::new();
+        let mut invalid = Vec::new();
+        for (car, blocks) in [
+            (&mut valid, blocks(b"other")),
+            (&mut invalid, blocks(b"wrong")),
+        ] {
+            let header = CarHeader {
+                roots: vec![cid],
+                version: 1,
+            };
+            header
+                .write_stream_async(car, &mut stream::iter(blocks))
+                .await
+                .unwrap();
+        }
+        let resolve = |car| async move {
+            resolver(vec![Box::new(StaticRouter::new(vec![provider(car)]))])
+                .with_verify()
+                .resolve_content(&cid.to_string(), None)
+                .await
+                .unwrap()
+        };
+
+        let resp = resolve(valid.clone()).await;
+        let body = hyper::body::to_bytes(resp.resp.into_body()).await.unwrap();
+        assert_eq!(body, valid);
+
+        // the first block matches, the mismatch of the second aborts the body
+        let resp = resolve(invalid).await;
+        assert!(hyper::body::to_bytes(resp.resp.into_body()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fetch_failure_reasons() {
+        let reason = |error| match error {
+            Error::Fetch { reason, .. } => reason,
+            error => panic!("unattributed error {error:?}"),
+        };
+        let cid = Cid::new_v1(0x55, Code::Sha2_256.digest(b"data")).to_string();
+
+        let error = resolver(vec![Box::new(Failing)])
+            .resolve_content(&cid, None)
+            .await
+            .unwrap_err();
+        assert_eq!(reason(error), FetchFailure::NoProviders);
+
+        let error = resolver(vec![Box::new(StaticRouter::new(vec![
+            "http://127.0.0.1:1".into(),
+        ]))])
+        .resolve_content(&cid, None)
+        .await
+        .unwrap_err();
+        assert_eq!(reason(error), FetchFailure::ProvidersUnreachable);
     }
 }
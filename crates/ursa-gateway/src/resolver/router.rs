@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use axum::http::response::Parts;
+use futures::future::join_all;
+use hyper::{body::to_bytes, StatusCode, Uri};
+use libp2p::multiaddr::Protocol;
+use metrics::gauge;
+use serde_json::from_slice;
+use tracing::{debug, error, info, warn};
+
+use super::{
+    model::{IndexerResponse, Metadata, ProviderResult},
+    Client,
+};
+use crate::util::error::Error;
+
+const FLEEK_NETWORK_FILTER: &[u8] = b"FleekNetwork";
+
+/// Providers of a content found by a [`ContentRouter`].
+#[derive(Debug, Clone)]
+pub struct Providers {
+    /// Node http addresses, queried in order
+    pub addresses: Vec<String>,
+    /// Size of the content, `None` if only known once fetched
+    pub size: Option<u64>,
+}
+
+/// Finds the nodes serving a content.
+#[async_trait]
+pub trait ContentRouter: Send + Sync {
+    async fn find_providers(&self, cid: &str) -> Result<Providers, Error>;
+}
+
+/// Looks up the providers in the indexer.
+pub struct IndexerRouter {
+    cid_url: String,
+    client: Client,
+}
+
+impl IndexerRouter {
+    pub fn new(cid_url: String, client: Client) -> Self {
+        Self { cid_url, client }
+    }
+
+    /// Request the indexer over `connections` concurrent connections, keeping them
+    /// pooled for the next lookups. Returns the number of connections that got a
+    /// response, any status proves the indexer up.
+    pub async fn ping(&self, connections: usize) -> usize {
+        let uri = match self.cid_url.parse::<Uri>() {
+            Ok(uri) => uri,
+            Err(e) => {
+                error!("Error parsed uri: {} {e:?}", self.cid_url);
+                return 0;
+            }
+        };
+        let responses = join_all((0..connections).map(|_| async {
+            // the body is read so the connection goes back to the pool
+            let resp = self.client.get(uri.clone()).await?;
+            to_bytes(resp.into_body()).await
+        }))
+        .await;
+        let warm = responses.iter().filter(|resp| resp.is_ok()).count();
+        gauge!("gateway_indexer_warm_connections", warm as f64);
+        if warm == 0 {
+            warn!("Indexer {} is unreachable", self.cid_url);
+        }
+        warm
+    }
+}
+
+#[async_trait]
+impl ContentRouter for IndexerRouter {
+    async fn find_providers(&self, cid: &str) -> Result<Providers, Error> {
+        let endpoint = format!("{}/{cid}", self.cid_url);
+
+        let uri = endpoint.parse::<Uri>().map_err(|e| {
+            error!("Error parsed uri: {endpoint} {e:?}");
+            anyhow!("Error parsed uri: {endpoint}")
+        })?;
+
+        let body = match self
+            .client
+            .get(uri)
+            .await
+            .map_err(|e| {
+                error!("Error requested indexer: {endpoint} {e:?}");
+                anyhow!("Error requested indexer: {endpoint}")
+            })?
+            .into_parts()
+        {
+            (
+                Parts {
+                    status: StatusCode::OK,
+                    ..
+                },
+                body,
+            ) => body,
+            (parts, body) => {
+                error!("Error requested indexer {endpoint} with parts {parts:?} and body {body:?}");
+                return Err(Error::Upstream(
+                    parts.status,
+                    format!("Error requested indexer: {endpoint}"),
+                ));
+            }
+        };
+
+        let bytes = to_bytes(body).await.map_err(|e| {
+            error!("Error read data from indexer: {endpoint} {e:?}");
+            anyhow!("Error read data from indexer {endpoint}")
+        })?;
+
+        let indexer_response: IndexerResponse = from_slice(&bytes).map_err(|e| {
+            error!("Error parsed indexer response from indexer: {endpoint} {e:?}");
+            anyhow!("Error parsed indexer response from indexer: {endpoint}")
+        })?;
+
+        debug!("Received indexer response for {cid}: {indexer_response:?}");
+
+        let providers: Vec<(&ProviderResult, Metadata)> = indexer_response
+            .multihash_results
+            .first()
+            .context("Indexer result did not contain a multi-hash result")?
+            .provider_results
+            .iter()
+            .filter_map(|provider| {
+                let metadata_bytes = match base64::decode(&provider.metadata) {
+                    Ok(b) => b,
+                    Err(e) => {
+                        error!("Failed to decode metadata {e:?}");
+                        return None;
+                    }
+                };
+                let metadata = match bincode::deserialize::<Metadata>(&metadata_bytes) {
+                    Ok(b) => b,
+                    Err(e) => {
+                        error!("Failed to deserialize metadata {e:?}");
+                        return None;
+                    }
+                };
+                if metadata.data == FLEEK_NETWORK_FILTER {
+                    return Some((provider, metadata));
+                }
+                warn!("Invalid data in metadata {:?}", metadata.data);
+                None
+            })
+            .collect();
+
+        // TODO:
+        // cherry-pick closest node
+        let (provider, metadata) = providers
+            .first() // FIXME: temporary
+            .context("Multi-hash result did not contain a provider")?;
+
+        info!("File size received {}", metadata.size);
+
+        let provider_addresses: Vec<String> = provider
+            .provider
+            .addrs
+            .iter()
+            .map(|m_addr| {
+                let (mut protocol, mut host, mut port) =
+                    (String::from("http"), String::new(), String::new());
+                for addr in m_addr.into_iter() {
+                    match addr {
+                        Protocol::Ip6(ip) => {
+                            host = ip.to_string();
+                        }
+                        Protocol::Ip4(ip) => {
+                            host = ip.to_string();
+                        }
+                        Protocol::Tcp(p) => {
+                            port = p.to_string();
+                        }
+                        Protocol::Https => {
+                            protocol = "https".to_string();
+                        }
+                        _ => {}
+                    };
+                }
+                (
+                    format!("{protocol}://{host}:{port}"),
+                    host.is_empty() || port.is_empty(),
+                )
+            })
+            .filter(|(_, incomplete)| !incomplete)
+            .map(|(addr, _)| addr)
+            .collect();
+
+        if provider_addresses.is_empty() {
+            return Err(Error::Internal(
+                "Failed to get a valid address for provider".to_string(),
+            ));
+        }
+
+        debug!(
+            "Indexer {} found providers of {cid}: {provider_addresses:?}",
+            self.cid_url
+        );
+
+        Ok(Providers {
+            addresses: provider_addresses,
+            size: Some(metadata.size),
+        })
+    }
+}
+
+/// Fixed list of nodes, each resolving the content through its own network.
+pub struct StaticRouter {
+    peers: Vec<String>,
+}
+
+impl StaticRouter {
+    pub fn new(peers: Vec<String>) -> Self {
+        Self { peers }
+    }
+}
+
+#[async_trait]
+impl ContentRouter for StaticRouter {
+    async fn find_providers(&self, _cid: &str) -> Result<Providers, Error> {
+        Ok(Providers {
+            addresses: self.peers.clone(),
+            size: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router, Server};
+    use hyper::client;
+    use hyper_tls::HttpsConnector;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn ping() {
+        let app = Router::new().route("/cid", get(|| async { StatusCode::NOT_FOUND }));
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = client::Client::builder().build(HttpsConnector::new());
+        let indexer = IndexerRouter::new(format!("http://{addr}/cid"), client.clone());
+        assert_eq!(indexer.ping(3).await, 3);
+
+        let unreachable = IndexerRouter::new("http://127.0.0.1:1/cid".into(), client);
+        assert_eq!(unreachable.ping(3).await, 0);
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::{self, Cursor};
+
+use bytes::Bytes;
+use futures::{stream, StreamExt};
+use hyper::{body::HttpBody, Body};
+use libipld::{
+    cbor::DagCborCodec,
+    codec::Codec,
+    multihash::{Code, MultihashDigest},
+    Cid, Ipld,
+};
+use metrics::increment_counter;
+
+use crate::server::listing::varint;
+
+/// Largest car section, the header or a block, buffered to check it.
+const MAX_SECTION_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Check that a car streamed through it is the content of `cid`: rooted at it,
+/// containing its block, and every block hashing to its cid. Only one section is
+/// buffered at a time, and bytes are passed on once their section is checked.
+pub struct CarVerifier {
+    cid: Cid,
+    /// bytes of the section being read
+    buf: Vec<u8>,
+    header: bool,
+    blocks: usize,
+    found: bool,
+}
+
+impl CarVerifier {
+    pub fn new(cid: Cid) -> Self {
+        Self {
+            cid,
+            buf: Vec::new(),
+            header: false,
+            blocks: 0,
+            found: false,
+        }
+    }
+
+    /// Whether the header and a block were checked.
+    pub fn started(&self) -> bool {
+        self.blocks > 0
+    }
+
+    /// Feed `chunk`, returning the bytes of the sections checked with it.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Bytes, String> {
+        self.buf.extend_from_slice(chunk);
+        let mut checked = 0;
+        loop {
+            let rest = &self.buf[checked..];
+            let Some((len, offset)) = varint(rest) else {
+                if rest.len() >= 10 {
+                    return Err("Invalid car section length".into());
+                }
+                break;
+            };
+            if len > MAX_SECTION_SIZE {
+                return Err(format!("Car section of {len} bytes is too large to verify"));
+            }
+            let Some(section) = rest.get(offset..offset + len as usize) else {
+                break;
+            };
+            if self.header {
+                self.check_block(section)?;
+            } else {
+                self.check_header(section)?;
+            }
+            checked += offset + len as usize;
+        }
+        Ok(Bytes::from(self.buf.drain(..checked).collect::<Vec<_>>()))
+    }
+
+    /// Check the end of the car, once every chunk was pushed.
+    pub fn finish(&self) -> Result<(), String> {
+        if !self.buf.is_empty() {
+            return Err("Car ends with a truncated section".into());
+        }
+        if !self.found {
+            return Err(format!("Car is missing the block of {}", self.cid));
+        }
+        Ok(())
+    }
+
+    fn check_header(&mut self, header: &[u8]) -> Result<(), String> {
+        let Ok(Ipld::Map(header)) = DagCborCodec.decode::<Ipld>(header) else {
+            return Err("Invalid car header".into());
+        };
+        let rooted = match header.get("roots") {
+            Some(Ipld::List(roots)) => roots.contains(&Ipld::Link(self.cid)),
+            _ => return Err("Invalid car header: missing roots".into()),
+        };
+        if !rooted {
+            return Err(format!("Car is not rooted at {}", self.cid));
+        }
+        self.header = true;
+        Ok(())
+    }
+
+    fn check_block(&mut self, block: &[u8]) -> Result<(), String> {
+        let mut cursor = Cursor::new(block);
+        let cid = Cid::read_bytes(&mut cursor).map_err(|e| format!("Invalid car block: {e}"))?;
+        let data = &block[cursor.position() as usize..];
+        let hash = cid.hash();
+        let code = Code::try_from(hash.code())
+            .map_err(|_| format!("Unsupported hash function {:#x}", hash.code()))?;
+        if code.digest(data) != *hash {
+            return Err(format!("Block {cid} doesn't match its hash"));
+        }
+        self.found |= cid == self.cid;
+        self.blocks += 1;
+        Ok(())
+    }
+}
+
+/// `prefix`, already checked by `verifier`, followed by the rest of `body` checked while
+/// it is streamed. The body fails at the first section not matching.
+pub fn verified_body(prefix: Vec<Bytes>, body: Body, verifier: CarVerifier) -> Body {
+    let rest = stream::unfold(Some((body, verifier)), |state| async move {
+        let (mut body, mut verifier) = state?;
+        let checked = match body.data().await {
+            Some(Ok(chunk)) => verifier.push(&chunk),
+            Some(Err(e)) => return Some((Err(io::Error::new(io::ErrorKind::Other, e)), None)),
+            None => return verifier.finish().err().map(|e| (Err(mismatch(e)), None)),
+        };
+        match checked {
+            Ok(bytes) => Some((Ok(bytes), Some((body, verifier)))),
+            Err(e) => Some((Err(mismatch(e)), None)),
+        }
+    });
+    Body::wrap_stream(stream::iter(prefix.into_iter().map(Ok)).chain(rest))
+}
+
+fn mismatch(e: String) -> io::Error {
+    increment_counter!("gateway_content_mismatch");
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,126 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum_server::accept::Accept;
+use futures::future::{self, BoxFuture};
+use metrics::{decrement_gauge, increment_gauge};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use tracing::warn;
+
+const CONNECTIONS_GAUGE: &str = "gateway_connections";
+
+/// Bound the number of simultaneously open connections.
+/// Connections accepted while saturated are dropped before the inner handshake.
+#[derive(Clone)]
+pub struct LimitAcceptor<A> {
+    inner: A,
+    semaphore: Arc<Semaphore>,
+}
+
+impl<A> LimitAcceptor<A> {
+    pub fn new(inner: A, max_connections: usize) -> Self {
+        Self {
+            inner,
+            semaphore: Arc::new(Semaphore::new(max_connections)),
+        }
+    }
+}
+
+impl<A, I, S> Accept<I, S> for LimitAcceptor<A>
+where
+    A: Accept<I, S>,
+    A::Stream: Send + 'static,
+    A::Service: Send + 'static,
+    A::Future: Send + 'static,
+{
+    type Stream = LimitedStream<A::Stream>;
+    type Service = A::Service;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let permit = match Arc::clone(&self.semaphore).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                warn!("Max connections reached, rejecting connection");
+                return Box::pin(future::ready(Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "max connections reached",
+                ))));
+            }
+        };
+        let accept = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = accept.await?;
+            Ok((LimitedStream::new(stream, permit), service))
+        })
+    }
+}
+
+/// Stream holding a connection permit until it is dropped.
+pub struct LimitedStream<S> {
+    inner: S,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<S> LimitedStream<S> {
+    fn new(inner: S, permit: OwnedSemaphorePermit) -> Self {
+        increment_gauge!(CONNECTIONS_GAUGE, 1.0);
+        Self {
+            inner,
+            _permit: permit,
+        }
+    }
+}
+
+impl<S> Drop for LimitedStream<S> {
+    fn drop(&mut self) {
+        decrement_gauge!(CONNECTIONS_GAUGE, 1.0);
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for LimitedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for LimitedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
@@ -0,0 +1,152 @@
+use axum::http::{header, Extensions, HeaderMap, HeaderValue, Request, StatusCode, Version};
+use axum::{middleware::Next, response::Response};
+use tower_http::compression::predicate::{DefaultPredicate, Predicate};
+
+use super::digest::DIGEST;
+
+/// Content types worth compressing, cars and other binary content are sent as is.
+const COMPRESSIBLE_TYPES: &[&str] = &[
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "image/svg+xml",
+];
+
+/// Responses compressed by the compression layer.
+pub fn compress_when() -> impl Predicate + Clone {
+    DefaultPredicate::new().and(compressible)
+}
+
+/// Whether a response is of compressible content, and not one whose bytes must stay the
+/// identity ones: partial content, content offering ranges or carrying its digest.
+fn compressible(status: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions) -> bool {
+    if status == StatusCode::PARTIAL_CONTENT
+        || headers.contains_key(header::CONTENT_RANGE)
+        || headers.contains_key(header::ACCEPT_RANGES)
+        || headers.contains_key(DIGEST)
+        || headers.contains_key(header::TRAILER)
+    {
+        return false;
+    }
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map_or(false, |content_type| {
+            COMPRESSIBLE_TYPES
+                .iter()
+                .any(|compressible| content_type.starts_with(compressible))
+        })
+}
+
+/// Mark the `ETag` of compressed responses as weak, it is the one of the identity bytes.
+pub async fn weaken_etag<B>(req: Request<B>, next: Next<B>) -> Response {
+    let mut response = next.run(req).await;
+    if !response.headers().contains_key(header::CONTENT_ENCODING) {
+        return response;
+    }
+    let weak = response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"))
+        .and_then(|etag| HeaderValue::from_str(&format!("W/{etag}")).ok());
+    if let Some(weak) = weak {
+        response.headers_mut().insert(header::ETAG, weak);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, response::IntoResponse, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::compression::CompressionLayer;
+
+    use super::*;
+
+    const BODY: &str = "compressible content, long enough to be worth compressing at all";
+
+    fn app() -> Router {
+        let response = |headers: &'static [(&'static str, &'static str)], status| {
+            move || async move {
+                let mut response = (status, BODY).into_response();
+                for (name, value) in headers {
+                    response
+                        .headers_mut()
+                        .insert(*name, HeaderValue::from_static(value));
+                }
+                response
+            }
+        };
+        Router::new()
+            .route(
+                "/html",
+                get(response(
+                    &[("content-type", "text/html"), ("etag", "\"cid\"")],
+                    StatusCode::OK,
+                )),
+            )
+            .route(
+                "/car",
+                get(response(
+                    &[("content-type", "application/vnd.ipld.car")],
+                    StatusCode::OK,
+                )),
+            )
+            .route(
+                "/partial",
+                get(response(
+                    &[
+                        ("content-type", "text/html"),
+                        ("content-range", "bytes 0-9/64"),
+                    ],
+                    StatusCode::PARTIAL_CONTENT,
+                )),
+            )
+            .route(
+                "/ranges",
+                get(response(
+                    &[("content-type", "text/html"), ("accept-ranges", "bytes")],
+                    StatusCode::OK,
+                )),
+            )
+            .route(
+                "/digest",
+                get(response(
+                    &[("content-type", "text/html"), ("digest", "sha-256=abc")],
+                    StatusCode::OK,
+                )),
+            )
+            .layer(CompressionLayer::new().compress_when(compress_when()))
+            .layer(middleware::from_fn(weaken_etag))
+    }
+
+    async fn get_gzip(uri: &str) -> Response {
+        app()
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .header(header::ACCEPT_ENCODING, "gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn compressed_responses() {
+        let response = get_gzip("/html").await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::ETAG], "W/\"cid\"");
+
+        for uri in ["/car", "/partial", "/ranges", "/digest"] {
+            let response = get_gzip(uri).await;
+            assert!(
+                !response.headers().contains_key(header::CONTENT_ENCODING),
+                "{uri}"
+            );
+        }
+    }
+}
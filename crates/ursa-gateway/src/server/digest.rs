@@ -0,0 +1,104 @@
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use bytes::Bytes;
+use hyper::body::{HttpBody, SizeHint};
+use libipld::multihash::{Hasher, Sha2_256};
+
+pub const DIGEST: &str = "digest";
+
+/// Largest content of a known length buffered to send its `Digest` up front, larger
+/// content is streamed without one.
+pub const MAX_DIGEST_SIZE: u64 = 4 * 1024 * 1024;
+
+/// `Digest` header of content known up front.
+pub fn digest(data: &[u8]) -> HeaderValue {
+    let mut hasher = Sha2_256::default();
+    hasher.update(data);
+    header_value(hasher.finalize())
+}
+
+fn header_value(hash: &[u8]) -> HeaderValue {
+    HeaderValue::from_str(&format!("sha-256={}", base64::encode(hash)))
+        .expect("base64 to be a valid header value")
+}
+
+/// Body computing the sha-256 of the streamed content, sent as a `Digest` trailer.
+pub struct DigestBody<B> {
+    inner: B,
+    hasher: Sha2_256,
+}
+
+impl<B> DigestBody<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            hasher: Sha2_256::default(),
+        }
+    }
+}
+
+impl<B> HttpBody for DigestBody<B>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &poll {
+            self.hasher.update(data);
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let mut trailers = ready!(Pin::new(&mut self.inner).poll_trailers(cx))?.unwrap_or_default();
+        trailers.insert(
+            HeaderName::from_static(DIGEST),
+            header_value(self.hasher.finalize()),
+        );
+        Poll::Ready(Ok(Some(trailers)))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn digest_trailer() {
+        let mut body = DigestBody::new(Body::wrap_stream(futures::stream::iter([
+            Ok::<_, std::io::Error>("hel"),
+            Ok("lo"),
+        ])));
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(data, b"hello");
+
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(
+            trailers.get(DIGEST).unwrap(),
+            "sha-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ="
+        );
+        assert_eq!(trailers.get(DIGEST).unwrap(), digest(b"hello"));
+    }
+}
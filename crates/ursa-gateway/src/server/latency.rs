@@ -0,0 +1,200 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    body::{boxed, BoxBody},
+    extract::State,
+    http::{HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use bytes::Bytes;
+use hyper::body::{HttpBody, SizeHint};
+use metrics::histogram;
+
+use super::openmetrics::Exemplars;
+
+pub const REQUEST_DURATION_HISTOGRAM: &str = "gateway_request_duration_seconds";
+
+/// Buckets of [`REQUEST_DURATION_HISTOGRAM`], in seconds.
+pub const REQUEST_DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Marks the responses served from the cache, set by the handlers as a response extension.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheHit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Hit,
+    Miss,
+    Error,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Hit => "hit",
+            Outcome::Miss => "miss",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+/// Response header holding the trace id of the request.
+const TRACE_ID: &str = "trace_id";
+
+/// Record the duration of the requests, from ingress until their body was
+/// streamed, labeled by outcome and status class. With `exemplars`, the
+/// durations are kept along the trace of their request.
+pub async fn track_latency<B>(
+    State(exemplars): State<Option<Arc<Exemplars>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let start = Instant::now();
+    let response = next.run(req).await;
+    let status = response.status();
+    let outcome = outcome(&response);
+    let exemplar = exemplars.and_then(|exemplars| {
+        let trace_id = response.headers().get(TRACE_ID)?.to_str().ok()?;
+        Some((exemplars, trace_id.to_string()))
+    });
+    response.map(|inner| {
+        boxed(TimedBody {
+            inner,
+            start,
+            outcome,
+            status,
+            exemplar,
+        })
+    })
+}
+
+/// Body recording the request duration once streamed, or dropped by the client.
+struct TimedBody {
+    inner: BoxBody,
+    start: Instant,
+    outcome: Outcome,
+    status: StatusCode,
+    exemplar: Option<(Arc<Exemplars>, String)>,
+}
+
+impl Drop for TimedBody {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let (outcome, status) = (self.outcome.as_str(), status_class(self.status));
+        histogram!(
+            REQUEST_DURATION_HISTOGRAM,
+            elapsed,
+            "outcome" => outcome,
+            "status" => status
+        );
+        if let Some((exemplars, trace_id)) = &self.exemplar {
+            exemplars.record(
+                &[("outcome", outcome), ("status", status)],
+                elapsed,
+                trace_id,
+            );
+        }
+    }
+}
+
+impl HttpBody for TimedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Err(_))) = &poll {
+            // the content failed midway, after the status was sent
+            self.outcome = Outcome::Error;
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Errors by status, whether served from the cache or not.
+fn outcome(response: &Response) -> Outcome {
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        Outcome::Error
+    } else if response.extensions().get::<CacheHit>().is_some() {
+        Outcome::Hit
+    } else {
+        Outcome::Miss
+    }
+}
+
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() / 100 {
+        1 => "1xx",
+        2 => "2xx",
+        3 => "3xx",
+        4 => "4xx",
+        _ => "5xx",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, response::IntoResponse};
+
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        let mut response = ().into_response();
+        assert_eq!(outcome(&response), Outcome::Miss);
+        response.extensions_mut().insert(CacheHit);
+        assert_eq!(outcome(&response), Outcome::Hit);
+        *response.status_mut() = StatusCode::BAD_GATEWAY;
+        assert_eq!(outcome(&response), Outcome::Error);
+
+        assert_eq!(status_class(StatusCode::OK), "2xx");
+        assert_eq!(status_class(StatusCode::NOT_MODIFIED), "3xx");
+        assert_eq!(status_class(StatusCode::NOT_FOUND), "4xx");
+        assert_eq!(status_class(StatusCode::BAD_GATEWAY), "5xx");
+    }
+
+    #[tokio::test]
+    async fn body_failure() {
+        let mut body = TimedBody {
+            inner: boxed(Body::wrap_stream(futures::stream::iter([
+                Ok("data"),
+                Err(std::io::Error::new(std::io::ErrorKind::Other, "failed")),
+            ]))),
+            start: Instant::now(),
+            outcome: Outcome::Hit,
+            status: StatusCode::OK,
+            exemplar: None,
+        };
+        assert!(body.data().await.unwrap().is_ok());
+        assert_eq!(body.outcome, Outcome::Hit);
+        assert!(body.data().await.unwrap().is_err());
+        assert_eq!(body.outcome, Outcome::Error);
+    }
+}
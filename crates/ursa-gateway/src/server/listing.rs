@@ -0,0 +1,291 @@
+use std::io::Cursor;
+
+use axum::http::{header, HeaderMap};
+use bytes::Bytes;
+use futures::{stream, StreamExt};
+use hyper::{body::HttpBody, Body};
+use libipld::{cbor::DagCborCodec, codec::Codec, pb::DagPbCodec, Cid, Ipld};
+use serde::Serialize;
+
+/// Root blocks past this size, with the car header, are served as is.
+const MAX_ROOT_PREFIX: usize = 4 * 1024 * 1024;
+
+/// UnixFS `Data.Type` of a plain directory.
+const UNIXFS_DIRECTORY: u64 = 1;
+
+const INDEX_FILE: &str = "index.html";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingFormat {
+    Html,
+    Json,
+}
+
+impl ListingFormat {
+    /// The listing format preferred by the `Accept` header, `None` if another type is.
+    pub fn preferred(headers: &HeaderMap) -> Option<Self> {
+        let accept = headers.get(header::ACCEPT)?.to_str().ok()?;
+        let mut preferred: Option<(f32, Option<Self>)> = None;
+        for item in accept.split(',') {
+            let mut params = item.split(';');
+            let media = params.next().unwrap_or_default().trim();
+            let q = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            let format = if media.eq_ignore_ascii_case("text/html") {
+                Some(Self::Html)
+            } else if media.eq_ignore_ascii_case("application/json") {
+                Some(Self::Json)
+            } else {
+                None
+            };
+            if q > 0.0 && preferred.map_or(true, |(best, _)| q > best) {
+                preferred = Some((q, format));
+            }
+        }
+        preferred.and_then(|(_, format)| format)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Html => "text/html; charset=utf-8",
+            Self::Json => "application/json",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub cid: String,
+    /// cumulative size of the entry dag
+    pub size: Option<u64>,
+}
+
+pub enum Root {
+    /// Entries of a UnixFS directory without an index file.
+    Directory(Vec<DirectoryEntry>),
+    /// Any other content, replayed from the start.
+    Content(Body),
+}
+
+/// Read the root block of the car streamed in `body`, only as far as needed.
+pub async fn inspect(root: &Cid, mut body: Body) -> Root {
+    let mut read: Vec<Result<Bytes, hyper::Error>> = vec![];
+    let mut prefix = Vec::new();
+    let mut directory = None;
+    while prefix.len() <= MAX_ROOT_PREFIX {
+        let Some(chunk) = body.data().await else {
+            break;
+        };
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                read.push(Err(e));
+                break;
+            }
+        };
+        prefix.extend_from_slice(&chunk);
+        read.push(Ok(chunk));
+        match first_block(&prefix) {
+            Some(Some((cid, data))) if cid == *root => {
+                directory = directory_entries(&cid, data);
+                break;
+            }
+            Some(_) => break,
+            None => continue,
+        }
+    }
+    match directory {
+        Some(entries) if entries.iter().all(|entry| entry.name != INDEX_FILE) => {
+            Root::Directory(entries)
+        }
+        _ => Root::Content(Body::wrap_stream(stream::iter(read).chain(body))),
+    }
+}
+
+/// Roots of the header of the car streamed in `body`, read only as far as needed,
+/// with the car replayed from the start.
+pub async fn car_roots(mut body: Body) -> (Vec<Cid>, Body) {
+    let mut read: Vec<Result<Bytes, hyper::Error>> = vec![];
+    let mut prefix = Vec::new();
+    let mut roots = vec![];
+    while prefix.len() <= MAX_ROOT_PREFIX {
+        let Some(chunk) = body.data().await else {
+            break;
+        };
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                read.push(Err(e));
+                break;
+            }
+        };
+        prefix.extend_from_slice(&chunk);
+        read.push(Ok(chunk));
+        if let Some(header) = header_roots(&prefix) {
+            roots = header;
+            break;
+        }
+    }
+    (roots, Body::wrap_stream(stream::iter(read).chain(body)))
+}
+
+/// Roots of a car header, `None` until enough of it was read, empty if not a car.
+fn header_roots(car: &[u8]) -> Option<Vec<Cid>> {
+    let (header_len, offset) = varint(car)?;
+    let header = car.get(offset..)?.get(..header_len as usize)?;
+    let Ok(Ipld::Map(header)) = DagCborCodec.decode::<Ipld>(header) else {
+        return Some(vec![]);
+    };
+    let Some(Ipld::List(roots)) = header.get("roots") else {
+        return Some(vec![]);
+    };
+    Some(
+        roots
+            .iter()
+            .filter_map(|root| match root {
+                Ipld::Link(cid) => Some(*cid),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+/// First block of a car, `None` until enough of it was read, `Some(None)` if not a car.
+fn first_block(car: &[u8]) -> Option<Option<(Cid, &[u8])>> {
+    let (header_len, offset) = varint(car)?;
+    let block = car.get(offset..)?.get(header_len as usize..)?;
+    let (block_len, offset) = varint(block)?;
+    let block = block.get(offset..offset.checked_add(block_len as usize)?)?;
+    let mut cursor = Cursor::new(block);
+    let Ok(cid) = Cid::read_bytes(&mut cursor) else {
+        return Some(None);
+    };
+    Some(Some((cid, &block[cursor.position() as usize..])))
+}
+
+/// Unsigned leb128 prefix of `bytes`, with its length.
+pub fn varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().take(10).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Links of a dag-pb block holding a plain UnixFS directory.
+fn directory_entries(cid: &Cid, data: &[u8]) -> Option<Vec<DirectoryEntry>> {
+    if cid.codec() != 0x70 {
+        return None;
+    }
+    let Ipld::Map(node) = DagPbCodec.decode::<Ipld>(data).ok()? else {
+        return None;
+    };
+    // Data.Type is the first field of the UnixFS data
+    let Some(Ipld::Bytes(unixfs)) = node.get("Data") else {
+        return None;
+    };
+    if unixfs.first() != Some(&0x08)
+        || varint(&unixfs[1..]).map(|(kind, _)| kind) != Some(UNIXFS_DIRECTORY)
+    {
+        return None;
+    }
+    let Some(Ipld::List(links)) = node.get("Links") else {
+        return Some(vec![]);
+    };
+    links
+        .iter()
+        .map(|link| {
+            let Ipld::Map(link) = link else {
+                return None;
+            };
+            let Some(Ipld::Link(cid)) = link.get("Hash") else {
+                return None;
+            };
+            let name = match link.get("Name") {
+                Some(Ipld::String(name)) => name.clone(),
+                _ => String::new(),
+            };
+            let size = match link.get("Tsize") {
+                Some(Ipld::Integer(size)) => u64::try_from(*size).ok(),
+                _ => None,
+            };
+            Some(DirectoryEntry {
+                name,
+                cid: cid.to_string(),
+                size,
+            })
+        })
+        .collect()
+}
+
+pub fn render_html(root: &Cid, entries: &[DirectoryEntry]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{root}</title></head>\n\
+         <body>\n<h1>{root}</h1>\n<table>\n<tr><th>Name</th><th>CID</th><th>Size</th></tr>\n"
+    );
+    for entry in entries {
+        html.push_str(&format!(
+            "<tr><td><a href=\"{cid}\">{name}</a></td><td>{cid}</td><td>{size}</td></tr>\n",
+            cid = entry.cid,
+            name = escape(&entry.name),
+            size = entry.size.map(|size| size.to_string()).unwrap_or_default(),
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => "&amp;".into(),
+            '<' => "&lt;".into(),
+            '>' => "&gt;".into(),
+            '"' => "&quot;".into(),
+            '\'' => "&#39;".into(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn preferred_format() {
+        let preferred = |accept: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+            ListingFormat::preferred(&headers)
+        };
+        assert_eq!(ListingFormat::preferred(&HeaderMap::new()), None);
+        assert_eq!(
+            preferred("text/html,application/xhtml+xml,*/*;q=0.8"),
+            Some(ListingFormat::Html)
+        );
+        assert_eq!(preferred("application/json"), Some(ListingFormat::Json));
+        assert_eq!(
+            preferred("application/vnd.ipld.car, application/json;q=0.5"),
+            None
+        );
+        assert_eq!(preferred("text/html;q=0, */*"), None);
+        assert_eq!(preferred("*/*"), None);
+    }
+
+    #[test]
+    fn escape_html() {
+        assert_eq!(
+            escape("<a href=\"x\">&'"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;"
+        );
+    }
+}
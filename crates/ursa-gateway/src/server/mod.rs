@@ -1,5 +1,13 @@
+mod acceptor;
+mod compression;
+mod digest;
+mod latency;
+pub mod listing;
 mod model;
+mod openmetrics;
+mod range;
 mod route;
+mod timeout;
 
 use std::{
     net::{Ipv4Addr, SocketAddr},
@@ -12,15 +20,23 @@ use axum::{
     body::Body,
     extract::Extension,
     headers::HeaderName,
-    http::{HeaderValue, Method, Request, StatusCode},
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
     routing::get,
     Json, Router, ServiceExt,
 };
-use axum_prometheus::PrometheusMetricLayerBuilder;
-use axum_server::{tls_rustls::RustlsConfig, Handle};
+use axum_prometheus::{
+    PrometheusMetricLayerBuilder, AXUM_HTTP_REQUESTS_DURATION_SECONDS, SECONDS_DURATION_BUCKETS,
+};
+use axum_server::{
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+    Handle,
+};
 use axum_tracing_opentelemetry::{find_current_trace_id, opentelemetry_tracing_layer};
-use route::api::v1::get::get_car_handler;
+use metrics::gauge;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use route::api::v1::get::{get_car_handler, head_car_handler};
 use serde_json::json;
 use tokio::{
     select, spawn,
@@ -34,14 +50,19 @@ use tower_http::{
     normalize_path::NormalizePath,
     request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
     set_header::SetRequestHeaderLayer,
-    timeout::TimeoutLayer,
     trace::{DefaultMakeSpan, DefaultOnFailure, DefaultOnResponse, TraceLayer},
 };
 use tracing::{error, info, Level};
 
 use crate::{
-    config::{GatewayConfig, ServerConfig},
-    server::model::HttpResponse,
+    config::{GatewayConfig, MetricsFormat, ServerConfig},
+    server::{
+        acceptor::LimitAcceptor,
+        latency::{track_latency, REQUEST_DURATION_BUCKETS, REQUEST_DURATION_HISTOGRAM},
+        model::HttpResponse,
+        openmetrics::{Exemplars, OPENMETRICS_CONTENT_TYPE},
+        timeout::{request_timeout, Timeouts, X_URSA_TIMEOUT_MS},
+    },
     worker::cache::server::ServerCache,
 };
 
@@ -59,11 +80,19 @@ pub async fn start<Cache: ServerCache>(
                 cert_path,
                 key_path,
                 concurrency_limit,
-                request_timeout,
+                max_connections,
+                request_timeout: default_timeout,
+                max_request_timeout,
+                maintenance,
+                metrics_format,
                 ..
             },
         ..
     } = &(*config_reader.read().await);
+    let timeouts = Timeouts::new(
+        Duration::from_millis(*default_timeout),
+        Duration::from_millis(*max_request_timeout),
+    )?;
 
     let rustls_config = RustlsConfig::from_pem_file(&cert_path, &key_path)
         .await
@@ -79,12 +108,54 @@ pub async fn start<Cache: ServerCache>(
 
     let (prometheus_layer, metric_handle) = PrometheusMetricLayerBuilder::new()
         .with_ignore_patterns(&["/metrics", "/ping"])
-        .with_default_metrics()
+        .with_metrics_from_fn(|| {
+            PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Full(AXUM_HTTP_REQUESTS_DURATION_SECONDS.to_string()),
+                    SECONDS_DURATION_BUCKETS,
+                )
+                .and_then(|builder| {
+                    builder.set_buckets_for_metric(
+                        Matcher::Full(REQUEST_DURATION_HISTOGRAM.to_string()),
+                        REQUEST_DURATION_BUCKETS,
+                    )
+                })
+                .expect("buckets to be non empty")
+                .install_recorder()
+                .expect("a single prometheus recorder")
+        })
         .build_pair();
+    gauge!(
+        "gateway_maintenance_mode",
+        if *maintenance { 1.0 } else { 0.0 }
+    );
+    let exemplars = (*metrics_format == MetricsFormat::OpenMetrics).then(|| {
+        Arc::new(Exemplars::new(
+            REQUEST_DURATION_HISTOGRAM,
+            REQUEST_DURATION_BUCKETS,
+        ))
+    });
+    let render_metrics = {
+        let exemplars = exemplars.clone();
+        move || async move {
+            let metrics = metric_handle.render();
+            match exemplars {
+                Some(exemplars) => (
+                    [(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)],
+                    openmetrics::render(&metrics, &exemplars),
+                )
+                    .into_response(),
+                None => metrics.into_response(),
+            }
+        }
+    };
 
     let app = NormalizePath::trim_trailing_slash(
         Router::new()
-            .route("/:cid", get(get_car_handler::<Cache>))
+            .route(
+                "/:cid",
+                get(get_car_handler::<Cache>).head(head_car_handler::<Cache>),
+            )
             .layer(Extension(config))
             .layer(Extension(cache))
             .layer(CatchPanicLayer::custom(recover))
@@ -115,14 +186,18 @@ pub async fn start<Cache: ServerCache>(
             .layer(
                 CorsLayer::new()
                     .allow_methods([Method::GET])
+                    .allow_headers([HeaderName::from_static(X_URSA_TIMEOUT_MS)])
                     .allow_origin(Any),
             )
-            .layer(CompressionLayer::new())
-            .layer(TimeoutLayer::new(Duration::from_millis(*request_timeout)))
+            .layer(CompressionLayer::new().compress_when(compression::compress_when()))
+            .layer(middleware::from_fn(compression::weaken_etag))
+            .layer(middleware::from_fn_with_state(timeouts, request_timeout))
             .layer(prometheus_layer)
             .layer(ConcurrencyLimitLayer::new(*concurrency_limit as usize))
+            // outermost, so the queueing behind the concurrency limit is timed too
+            .layer(middleware::from_fn_with_state(exemplars, track_latency))
             // put trivial route first to prevent annoying log and trace
-            .route("/metrics", get(|| async move { metric_handle.render() }))
+            .route("/metrics", get(render_metrics))
             .route("/ping", get(|| async { "pong" })),
     );
 
@@ -131,9 +206,13 @@ pub async fn start<Cache: ServerCache>(
     let handle = Handle::new();
     spawn(graceful_shutdown(handle.clone(), shutdown_rx));
 
-    axum_server::bind_rustls(addr, rustls_config)
+    axum_server::bind(addr)
+        .acceptor(LimitAcceptor::new(
+            RustlsAcceptor::new(rustls_config),
+            *max_connections as usize,
+        ))
         .handle(handle)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .context("Failed to start server")?;
 
@@ -165,6 +244,7 @@ fn recover(e: Box<dyn std::any::Any + Send + 'static>) -> Response {
         StatusCode::INTERNAL_SERVER_ERROR,
         Json(json!(HttpResponse {
             message: Some("Internal Server Error".into()),
+            reason: None,
         })),
     )
         .into_response()
@@ -3,4 +3,7 @@ use serde::{Deserialize, Serialize};
 #[derive(Deserialize, Serialize)]
 pub struct HttpResponse {
     pub message: Option<String>,
+    /// Why the fetch failed, see [`crate::util::error::FetchFailure`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
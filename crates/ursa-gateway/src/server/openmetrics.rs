@@ -0,0 +1,203 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Latest sample of a histogram bucket with the trace it was recorded in.
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    timestamp: f64,
+}
+
+/// Exemplars of a histogram, one per series and bucket.
+pub struct Exemplars {
+    histogram: &'static str,
+    buckets: &'static [f64],
+    /// keyed by the sorted labels of the series and the bucket bound
+    samples: Mutex<HashMap<(String, u64), Exemplar>>,
+}
+
+impl Exemplars {
+    pub fn new(histogram: &'static str, buckets: &'static [f64]) -> Self {
+        Self {
+            histogram,
+            buckets,
+            samples: Default::default(),
+        }
+    }
+
+    /// Keep `value` as the exemplar of the bucket it falls in.
+    pub fn record(&self, labels: &[(&str, &str)], value: f64, trace_id: &str) {
+        let bound = self
+            .buckets
+            .iter()
+            .copied()
+            .find(|bound| value <= *bound)
+            .unwrap_or(f64::INFINITY);
+        let labels: BTreeMap<_, _> = labels.iter().copied().collect();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |now| now.as_secs_f64());
+        self.samples.lock().unwrap().insert(
+            (series(&labels), bound.to_bits()),
+            Exemplar {
+                trace_id: trace_id.to_string(),
+                value,
+                timestamp,
+            },
+        );
+    }
+
+    /// Exemplar of a `_bucket` sample line of the histogram.
+    fn find(&self, sample: &str) -> Option<String> {
+        let labels = sample
+            .strip_prefix(self.histogram)?
+            .strip_prefix("_bucket{")?
+            .split_once('}')?
+            .0;
+        let mut labels = parse_labels(labels);
+        let bound = match labels.remove("le")? {
+            "+Inf" => f64::INFINITY,
+            le => le.parse().ok()?,
+        };
+        let samples = self.samples.lock().unwrap();
+        let exemplar = samples.get(&(series(&labels), bound.to_bits()))?;
+        Some(format!(
+            "# {{trace_id=\"{}\"}} {} {:.3}",
+            exemplar.trace_id, exemplar.value, exemplar.timestamp
+        ))
+    }
+}
+
+fn series(labels: &BTreeMap<&str, &str>) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Labels of a sample, whose values never contain `",` in our metrics.
+fn parse_labels(labels: &str) -> BTreeMap<&str, &str> {
+    labels
+        .split("\",")
+        .filter_map(|label| {
+            let (name, value) = label.split_once("=\"")?;
+            Some((name.trim(), value.trim_end_matches('"')))
+        })
+        .collect()
+}
+
+/// Convert the prometheus text exposition to OpenMetrics: counter samples
+/// get their `_total` suffix, the histogram buckets their exemplars, and the
+/// exposition is terminated by `# EOF`.
+pub fn render(prometheus: &str, exemplars: &Exemplars) -> String {
+    let counters: HashSet<&str> = prometheus
+        .lines()
+        .filter_map(|line| line.strip_prefix("# TYPE "))
+        .filter_map(|kind| kind.split_once(' '))
+        .filter(|(_, kind)| *kind == "counter")
+        .map(|(name, _)| name)
+        .collect();
+    let family = |name: &str| name.strip_suffix("_total").unwrap_or(name).to_string();
+
+    let mut out = String::with_capacity(prometheus.len());
+    for line in prometheus.lines().filter(|line| !line.trim().is_empty()) {
+        if let Some((prefix, rest)) = line
+            .strip_prefix("# TYPE ")
+            .map(|rest| ("# TYPE ", rest))
+            .or_else(|| line.strip_prefix("# HELP ").map(|rest| ("# HELP ", rest)))
+        {
+            let (name, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+            let name = if counters.contains(name) {
+                family(name)
+            } else {
+                name.to_string()
+            };
+            let _ = writeln!(out, "{prefix}{name} {tail}");
+            continue;
+        }
+        if line.starts_with('#') {
+            let _ = writeln!(out, "{line}");
+            continue;
+        }
+        let name_end = line.find(|c| c == '{' || c == ' ').unwrap_or(line.len());
+        let (name, rest) = line.split_at(name_end);
+        if counters.contains(name) {
+            let _ = writeln!(out, "{}_total{rest}", family(name));
+        } else if let Some(exemplar) = exemplars.find(line) {
+            let _ = writeln!(out, "{line} {exemplar}");
+        } else {
+            let _ = writeln!(out, "{line}");
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUCKETS: &[f64] = &[0.1, 1.0];
+
+    #[test]
+    fn render_openmetrics() {
+        let exemplars = Exemplars::new("latency", BUCKETS);
+        exemplars.record(&[("status", "2xx"), ("outcome", "hit")], 0.05, "abc");
+        exemplars.record(&[("outcome", "hit"), ("status", "2xx")], 0.5, "def");
+        exemplars.record(&[("outcome", "miss"), ("status", "2xx")], 5.0, "ghi");
+
+        let prometheus = "\
+# HELP hits cache hits
+# TYPE hits counter
+hits 3
+
+# TYPE requests_total counter
+requests_total{route=\"get\"} 7
+
+# TYPE latency histogram
+latency_bucket{outcome=\"hit\",status=\"2xx\",le=\"0.1\"} 1
+latency_bucket{outcome=\"hit\",status=\"2xx\",le=\"1\"} 2
+latency_bucket{outcome=\"hit\",status=\"2xx\",le=\"+Inf\"} 2
+latency_bucket{outcome=\"miss\",status=\"2xx\",le=\"+Inf\"} 1
+latency_sum{outcome=\"hit\",status=\"2xx\"} 0.55
+latency_count{outcome=\"hit\",status=\"2xx\"} 2
+";
+        let rendered = render(prometheus, &exemplars);
+        let lines: Vec<_> = rendered.lines().collect();
+        assert_eq!(
+            &lines[..5],
+            &[
+                "# HELP hits cache hits",
+                "# TYPE hits counter",
+                "hits_total 3",
+                "# TYPE requests counter",
+                "requests_total{route=\"get\"} 7",
+            ]
+        );
+        assert!(lines[6].starts_with(
+            "latency_bucket{outcome=\"hit\",status=\"2xx\",le=\"0.1\"} 1 # {trace_id=\"abc\"} 0.05 "
+        ));
+        assert!(lines[7].starts_with(
+            "latency_bucket{outcome=\"hit\",status=\"2xx\",le=\"1\"} 2 # {trace_id=\"def\"} 0.5 "
+        ));
+        assert_eq!(
+            lines[8],
+            "latency_bucket{outcome=\"hit\",status=\"2xx\",le=\"+Inf\"} 2"
+        );
+        assert!(lines[9].contains("# {trace_id=\"ghi\"} 5 "));
+        assert_eq!(
+            lines[10],
+            "latency_sum{outcome=\"hit\",status=\"2xx\"} 0.55"
+        );
+        assert_eq!(lines.last(), Some(&"# EOF"));
+        assert!(!lines.contains(&""));
+    }
+}
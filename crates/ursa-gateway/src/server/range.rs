@@ -0,0 +1,180 @@
+use std::future::ready;
+
+use futures::StreamExt;
+use hyper::Body;
+
+/// Inclusive window of a content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// `Content-Range` of the window of a content of `size` bytes.
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{size}", self.start, self.end)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Range {
+    /// No range, or a malformed one which is ignored.
+    Full,
+    /// The first satisfiable range of the request, multiple ranges are not
+    /// served as `multipart/byteranges`.
+    Partial(ByteRange),
+    /// None of the ranges start within the content.
+    Unsatisfiable,
+}
+
+impl Range {
+    /// Ranges of a `Range: bytes=...` header, validated against the content `size`.
+    pub fn parse(header: &str, size: u64) -> Self {
+        let Some(specs) = header.trim().strip_prefix("bytes=") else {
+            // other units are ignored
+            return Range::Full;
+        };
+        let mut first = None;
+        for spec in specs.split(',') {
+            let Some((start, end)) = spec.trim().split_once('-') else {
+                return Range::Full;
+            };
+            let range = match (start.trim(), end.trim()) {
+                ("", "") => return Range::Full,
+                // suffix of the last `len` bytes
+                ("", len) => {
+                    let Ok(len) = len.parse::<u64>() else {
+                        return Range::Full;
+                    };
+                    (len > 0 && size > 0).then(|| ByteRange {
+                        start: size.saturating_sub(len),
+                        end: size - 1,
+                    })
+                }
+                (start, end) => {
+                    let Ok(start) = start.parse::<u64>() else {
+                        return Range::Full;
+                    };
+                    let end = match end {
+                        "" => u64::MAX,
+                        end => match end.parse::<u64>() {
+                            Ok(end) if end >= start => end,
+                            _ => return Range::Full,
+                        },
+                    };
+                    (start < size).then(|| ByteRange {
+                        start,
+                        end: end.min(size - 1),
+                    })
+                }
+            };
+            if first.is_none() {
+                first = range;
+            }
+        }
+        first.map_or(Range::Unsatisfiable, Range::Partial)
+    }
+}
+
+/// Whether the `If-Range` validator of a request still designates the content with `etag`,
+/// only then is its range served. The etag is strong, a date never matches as the content
+/// has no `Last-Modified`.
+pub fn if_range(value: &str, etag: &str) -> bool {
+    value.trim() == etag
+}
+
+/// The `range` window of the content streamed in `body`.
+pub fn slice(body: Body, range: ByteRange) -> Body {
+    let window = body.scan((range.start, range.len()), |(skip, take), chunk| {
+        if *take == 0 {
+            return ready(None);
+        }
+        ready(Some(chunk.map(|chunk| {
+            let skipped = (*skip).min(chunk.len() as u64);
+            *skip -= skipped;
+            let chunk = chunk.slice(skipped as usize..);
+            let taken = (*take).min(chunk.len() as u64);
+            *take -= taken;
+            chunk.slice(..taken as usize)
+        })))
+    });
+    Body::wrap_stream(window)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+
+    fn partial(start: u64, end: u64) -> Range {
+        Range::Partial(ByteRange { start, end })
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(Range::parse("bytes=0-499", 10_000), partial(0, 499));
+        assert_eq!(Range::parse("bytes=0-0", 10_000), partial(0, 0));
+        // clamped to the content
+        assert_eq!(
+            Range::parse("bytes=9500-20000", 10_000),
+            partial(9500, 9999)
+        );
+        // first of a multi-range request
+        assert_eq!(
+            Range::parse("bytes=20000-30000, 0-99, 200-299", 10_000),
+            partial(0, 99)
+        );
+    }
+
+    #[test]
+    fn parse_open_ended() {
+        assert_eq!(Range::parse("bytes=9500-", 10_000), partial(9500, 9999));
+        assert_eq!(Range::parse("bytes=0-", 1), partial(0, 0));
+        assert_eq!(Range::parse("bytes=10000-", 10_000), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn parse_suffix() {
+        assert_eq!(Range::parse("bytes=-500", 10_000), partial(9500, 9999));
+        // longer than the content
+        assert_eq!(Range::parse("bytes=-500", 100), partial(0, 99));
+        assert_eq!(Range::parse("bytes=-0", 100), Range::Unsatisfiable);
+        assert_eq!(Range::parse("bytes=-1", 0), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for header in [
+            "bytes=",
+            "bytes=-",
+            "bytes=a-b",
+            "bytes=500-100",
+            "bytes=0-99,x",
+            "bytes 0-99",
+            "items=0-99",
+        ] {
+            assert_eq!(Range::parse(header, 10_000), Range::Full, "{header}");
+        }
+        assert_eq!(
+            Range::parse("bytes=10000-10005, 20000-", 10_000),
+            Range::Unsatisfiable
+        );
+    }
+
+    #[tokio::test]
+    async fn slice_chunks() {
+        let chunks: Vec<Result<_, std::io::Error>> = vec![Ok("abc"), Ok("def"), Ok("ghi")];
+        let body = Body::wrap_stream(stream::iter(chunks));
+        let body = slice(body, ByteRange { start: 2, end: 6 });
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "cdefg");
+
+        let body = slice(Body::from("abcdef"), ByteRange { start: 0, end: 0 });
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "a");
+    }
+}
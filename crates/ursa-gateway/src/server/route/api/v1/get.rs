@@ -1,74 +1,562 @@
-use std::{str::FromStr, sync::Arc};
+use std::{net::SocketAddr, str::FromStr, sync::Arc};
 
 use axum::{
-    extract::Path,
+    body::boxed,
+    extract::{ConnectInfo, Path, Query, RawQuery},
     headers::CacheControl,
-    http::{header, StatusCode},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Extension, Json, TypedHeader,
 };
+use hyper::Body;
 use libipld::Cid;
+use metrics::increment_counter;
+use serde::Deserialize;
 use serde_json::{json, Value};
 use tokio::sync::RwLock;
 use tracing::{info_span, Instrument};
 
 use crate::{
-    config::GatewayConfig, server::model::HttpResponse, util::error::Error,
-    worker::cache::server::ServerCache,
+    config::{GatewayConfig, ServerConfig},
+    server::{
+        digest::{self, DigestBody, DIGEST, MAX_DIGEST_SIZE},
+        latency::CacheHit,
+        listing::{self, ListingFormat, Root},
+        model::HttpResponse,
+        range::{self, Range},
+    },
+    util::error::{Error, X_URSA_FETCH_ERROR},
+    worker::cache::{
+        key::CacheKey,
+        server::{ServerCache, StreamResponseBody},
+    },
 };
 
+/// Roots of the dags traversed to resolve the requested path, the requested
+/// cid followed by the other roots of the car.
+const X_IPFS_ROOTS: &str = "x-ipfs-roots";
+
+const CAR_CONTENT_TYPE: &str = "application/vnd.curl.car; charset=utf-8";
+
+/// Content types a request can ask for, with the file extension implying them.
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("car", "application/vnd.ipld.car"),
+    ("bin", "application/octet-stream"),
+    ("json", "application/json"),
+    ("cbor", "application/cbor"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("html", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mp3", "audio/mpeg"),
+    ("pdf", "application/pdf"),
+    ("wasm", "application/wasm"),
+];
+
+/// Content type and filename overrides, for content whose type is known to the caller.
+/// `format` takes precedence over the type implied by the `filename` extension.
+#[derive(Debug, Default, Deserialize)]
+pub struct ContentQuery {
+    pub filename: Option<String>,
+    pub format: Option<String>,
+}
+
+impl ContentQuery {
+    /// Content type and filename to serve, only allowlisted types and plain
+    /// filenames are accepted so the values are safe to put in the headers.
+    fn resolve(&self, cid: &str) -> Result<(&'static str, String), String> {
+        let filename = match &self.filename {
+            Some(filename) => {
+                if filename.is_empty()
+                    || filename.len() > 255
+                    || !filename
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+                {
+                    return Err(format!("Invalid filename {filename:?}"));
+                }
+                filename.clone()
+            }
+            None => format!("{cid}.car"),
+        };
+        let content_type = match (&self.format, &self.filename) {
+            (Some(format), _) => CONTENT_TYPES
+                .iter()
+                .find(|(_, mime)| mime.split(';').next() == Some(format.as_str()))
+                .map(|(_, mime)| *mime)
+                .ok_or_else(|| format!("Unsupported format {format:?}"))?,
+            (None, Some(filename)) => {
+                let extension = filename
+                    .rsplit_once('.')
+                    .map(|(_, ext)| ext.to_ascii_lowercase())
+                    .unwrap_or_default();
+                CONTENT_TYPES
+                    .iter()
+                    .find(|(ext, _)| *ext == extension)
+                    .map(|(_, mime)| *mime)
+                    .ok_or_else(|| format!("Unsupported file extension {extension:?}"))?
+            }
+            (None, None) => CAR_CONTENT_TYPE,
+        };
+        Ok((content_type, filename))
+    }
+}
+
+/// Validated car request, shared by the GET and HEAD handlers.
+struct CarRequest {
+    cid: String,
+    parsed: Cid,
+    key: CacheKey,
+    content_type: &'static str,
+    filename: String,
+    no_cache: bool,
+    no_store: bool,
+    /// directories are listed instead of served as a car
+    listing: Option<ListingFormat>,
+    /// the content behind a cid never changes, it is its own strong validator
+    etag: String,
+    /// the client already has the representation
+    not_modified: bool,
+}
+
+impl CarRequest {
+    fn parse(
+        cid: String,
+        content: &ContentQuery,
+        query: Option<&str>,
+        cache_control: Option<TypedHeader<CacheControl>>,
+        headers: &HeaderMap,
+        config: &ServerConfig,
+    ) -> Result<Self, Response> {
+        // browsers never send the fragment, only an encoded `#` of other clients reaches us
+        let cid = match cid.split_once('#') {
+            Some((cid, _)) => cid.to_string(),
+            None => cid,
+        };
+        let Ok(parsed) = Cid::from_str(&cid) else {
+            return Err(error_handler(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid cid string, cannot parse {cid} to CID"),
+            )
+            .into_response());
+        };
+        if let Err(message) = check_cid(config, &parsed) {
+            return Err(error_handler(StatusCode::UNSUPPORTED_MEDIA_TYPE, message).into_response());
+        }
+        let (content_type, filename) = content
+            .resolve(&cid)
+            .map_err(|message| error_handler(StatusCode::BAD_REQUEST, message).into_response())?;
+        // an explicit content type is served as is
+        let listing = (content.format.is_none() && content.filename.is_none())
+            .then(|| ListingFormat::preferred(headers))
+            .flatten();
+        let key = CacheKey::new(&cid).with_query(query, &config.cache_key_params);
+        let tag = match &key.query {
+            Some(query) => format!("{parsed}?{query}"),
+            None => parsed.to_string(),
+        };
+        // listings are another representation of the same cid
+        let etag = match listing {
+            None => format!("\"{tag}\""),
+            Some(ListingFormat::Html) => format!("\"{tag}.html\""),
+            Some(ListingFormat::Json) => format!("\"{tag}.json\""),
+        };
+        let not_modified = headers
+            .get_all(header::IF_NONE_MATCH)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| if_none_match(value, &etag));
+        Ok(Self {
+            cid,
+            parsed,
+            key,
+            content_type,
+            filename,
+            no_cache: cache_control.as_ref().map_or(false, |c| c.no_cache()),
+            no_store: cache_control.map_or(false, |c| c.no_store()),
+            listing,
+            etag,
+            not_modified,
+        })
+    }
+
+    /// Fetches are scheduled fairly across client ips.
+    fn client(&self, client: Option<ConnectInfo<SocketAddr>>) -> String {
+        client.map_or_else(
+            || self.cid.clone(),
+            |ConnectInfo(addr)| addr.ip().to_string(),
+        )
+    }
+
+    fn cache_control(&self, config: &ServerConfig) -> String {
+        if self.no_store {
+            "no-store".into()
+        } else if self.no_cache {
+            "no-cache".into()
+        } else {
+            format!(
+                "public, max-age={}, immutable",
+                config.cache_control_max_age
+            )
+        }
+    }
+
+    fn response(&self, config: &ServerConfig, roots: &[Cid], body: impl IntoResponse) -> Response {
+        let mut response = (
+            [
+                (header::CONTENT_TYPE, self.content_type),
+                (
+                    header::CONTENT_DISPOSITION,
+                    &format!("attachment; filename=\"{}\"", self.filename),
+                ),
+                (header::CACHE_CONTROL, &self.cache_control(config)),
+                (header::ETAG, &self.etag),
+            ],
+            body,
+        )
+            .into_response();
+        if config.roots_header {
+            let roots: Vec<_> = std::iter::once(&self.parsed)
+                .chain(roots.iter().filter(|root| **root != self.parsed))
+                .map(Cid::to_string)
+                .collect();
+            response.headers_mut().insert(
+                HeaderName::from_static(X_IPFS_ROOTS),
+                HeaderValue::from_str(&roots.join(",")).expect("cids to be a valid header value"),
+            );
+        }
+        response
+    }
+
+    /// Answer to a request whose `If-None-Match` matched, without fetching the content.
+    fn not_modified_response(&self, config: &ServerConfig) -> Response {
+        (
+            StatusCode::NOT_MODIFIED,
+            [
+                (header::CACHE_CONTROL, self.cache_control(config)),
+                (header::ETAG, self.etag.clone()),
+            ],
+        )
+            .into_response()
+    }
+
+    /// The listing of the directory, or the content to serve as is if it isn't
+    /// a directory without an index.
+    async fn directory_listing(
+        &self,
+        config: &ServerConfig,
+        format: ListingFormat,
+        stream: StreamResponseBody,
+    ) -> Result<Response, Body> {
+        let entries = match listing::inspect(&self.parsed, stream.into_body()).await {
+            Root::Directory(entries) => entries,
+            Root::Content(body) => return Err(body),
+        };
+        if !config.directory_listing {
+            return Ok(error_handler(
+                StatusCode::NOT_FOUND,
+                format!("{} is a directory without an index", self.cid),
+            )
+            .into_response());
+        }
+        let body = match format {
+            ListingFormat::Html => listing::render_html(&self.parsed, &entries),
+            ListingFormat::Json => json!({ "cid": self.cid, "entries": entries }).to_string(),
+        };
+        Ok((
+            [
+                (header::CONTENT_TYPE, format.content_type()),
+                (header::CACHE_CONTROL, &self.cache_control(config)),
+                (header::ETAG, &self.etag),
+            ],
+            body,
+        )
+            .into_response())
+    }
+}
+
 pub async fn get_car_handler<Cache: ServerCache>(
     Path(cid): Path<String>,
+    Query(content): Query<ContentQuery>,
+    RawQuery(query): RawQuery,
+    client: Option<ConnectInfo<SocketAddr>>,
     cache_control: Option<TypedHeader<CacheControl>>,
+    headers: HeaderMap,
     Extension(cache): Extension<Arc<RwLock<Cache>>>,
     Extension(config): Extension<Arc<RwLock<GatewayConfig>>>,
 ) -> Response {
     let span = info_span!("Get car handler");
-    if Cid::from_str(&cid).is_err() {
-        return error_handler(
-            StatusCode::BAD_REQUEST,
-            format!("Invalid cid string, cannot parse {cid} to CID"),
-        )
-        .into_response();
+    let request = match CarRequest::parse(
+        cid,
+        &content,
+        query.as_deref(),
+        cache_control,
+        &headers,
+        &config.read().await.server,
+    ) {
+        Ok(request) => request,
+        Err(response) => return response,
     };
-    let no_cache = cache_control.map_or(false, |c| c.no_cache());
-    match cache
-        .read()
-        .await
-        .get_announce(&cid, no_cache)
-        .instrument(span)
-        .await
-    {
-        Ok(stream) => (
-            [
-                (
-                    header::CONTENT_TYPE,
-                    "application/vnd.curl.car; charset=utf-8",
-                ),
-                (
-                    header::CONTENT_DISPOSITION,
-                    &format!("attachment; filename=\"{cid}.car\""),
-                ),
-                (
-                    header::CACHE_CONTROL,
-                    &(if no_cache {
-                        "no-cache".into()
-                    } else {
-                        format!(
-                            "public, max-age={}, immutable",
-                            config.read().await.server.cache_control_max_age
-                        )
-                    }),
+    if request.not_modified {
+        return request.not_modified_response(&config.read().await.server);
+    }
+    let cache = cache.read().await;
+    let key = &request.key;
+    let maintenance = config.read().await.server.maintenance;
+    let result = if maintenance {
+        // cache only, whatever the cache control of the request
+        match cache.cached(key) {
+            Ok(Some(stream)) => Ok(stream),
+            Ok(None) => return maintenance_error(&config.read().await.server),
+            Err(e) => Err(e),
+        }
+    } else {
+        cache
+            .get_announce(
+                key,
+                request.no_cache,
+                request.no_store,
+                &request.client(client),
+            )
+            .instrument(span)
+            .await
+    };
+    match result {
+        Ok(stream) => {
+            let hit = maintenance || stream.is_cached();
+            // known before a listing replays the content
+            let size = stream.size();
+            let config = config.read().await;
+            let body = match request.listing {
+                Some(format) => {
+                    match request
+                        .directory_listing(&config.server, format, stream)
+                        .await
+                    {
+                        Ok(mut listing) => {
+                            if hit {
+                                listing.extensions_mut().insert(CacheHit);
+                            }
+                            return listing;
+                        }
+                        Err(body) => StreamResponseBody::Direct(body),
+                    }
+                }
+                None => stream,
+            };
+            let (roots, body) = if config.server.roots_header {
+                let (roots, body) = listing::car_roots(body.into_body()).await;
+                (roots, StreamResponseBody::Direct(body))
+            } else {
+                (vec![], body)
+            };
+            // ranges are only served for content of a known size, and the content
+            // `If-Range` validates
+            let range = headers.get(header::RANGE).filter(|_| {
+                headers.get(header::IF_RANGE).map_or(true, |value| {
+                    value
+                        .to_str()
+                        .map_or(false, |value| range::if_range(value, &request.etag))
+                })
+            });
+            let (range, total) = match (range, size) {
+                (Some(range), Some(size)) => (
+                    range
+                        .to_str()
+                        .map_or(Range::Full, |range| Range::parse(range, size)),
+                    size,
                 ),
-            ],
-            stream,
+                _ => (Range::Full, 0),
+            };
+            let digest_header = config.server.digest_header;
+            let mut response = match range {
+                Range::Full
+                    if digest_header && size.map_or(false, |size| size <= MAX_DIGEST_SIZE) =>
+                {
+                    // content of a known length is sent without trailers
+                    let content = match hyper::body::to_bytes(body.into_body()).await {
+                        Ok(content) => content,
+                        Err(e) => {
+                            return error_handler(
+                                StatusCode::BAD_GATEWAY,
+                                format!("Failed to read the content: {e}"),
+                            )
+                            .into_response()
+                        }
+                    };
+                    let digest = digest::digest(&content);
+                    let mut response = request.response(&config.server, &roots, content);
+                    response
+                        .headers_mut()
+                        .insert(HeaderName::from_static(DIGEST), digest);
+                    response
+                }
+                Range::Full => {
+                    let mut response = request.response(&config.server, &roots, body);
+                    if digest_header && size.is_none() {
+                        response
+                            .headers_mut()
+                            .insert(header::TRAILER, HeaderValue::from_static(DIGEST));
+                        response = response.map(|body| boxed(DigestBody::new(body)));
+                    }
+                    response
+                }
+                Range::Partial(window) => {
+                    let mut response = request.response(
+                        &config.server,
+                        &roots,
+                        range::slice(body.into_body(), window),
+                    );
+                    *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+                    let headers = response.headers_mut();
+                    headers.insert(
+                        header::CONTENT_RANGE,
+                        HeaderValue::from_str(&window.content_range(total))
+                            .expect("content range to be a valid header value"),
+                    );
+                    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(window.len()));
+                    response
+                }
+                Range::Unsatisfiable => (
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{total}"))],
+                )
+                    .into_response(),
+            };
+            if size.is_some() {
+                response
+                    .headers_mut()
+                    .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+            }
+            if hit {
+                response.extensions_mut().insert(CacheHit);
+            }
+            response
+        }
+        Err(e) => cache_error(e),
+    }
+}
+
+/// Headers of the content without fetching it, unless `head_fetch` is set.
+/// `Content-Length` is omitted when the size is only known once fetched.
+pub async fn head_car_handler<Cache: ServerCache>(
+    Path(cid): Path<String>,
+    Query(content): Query<ContentQuery>,
+    RawQuery(query): RawQuery,
+    client: Option<ConnectInfo<SocketAddr>>,
+    cache_control: Option<TypedHeader<CacheControl>>,
+    headers: HeaderMap,
+    Extension(cache): Extension<Arc<RwLock<Cache>>>,
+    Extension(config): Extension<Arc<RwLock<GatewayConfig>>>,
+) -> Response {
+    if config.read().await.server.head_fetch {
+        // the body is dropped by the server, the fetched content still gets cached
+        return get_car_handler(
+            Path(cid),
+            Query(content),
+            RawQuery(query),
+            client,
+            cache_control,
+            headers,
+            Extension(cache),
+            Extension(config),
         )
-            .into_response(),
-        Err(Error::Upstream(status, message)) => error_handler(status, message).into_response(),
-        Err(Error::Internal(message)) => {
+        .await;
+    }
+    let span = info_span!("Head car handler");
+    let request = match CarRequest::parse(
+        cid,
+        &content,
+        query.as_deref(),
+        cache_control,
+        &headers,
+        &config.read().await.server,
+    ) {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    if request.not_modified {
+        return request.not_modified_response(&config.read().await.server);
+    }
+    let key = &request.key;
+    let result = if config.read().await.server.maintenance {
+        match cache.read().await.cached_size(key) {
+            Some(size) => Ok(Some(size)),
+            None => return maintenance_error(&config.read().await.server),
+        }
+    } else {
+        cache.read().await.head(key).instrument(span).await
+    };
+    match result {
+        Ok(size) => {
+            let mut response = request.response(&config.read().await.server, &[], ());
+            if let Some(size) = size {
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+            }
+            response
+        }
+        Err(e) => cache_error(e),
+    }
+}
+
+/// Whether an `If-None-Match` list matches `etag`, weakly compared as the spec requires.
+fn if_none_match(value: &str, etag: &str) -> bool {
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+fn cache_error(error: Error) -> Response {
+    match error {
+        Error::Upstream(status, message) => error_handler(status, message).into_response(),
+        Error::Internal(message) => {
             error_handler(StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
         }
+        Error::Fetch {
+            reason,
+            status,
+            message,
+        } => (
+            status,
+            [(X_URSA_FETCH_ERROR, reason.as_str())],
+            Json(json!(HttpResponse {
+                message: Some(message),
+                reason: Some(reason.as_str().to_string()),
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Response to the cache misses in maintenance mode.
+fn maintenance_error(config: &ServerConfig) -> Response {
+    increment_counter!("gateway_maintenance_miss");
+    let status =
+        StatusCode::from_u16(config.maintenance_status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+    error_handler(status, config.maintenance_message.clone()).into_response()
+}
+
+/// Reject cids outside of the configured codec and hash allowlists.
+fn check_cid(config: &ServerConfig, cid: &Cid) -> Result<(), String> {
+    if !config.allowed_codecs.is_empty() && !config.allowed_codecs.contains(&cid.codec()) {
+        return Err(format!("Unsupported cid codec {:#x}", cid.codec()));
+    }
+    let hash = cid.hash().code();
+    if !config.allowed_hashes.is_empty() && !config.allowed_hashes.contains(&hash) {
+        return Err(format!("Unsupported cid hash function {hash:#x}"));
     }
+    Ok(())
 }
 
 fn error_handler(status_code: StatusCode, message: String) -> (StatusCode, Json<Value>) {
@@ -76,6 +564,643 @@ fn error_handler(status_code: StatusCode, message: String) -> (StatusCode, Json<
         status_code,
         Json(json!(HttpResponse {
             message: Some(message),
+            reason: None,
         })),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::util::error::FetchFailure;
+    use libipld::{
+        cbor::DagCborCodec,
+        codec::Codec,
+        ipld,
+        multihash::{Code, MultihashDigest},
+        pb::DagPbCodec,
+        Ipld,
+    };
+
+    const RAW_CID: &str = "bafkreihwcrnsi2tqozwq22k4vl7flutu43jlxgb3tenewysm2xvfuej5i4";
+    const DAG_PB: u64 = 0x70;
+    const BLAKE3: u64 = 0x1e;
+    const LARGE_SIZE: u64 = 10_000_000_000;
+
+    /// Cache answering every request the same way. The methods left unset panic, for
+    /// the tests asserting a request doesn't fetch or resolve the content.
+    #[derive(Default)]
+    struct TestCache {
+        /// content fetched for any cid
+        fetched: Option<Vec<u8>>,
+        /// size resolved for any cid
+        size: Option<Option<u64>>,
+        /// content cached under `RAW_CID`
+        cached: Option<Vec<u8>>,
+        /// keys of the fetches
+        keys: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl TestCache {
+        /// Cache fetching `content` for any cid, of a size not known without fetching it.
+        fn fetching(content: impl Into<Vec<u8>>) -> Self {
+            Self {
+                fetched: Some(content.into()),
+                size: Some(None),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ServerCache for TestCache {
+        async fn get_announce(
+            &self,
+            key: &CacheKey,
+            _: bool,
+            _: bool,
+            _: &str,
+        ) -> Result<StreamResponseBody, Error> {
+            let content = self.fetched.clone().expect("unexpected fetch");
+            self.keys.lock().unwrap().push(key.to_string());
+            Ok(StreamResponseBody::Direct(Body::from(content)))
+        }
+
+        async fn head(&self, _: &CacheKey) -> Result<Option<u64>, Error> {
+            Ok(self.size.expect("unexpected size resolution"))
+        }
+
+        fn cached(&self, key: &CacheKey) -> Result<Option<StreamResponseBody>, Error> {
+            Ok(self
+                .cached
+                .clone()
+                .filter(|_| key.cid == RAW_CID)
+                .map(|content| StreamResponseBody::Direct(Body::from(content))))
+        }
+
+        fn cached_size(&self, key: &CacheKey) -> Option<u64> {
+            self.cached
+                .as_ref()
+                .filter(|_| key.cid == RAW_CID)
+                .map(|content| content.len() as u64)
+        }
+    }
+
+    async fn head<Cache: ServerCache>(cache: Cache) -> Response {
+        head_car_handler(
+            Path(RAW_CID.to_string()),
+            Query(ContentQuery::default()),
+            RawQuery(None),
+            None,
+            None,
+            HeaderMap::new(),
+            Extension(Arc::new(RwLock::new(cache))),
+            Extension(Arc::new(RwLock::new(GatewayConfig::default()))),
+        )
+        .await
+    }
+
+    async fn get_with(query: ContentQuery) -> Response {
+        get_car_handler(
+            Path(RAW_CID.to_string()),
+            Query(query),
+            RawQuery(None),
+            None,
+            None,
+            HeaderMap::new(),
+            Extension(Arc::new(RwLock::new(TestCache::fetching("")))),
+            Extension(Arc::new(RwLock::new(GatewayConfig::default()))),
+        )
+        .await
+    }
+
+    fn content_type(response: &Response) -> &str {
+        response.headers()[header::CONTENT_TYPE].to_str().unwrap()
+    }
+
+    async fn get(cid: &str, config: GatewayConfig) -> StatusCode {
+        get_car_handler(
+            Path(cid.to_string()),
+            Query(ContentQuery::default()),
+            RawQuery(None),
+            None,
+            None,
+            HeaderMap::new(),
+            Extension(Arc::new(RwLock::new(TestCache::default()))),
+            Extension(Arc::new(RwLock::new(config))),
+        )
+        .await
+        .status()
+    }
+
+    #[tokio::test]
+    async fn malformed_cid() {
+        assert_eq!(
+            get("not-a-cid", GatewayConfig::default()).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn disallowed_codec() {
+        let mut config = GatewayConfig::default();
+        config.server.allowed_codecs = vec![DAG_PB];
+        assert_eq!(
+            get(RAW_CID, config).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+
+    #[tokio::test]
+    async fn disallowed_hash() {
+        let mut config = GatewayConfig::default();
+        config.server.allowed_hashes = vec![BLAKE3];
+        assert_eq!(
+            get(RAW_CID, config).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+
+    #[tokio::test]
+    async fn content_type_override() {
+        let response = get_with(ContentQuery::default()).await;
+        assert_eq!(content_type(&response), CAR_CONTENT_TYPE);
+
+        let response = get_with(ContentQuery {
+            filename: Some("cat.PNG".into()),
+            format: None,
+        })
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(content_type(&response), "image/png");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"cat.PNG\""
+        );
+
+        let response = get_with(ContentQuery {
+            filename: Some("data.bin".into()),
+            format: Some("application/json".into()),
+        })
+        .await;
+        assert_eq!(content_type(&response), "application/json");
+    }
+
+    #[tokio::test]
+    async fn content_type_override_rejected() {
+        for (filename, format) in [
+            (Some("a\"\r\nx-injected: 1"), None),
+            (Some("script.exe"), None),
+            (None, Some("text/html\r\nx-injected: 1")),
+            (None, Some("application/x-unknown")),
+        ] {
+            let response = get_with(ContentQuery {
+                filename: filename.map(Into::into),
+                format: format.map(Into::into),
+            })
+            .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn head_resolves_size_without_fetch() {
+        // the content is only known to the resolver
+        let response = head(TestCache {
+            size: Some(Some(LARGE_SIZE)),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(content_type(&response), CAR_CONTENT_TYPE);
+        assert_eq!(
+            response.headers()[header::CONTENT_LENGTH],
+            LARGE_SIZE.to_string().as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn head_unknown_size() {
+        let response = head(TestCache::fetching("")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::CONTENT_LENGTH).is_none());
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode() {
+        let mut config = GatewayConfig::default();
+        config.server.maintenance = true;
+        config.server.maintenance_message = "back soon".into();
+        let config = Arc::new(RwLock::new(config));
+        // maintenance mode neither fetches nor resolves sizes
+        let cache = Arc::new(RwLock::new(TestCache {
+            cached: Some(b"data".to_vec()),
+            ..Default::default()
+        }));
+        let missed = Cid::new_v1(0x55, Code::Sha2_256.digest(b"missed")).to_string();
+
+        for (cid, status) in [
+            (RAW_CID.to_string(), StatusCode::OK),
+            (missed, StatusCode::SERVICE_UNAVAILABLE),
+        ] {
+            let response = get_car_handler(
+                Path(cid.clone()),
+                Query(ContentQuery::default()),
+                RawQuery(None),
+                None,
+                None,
+                HeaderMap::new(),
+                Extension(Arc::clone(&cache)),
+                Extension(Arc::clone(&config)),
+            )
+            .await;
+            assert_eq!(response.status(), status);
+            let response = head_car_handler(
+                Path(cid),
+                Query(ContentQuery::default()),
+                RawQuery(None),
+                None,
+                None,
+                HeaderMap::new(),
+                Extension(Arc::clone(&cache)),
+                Extension(Arc::clone(&config)),
+            )
+            .await;
+            assert_eq!(response.status(), status);
+            if status != StatusCode::OK {
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                let body: Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["message"], "back soon");
+            }
+        }
+    }
+
+    fn push_varint(mut n: usize, out: &mut Vec<u8>) {
+        while n >= 0x80 {
+            out.push((n as u8 & 0x7f) | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+    }
+
+    /// Car of a UnixFS directory linking a file under each of `names`,
+    /// with the file cid.
+    fn directory_car(names: &[&str]) -> (Cid, Cid, Vec<u8>) {
+        let file = Cid::new_v1(0x55, Code::Sha2_256.digest(b"file"));
+        let links = names
+            .iter()
+            .map(|name| ipld!({ "Hash": file, "Name": *name, "Tsize": 4 }))
+            .collect();
+        let node = ipld!({ "Data": Ipld::Bytes(vec![0x08, 0x01]), "Links": Ipld::List(links) });
+        let block = DagPbCodec.encode(&node).unwrap();
+        let root = Cid::new_v1(DAG_PB, Code::Sha2_256.digest(&block));
+        let header = DagCborCodec
+            .encode(&ipld!({ "roots": [root], "version": 1 }))
+            .unwrap();
+
+        let mut car = vec![];
+        push_varint(header.len(), &mut car);
+        car.extend(header);
+        let cid = root.to_bytes();
+        push_varint(cid.len() + block.len(), &mut car);
+        car.extend(cid);
+        car.extend(block);
+        (root, file, car)
+    }
+
+    async fn get_listing(cid: Cid, car: Vec<u8>, accept: &str, enabled: bool) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+        let mut config = GatewayConfig::default();
+        config.server.directory_listing = enabled;
+        get_car_handler(
+            Path(cid.to_string()),
+            Query(ContentQuery::default()),
+            RawQuery(None),
+            None,
+            None,
+            headers,
+            Extension(Arc::new(RwLock::new(TestCache::fetching(car)))),
+            Extension(Arc::new(RwLock::new(config))),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn directory_listing_html() {
+        let (root, file, car) = directory_car(&["a.txt", "<b>"]);
+        let response = get_listing(root, car, "text/html,*/*;q=0.8", true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(content_type(&response), "text/html; charset=utf-8");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(&format!("<a href=\"{file}\">a.txt</a>")));
+        assert!(body.contains("&lt;b&gt;"));
+        assert!(!body.contains("<b>"));
+    }
+
+    #[tokio::test]
+    async fn directory_listing_json() {
+        let (root, file, car) = directory_car(&["a.txt", "b.txt"]);
+        let response = get_listing(root, car, "application/json", true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(content_type(&response), "application/json");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "cid": root.to_string(),
+                "entries": [
+                    { "name": "a.txt", "cid": file.to_string(), "size": 4 },
+                    { "name": "b.txt", "cid": file.to_string(), "size": 4 },
+                ],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_listing_disabled() {
+        let (root, _, car) = directory_car(&["a.txt"]);
+        let response = get_listing(root, car, "text/html", false).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn directory_listing_served_as_car() {
+        // a directory with an index, a car asked for and content that isn't a car
+        let (root, _, with_index) = directory_car(&["index.html"]);
+        let (listed, _, car) = directory_car(&["a.txt"]);
+        let raw = Cid::from_str(RAW_CID).unwrap();
+        for (cid, car, accept) in [
+            (root, with_index, "text/html"),
+            (listed, car, "application/vnd.ipld.car"),
+            (raw, b"data".to_vec(), "application/json"),
+        ] {
+            let response = get_listing(cid, car.clone(), accept, true).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(content_type(&response), CAR_CONTENT_TYPE);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, car);
+        }
+    }
+
+    #[tokio::test]
+    async fn digest_and_roots_headers() {
+        let (root, _, car) = directory_car(&["index.html"]);
+        let other = Cid::new_v1(0x55, Code::Sha2_256.digest(b"other"));
+        let mut config = GatewayConfig::default();
+        config.server.digest_header = true;
+        config.server.roots_header = true;
+        let config = Arc::new(RwLock::new(config));
+        let response = get_car_handler(
+            Path(other.to_string()),
+            Query(ContentQuery::default()),
+            RawQuery(None),
+            None,
+            None,
+            HeaderMap::new(),
+            Extension(Arc::new(RwLock::new(TestCache::fetching(car.clone())))),
+            Extension(Arc::clone(&config)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(X_IPFS_ROOTS).unwrap(),
+            &format!("{other},{root}")
+        );
+        // the length is known, the digest isn't left to a trailer
+        assert_eq!(
+            response.headers().get(DIGEST).unwrap(),
+            digest::digest(&car)
+        );
+        assert!(response.headers().get(header::TRAILER).is_none());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, car);
+
+        // larger content is streamed without a digest
+        let large = vec![0; MAX_DIGEST_SIZE as usize + 1];
+        let response = get_car_handler(
+            Path(other.to_string()),
+            Query(ContentQuery::default()),
+            RawQuery(None),
+            None,
+            None,
+            HeaderMap::new(),
+            Extension(Arc::new(RwLock::new(TestCache::fetching(large.clone())))),
+            Extension(Arc::clone(&config)),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(DIGEST).is_none());
+        assert!(response.headers().get(header::TRAILER).is_none());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body.len(), large.len());
+    }
+
+    async fn get_range(range: &str, if_range: Option<&str>) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(range).unwrap());
+        if let Some(if_range) = if_range {
+            headers.insert(header::IF_RANGE, HeaderValue::from_str(if_range).unwrap());
+        }
+        get_car_handler(
+            Path(RAW_CID.to_string()),
+            Query(ContentQuery::default()),
+            RawQuery(None),
+            None,
+            None,
+            headers,
+            Extension(Arc::new(RwLock::new(TestCache::fetching("0123456789")))),
+            Extension(Arc::new(RwLock::new(GatewayConfig::default()))),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn range_requests() {
+        for (range, content_range, expected) in [
+            ("bytes=2-4", "bytes 2-4/10", "234"),
+            ("bytes=7-", "bytes 7-9/10", "789"),
+            ("bytes=-3", "bytes 7-9/10", "789"),
+            ("bytes=8-20, 0-1", "bytes 8-9/10", "89"),
+        ] {
+            let response = get_range(range, None).await;
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{range}");
+            let headers = response.headers();
+            assert_eq!(headers[header::CONTENT_RANGE], content_range);
+            assert_eq!(headers[header::CONTENT_LENGTH], expected.len().to_string());
+            assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, expected);
+        }
+
+        let response = get_range("bytes=10-", None).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+
+        // invalid ranges are ignored
+        let response = get_range("bytes=4-2", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "0123456789");
+    }
+
+    #[tokio::test]
+    async fn if_range_requests() {
+        let response = get_range("bytes=2-4", Some(&format!("\"{RAW_CID}\""))).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "234");
+
+        // another etag, a weak one or a date get the full content
+        for if_range in [
+            "\"bafkreiother\"".to_string(),
+            format!("W/\"{RAW_CID}\""),
+            "Wed, 21 Oct 2015 07:28:00 GMT".to_string(),
+        ] {
+            let response = get_range("bytes=2-4", Some(&if_range)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{if_range}");
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, "0123456789");
+        }
+    }
+
+    async fn get_if_none_match<Cache: ServerCache>(cache: Cache, etag: &str) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(etag).unwrap());
+        get_car_handler(
+            Path(RAW_CID.to_string()),
+            Query(ContentQuery::default()),
+            RawQuery(None),
+            None,
+            None,
+            headers,
+            Extension(Arc::new(RwLock::new(cache))),
+            Extension(Arc::new(RwLock::new(GatewayConfig::default()))),
+        )
+        .await
+    }
+
+    /// Keys of the requests of `cid` with each of `queries`.
+    async fn requested_keys(cid: &str, queries: &[&str], params: &[&str]) -> Vec<String> {
+        let cache = Arc::new(RwLock::new(TestCache::fetching("data")));
+        let mut config = GatewayConfig::default();
+        config.server.cache_key_params = params.iter().map(|p| p.to_string()).collect();
+        let config = Arc::new(RwLock::new(config));
+        for query in queries {
+            let response = get_car_handler(
+                Path(cid.to_string()),
+                Query(ContentQuery::default()),
+                RawQuery(Some(query.to_string())),
+                None,
+                None,
+                HeaderMap::new(),
+                Extension(Arc::clone(&cache)),
+                Extension(Arc::clone(&config)),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK, "{query}");
+        }
+        let keys = cache.read().await.keys.lock().unwrap().clone();
+        keys
+    }
+
+    #[tokio::test]
+    async fn query_does_not_fragment_cache() {
+        let keys = requested_keys(
+            RAW_CID,
+            &["", "utm_source=x", "a=1&b=2", "b=2&a=1", "v=1"],
+            &[],
+        )
+        .await;
+        assert!(keys.iter().all(|key| key == RAW_CID), "{keys:?}");
+
+        // the fragment is not part of the cid
+        let keys = requested_keys(&format!("{RAW_CID}#section"), &[""], &[]).await;
+        assert_eq!(keys, [RAW_CID]);
+    }
+
+    #[tokio::test]
+    async fn configured_query_params_in_cache_key() {
+        let keys = requested_keys(
+            RAW_CID,
+            &["v=1", "utm_source=x&v=1", "v=2", "utm_source=x"],
+            &["v"],
+        )
+        .await;
+        assert_eq!(
+            keys,
+            [
+                format!("{RAW_CID}?v=1"),
+                format!("{RAW_CID}?v=1"),
+                format!("{RAW_CID}?v=2"),
+                RAW_CID.to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn etag() {
+        let etag = format!("\"{RAW_CID}\"");
+        let response = get_with(ContentQuery::default()).await;
+        assert_eq!(response.headers()[header::ETAG], etag);
+        let response = head(TestCache::fetching("")).await;
+        assert_eq!(response.headers()[header::ETAG], etag);
+    }
+
+    #[tokio::test]
+    async fn if_none_match_matched() {
+        let etag = format!("\"{RAW_CID}\"");
+        for if_none_match in [
+            etag.clone(),
+            format!("\"other\", W/{etag}"),
+            "*".to_string(),
+        ] {
+            // answered without reaching the cache
+            let response = get_if_none_match(TestCache::default(), &if_none_match).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(response.headers()[header::ETAG], etag);
+            assert_eq!(
+                response.headers()[header::CACHE_CONTROL],
+                format!(
+                    "public, max-age={}, immutable",
+                    GatewayConfig::default().server.cache_control_max_age
+                )
+            );
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert!(body.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn if_none_match_mismatched() {
+        // unquoted, or the etag of another cid
+        for if_none_match in [RAW_CID.to_string(), "\"bafkreiother\"".to_string()] {
+            let response = get_if_none_match(TestCache::fetching("data"), &if_none_match).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::ETAG], format!("\"{RAW_CID}\""));
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, "data");
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_error_reason() {
+        let response = cache_error(Error::Fetch {
+            reason: FetchFailure::NoProviders,
+            status: StatusCode::NOT_FOUND,
+            message: "no provider".into(),
+        });
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[X_URSA_FETCH_ERROR], "no-providers");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["reason"], "no-providers");
+
+        let response = cache_error(Error::Internal("internal".into()));
+        assert!(response.headers().get(X_URSA_FETCH_ERROR).is_none());
+    }
+}
@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use super::model::HttpResponse;
+use crate::util::error::{FetchFailure, X_URSA_FETCH_ERROR};
+
+/// Request header overriding the `request_timeout` of a request, in ms.
+pub const X_URSA_TIMEOUT_MS: &str = "x-ursa-timeout-ms";
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub default: Duration,
+    /// bound of the timeouts asked for in [`X_URSA_TIMEOUT_MS`]
+    pub max: Duration,
+}
+
+impl Timeouts {
+    /// Fails for a `max` below `default`, the requests not overriding their timeout
+    /// would get more than the others can ask for.
+    pub fn new(default: Duration, max: Duration) -> anyhow::Result<Self> {
+        if max < default {
+            anyhow::bail!(
+                "max_request_timeout of {}ms is below the request_timeout of {}ms",
+                max.as_millis(),
+                default.as_millis()
+            );
+        }
+        Ok(Self { default, max })
+    }
+
+    /// Timeout of a request, the one it asks for clamped to `max`, or `default`.
+    fn of(&self, headers: &HeaderMap) -> Result<Duration, String> {
+        let Some(value) = headers.get(X_URSA_TIMEOUT_MS) else {
+            return Ok(self.default);
+        };
+        let ms = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .filter(|ms| *ms > 0)
+            .ok_or_else(|| {
+                format!("Invalid {X_URSA_TIMEOUT_MS} {value:?}, expected a positive number of ms")
+            })?;
+        Ok(Duration::from_millis(ms).min(self.max))
+    }
+}
+
+/// Answer `408 Request Timeout` to the requests not responded to within their timeout,
+/// with the `timeout` fetch error reason.
+pub async fn request_timeout<B>(
+    State(timeouts): State<Timeouts>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let duration = match timeouts.of(req.headers()) {
+        Ok(duration) => duration,
+        Err(message) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!(HttpResponse {
+                    message: Some(message),
+                    reason: None,
+                })),
+            )
+                .into_response()
+        }
+    };
+    match tokio::time::timeout(duration, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => (
+            StatusCode::REQUEST_TIMEOUT,
+            [(X_URSA_FETCH_ERROR, FetchFailure::Timeout.as_str())],
+            Json(json!(HttpResponse {
+                message: Some(format!(
+                    "The request didn't complete within {}ms",
+                    duration.as_millis()
+                )),
+                reason: Some(FetchFailure::Timeout.as_str().to_string()),
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tokio::time::sleep;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const TIMEOUTS: Timeouts = Timeouts {
+        default: Duration::from_millis(50),
+        max: Duration::from_millis(200),
+    };
+
+    fn headers(timeout: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_URSA_TIMEOUT_MS, timeout.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn max_below_default() {
+        assert!(Timeouts::new(Duration::from_millis(50), Duration::from_millis(49)).is_err());
+        assert!(Timeouts::new(Duration::from_millis(50), Duration::from_millis(50)).is_ok());
+    }
+
+    #[test]
+    fn clamped_override() {
+        assert_eq!(TIMEOUTS.of(&HeaderMap::new()), Ok(TIMEOUTS.default));
+        assert_eq!(TIMEOUTS.of(&headers("10")), Ok(Duration::from_millis(10)));
+        assert_eq!(
+            TIMEOUTS.of(&headers(" 100 ")),
+            Ok(Duration::from_millis(100))
+        );
+        assert_eq!(TIMEOUTS.of(&headers("60000")), Ok(TIMEOUTS.max));
+        for invalid in ["0", "-1", "1.5", "soon", ""] {
+            assert!(TIMEOUTS.of(&headers(invalid)).is_err(), "{invalid}");
+        }
+    }
+
+    /// Status of a request taking 100ms with the `timeout` header.
+    async fn status(timeout: Option<&str>) -> StatusCode {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    sleep(Duration::from_millis(100)).await;
+                    "done"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(TIMEOUTS, request_timeout));
+        let mut request = Request::builder().uri("/");
+        if let Some(timeout) = timeout {
+            request = request.header(X_URSA_TIMEOUT_MS, timeout);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn override_applied() {
+        assert_eq!(status(None).await, StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(status(Some("150")).await, StatusCode::OK);
+        assert_eq!(status(Some("10")).await, StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(status(Some("abc")).await, StatusCode::BAD_REQUEST);
+
+        // clamped to the 200ms max
+        let slow = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    sleep(Duration::from_millis(400)).await;
+                    "done"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(TIMEOUTS, request_timeout));
+        let request = Request::builder()
+            .uri("/")
+            .header(X_URSA_TIMEOUT_MS, "1000")
+            .body(Body::empty())
+            .unwrap();
+        let response = slow.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        // the reason is in the header and the body
+        assert_eq!(response.headers()[X_URSA_FETCH_ERROR], "timeout");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: HttpResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.reason.as_deref(), Some("timeout"));
+        assert_eq!(
+            body.message.as_deref(),
+            Some("The request didn't complete within 200ms")
+        );
+    }
+}
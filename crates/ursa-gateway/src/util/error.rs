@@ -1,9 +1,64 @@
 use axum::http::StatusCode;
 
-#[derive(Debug)]
+/// Header telling the client why a fetch failed.
+pub const X_URSA_FETCH_ERROR: &str = "x-ursa-fetch-error";
+
+#[derive(Debug, Clone)]
 pub enum Error {
     Upstream(StatusCode, String),
     Internal(String),
+    /// Failed fetch of a content, with the stage that failed.
+    Fetch {
+        reason: FetchFailure,
+        status: StatusCode,
+        message: String,
+    },
+}
+
+/// Stage of the fetch path that failed, reported in [`X_URSA_FETCH_ERROR`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchFailure {
+    /// No router found a provider of the content.
+    NoProviders,
+    /// None of the providers served the content.
+    ProvidersUnreachable,
+    /// Providers served content not matching the cid.
+    VerificationFailed,
+    /// The upstream requests allowed for the content were used up.
+    RetryBudgetExhausted,
+    /// The request didn't complete within the request timeout.
+    Timeout,
+}
+
+impl FetchFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FetchFailure::NoProviders => "no-providers",
+            FetchFailure::ProvidersUnreachable => "providers-unreachable",
+            FetchFailure::VerificationFailed => "verification-failed",
+            FetchFailure::RetryBudgetExhausted => "retry-budget-exhausted",
+            FetchFailure::Timeout => "timeout",
+        }
+    }
+}
+
+impl Error {
+    /// Attribute the error to a fetch stage, keeping the reason of errors already attributed.
+    pub fn with_reason(self, reason: FetchFailure) -> Self {
+        match self {
+            Error::Upstream(status, message) => Error::Fetch {
+                reason,
+                status,
+                message,
+            },
+            Error::Internal(message) => Error::Fetch {
+                reason,
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message,
+            },
+            error @ Error::Fetch { .. } => error,
+        }
+    }
 }
 
 impl From<anyhow::Error> for Error {
@@ -1,11 +1,48 @@
-use super::Cache;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use futures::{future::BoxFuture, FutureExt};
+use libipld::Cid;
+use tokio::sync::oneshot;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use super::{stats::CacheStatsSnapshot, Cache, CacheCommand};
 
 pub trait AdminCache: Send + Sync + 'static {
-    fn purge(&mut self);
+    /// Empty the memory tier, returning the purge of the disk tier to await once the cache is released.
+    fn purge(&mut self) -> BoxFuture<'static, ()>;
+    /// Have the worker remove the entries of `cid`, the receiver resolves to whether any was cached.
+    fn purge_cid(&self, cid: Cid) -> Result<oneshot::Receiver<Result<bool>>>;
+    fn stats(&self) -> CacheStatsSnapshot;
 }
 
 impl AdminCache for Cache {
-    fn purge(&mut self) {
+    fn purge(&mut self) -> BoxFuture<'static, ()> {
         self.tlrfu.purge();
+        self.stats.set_size(0, 0);
+        let disk = self.disk.as_ref().map(Arc::clone);
+        async move {
+            if let Some(disk) = disk {
+                disk.purge().await;
+            }
+        }
+        .boxed()
+    }
+
+    fn purge_cid(&self, cid: Cid) -> Result<oneshot::Receiver<Result<bool>>> {
+        let (sender, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCommand::Purge {
+                cid,
+                sender,
+                ctx: Span::current().context(),
+            })
+            .map_err(|e| anyhow!("Failed to dispatch Purge command: {e:?}"))?;
+        Ok(rx)
+    }
+
+    fn stats(&self) -> CacheStatsSnapshot {
+        self.stats.snapshot()
     }
 }
@@ -0,0 +1,551 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use libipld::multihash::{Hasher, Sha2_256};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use tracing::warn;
+
+use super::key::cid_of;
+use crate::util::timer::now;
+
+const TMP_EXTENSION: &str = "tmp";
+
+/// Tag files start with, before the ttl of their entry. Files without it are of a
+/// previous format, and dropped.
+const MAGIC: &[u8; 8] = b"ursattl1";
+const HEADER_SIZE: usize = MAGIC.len() + 16;
+
+/// Second tier of the cache: the entries evicted from memory are kept as files
+/// under `dir`, named after the sha-256 of their cid and of their key. Each file
+/// starts with the ttl of the entry, as nanoseconds since the unix epoch, expired
+/// entries are removed instead of served. Past `max_bytes`, the files written first are removed.
+pub struct DiskCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    files: Mutex<Files>,
+    tmp_seq: AtomicU64,
+}
+
+struct Entry {
+    size: u64,
+    ttl: u128,
+}
+
+#[derive(Default)]
+struct Files {
+    entries: HashMap<String, Entry>,
+    /// oldest written first
+    order: VecDeque<String>,
+    used: u64,
+    /// bumped on every removal, writes started before are dropped
+    generation: u64,
+}
+
+impl Files {
+    fn insert(&mut self, name: String, size: u64, ttl: u128) {
+        self.used += size;
+        self.entries.insert(name.clone(), Entry { size, ttl });
+        self.order.push_back(name);
+    }
+
+    fn remove(&mut self, name: &str) {
+        if let Some(entry) = self.entries.remove(name) {
+            self.used -= entry.size;
+            self.order.retain(|n| n != name);
+        }
+    }
+
+    /// Whether `name` is on disk and not expired at `now`.
+    fn is_live(&self, name: &str, now: u128) -> bool {
+        self.entries
+            .get(name)
+            .map_or(false, |entry| entry.ttl > now)
+    }
+
+    /// Files to remove to fit `size` more bytes under `max_bytes`.
+    fn make_room(&mut self, size: u64, max_bytes: u64) -> Vec<String> {
+        let mut removed = vec![];
+        while self.used + size > max_bytes {
+            let Some(name) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&name) {
+                self.used -= entry.size;
+            }
+            removed.push(name);
+        }
+        removed
+    }
+}
+
+impl DiskCache {
+    /// Open the disk tier at `dir`, keeping the files written by a previous run
+    /// and dropping the expired entries and the interrupted writes.
+    pub async fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to create disk cache dir: {dir:?}"))?;
+        let mut entries = fs::read_dir(&dir)
+            .await
+            .with_context(|| format!("Failed to read disk cache dir: {dir:?}"))?;
+        let now = now_nanos();
+        let mut found = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().map_or(false, |ext| ext == TMP_EXTENSION) {
+                let _ = fs::remove_file(&path).await;
+                continue;
+            }
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+            match read_ttl(&path).await {
+                Some(ttl) if ttl > now => {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    let size = metadata.len() - HEADER_SIZE as u64;
+                    found.push((metadata.modified()?, name, size, ttl));
+                }
+                _ => {
+                    let _ = fs::remove_file(&path).await;
+                }
+            }
+        }
+        found.sort();
+
+        let mut files = Files::default();
+        for (_, name, size, ttl) in found {
+            files.insert(name, size, ttl);
+        }
+        let removed = files.make_room(0, max_bytes);
+        let cache = Self {
+            dir,
+            max_bytes,
+            files: Mutex::new(files),
+            tmp_seq: AtomicU64::new(0),
+        };
+        cache.remove_files(removed).await;
+        Ok(cache)
+    }
+
+    /// To pass to [`DiskCache::write`] when the entry to write is taken.
+    pub fn generation(&self) -> u64 {
+        self.files.lock().unwrap().generation
+    }
+
+    /// Whether the entry is on disk and not expired.
+    pub fn contains(&self, key: &str) -> bool {
+        self.files
+            .lock()
+            .unwrap()
+            .is_live(&file_name(key), now_nanos())
+    }
+
+    /// Content of the entry with its ttl, `None` if it isn't on disk or expired.
+    pub async fn read(&self, key: &str) -> Option<(Bytes, u128)> {
+        let name = file_name(key);
+        let now = now_nanos();
+        let live = {
+            let mut files = self.files.lock().unwrap();
+            if !files.entries.contains_key(&name) {
+                return None;
+            }
+            let live = files.is_live(&name, now);
+            if !live {
+                files.remove(&name);
+            }
+            live
+        };
+        if !live {
+            self.remove_files(vec![name]).await;
+            return None;
+        }
+        match fs::read(self.dir.join(&name)).await {
+            Ok(bytes) => match parse_header(&bytes) {
+                Some(ttl) => Some((Bytes::from(bytes).slice(HEADER_SIZE..), ttl)),
+                None => {
+                    warn!("[DiskCache]: Entry {key} is missing its ttl");
+                    self.files.lock().unwrap().remove(&name);
+                    self.remove_files(vec![name]).await;
+                    None
+                }
+            },
+            Err(e) => {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("[DiskCache]: Failed to read entry {key}: {e:?}");
+                }
+                self.files.lock().unwrap().remove(&name);
+                None
+            }
+        }
+    }
+
+    /// Write the entry expiring at `ttl` unless it is already on disk. The content
+    /// is written to a temporary file renamed into place, so concurrent writes of
+    /// the same entry never leave a partial file.
+    ///
+    /// The write is dropped if entries were removed since `generation`, so a
+    /// write in flight doesn't bring back a purged entry.
+    pub async fn write(&self, key: &str, value: &[u8], ttl: u128, generation: u64) -> Result<()> {
+        let name = file_name(key);
+        let size = value.len() as u64;
+        let now = now_nanos();
+        if size > self.max_bytes || ttl <= now || self.files.lock().unwrap().is_live(&name, now) {
+            return Ok(());
+        }
+        let path = self.dir.join(&name);
+        let tmp = self.dir.join(format!(
+            "{name}.{}.{TMP_EXTENSION}",
+            self.tmp_seq.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = async {
+            let mut file = fs::File::create(&tmp).await?;
+            file.write_all(MAGIC).await?;
+            file.write_all(&ttl.to_be_bytes()).await?;
+            file.write_all(value).await?;
+            file.flush().await
+        };
+        if let Err(e) = written.await {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(e).with_context(|| format!("Failed to write disk cache entry: {tmp:?}"));
+        }
+        fs::rename(&tmp, &path)
+            .await
+            .with_context(|| format!("Failed to move disk cache entry to: {path:?}"))?;
+
+        let removed = {
+            let mut files = self.files.lock().unwrap();
+            if files.is_live(&name, now) {
+                // written concurrently
+                return Ok(());
+            }
+            if files.generation != generation {
+                drop(files);
+                self.remove_files(vec![name]).await;
+                return Ok(());
+            }
+            // replaces the expired entry, if any
+            files.remove(&name);
+            let removed = files.make_room(size, self.max_bytes);
+            files.insert(name, size, ttl);
+            removed
+        };
+        self.remove_files(removed).await;
+        Ok(())
+    }
+
+    /// Remove every entry of `cid`, `false` if none was on disk.
+    pub async fn remove_cid(&self, cid: &str) -> bool {
+        let prefix = format!("{}-", sha256(cid));
+        let removed: Vec<_> = {
+            let mut files = self.files.lock().unwrap();
+            files.generation += 1;
+            let removed: Vec<_> = files
+                .order
+                .iter()
+                .filter(|name| name.starts_with(&prefix))
+                .cloned()
+                .collect();
+            for name in &removed {
+                files.remove(name);
+            }
+            removed
+        };
+        let found = !removed.is_empty();
+        self.remove_files(removed).await;
+        found
+    }
+
+    /// Remove every entry.
+    pub async fn purge(&self) {
+        let removed = {
+            let mut files = self.files.lock().unwrap();
+            let removed = files.order.drain(..).collect();
+            *files = Files {
+                generation: files.generation + 1,
+                ..Default::default()
+            };
+            removed
+        };
+        self.remove_files(removed).await;
+    }
+
+    async fn remove_files(&self, names: Vec<String>) {
+        for name in names {
+            if let Err(e) = fs::remove_file(self.dir.join(&name)).await {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("[DiskCache]: Failed to remove entry {name}: {e:?}");
+                }
+            }
+        }
+    }
+}
+
+/// Ttl of the entry in `file`, `None` if the file doesn't start with one.
+fn parse_header(file: &[u8]) -> Option<u128> {
+    let header = file.get(..HEADER_SIZE)?;
+    if !header.starts_with(MAGIC) {
+        return None;
+    }
+    let mut ttl = [0; 16];
+    ttl.copy_from_slice(&header[MAGIC.len()..]);
+    Some(u128::from_be_bytes(ttl))
+}
+
+/// Ttl of the entry in the file at `path`, `None` if it can't be read.
+async fn read_ttl(path: &Path) -> Option<u128> {
+    let mut file = fs::File::open(path).await.ok()?;
+    let mut header = [0; HEADER_SIZE];
+    file.read_exact(&mut header).await.ok()?;
+    parse_header(&header)
+}
+
+/// Nanoseconds since the unix epoch, as the ttls of the entries.
+fn now_nanos() -> u128 {
+    now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_nanos())
+}
+
+/// `{sha-256 of the cid}-{sha-256 of the key}`, to find the entries of a cid.
+fn file_name(key: &str) -> String {
+    format!("{}-{}", sha256(&cid_of(key)), sha256(key))
+}
+
+fn sha256(value: &str) -> String {
+    let mut hasher = Sha2_256::default();
+    hasher.update(value.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .fold(String::with_capacity(64), |mut name, byte| {
+            let _ = write!(name, "{byte:02x}");
+            name
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::util::timer::{clear_mock_time, set_mock_time};
+
+    const TTL: u128 = u128::MAX;
+
+    fn dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ursa-gateway-disk-{name}-{}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn write_read() {
+        let dir = dir("write-read");
+        let cache = DiskCache::open(&dir, 1024).await.unwrap();
+        assert!(cache.read("a").await.is_none());
+
+        cache
+            .write("a", b"data", TTL, cache.generation())
+            .await
+            .unwrap();
+        assert!(cache.contains("a"));
+        assert_eq!(
+            cache.read("a").await.unwrap().0,
+            Bytes::from_static(b"data")
+        );
+        // already on disk, kept as is
+        cache
+            .write("a", b"other", TTL, cache.generation())
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.read("a").await.unwrap().0,
+            Bytes::from_static(b"data")
+        );
+
+        // kept across restarts, without the interrupted writes
+        fs::write(dir.join(format!("{}.0.tmp", file_name("b"))), b"partial")
+            .await
+            .unwrap();
+        drop(cache);
+        let cache = DiskCache::open(&dir, 1024).await.unwrap();
+        assert_eq!(
+            cache.read("a").await.unwrap().0,
+            Bytes::from_static(b"data")
+        );
+        assert!(cache.read("b").await.is_none());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        cache
+            .write("b", b"data", TTL, cache.generation())
+            .await
+            .unwrap();
+        assert!(cache.remove_cid("b").await);
+        assert!(!cache.remove_cid("b").await);
+        assert!(cache.read("b").await.is_none());
+
+        cache.purge().await;
+        assert!(cache.read("a").await.is_none());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired() {
+        let dir = dir("expired");
+        let start = SystemTime::now();
+        set_mock_time(start);
+        let ttl = (start + Duration::from_secs(1))
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let cache = DiskCache::open(&dir, 1024).await.unwrap();
+        cache
+            .write("a", b"data", ttl, cache.generation())
+            .await
+            .unwrap();
+        cache
+            .write("b", b"data", TTL, cache.generation())
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.read("a").await.unwrap(),
+            (Bytes::from_static(b"data"), ttl)
+        );
+
+        // expired entries are dropped on restart, with the files of a previous format
+        set_mock_time(start + Duration::from_secs(2));
+        fs::write(dir.join(file_name("c")), b"without a ttl")
+            .await
+            .unwrap();
+        drop(cache);
+        let cache = DiskCache::open(&dir, 1024).await.unwrap();
+        assert!(!cache.contains("a"));
+        assert!(cache.contains("b"));
+        assert!(!cache.contains("c"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        // and aren't written
+        cache
+            .write("a", b"data", ttl, cache.generation())
+            .await
+            .unwrap();
+        assert!(!cache.contains("a"));
+
+        // or once read
+        set_mock_time(start);
+        cache
+            .write("a", b"data", ttl, cache.generation())
+            .await
+            .unwrap();
+        assert!(cache.contains("a"));
+        set_mock_time(start + Duration::from_secs(2));
+        assert!(cache.read("a").await.is_none());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // an expired entry is replaced by a fresh write
+        set_mock_time(start);
+        cache
+            .write("a", b"data", ttl, cache.generation())
+            .await
+            .unwrap();
+        set_mock_time(start + Duration::from_secs(2));
+        cache
+            .write("a", b"new", TTL, cache.generation())
+            .await
+            .unwrap();
+        assert_eq!(cache.read("a").await.unwrap().0, Bytes::from_static(b"new"));
+        assert_eq!(cache.files.lock().unwrap().used, 7);
+        clear_mock_time();
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn remove_cid() {
+        let dir = dir("remove-cid");
+        let cache = DiskCache::open(&dir, 1024).await.unwrap();
+        for key in ["a", "a/b", "a?v=1", "a/b;gzip", "ab"] {
+            cache
+                .write(key, b"data", TTL, cache.generation())
+                .await
+                .unwrap();
+        }
+        // taken before the removal, written after
+        let generation = cache.generation();
+
+        assert!(cache.remove_cid("a").await);
+        for key in ["a", "a/b", "a?v=1", "a/b;gzip"] {
+            assert!(!cache.contains(key), "{key}");
+        }
+        assert!(cache.contains("ab"));
+
+        cache.write("a/c", b"data", TTL, generation).await.unwrap();
+        assert!(!cache.contains("a/c"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn max_bytes() {
+        let dir = dir("max-bytes");
+        let cache = DiskCache::open(&dir, 8).await.unwrap();
+        cache
+            .write("a", b"aaaa", TTL, cache.generation())
+            .await
+            .unwrap();
+        cache
+            .write("b", b"bbbb", TTL, cache.generation())
+            .await
+            .unwrap();
+        cache
+            .write("c", b"cccc", TTL, cache.generation())
+            .await
+            .unwrap();
+        // larger than the whole tier
+        cache
+            .write("d", b"ddddddddd", TTL, cache.generation())
+            .await
+            .unwrap();
+
+        assert!(!cache.contains("a"));
+        assert!(cache.contains("b"));
+        assert!(cache.contains("c"));
+        assert!(!cache.contains("d"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes() {
+        let dir = dir("concurrent");
+        let cache = std::sync::Arc::new(DiskCache::open(&dir, 1 << 20).await.unwrap());
+        let value = vec![7u8; 64 * 1024];
+        let writes: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, value) = (cache.clone(), value.clone());
+                tokio::spawn(async move { cache.write("a", &value, TTL, cache.generation()).await })
+            })
+            .collect();
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+
+        assert_eq!(cache.read("a").await.unwrap().0.as_ref(), &value[..]);
+        assert_eq!(cache.files.lock().unwrap().used, value.len() as u64);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
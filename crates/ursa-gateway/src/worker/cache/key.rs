@@ -0,0 +1,186 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use libipld::Cid;
+
+/// Key of a cached response.
+///
+/// Composed of every request attribute that changes the response body:
+/// - `cid`: the requested root
+/// - `path`: the resolved path under the root, normalized so that
+///   `a/b`, `/a/b/` and `a//b` share an entry
+/// - `query`: the configured `cache_key_params` of the query string, sorted so
+///   their order doesn't matter, every other param is ignored
+/// - `encoding`: the content encoding of the stored body
+///
+/// Byte ranges are deliberately left out, a range is a slice of the same
+/// cached body and is served from the full entry.
+///
+/// The string form is `{cid}[/{path}][?{query}][;{encoding}]`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub cid: String,
+    pub path: Option<String>,
+    pub query: Option<String>,
+    pub encoding: Option<String>,
+}
+
+impl CacheKey {
+    pub fn new(cid: impl Into<String>) -> Self {
+        Self {
+            cid: cid.into(),
+            path: None,
+            query: None,
+            encoding: None,
+        }
+    }
+
+    /// Keep the `params` pairs of the raw `query` string in the key.
+    pub fn with_query(self, query: Option<&str>, params: &[String]) -> Self {
+        let mut pairs: Vec<_> = query
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+                params.iter().any(|param| param == name)
+            })
+            .collect();
+        pairs.sort_unstable();
+        Self {
+            query: (!pairs.is_empty()).then(|| pairs.join("&")),
+            ..self
+        }
+    }
+}
+
+/// Cid of the string form of a key, whatever its path, query and encoding,
+/// in its canonical form if it parses.
+pub fn cid_of(key: &str) -> String {
+    let end = key.find(&['/', '?', ';'][..]).unwrap_or(key.len());
+    let cid = &key[..end];
+    Cid::from_str(cid).map_or_else(|_| cid.to_string(), |cid| cid.to_string())
+}
+
+impl Display for CacheKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.cid)?;
+        if let Some(path) = &self.path {
+            for segment in path.split('/').filter(|s| !s.is_empty()) {
+                write!(f, "/{segment}")?;
+            }
+        }
+        if let Some(query) = &self.query {
+            write!(f, "?{query}")?;
+        }
+        if let Some(encoding) = &self.encoding {
+            write!(f, ";{encoding}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CID: &str = "bafybeifyjj2bjhtxmp235vlfeeiy7sz6rzyx3lervfk3ap2nyn4rggqgei";
+
+    fn with_path(path: &str) -> CacheKey {
+        CacheKey {
+            path: Some(path.into()),
+            ..CacheKey::new(CID)
+        }
+    }
+
+    #[test]
+    fn cid_only() {
+        assert_eq!(CacheKey::new(CID).to_string(), CID);
+        assert_eq!(with_path("").to_string(), CID);
+        assert_eq!(with_path("/").to_string(), CID);
+    }
+
+    #[test]
+    fn different_paths_do_not_collide() {
+        assert_ne!(with_path("a").to_string(), with_path("b").to_string());
+        assert_ne!(with_path("a/b").to_string(), with_path("a").to_string());
+        assert_ne!(with_path("a/b").to_string(), CacheKey::new(CID).to_string());
+    }
+
+    #[test]
+    fn same_resolved_path_shares_key() {
+        let key = with_path("a/b").to_string();
+        assert_eq!(key, format!("{CID}/a/b"));
+        assert_eq!(with_path("/a/b/").to_string(), key);
+        assert_eq!(with_path("a//b").to_string(), key);
+    }
+
+    #[test]
+    fn encoding() {
+        let gzip = CacheKey {
+            encoding: Some("gzip".into()),
+            ..with_path("a")
+        };
+        assert_eq!(gzip.to_string(), format!("{CID}/a;gzip"));
+        assert_ne!(gzip.to_string(), with_path("a").to_string());
+    }
+
+    #[test]
+    fn ignored_query_shares_key() {
+        let params = ["v".to_string()];
+        for query in [
+            None,
+            Some(""),
+            Some("utm_source=x"),
+            Some("filename=a.png&format="),
+        ] {
+            assert_eq!(
+                CacheKey::new(CID).with_query(query, &params).to_string(),
+                CID,
+                "{query:?}"
+            );
+        }
+        assert_eq!(
+            CacheKey::new(CID).with_query(Some("v=1"), &[]).to_string(),
+            CID
+        );
+    }
+
+    #[test]
+    fn configured_query_params() {
+        let params = ["v".to_string(), "w".to_string()];
+        let key = |query| {
+            CacheKey::new(CID)
+                .with_query(Some(query), &params)
+                .to_string()
+        };
+        assert_eq!(key("v=1"), format!("{CID}?v=1"));
+        assert_ne!(key("v=1"), key("v=2"));
+        // order and unrelated params don't matter
+        assert_eq!(key("w=2&x=3&v=1"), key("v=1&w=2"));
+        assert_eq!(key("v=1&w=2"), format!("{CID}?v=1&w=2"));
+        let gzip = CacheKey {
+            encoding: Some("gzip".into()),
+            ..with_path("a").with_query(Some("v"), &params)
+        };
+        assert_eq!(gzip.to_string(), format!("{CID}/a?v;gzip"));
+    }
+
+    #[test]
+    fn cid_of_key() {
+        let key = CacheKey {
+            encoding: Some("gzip".into()),
+            ..with_path("a").with_query(Some("v=1"), &["v".to_string()])
+        };
+        for key in [key.to_string(), with_path("a").to_string(), CID.to_string()] {
+            assert_eq!(cid_of(&key), CID, "{key}");
+        }
+        // the same cid in base58btc
+        assert_eq!(
+            cid_of("zdj7WhqHwce7DVB2uT5pjURQygeZpLZSk3z4UBAQd1X1isQqf/a"),
+            CID
+        );
+        assert_eq!(cid_of("not-a-cid/a"), "not-a-cid");
+    }
+}
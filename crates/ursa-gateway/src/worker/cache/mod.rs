@@ -1,18 +1,33 @@
 pub mod admin;
+pub mod disk;
+pub mod key;
+pub mod recent;
 pub mod server;
+pub mod stats;
 pub mod worker;
 
-use std::sync::Arc;
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use bytes::Bytes;
+use libipld::Cid;
 use opentelemetry::Context;
-use tokio::sync::{mpsc::UnboundedSender, oneshot};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    sync::{mpsc::UnboundedSender, oneshot},
+};
+use tracing::warn;
 
+use self::{disk::DiskCache, recent::RecentFetches, stats::CacheStats};
 use crate::{
-    cache::{ByteSize, Tlrfu},
+    cache::{ByteSize, CacheEvictionPolicy, Tlrfu},
     resolver::NodeResponse,
-    util::error::Error,
+    util::{error::Error, timer::now},
 };
 
 impl ByteSize for Bytes {
@@ -23,6 +38,9 @@ impl ByteSize for Bytes {
 
 pub struct Cache {
     tlrfu: Tlrfu<Bytes>,
+    recent: Arc<RecentFetches>,
+    disk: Option<Arc<DiskCache>>,
+    stats: CacheStats,
     tx: UnboundedSender<CacheCommand>,
     stream_buf: u64,
     cache_control_max_size: u64,
@@ -38,11 +56,118 @@ impl Cache {
     ) -> Self {
         Self {
             tlrfu: Tlrfu::new(max_size, ttl_buf),
+            recent: Default::default(),
+            disk: None,
+            stats: Default::default(),
             tx,
             stream_buf,
             cache_control_max_size,
         }
     }
+
+    /// See [`Tlrfu::with_watermarks`].
+    pub fn with_watermarks(mut self, low_water: u64, high_water: u64) -> Self {
+        self.tlrfu = self.tlrfu.with_watermarks(low_water, high_water);
+        self
+    }
+
+    /// Keep the entries evicted from memory in `disk`, checked on a miss before fetching.
+    pub fn with_disk_cache(mut self, disk: DiskCache) -> Self {
+        self.disk = Some(Arc::new(disk));
+        self
+    }
+
+    /// See [`CacheEvictionPolicy`].
+    pub fn with_eviction_policy(mut self, policy: CacheEvictionPolicy) -> Self {
+        self.tlrfu = self.tlrfu.with_policy(policy);
+        self
+    }
+
+    /// Serve the content of a fetch completed less than `window` ago
+    /// until it is inserted, instead of fetching it again.
+    pub fn with_dedup_window(mut self, window: Duration) -> Self {
+        self.recent = Arc::new(RecentFetches::new(window));
+        self
+    }
+}
+
+impl Cache {
+    /// Write the cached entries with their frequency and ttl to `path`, to be restored on startup.
+    ///
+    /// The entry count is followed by each entry, length prefixed, so entries
+    /// are serialized one at a time instead of all at once.
+    pub async fn persist(&self, path: &Path) -> Result<usize> {
+        let entries = self.tlrfu.entries();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let file = fs::File::create(path)
+            .await
+            .with_context(|| format!("Failed to create cache entries file: {path:?}"))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_u64(entries.len() as u64).await?;
+        for (key, value, freq, ttl) in &entries {
+            let entry = bincode::serialize(&(key.as_str(), &value[..], freq, ttl))
+                .context("Failed to serialize cache entry")?;
+            writer.write_u64(entry.len() as u64).await?;
+            writer.write_all(&entry).await?;
+        }
+        writer
+            .flush()
+            .await
+            .with_context(|| format!("Failed to write cache entries to: {path:?}"))?;
+        Ok(entries.len())
+    }
+
+    /// Insert the entries persisted at `path`. Entries are inserted least
+    /// frequently used first, so the most used ones are kept if the cache is smaller.
+    ///
+    /// The memory tier is authoritative: entries already cached are kept as is,
+    /// and persisted entries keep their ttl, expired ones are dropped instead of served stale.
+    pub async fn restore(&mut self, path: &Path) -> Result<usize> {
+        let file = fs::File::open(path)
+            .await
+            .with_context(|| format!("Failed to read cache entries from: {path:?}"))?;
+        let mut reader = BufReader::new(file);
+        let entries = reader
+            .read_u64()
+            .await
+            .context("Failed to read cache entry count")?;
+        let now = now()
+            .duration_since(UNIX_EPOCH)
+            .context("Failed to get system time from unix epoch")?
+            .as_nanos();
+        let mut count = 0;
+        let mut entry = Vec::new();
+        for _ in 0..entries {
+            let len = reader
+                .read_u64()
+                .await
+                .context("Failed to read cache entry length")?;
+            entry.resize(len as usize, 0);
+            reader
+                .read_exact(&mut entry)
+                .await
+                .context("Failed to read cache entry")?;
+            let (key, value, _, ttl): (String, Vec<u8>, usize, u128) =
+                bincode::deserialize(&entry).context("Failed to deserialize cache entry")?;
+            if self.tlrfu.contains(&key) || ttl <= now {
+                continue;
+            }
+            if let Err(e) = self
+                .tlrfu
+                .insert_until(key, Arc::new(value.into()), ttl)
+                .await
+            {
+                warn!("[Cache]: Stop restoring entries: {e:?}");
+                break;
+            }
+            count += 1;
+        }
+        self.stats
+            .set_size(self.tlrfu.count(), self.tlrfu.used_size());
+        Ok(count)
+    }
 }
 
 #[derive(Debug)]
@@ -54,12 +179,143 @@ pub enum CacheCommand {
     InsertSync {
         key: String,
         value: Arc<Bytes>,
+        /// ttl of an entry promoted from disk, kept instead of a fresh one
+        ttl: Option<u128>,
         ctx: Context,
     },
     Fetch {
+        /// scheduling key, fetches are served fairly across clients
+        client: String,
         cid: String,
+        /// the cache key params of the request, sent along to the providers
+        query: Option<String>,
         sender: oneshot::Sender<Result<NodeResponse, Error>>,
         ctx: Context,
     },
+    /// Size of the content, resolved without fetching it.
+    ResolveSize {
+        cid: String,
+        sender: oneshot::Sender<Result<Option<u64>, Error>>,
+        ctx: Context,
+    },
+    /// Remove the entries of a cid from memory and disk, whether any was cached.
+    Purge {
+        cid: Cid,
+        sender: oneshot::Sender<Result<bool>>,
+        ctx: Context,
+    },
     TtlCleanUp,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::util::timer::{clear_mock_time, set_mock_time};
+
+    fn cache() -> Cache {
+        cache_with_ttl(Duration::from_secs(60).as_nanos())
+    }
+
+    fn cache_with_ttl(ttl_buf: u128) -> Cache {
+        let (tx, _) = unbounded_channel();
+        Cache::new(200_000_000, ttl_buf, tx, 0, 0)
+    }
+
+    #[tokio::test]
+    async fn persist_restore() {
+        let path = std::env::temp_dir().join(format!("ursa-gateway-cache-{}", std::process::id()));
+        let mut cache = cache();
+        cache
+            .tlrfu
+            .insert("a".into(), Arc::new(Bytes::from_static(b"a")))
+            .await
+            .unwrap();
+        cache
+            .tlrfu
+            .insert("b".into(), Arc::new(Bytes::from_static(b"bb")))
+            .await
+            .unwrap();
+        cache.tlrfu.get(&"b".to_string()).await.unwrap();
+        assert_eq!(cache.persist(&path).await.unwrap(), 2);
+
+        let mut restored = cache();
+        assert_eq!(restored.restore(&path).await.unwrap(), 2);
+        fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(
+            restored.tlrfu.dirty_get(&"a".to_string()).unwrap().as_ref(),
+            &Bytes::from_static(b"a")
+        );
+        assert_eq!(
+            restored.tlrfu.dirty_get(&"b".to_string()).unwrap().as_ref(),
+            &Bytes::from_static(b"bb")
+        );
+    }
+
+    #[tokio::test]
+    async fn restore_drops_expired() {
+        let path =
+            std::env::temp_dir().join(format!("ursa-gateway-cache-expired-{}", std::process::id()));
+        let start = SystemTime::now();
+        set_mock_time(start);
+
+        let mut cache = cache_with_ttl(1_000);
+        cache
+            .tlrfu
+            .insert("a".into(), Arc::new(Bytes::from_static(b"a")))
+            .await
+            .unwrap();
+        set_mock_time(start + Duration::from_nanos(500));
+        cache
+            .tlrfu
+            .insert("b".into(), Arc::new(Bytes::from_static(b"b")))
+            .await
+            .unwrap();
+        assert_eq!(cache.persist(&path).await.unwrap(), 2);
+
+        // "a" expired on disk, "b" is still fresh
+        set_mock_time(start + Duration::from_nanos(1_200));
+        let mut restored = cache_with_ttl(1_000);
+        assert_eq!(restored.restore(&path).await.unwrap(), 1);
+        fs::remove_file(&path).await.unwrap();
+
+        let a = restored.tlrfu.get(&"a".to_string()).await.unwrap();
+        assert!(a.is_none());
+        assert!(restored.tlrfu.dirty_get(&"b".to_string()).is_some());
+
+        // the restored entry keeps its ttl
+        set_mock_time(start + Duration::from_nanos(1_600));
+        assert_eq!(restored.tlrfu.process_ttl_clean_up().await.unwrap(), 1);
+        clear_mock_time();
+    }
+
+    #[tokio::test]
+    async fn restore_truncated_file() {
+        let path = std::env::temp_dir().join(format!(
+            "ursa-gateway-cache-truncated-{}",
+            std::process::id()
+        ));
+        let mut cache = cache();
+        cache
+            .tlrfu
+            .insert("a".into(), Arc::new(Bytes::from_static(b"a")))
+            .await
+            .unwrap();
+        assert_eq!(cache.persist(&path).await.unwrap(), 1);
+
+        let bytes = fs::read(&path).await.unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).await.unwrap();
+        assert!(cache().restore(&path).await.is_err());
+        fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn restore_missing_file() {
+        let path = std::env::temp_dir().join("ursa-gateway-cache-missing");
+        assert!(cache().restore(&path).await.is_err());
+    }
+}
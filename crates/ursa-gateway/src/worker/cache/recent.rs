@@ -0,0 +1,80 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+
+use crate::util::timer::now;
+
+/// Content of the fetches completed in the last `window`, served to the requests
+/// arriving before the content is inserted in the cache. A window of 0 disables it.
+#[derive(Default)]
+pub struct RecentFetches {
+    window: Duration,
+    entries: Mutex<HashMap<String, (Arc<Bytes>, SystemTime)>>,
+}
+
+impl RecentFetches {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Default::default(),
+        }
+    }
+
+    pub fn insert(&self, key: String, value: Arc<Bytes>) {
+        if self.window.is_zero() {
+            return;
+        }
+        let now = now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, expiry)| *expiry > now);
+        entries.insert(key, (value, now + self.window));
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<Bytes>> {
+        let entries = self.entries.lock().unwrap();
+        let (value, expiry) = entries.get(key)?;
+        (*expiry > now()).then(|| Arc::clone(value))
+    }
+
+    /// Forget the fetch once its content is cached.
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    /// Forget the fetches of every key matching `remove`.
+    pub fn remove_matching(&self, remove: impl Fn(&str) -> bool) {
+        self.entries.lock().unwrap().retain(|key, _| !remove(key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::timer::{clear_mock_time, set_mock_time};
+
+    #[test]
+    fn window() {
+        let start = SystemTime::now();
+        set_mock_time(start);
+        let recent = RecentFetches::new(Duration::from_millis(100));
+        recent.insert("a".into(), Arc::new(Bytes::from_static(b"a")));
+        assert_eq!(recent.get("a").unwrap().as_ref(), &Bytes::from_static(b"a"));
+        assert!(recent.get("b").is_none());
+
+        set_mock_time(start + Duration::from_millis(100));
+        assert!(recent.get("a").is_none());
+
+        recent.insert("b".into(), Arc::new(Bytes::from_static(b"b")));
+        recent.remove("b");
+        assert!(recent.get("b").is_none());
+        clear_mock_time();
+
+        let disabled = RecentFetches::new(Duration::ZERO);
+        disabled.insert("a".into(), Arc::new(Bytes::from_static(b"a")));
+        assert!(disabled.get("a").is_none());
+    }
+}
@@ -7,8 +7,9 @@ use axum::{
     http::{response::Parts, StatusCode},
     response::{IntoResponse, Response},
 };
-use bytes::BufMut;
+use bytes::{BufMut, Bytes};
 use hyper::Body;
+use metrics::increment_counter;
 use tokio::{
     io::{duplex, AsyncWriteExt, DuplexStream},
     spawn,
@@ -18,62 +19,199 @@ use tokio_util::io::ReaderStream;
 use tracing::{error, info, info_span, warn, Instrument, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use super::{Cache, CacheCommand};
+use super::{key::CacheKey, recent::RecentFetches, Cache, CacheCommand};
 use crate::util::error::Error;
 
 #[async_trait]
 pub trait ServerCache: Send + Sync + 'static {
-    async fn get_announce(&self, k: &str, no_cache: bool) -> Result<StreamResponseBody, Error>;
+    /// Cached content, fetched on a miss. `no_cache` skips reading the cache,
+    /// `no_store` also skips caching the fetched content.
+    async fn get_announce(
+        &self,
+        key: &CacheKey,
+        no_cache: bool,
+        no_store: bool,
+        client: &str,
+    ) -> Result<StreamResponseBody, Error>;
+
+    /// Size of the content if known without fetching it, from the cache or the resolver.
+    async fn head(&self, key: &CacheKey) -> Result<Option<u64>, Error>;
+
+    /// Cached content, `None` on a miss instead of fetching it.
+    fn cached(&self, key: &CacheKey) -> Result<Option<StreamResponseBody>, Error>;
+
+    /// Size of the cached content, `None` on a miss instead of resolving it.
+    fn cached_size(&self, key: &CacheKey) -> Option<u64>;
 }
 
 #[async_trait]
 impl ServerCache for Cache {
-    async fn get_announce(&self, k: &str, no_cache: bool) -> Result<StreamResponseBody, Error> {
-        if no_cache {
+    async fn get_announce(
+        &self,
+        key: &CacheKey,
+        no_cache: bool,
+        no_store: bool,
+        client: &str,
+    ) -> Result<StreamResponseBody, Error> {
+        if no_store {
+            let span = info_span!("Cache bypass");
+            increment_counter!("gateway_cache_no_store");
+            let (body, _) = fetch(key, client, &self.tx).instrument(span).await?;
+            Ok(StreamResponseBody::Direct(body))
+        } else if no_cache {
             let span = info_span!("Cache invalidate");
-            fetch_and_insert(k, &self.tx, self.stream_buf, self.cache_control_max_size)
-                .instrument(span)
-                .await
-        } else if let Some(data) = self.tlrfu.dirty_get(&String::from(k)) {
-            let (mut w, r) = duplex(self.stream_buf as usize);
+            fetch_and_insert(
+                key,
+                client,
+                &self.tx,
+                &self.recent,
+                self.stream_buf,
+                self.cache_control_max_size,
+            )
+            .instrument(span)
+            .await
+        } else if let Some(body) = self.lookup(key)? {
+            self.stats.hit();
+            Ok(body)
+        } else if let Some(body) = self.promote(key).await? {
+            self.stats.hit();
+            Ok(body)
+        } else {
+            self.stats.miss();
+            let span = info_span!("Cache missed");
+            fetch_and_insert(
+                key,
+                client,
+                &self.tx,
+                &self.recent,
+                self.stream_buf,
+                self.cache_control_max_size,
+            )
+            .instrument(span)
+            .await
+        }
+    }
+
+    async fn head(&self, key: &CacheKey) -> Result<Option<u64>, Error> {
+        if let Some(data) = self.tlrfu.dirty_get(&key.to_string()) {
+            return Ok(Some(data.len() as u64));
+        }
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(CacheCommand::ResolveSize {
+                cid: key.cid.clone(),
+                sender: tx,
+                ctx: Span::current().context(),
+            })
+            .map_err(|e| {
+                error!("Failed to dispatch ResolveSize command: {e:?}");
+                anyhow!("Failed to dispatch ResolveSize command")
+            })?;
+        rx.await.map_err(|e| {
+            error!("Failed to receive size from resolver: {e:?}");
+            anyhow!("Failed to receive size from resolver")
+        })?
+    }
+
+    fn cached(&self, key: &CacheKey) -> Result<Option<StreamResponseBody>, Error> {
+        let body = self.lookup(key)?;
+        match body {
+            Some(_) => self.stats.hit(),
+            None => self.stats.miss(),
+        }
+        Ok(body)
+    }
+
+    fn cached_size(&self, key: &CacheKey) -> Option<u64> {
+        let k = key.to_string();
+        match self.tlrfu.dirty_get(&k) {
+            Some(data) => Some(data.len() as u64),
+            None => self.recent.get(&k).map(|data| data.len() as u64),
+        }
+    }
+}
+
+impl Cache {
+    /// Content of the memory tier or of a recent fetch.
+    fn lookup(&self, key: &CacheKey) -> Result<Option<StreamResponseBody>, Error> {
+        let k = key.to_string();
+        if let Some(data) = self.tlrfu.dirty_get(&k) {
             let span = info_span!("Cache hit");
             let data = Arc::clone(data);
             self.tx
                 .send(CacheCommand::GetSync {
-                    key: String::from(k),
+                    key: k,
                     ctx: Span::current().context(),
                 })
                 .map_err(|e| {
                     error!("Failed to dispatch GetSync command: {e:?}");
                     anyhow!("Failed to dispatch GetSync command")
                 })?;
-            let stream_writer = async move {
-                let span = info_span!("Stream writing");
-                if let Err(e) = w.write_all(data.as_ref()).instrument(span).await {
-                    warn!("Failed to write to stream: {e:?}");
-                }
-            };
-            spawn(stream_writer.instrument(span));
-            Ok(StreamResponseBody::Duplex(r))
+            Ok(Some(stream(data, self.stream_buf, span)))
+        } else if let Some(data) = self.recent.get(&k) {
+            let span = info_span!("Recent fetch");
+            increment_counter!("gateway_cache_recent_fetch_hit");
+            Ok(Some(stream(data, self.stream_buf, span)))
         } else {
-            let span = info_span!("Cache missed");
-            fetch_and_insert(k, &self.tx, self.stream_buf, self.cache_control_max_size)
-                .instrument(span)
-                .await
+            Ok(None)
         }
     }
+
+    /// Content of the disk tier, inserted back in memory.
+    async fn promote(&self, key: &CacheKey) -> Result<Option<StreamResponseBody>, Error> {
+        let Some(disk) = &self.disk else {
+            return Ok(None);
+        };
+        let k = key.to_string();
+        let Some((data, ttl)) = disk.read(&k).await else {
+            return Ok(None);
+        };
+        let span = info_span!("Disk hit");
+        increment_counter!("gateway_cache_disk_hit");
+        let data = Arc::new(data);
+        // served to the requests arriving before it is back in memory
+        self.recent.insert(k.clone(), Arc::clone(&data));
+        self.tx
+            .send(CacheCommand::InsertSync {
+                key: k,
+                value: Arc::clone(&data),
+                ttl: Some(ttl),
+                ctx: Span::current().context(),
+            })
+            .map_err(|e| {
+                error!("Failed to dispatch InsertSync command: {e:?}");
+                anyhow!("Failed to dispatch InsertSync command")
+            })?;
+        Ok(Some(stream(data, self.stream_buf, span)))
+    }
 }
 
-async fn fetch_and_insert(
-    k: &str,
+/// Stream already fetched content.
+fn stream(data: Arc<Bytes>, stream_buf: u64, span: Span) -> StreamResponseBody {
+    let size = data.len() as u64;
+    let (mut w, r) = duplex(stream_buf as usize);
+    let stream_writer = async move {
+        let span = info_span!("Stream writing");
+        if let Err(e) = w.write_all(data.as_ref()).instrument(span).await {
+            warn!("Failed to write to stream: {e:?}");
+        }
+    };
+    spawn(stream_writer.instrument(span));
+    StreamResponseBody::Cached(r, size)
+}
+
+/// Fetch the content through the worker, with its size. Fails on non OK responses.
+async fn fetch(
+    key: &CacheKey,
+    client: &str,
     cmd_sender: &UnboundedSender<CacheCommand>,
-    stream_buf: u64,
-    cache_control_max_size: u64,
-) -> Result<StreamResponseBody, Error> {
+) -> Result<(Body, u64), Error> {
     let (tx, rx) = oneshot::channel();
     cmd_sender
         .send(CacheCommand::Fetch {
-            cid: String::from(k),
+            client: client.to_string(),
+            cid: key.cid.clone(),
+            query: key.query.clone(),
             sender: tx,
             ctx: Span::current().context(),
         })
@@ -85,28 +223,40 @@ async fn fetch_and_insert(
         error!("Failed to receive response from resolver: {e:?}");
         anyhow!("Failed to receive response from resolver")
     })??;
-    let mut body = match response.resp.into_parts() {
+    match response.resp.into_parts() {
         (
             Parts {
                 status: StatusCode::OK,
                 ..
             },
             body,
-        ) => body,
+        ) => Ok((body, response.size)),
         (parts, body) => {
             error!("Error requested provider with parts: {parts:?} and body: {body:?}");
-            return Err(Error::Upstream(
+            Err(Error::Upstream(
                 parts.status,
                 "Error requested provider".to_string(),
-            ));
+            ))
         }
-    };
-    if response.size > cache_control_max_size {
-        info!("Content size is {}..skipping cache", response.size);
+    }
+}
+
+async fn fetch_and_insert(
+    key: &CacheKey,
+    client: &str,
+    cmd_sender: &UnboundedSender<CacheCommand>,
+    recent: &Arc<RecentFetches>,
+    stream_buf: u64,
+    cache_control_max_size: u64,
+) -> Result<StreamResponseBody, Error> {
+    let (mut body, size) = fetch(key, client, cmd_sender).await?;
+    if size > cache_control_max_size {
+        info!("Content size is {size}..skipping cache");
         return Ok(StreamResponseBody::Direct(body));
     }
-    let key = String::from(k); // move to [worker|writer] thread
+    let k = key.to_string(); // move to [worker|writer] thread
     let tx = cmd_sender.clone(); // move to [worker|writer] thread
+    let recent = Arc::clone(recent); // move to [worker|writer] thread
     let (mut stream_writer, stream_reader) = duplex(stream_buf as usize);
     let stream_writer = async move {
         let mut bytes = Vec::with_capacity(body.size_hint().lower() as usize);
@@ -124,30 +274,267 @@ async fn fetch_and_insert(
                 }
             }
         }
+        let value = Arc::new(bytes.into());
+        recent.insert(k.clone(), Arc::clone(&value));
         if let Err(e) = tx.send(CacheCommand::InsertSync {
-            key,
-            value: Arc::new(bytes.into()),
+            key: k,
+            value,
+            ttl: None,
             ctx: Span::current().context(),
         }) {
             error!("Failed to dispatch InsertSync command: {e:?}");
         };
     };
     spawn(stream_writer.instrument(info_span!("Stream writing")));
-    Ok(StreamResponseBody::Duplex(stream_reader))
+    Ok(StreamResponseBody::Duplex(stream_reader, size))
 }
 
+/// Content streamed with its size, if known.
 pub enum StreamResponseBody {
     Direct(Body),
-    Duplex(DuplexStream),
+    Duplex(DuplexStream, u64),
+    /// Content served from the cache.
+    Cached(DuplexStream, u64),
+}
+
+impl StreamResponseBody {
+    pub fn is_cached(&self) -> bool {
+        matches!(self, StreamResponseBody::Cached(..))
+    }
+
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            StreamResponseBody::Direct(body) => body.size_hint().exact(),
+            StreamResponseBody::Duplex(_, size) | StreamResponseBody::Cached(_, size) => {
+                Some(*size)
+            }
+        }
+    }
+
+    pub fn into_body(self) -> Body {
+        match self {
+            StreamResponseBody::Direct(body) => body,
+            StreamResponseBody::Duplex(duplex_stream, _)
+            | StreamResponseBody::Cached(duplex_stream, _) => {
+                Body::wrap_stream(ReaderStream::new(duplex_stream))
+            }
+        }
+    }
 }
 
 impl IntoResponse for StreamResponseBody {
     fn into_response(self) -> Response {
         match self {
             StreamResponseBody::Direct(body) => StreamBody::new(body).into_response(),
-            StreamResponseBody::Duplex(duplex_stream) => {
+            StreamResponseBody::Duplex(duplex_stream, _)
+            | StreamResponseBody::Cached(duplex_stream, _) => {
                 StreamBody::new(ReaderStream::new(duplex_stream)).into_response()
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{io::AsyncReadExt, sync::mpsc::unbounded_channel};
+
+    use super::*;
+    use crate::{
+        resolver::NodeResponse,
+        worker::cache::{disk::DiskCache, stats::CacheStatsSnapshot, worker::WorkerCache},
+    };
+
+    async fn read(body: StreamResponseBody) -> Vec<u8> {
+        match body {
+            StreamResponseBody::Direct(body) => hyper::body::to_bytes(body).await.unwrap().to_vec(),
+            StreamResponseBody::Duplex(mut reader, _)
+            | StreamResponseBody::Cached(mut reader, _) => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes).await.unwrap();
+                bytes
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn no_store_skips_caching() {
+        let (tx, mut rx) = unbounded_channel();
+        let cache = Cache::new(200_000_000, 0, tx, 1024, 1024);
+        let key = CacheKey::new("bafkreihwcrnsi2tqozwq22k4vl7flutu43jlxgb3tenewysm2xvfuej5i4");
+
+        // stands in for the worker, counting the inserts
+        let worker = spawn(async move {
+            let mut inserts = 0;
+            while let Some(command) = rx.recv().await {
+                match command {
+                    CacheCommand::Fetch { sender, .. } => sender
+                        .send(Ok(NodeResponse {
+                            resp: Response::new(Body::from("data")),
+                            size: 4,
+                        }))
+                        .unwrap(),
+                    CacheCommand::InsertSync { .. } => inserts += 1,
+                    _ => {}
+                }
+            }
+            inserts
+        });
+
+        let body = cache
+            .get_announce(&key, false, true, "client")
+            .await
+            .unwrap();
+        assert_eq!(read(body).await, b"data");
+        assert!(!cache.tlrfu.contains(&key.to_string()));
+
+        let body = cache
+            .get_announce(&key, true, false, "client")
+            .await
+            .unwrap();
+        assert_eq!(read(body).await, b"data");
+
+        drop(cache);
+        // only the no-cache fetch is inserted
+        assert_eq!(worker.await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn disk_promotion() {
+        let dir = std::env::temp_dir().join(format!("ursa-gateway-promote-{}", std::process::id()));
+        let disk = DiskCache::open(&dir, 1024).await.unwrap();
+        let (tx, mut rx) = unbounded_channel();
+        let ttl_buf = Duration::from_secs(60).as_nanos();
+        let mut cache = Cache::new(4, ttl_buf, tx, 1024, 1024).with_disk_cache(disk);
+        let a = CacheKey::new("bafkreihwcrnsi2tqozwq22k4vl7flutu43jlxgb3tenewysm2xvfuej5i4");
+        let b = CacheKey::new("bafkreiaimrc5uvz7tdaxa3k5f2kpkyboeuqjlymqwwesbvgezhtz5lhzwa");
+
+        // "a" is evicted from memory to disk
+        let data = Arc::new(Bytes::from_static(b"aaaa"));
+        cache.insert(a.to_string(), data).await.unwrap();
+        let data = Arc::new(Bytes::from_static(b"bbbb"));
+        cache.insert(b.to_string(), data).await.unwrap();
+        assert!(!cache.tlrfu.contains(&a.to_string()));
+        let disk = Arc::clone(cache.disk.as_ref().unwrap());
+        while !disk.contains(&a.to_string()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // served from disk instead of fetched, and inserted back in memory
+        let body = cache
+            .get_announce(&a, false, false, "client")
+            .await
+            .unwrap();
+        assert!(body.is_cached());
+        assert_eq!(read(body).await, b"aaaa");
+        match rx.try_recv().unwrap() {
+            // keeping the ttl it had in memory
+            CacheCommand::InsertSync {
+                key,
+                value,
+                ttl: Some(ttl),
+                ..
+            } => {
+                assert_eq!(key, a.to_string());
+                cache.insert_until(key, value, ttl).await.unwrap();
+            }
+            command => panic!("Unexpected command: {command:?}"),
+        }
+        assert!(rx.try_recv().is_err());
+        assert!(cache.tlrfu.contains(&a.to_string()));
+        assert!(!cache.tlrfu.contains(&b.to_string()));
+        while !disk.contains(&b.to_string()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(cache);
+        disk.purge().await;
+        tokio::fs::remove_dir(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let (tx, mut rx) = unbounded_channel();
+        let mut cache = Cache::new(8, 0, tx, 1024, 1024);
+        let a = CacheKey::new("bafkreihwcrnsi2tqozwq22k4vl7flutu43jlxgb3tenewysm2xvfuej5i4");
+        let b = CacheKey::new("bafkreiaimrc5uvz7tdaxa3k5f2kpkyboeuqjlymqwwesbvgezhtz5lhzwa");
+        let data = Arc::new(Bytes::from_static(b"aaaa"));
+        cache.insert(a.to_string(), data).await.unwrap();
+
+        // two hits, from the handler and the maintenance lookup, and a miss
+        cache
+            .get_announce(&a, false, false, "client")
+            .await
+            .unwrap();
+        assert!(cache.cached(&a).unwrap().is_some());
+        assert!(cache.cached(&b).unwrap().is_none());
+        // bypasses are neither
+        spawn(async move {
+            while let Some(command) = rx.recv().await {
+                if let CacheCommand::Fetch { sender, .. } = command {
+                    let resp = Response::new(Body::from("bbbbbbbb"));
+                    sender.send(Ok(NodeResponse { resp, size: 8 })).unwrap();
+                }
+            }
+        });
+        cache.get_announce(&a, false, true, "client").await.unwrap();
+
+        // "a" is evicted by the insert of "b"
+        let data = Arc::new(Bytes::from_static(b"bbbbbbbb"));
+        cache.insert(b.to_string(), data).await.unwrap();
+        assert_eq!(
+            cache.stats.snapshot(),
+            CacheStatsSnapshot {
+                hits: 2,
+                misses: 1,
+                evictions: 1,
+                ttl_evictions: 0,
+                entries: 1,
+                bytes: 8,
+            }
+        );
+
+        cache.ttl_cleanup().await.unwrap();
+        let stats = cache.stats.snapshot();
+        assert_eq!(stats.ttl_evictions, 1);
+        assert_eq!((stats.entries, stats.bytes), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn dedup_window() {
+        let key = CacheKey::new("bafkreihwcrnsi2tqozwq22k4vl7flutu43jlxgb3tenewysm2xvfuej5i4");
+        for (window, fetches) in [(Duration::from_secs(60), 1), (Duration::ZERO, 2)] {
+            let (tx, mut rx) = unbounded_channel();
+            let cache = Cache::new(200_000_000, 0, tx, 1024, 1024).with_dedup_window(window);
+
+            // stands in for the worker, never inserting the fetched content
+            let worker = spawn(async move {
+                let mut fetches = 0;
+                while let Some(command) = rx.recv().await {
+                    if let CacheCommand::Fetch { sender, .. } = command {
+                        fetches += 1;
+                        sender
+                            .send(Ok(NodeResponse {
+                                resp: Response::new(Body::from("data")),
+                                size: 4,
+                            }))
+                            .unwrap();
+                    }
+                }
+                fetches
+            });
+
+            for _ in 0..2 {
+                let body = cache
+                    .get_announce(&key, false, false, "client")
+                    .await
+                    .unwrap();
+                assert_eq!(read(body).await, b"data");
+            }
+
+            drop(cache);
+            assert_eq!(worker.await.unwrap(), fetches);
+        }
+    }
+}
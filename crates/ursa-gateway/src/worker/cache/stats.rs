@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use metrics::{counter, gauge, increment_counter};
+use serde::Serialize;
+
+/// Counters of the cache, updated without taking the cache lock and mirrored
+/// to the `gateway_cache_*` metrics.
+#[derive(Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    ttl_evictions: AtomicU64,
+    entries: AtomicU64,
+    bytes: AtomicU64,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct CacheStatsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    /// entries evicted for the size of the cache
+    pub evictions: u64,
+    /// expired entries removed by the ttl cleanup
+    pub ttl_evictions: u64,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+impl CacheStats {
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        increment_counter!("gateway_cache_hits");
+    }
+
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        increment_counter!("gateway_cache_misses");
+    }
+
+    pub fn evicted(&self, count: usize) {
+        self.evictions.fetch_add(count as u64, Ordering::Relaxed);
+        counter!("gateway_cache_evictions", count as u64);
+    }
+
+    pub fn ttl_evicted(&self, count: usize) {
+        self.ttl_evictions
+            .fetch_add(count as u64, Ordering::Relaxed);
+        counter!("gateway_cache_ttl_evictions", count as u64);
+    }
+
+    /// Entries and bytes held in memory.
+    pub fn set_size(&self, entries: usize, bytes: u64) {
+        self.entries.store(entries as u64, Ordering::Relaxed);
+        self.bytes.store(bytes, Ordering::Relaxed);
+        gauge!("gateway_cache_entries", entries as f64);
+        gauge!("gateway_cache_bytes", bytes as f64);
+    }
+
+    pub fn snapshot(&self) -> CacheStatsSnapshot {
+        CacheStatsSnapshot {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            ttl_evictions: self.ttl_evictions.load(Ordering::Relaxed),
+            entries: self.entries.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
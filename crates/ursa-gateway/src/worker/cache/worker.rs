@@ -3,15 +3,22 @@ use std::sync::Arc;
 use anyhow::Result;
 use async_trait::async_trait;
 use bytes::Bytes;
+use libipld::Cid;
+use tokio::spawn;
 use tracing::{info, log::warn};
 
-use super::Cache;
+use super::{key::cid_of, Cache};
 
 #[async_trait]
 pub trait WorkerCache: Send + Sync + 'static {
     async fn get(&mut self, k: &str) -> Result<()>;
     async fn insert(&mut self, k: String, v: Arc<Bytes>) -> Result<()>;
+    /// Insert an entry expiring at `ttl` nanoseconds since the unix epoch.
+    async fn insert_until(&mut self, k: String, v: Arc<Bytes>, ttl: u128) -> Result<()>;
     async fn ttl_cleanup(&mut self) -> Result<()>;
+    /// Remove every entry of the cid from every tier, whatever their path, query and
+    /// encoding, `false` if none was cached.
+    async fn remove_cid(&mut self, cid: &Cid) -> Result<bool>;
 }
 
 #[async_trait]
@@ -22,17 +29,75 @@ impl WorkerCache for Cache {
     }
 
     async fn insert(&mut self, k: String, v: Arc<Bytes>) -> Result<()> {
-        if !self.tlrfu.contains(&k) {
-            self.tlrfu.insert(k, v).await?;
-        } else {
-            warn!("[Cache]: Attempt to insert existed key: {k}");
-        }
-        Ok(())
+        self.insert_entry(k, v, None).await
+    }
+
+    async fn insert_until(&mut self, k: String, v: Arc<Bytes>, ttl: u128) -> Result<()> {
+        self.insert_entry(k, v, Some(ttl)).await
     }
 
     async fn ttl_cleanup(&mut self) -> Result<()> {
         let count = self.tlrfu.process_ttl_clean_up().await?;
+        self.stats.ttl_evicted(count);
+        self.stats
+            .set_size(self.tlrfu.count(), self.tlrfu.used_size());
         info!("[Cache]: TTL cleanup total {count} record(s)");
         Ok(())
     }
+
+    async fn remove_cid(&mut self, cid: &Cid) -> Result<bool> {
+        let cid = cid.to_string();
+        let keys: Vec<String> = self
+            .tlrfu
+            .entries()
+            .into_iter()
+            .filter(|(key, ..)| cid_of(key) == cid)
+            .map(|(key, ..)| key.clone())
+            .collect();
+        let mut removed = false;
+        for key in keys {
+            removed |= self.tlrfu.remove(&key).await?.is_some();
+        }
+        self.stats
+            .set_size(self.tlrfu.count(), self.tlrfu.used_size());
+        if let Some(disk) = &self.disk {
+            removed |= disk.remove_cid(&cid).await;
+        }
+        self.recent.remove_matching(|key| cid_of(key) == cid);
+        Ok(removed)
+    }
+}
+
+impl Cache {
+    /// Insert an entry expiring at `ttl`, or after the ttl buffer, writing the
+    /// entries it evicts to the disk tier.
+    async fn insert_entry(&mut self, k: String, v: Arc<Bytes>, ttl: Option<u128>) -> Result<()> {
+        if !self.tlrfu.contains(&k) {
+            let evicted = match ttl {
+                Some(ttl) => self.tlrfu.insert_until(k.clone(), v, ttl).await?,
+                None => self.tlrfu.insert(k.clone(), v).await?,
+            };
+            self.stats.evicted(evicted.len());
+            self.stats
+                .set_size(self.tlrfu.count(), self.tlrfu.used_size());
+            if let Some(disk) = &self.disk {
+                let generation = disk.generation();
+                for (key, value, ttl) in evicted {
+                    if disk.contains(&key) {
+                        continue;
+                    }
+                    let disk = Arc::clone(disk);
+                    spawn(async move {
+                        if let Err(e) = disk.write(&key, &value, ttl, generation).await {
+                            warn!("[Cache]: Failed to write evicted key {key} to disk: {e:?}");
+                        }
+                    });
+                }
+            }
+        } else {
+            warn!("[Cache]: Attempt to insert existed key: {k}");
+        }
+        self.recent.remove(&k);
+        Ok(())
+    }
 }
@@ -0,0 +1,88 @@
+use std::collections::{HashMap, VecDeque};
+
+use metrics::gauge;
+
+const QUEUE_DEPTH_GAUGE: &str = "gateway_fetch_queue_depth";
+
+/// Queue served round-robin across scheduling keys,
+/// so a key with many queued items can't delay the others.
+pub struct FairQueue<T> {
+    queues: HashMap<String, VecDeque<T>>,
+    /// keys with queued items, in serving order
+    order: VecDeque<String>,
+    /// items queued across keys
+    len: usize,
+}
+
+impl<T> Default for FairQueue<T> {
+    fn default() -> Self {
+        Self {
+            queues: HashMap::new(),
+            order: VecDeque::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> FairQueue<T> {
+    pub fn push(&mut self, key: String, item: T) {
+        let queue = self.queues.entry(key.clone()).or_default();
+        if queue.is_empty() {
+            self.order.push_back(key.clone());
+        }
+        queue.push_back(item);
+        self.len += 1;
+        // keys are client ips, too many to label with
+        gauge!(QUEUE_DEPTH_GAUGE, self.len as f64);
+    }
+
+    /// Next item of the next key in turn.
+    pub fn pop(&mut self) -> Option<T> {
+        let key = self.order.pop_front()?;
+        let queue = self.queues.get_mut(&key)?;
+        let item = queue.pop_front();
+        if item.is_some() {
+            self.len -= 1;
+            gauge!(QUEUE_DEPTH_GAUGE, self.len as f64);
+        }
+        if queue.is_empty() {
+            self.queues.remove(&key);
+        } else {
+            self.order.push_back(key);
+        }
+        item
+    }
+
+    pub fn depth(&self, key: &str) -> usize {
+        self.queues.get(key).map_or(0, VecDeque::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin() {
+        let mut queue = FairQueue::default();
+        for i in 0..3 {
+            queue.push("heavy".into(), format!("heavy-{i}"));
+        }
+        queue.push("light".into(), "light-0".to_string());
+        assert_eq!(queue.depth("heavy"), 3);
+        assert_eq!(queue.depth("light"), 1);
+        assert_eq!(queue.len, 4);
+
+        assert_eq!(queue.pop().unwrap(), "heavy-0");
+        assert_eq!(queue.pop().unwrap(), "light-0");
+        assert_eq!(queue.pop().unwrap(), "heavy-1");
+        assert_eq!(queue.depth("light"), 0);
+
+        queue.push("light".into(), "light-1".to_string());
+        assert_eq!(queue.pop().unwrap(), "heavy-2");
+        assert_eq!(queue.pop().unwrap(), "light-1");
+        assert!(queue.pop().is_none());
+        assert_eq!(queue.depth("heavy"), 0);
+        assert_eq!(queue.len, 0);
+    }
+}
@@ -1,30 +1,50 @@
 pub mod cache;
+mod fair;
 
 use std::sync::Arc;
 
 use cache::{worker::WorkerCache, CacheCommand};
+use fair::FairQueue;
+use opentelemetry::Context;
 use tokio::{
     select, spawn,
     sync::{
-        mpsc::{Receiver, Sender, UnboundedReceiver},
-        RwLock,
+        mpsc::{unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        oneshot, RwLock,
     },
     task::JoinHandle,
 };
-use tracing::{error, info, info_span, warn, Instrument};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use crate::resolver::Resolver;
+use crate::{
+    resolver::{NodeResponse, Resolver},
+    util::error::Error,
+};
+
+/// Fetch waiting for a free slot.
+struct QueuedFetch {
+    cid: String,
+    query: Option<String>,
+    sender: oneshot::Sender<Result<NodeResponse, Error>>,
+    ctx: Context,
+}
 
+/// Fetches are bounded to `max_concurrent_fetches` (0 = unbounded), fetches over the bound
+/// are queued per client and served round-robin across clients.
 pub fn start<Cache: WorkerCache>(
     mut cache_worker_rx: UnboundedReceiver<CacheCommand>,
     cache: Arc<RwLock<Cache>>,
     resolver: Arc<Resolver>,
+    max_concurrent_fetches: usize,
     signal_tx: Sender<()>,
     mut shutdown_rx: Receiver<()>,
 ) -> JoinHandle<()> {
     spawn(async move {
         info!("Main worker start");
+        let mut fetch_queue = FairQueue::default();
+        let mut fetching = 0;
+        let (fetch_done_tx, mut fetch_done_rx) = unbounded_channel();
         loop {
             let signal_tx = signal_tx.clone(); // move to cache worker thread
             select! {
@@ -43,24 +63,49 @@ pub fn start<Cache: WorkerCache>(
                                 };
                             }.instrument(span));
                         },
-                        CacheCommand::InsertSync{ key, value, ctx } => {
+                        CacheCommand::InsertSync{ key, value, ttl, ctx } => {
                             let span = info_span!("[Worker]: InsertSync");
                             span.set_parent(ctx);
                             spawn(async move {
                                 info!("Process InsertSyncAnnounce command with key: {key:?}");
-                                if let Err(e) = cache.write().await.insert(String::from(&key), value).await {
+                                let mut cache = cache.write().await;
+                                let inserted = match ttl {
+                                    Some(ttl) => cache.insert_until(String::from(&key), value, ttl).await,
+                                    None => cache.insert(String::from(&key), value).await,
+                                };
+                                if let Err(e) = inserted {
                                     error!("Process InsertSyncAnnounce command error with key: {key:?} {e:?}");
                                     signal_tx.send(()).await.expect("Send signal successfully");
                                 };
                             }.instrument(span));
                         },
-                        CacheCommand::Fetch{ cid, sender, ctx } => {
-                            let span = info_span!("[Worker]: Fetch");
+                        CacheCommand::Fetch{ client, cid, query, sender, ctx } => {
+                            let fetch = QueuedFetch { cid, query, sender, ctx };
+                            if max_concurrent_fetches == 0 || fetching < max_concurrent_fetches {
+                                fetching += 1;
+                                spawn_fetch(fetch, resolver, fetch_done_tx.clone());
+                            } else {
+                                debug!("Queue fetch of cid: {:?} for client: {client}", fetch.cid);
+                                fetch_queue.push(client, fetch);
+                            }
+                        },
+                        CacheCommand::ResolveSize{ cid, sender, ctx } => {
+                            let span = info_span!("[Worker]: ResolveSize");
+                            span.set_parent(ctx);
+                            spawn(async move {
+                                info!("Process ResolveSize command with cid: {cid:?}");
+                                if let Err(e) = sender.send(resolver.resolve_size(&cid).await) {
+                                    warn!("Process ResolveSize command error with cid: {cid:?}. Receiver stopped\n{e:?}");
+                                }
+                            }.instrument(span));
+                        },
+                        CacheCommand::Purge{ cid, sender, ctx } => {
+                            let span = info_span!("[Worker]: Purge");
                             span.set_parent(ctx);
                             spawn(async move {
-                                info!("Process FetchAnnounce command with cid: {cid:?}");
-                                if let Err(e) = sender.send(resolver.resolve_content(&cid).await) {
-                                    warn!("Process FetchAnnounce command error with cid: {cid:?}. Receiver stopped\n{e:?}");
+                                info!("Process Purge command with cid: {cid}");
+                                if let Err(e) = sender.send(cache.write().await.remove_cid(&cid).await) {
+                                    warn!("Process Purge command error with cid: {cid}. Receiver stopped\n{e:?}");
                                 }
                             }.instrument(span));
                         },
@@ -76,6 +121,12 @@ pub fn start<Cache: WorkerCache>(
                         }
                     }
                 }
+                Some(()) = fetch_done_rx.recv() => {
+                    match fetch_queue.pop() {
+                        Some(fetch) => spawn_fetch(fetch, Arc::clone(&resolver), fetch_done_tx.clone()),
+                        None => fetching -= 1,
+                    }
+                }
                 _ = shutdown_rx.recv() => {
                     info!("Main worker stopped");
                     break;
@@ -84,3 +135,25 @@ pub fn start<Cache: WorkerCache>(
         }
     }.instrument(info_span!("Main worker")))
 }
+
+fn spawn_fetch(fetch: QueuedFetch, resolver: Arc<Resolver>, done_tx: UnboundedSender<()>) {
+    let QueuedFetch {
+        cid,
+        query,
+        sender,
+        ctx,
+    } = fetch;
+    let span = info_span!("[Worker]: Fetch");
+    span.set_parent(ctx);
+    spawn(
+        async move {
+            info!("Process FetchAnnounce command with cid: {cid:?}");
+            if let Err(e) = sender.send(resolver.resolve_content(&cid, query.as_deref()).await) {
+                warn!("Process FetchAnnounce command error with cid: {cid:?}. Receiver stopped\n{e:?}");
+            }
+            // the worker only stops on shutdown
+            done_tx.send(()).ok();
+        }
+        .instrument(span),
+    );
+}
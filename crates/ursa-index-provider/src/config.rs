@@ -13,6 +13,10 @@ pub struct ProviderConfig {
     /// database_path for index provider db
     #[serde(default = "ProviderConfig::default_database_path")]
     pub database_path: PathBuf,
+    /// zstd level of the blocks stored in the index provider db, disabled if unset.
+    /// Levels zstd doesn't support fail at startup
+    #[serde(default)]
+    pub database_compression: Option<i32>,
 }
 
 impl ProviderConfig {
@@ -30,6 +34,7 @@ impl Default for ProviderConfig {
             domain: None,
             indexer_url: Self::default_indexer_url(),
             database_path: Self::default_database_path(),
+            database_compression: None,
         }
     }
 }
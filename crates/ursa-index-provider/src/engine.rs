@@ -192,10 +192,10 @@ where
     }
 
     pub async fn publish_local(&mut self, root_cid: Cid, file_size: u64) -> Result<()> {
-        let (listener_addresses_sender, listener_addresses_receiver) = oneshot::channel();
+        let (advertised_addresses_sender, advertised_addresses_receiver) = oneshot::channel();
         self.network_command_sender
-            .send(NetworkCommand::GetListenerAddresses {
-                sender: listener_addresses_sender,
+            .send(NetworkCommand::GetAdvertisedAddresses {
+                sender: advertised_addresses_sender,
             })?;
 
         let context_id = root_cid.to_bytes();
@@ -205,9 +205,9 @@ where
         );
         let peer_id = PeerId::from(self.provider.keypair().public());
 
-        let listener_addresses = listener_addresses_receiver.await?;
+        let advertised_addresses = advertised_addresses_receiver.await?;
         let mut addresses = vec![self.server_address.to_string()];
-        for la in listener_addresses {
+        for la in advertised_addresses {
             let mut address = Multiaddr::empty();
             for protocol in la.into_iter() {
                 match protocol {
@@ -0,0 +1,10 @@
+use crate::Recorder;
+use libp2p::bandwidth::BandwidthSinks;
+use metrics::absolute_counter;
+
+impl Recorder for BandwidthSinks {
+    fn record(&self) {
+        absolute_counter!("network_inbound_bytes", self.total_inbound());
+        absolute_counter!("network_outbound_bytes", self.total_outbound());
+    }
+}
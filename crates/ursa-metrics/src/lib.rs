@@ -2,6 +2,7 @@ use lazy_static::lazy_static;
 use prometheus::Registry;
 use std::sync::Arc;
 
+mod bandwidth;
 mod gossipsub;
 mod identify;
 mod kad;
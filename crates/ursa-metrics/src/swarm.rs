@@ -21,6 +21,11 @@ impl<TBvEv, THandleErr> Recorder for SwarmEvent<TBvEv, THandleErr> {
                     "swarm_connections_established",
                     vec![Role::from(endpoint.clone()).into()]
                 );
+                increment_gauge!(
+                    "swarm_open_connections",
+                    1.0,
+                    vec![Role::from(endpoint.clone()).into()]
+                );
             }
             SwarmEvent::ConnectionClosed {
                 endpoint,
@@ -32,6 +37,11 @@ impl<TBvEv, THandleErr> Recorder for SwarmEvent<TBvEv, THandleErr> {
                     "swarm_connections_closed",
                     vec![Role::from(endpoint.clone()).into()]
                 );
+                decrement_gauge!(
+                    "swarm_open_connections",
+                    1.0,
+                    vec![Role::from(endpoint.clone()).into()]
+                );
 
                 // If the last connection to a peer is closed, decrement the protocols identified by them
                 if *num_established == 0 {
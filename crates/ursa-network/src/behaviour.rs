@@ -28,7 +28,10 @@ use libp2p::{
     },
     identify::{Behaviour as Identify, Config as IdentifyConfig},
     identity::Keypair,
-    kad::{store::MemoryStore, Kademlia, KademliaConfig},
+    kad::{
+        store::{MemoryStore, MemoryStoreConfig},
+        Kademlia, KademliaConfig,
+    },
     mdns::tokio::Behaviour as Mdns,
     multiaddr::Protocol,
     ping::Behaviour as Ping,
@@ -54,6 +57,7 @@ use crate::gossipsub::build_gossipsub;
 use crate::{
     codec::protocol::{UrsaExchangeCodec, UrsaProtocol},
     config::NetworkConfig,
+    utils::routing_table,
 };
 
 pub const IPFS_PROTOCOL: &str = "ipfs/0.1.0";
@@ -133,7 +137,7 @@ where
             .expect("PeerScoreParams and PeerScoreThresholds");
 
         // Setup the bitswap behaviour
-        let bitswap = Bitswap::new(BitswapConfig::default(), bitswap_store);
+        let mut bitswap = Bitswap::new(BitswapConfig::default(), bitswap_store);
 
         if let Err(e) = bitswap.register_metrics(&BITSWAP_REGISTRY) {
             // cargo tests will attempt to register duplicate registries, can ignore safely
@@ -193,14 +197,14 @@ where
 
         // setup the kademlia behaviour
         let mut kad = {
-            let store = MemoryStore::new(local_peer_id);
-            let replication_factor = NonZeroUsize::new(config.kad_replication_factor).unwrap();
-            let mut kad_config = KademliaConfig::default();
-            kad_config
-                .set_protocol_names(vec![Cow::from(KAD_PROTOCOL)])
-                .set_replication_factor(replication_factor);
-
-            Kademlia::with_config(local_peer_id, store, kad_config.clone())
+            let store = MemoryStore::with_config(
+                local_peer_id,
+                MemoryStoreConfig {
+                    max_value_bytes: config.kad_max_record_size,
+                    ..Default::default()
+                },
+            );
+            Kademlia::with_config(local_peer_id, store, kad_config(config))
         };
 
         // Set up the Graphsync behaviour.
@@ -218,16 +222,24 @@ where
             }
         }
 
-        if !config.bootstrapper && !config.bootstrap_nodes.is_empty() {
-            if let Err(e) = kad.bootstrap() {
-                warn!("Failed to bootstrap: {}", e);
-            } else {
-                info!("Bootstrapping into the network...");
+        // restore the routing table of the previous run
+        if let Some(path) = config.routing_table_path.as_deref().filter(|p| p.exists()) {
+            match routing_table::load(path) {
+                Ok(entries) => {
+                    info!("Restoring {} routing table entries", entries.len());
+                    for (peer_id, addr) in entries {
+                        if !config.blocklist.contains(&peer_id) {
+                            // bitswap may dial them for blocks the connected peers lack
+                            bitswap.add_address(&peer_id, addr.clone());
+                            kad.add_address(&peer_id, addr);
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to restore the routing table: {e:?}"),
             }
-        } else {
-            warn!("Skipping bootstrap");
         }
 
+        // bootstrapped by the service once listening
         Behaviour {
             ping,
             autonat,
@@ -287,3 +299,23 @@ where
         Ok(self.bitswap.sync(cid, providers, iter::once(cid)))
     }
 }
+
+/// Kademlia config of the node, the protocol name and replication factor are shared by the network.
+fn kad_config(config: &NetworkConfig) -> KademliaConfig {
+    // validated by the service
+    let replication_factor = NonZeroUsize::new(config.kad_replication_factor).unwrap();
+    let mut kad_config = KademliaConfig::default();
+    kad_config
+        .set_protocol_names(vec![Cow::from(KAD_PROTOCOL)])
+        .set_replication_factor(replication_factor)
+        .set_query_timeout(kad_query_timeout(config));
+    kad_config
+}
+
+/// Timeout of the kademlia queries, the libp2p default of 60 seconds if unset.
+pub(crate) fn kad_query_timeout(config: &NetworkConfig) -> Duration {
+    match config.kad_query_timeout_secs {
+        0 => Duration::from_secs(60),
+        secs => Duration::from_secs(secs),
+    }
+}
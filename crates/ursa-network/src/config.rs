@@ -1,7 +1,9 @@
-use libp2p::Multiaddr;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
+use crate::utils::listen::is_public;
+
 /// Ursa Configuration
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct NetworkConfig {
@@ -30,6 +32,11 @@ pub struct NetworkConfig {
     /// Database path.
     #[serde(default = "NetworkConfig::default_database_path")]
     pub database_path: PathBuf,
+    /// zstd level of the blocks stored in the database, decompressed and checked against
+    /// their cid on read. Levels zstd doesn't support, such as those above 22, fail at startup.
+    /// Disabled by default
+    #[serde(default)]
+    pub database_compression: Option<i32>,
     /// user identity name
     #[serde(default = "NetworkConfig::default_identity")]
     pub identity: String,
@@ -40,12 +47,166 @@ pub struct NetworkConfig {
     /// Defaults to devnet tracker.
     #[serde(default = "NetworkConfig::default_tracker")]
     pub tracker: String,
-    /// Determines the number of closest peers to which a record is replicated
+    /// Determines the number of closest peers to which a record is replicated, must be non zero.
+    /// Defaults to 8
     #[serde(default = "NetworkConfig::default_kad_replication_factor")]
     pub kad_replication_factor: usize,
+    /// Max size in bytes of a kademlia record value, larger records are rejected on put.
+    /// Defaults to 65KiB
+    #[serde(default = "NetworkConfig::default_kad_max_record_size")]
+    pub kad_max_record_size: usize,
+    /// Random kademlia walks run every `kad_walk_interval`, moved by up to this percentage
+    /// of it either way so nodes started together don't walk in lockstep. The first bootstrap,
+    /// a second after the listen address, is jittered the same way. Defaults to 10
+    #[serde(default = "NetworkConfig::default_bootstrap_jitter_percent")]
+    pub bootstrap_jitter_percent: u8,
+    /// Seconds the first kademlia bootstrap waits for a confirmed listen address to advertise,
+    /// bootstrapping without one afterwards. 0 bootstraps right away. Defaults to 5
+    #[serde(default = "NetworkConfig::default_bootstrap_listen_grace")]
+    pub bootstrap_listen_grace: u64,
+    /// Timeout in seconds of the kademlia queries, e.g. bootstraps and provider lookups,
+    /// 0 uses the libp2p default of 60 seconds. Defaults to 0
+    #[serde(default = "NetworkConfig::default_kad_query_timeout_secs")]
+    pub kad_query_timeout_secs: u64,
     /// Interval to run random kademlia walks to refresh the routing table. Defaults to 5 minutes
     #[serde(default = "NetworkConfig::default_kad_walk_interval")]
     pub kad_walk_interval: u64,
+    /// Persist the kademlia routing table here, restoring it on startup. Disabled by default
+    #[serde(default)]
+    pub routing_table_path: Option<PathBuf>,
+    /// Interval in seconds to persist the routing table, must be greater than 0. Defaults to 5 minutes
+    #[serde(default = "NetworkConfig::default_routing_table_interval")]
+    pub routing_table_interval: u64,
+    /// Peers refused connections and kept out of the routing table. Defaults to none
+    #[serde(default)]
+    pub blocklist: Vec<PeerId>,
+    /// Random kademlia walks only run while fewer peers are known, 0 always runs them. Defaults to 12
+    #[serde(default = "NetworkConfig::default_bootstrap_peer_threshold")]
+    pub bootstrap_peer_threshold: usize,
+    /// Stop dialing the bootstrap nodes left once mDNS found `bootstrap_peer_threshold` local peers,
+    /// saving WAN traffic on isolated LANs, and dial them again once fewer local peers are left.
+    /// Local peers always count toward the threshold of the kademlia walks. Defaults to false
+    #[serde(default = "NetworkConfig::default_prefer_local_peers")]
+    pub prefer_local_peers: bool,
+    /// Fetch blocks from the connected peers first, only asking the 8 most reliable other peers of
+    /// the routing table if they don't have it, instead of asking all of them at once. Defaults
+    /// to true
+    #[serde(default = "NetworkConfig::default_prefer_connected_providers")]
+    pub prefer_connected_providers: bool,
+    /// Distinct peers a single bitswap fetch asks for a cid at once, 0 is unbounded. Falling back
+    /// from the connected peers to the rest of the routing table asks at most this many more.
+    /// The unconnected peers asked are dialed and count toward the connection limits of the swarm,
+    /// 1024 established connections each way and 8 per peer, until reaped once idle beyond
+    /// `target_connections`. Defaults to 8
+    #[serde(default = "NetworkConfig::default_max_providers_per_fetch")]
+    pub max_providers_per_fetch: usize,
+    /// Interval in seconds to close idle connections. Defaults to 1 minute
+    #[serde(default = "NetworkConfig::default_idle_reap_interval")]
+    pub idle_reap_interval: u64,
+    /// Seconds without activity after which a connection is idle. Defaults to 10 minutes
+    #[serde(default = "NetworkConfig::default_idle_connection_timeout")]
+    pub idle_connection_timeout: u64,
+    /// Idle connections are only closed while connected to more peers than this
+    #[serde(default = "NetworkConfig::default_target_connections")]
+    pub target_connections: usize,
+    /// Number of addresses of a peer dialed concurrently. Defaults to 8
+    #[serde(default = "NetworkConfig::default_dial_concurrency_factor")]
+    pub dial_concurrency_factor: u8,
+    /// Bootstrap nodes dialed concurrently at startup, 0 dials all at once. Defaults to 4
+    #[serde(default = "NetworkConfig::default_bootstrap_dial_concurrency")]
+    pub bootstrap_dial_concurrency: usize,
+    /// Seconds a peer whose dial failed is not dialed again, cleared once connected.
+    /// 0 disables the cooldown. Defaults to 30
+    #[serde(default = "NetworkConfig::default_dial_cooldown")]
+    pub dial_cooldown: u64,
+    /// Outstanding bitswap queries per provider, each on its own substreams of the peer
+    /// connection. Further queries to the provider are queued, 0 is unbounded. Defaults to 8
+    #[serde(default = "NetworkConfig::default_max_requests_per_peer")]
+    pub max_requests_per_peer: usize,
+    /// Gossipsub messages queued for publishing, further publishes fail instead of
+    /// piling up in the outbound queues of the peers. Defaults to 1024
+    #[serde(default = "NetworkConfig::default_gossip_outbound_limit")]
+    pub gossip_outbound_limit: usize,
+    /// Max size in bytes of a gossipsub message. Larger publishes are rejected before they are
+    /// queued, bounding what each one adds to the send queue of every peer. Defaults to 4MiB
+    #[serde(default = "NetworkConfig::default_gossip_max_transmit_size")]
+    pub gossip_max_transmit_size: usize,
+    /// Seconds in-flight transfers are given to complete on shutdown. Defaults to 5
+    #[serde(default = "NetworkConfig::default_shutdown_grace_period")]
+    pub shutdown_grace_period: u64,
+    /// Seconds a tcp connection is given to complete the noise and muxer upgrade,
+    /// stalled peers are dropped afterwards. Must be greater than 0. Defaults to 10
+    #[serde(default = "NetworkConfig::default_transport_upgrade_timeout")]
+    pub transport_upgrade_timeout: u64,
+    /// Concurrent substreams a peer may open on a tcp connection. Past it, yamux closes the
+    /// connection and mplex resets the new substreams. Must be greater than 0. Defaults to 512
+    #[serde(default = "NetworkConfig::default_max_substreams_per_connection")]
+    pub max_substreams_per_connection: usize,
+    /// Listen addresses advertised as external addresses and in the index provider advertisements:
+    /// `"public"` skips the loopback, private and link-local addresses, `"all"` advertises
+    /// every address and `{ allowlist = [...] }` only the listed ones. Defaults to public
+    #[serde(default)]
+    pub advertise_addresses: AdvertisePolicy,
+    /// Start without `swarm_addrs`, only dialing out. Fails to start otherwise. Defaults to true
+    #[serde(default = "NetworkConfig::default_allow_dial_only")]
+    pub allow_dial_only: bool,
+    /// Transports the node listens and dials on, `swarm_addrs` of the other transports are
+    /// skipped. The relay client requires tcp. Defaults to both
+    #[serde(default)]
+    pub transport: TransportKind,
+    /// Bytes per second uploaded to all the peers together, writes past it wait for the
+    /// budget to refill. Must be greater than 0. Unlimited by default
+    #[serde(default)]
+    pub max_upload_bytes_per_sec: Option<u64>,
+}
+
+/// Transports of the swarm.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// Tcp upgraded with noise and yamux or mplex.
+    Tcp,
+    /// QUIC v1, encrypted and multiplexed on its own.
+    Quic,
+    /// QUIC, falling over to tcp for the addresses without QUIC.
+    #[default]
+    Both,
+}
+
+impl TransportKind {
+    /// Whether the transport can listen on and dial `addr`.
+    pub fn supports(&self, addr: &Multiaddr) -> bool {
+        let tcp = addr.iter().any(|p| matches!(p, Protocol::Tcp(_)));
+        let quic = addr.iter().any(|p| matches!(p, Protocol::QuicV1));
+        match self {
+            TransportKind::Tcp => tcp,
+            TransportKind::Quic => quic,
+            TransportKind::Both => tcp || quic,
+        }
+    }
+}
+
+/// Which of the listen addresses are advertised to the network.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum AdvertisePolicy {
+    /// Only the addresses reachable from other networks.
+    #[default]
+    Public,
+    /// Every listen address.
+    All,
+    /// Only these exact addresses.
+    Allowlist(Vec<Multiaddr>),
+}
+
+impl AdvertisePolicy {
+    pub fn allows(&self, addr: &Multiaddr) -> bool {
+        match self {
+            AdvertisePolicy::Public => is_public(addr),
+            AdvertisePolicy::All => true,
+            AdvertisePolicy::Allowlist(allowed) => allowed.contains(addr),
+        }
+    }
 }
 
 impl NetworkConfig {
@@ -91,9 +252,75 @@ impl NetworkConfig {
     fn default_kad_replication_factor() -> usize {
         8
     }
+    fn default_bootstrap_jitter_percent() -> u8 {
+        10
+    }
+    fn default_bootstrap_listen_grace() -> u64 {
+        5
+    }
+    fn default_kad_query_timeout_secs() -> u64 {
+        0
+    }
+    fn default_kad_max_record_size() -> usize {
+        65 * 1024
+    }
     fn default_kad_walk_interval() -> u64 {
         300
     }
+    fn default_routing_table_interval() -> u64 {
+        300
+    }
+    fn default_bootstrap_peer_threshold() -> usize {
+        12
+    }
+    fn default_prefer_local_peers() -> bool {
+        false
+    }
+    fn default_prefer_connected_providers() -> bool {
+        true
+    }
+    fn default_max_providers_per_fetch() -> usize {
+        8
+    }
+    fn default_idle_reap_interval() -> u64 {
+        60
+    }
+    fn default_idle_connection_timeout() -> u64 {
+        600
+    }
+    fn default_target_connections() -> usize {
+        64
+    }
+    fn default_dial_concurrency_factor() -> u8 {
+        8
+    }
+    fn default_bootstrap_dial_concurrency() -> usize {
+        4
+    }
+    fn default_dial_cooldown() -> u64 {
+        30
+    }
+    fn default_gossip_outbound_limit() -> usize {
+        1024
+    }
+    fn default_gossip_max_transmit_size() -> usize {
+        4 * 1024 * 1024
+    }
+    fn default_max_requests_per_peer() -> usize {
+        8
+    }
+    fn default_shutdown_grace_period() -> u64 {
+        5
+    }
+    fn default_transport_upgrade_timeout() -> u64 {
+        10
+    }
+    fn default_max_substreams_per_connection() -> usize {
+        512
+    }
+    fn default_allow_dial_only() -> bool {
+        true
+    }
 }
 
 impl Default for NetworkConfig {
@@ -107,11 +334,39 @@ impl Default for NetworkConfig {
             bootstrap_nodes: Self::default_bootstrap_nodes(),
             swarm_addrs: Self::default_swarm_addrs(),
             database_path: Self::default_database_path(),
+            database_compression: None,
             identity: Self::default_identity(),
             tracker: Self::default_tracker(),
             keystore_path: Self::default_keystore_path(),
             kad_replication_factor: Self::default_kad_replication_factor(),
+            kad_max_record_size: Self::default_kad_max_record_size(),
+            bootstrap_jitter_percent: Self::default_bootstrap_jitter_percent(),
+            bootstrap_listen_grace: Self::default_bootstrap_listen_grace(),
+            kad_query_timeout_secs: Self::default_kad_query_timeout_secs(),
             kad_walk_interval: Self::default_kad_walk_interval(),
+            routing_table_path: None,
+            routing_table_interval: Self::default_routing_table_interval(),
+            blocklist: Vec::new(),
+            bootstrap_peer_threshold: Self::default_bootstrap_peer_threshold(),
+            prefer_local_peers: Self::default_prefer_local_peers(),
+            prefer_connected_providers: Self::default_prefer_connected_providers(),
+            max_providers_per_fetch: Self::default_max_providers_per_fetch(),
+            idle_reap_interval: Self::default_idle_reap_interval(),
+            idle_connection_timeout: Self::default_idle_connection_timeout(),
+            target_connections: Self::default_target_connections(),
+            dial_concurrency_factor: Self::default_dial_concurrency_factor(),
+            bootstrap_dial_concurrency: Self::default_bootstrap_dial_concurrency(),
+            dial_cooldown: Self::default_dial_cooldown(),
+            max_requests_per_peer: Self::default_max_requests_per_peer(),
+            gossip_outbound_limit: Self::default_gossip_outbound_limit(),
+            gossip_max_transmit_size: Self::default_gossip_max_transmit_size(),
+            shutdown_grace_period: Self::default_shutdown_grace_period(),
+            transport_upgrade_timeout: Self::default_transport_upgrade_timeout(),
+            max_substreams_per_connection: Self::default_max_substreams_per_connection(),
+            advertise_addresses: AdvertisePolicy::default(),
+            allow_dial_only: Self::default_allow_dial_only(),
+            transport: TransportKind::default(),
+            max_upload_bytes_per_sec: None,
         }
     }
 }
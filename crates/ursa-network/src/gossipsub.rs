@@ -23,7 +23,6 @@ pub(crate) fn build_gossipsub(keypair: &Keypair, config: &NetworkConfig) -> Goss
     let gossip_lazy = mesh_n;
     // D_out
     let mesh_outbound_min = if is_bootstrapper { 0 } else { (mesh_n / 2) - 1 };
-    let max_transmit_size = 4 * 1024 * 1024;
     let message_id_fn = move |message: &GossipsubMessage| {
         let mut hasher = DefaultHasher::new();
         message.data.hash(&mut hasher);
@@ -37,7 +36,7 @@ pub(crate) fn build_gossipsub(keypair: &Keypair, config: &NetworkConfig) -> Goss
         .mesh_n_high(mesh_n_high)
         // default to mesh_n
         .gossip_lazy(gossip_lazy)
-        .max_transmit_size(max_transmit_size)
+        .max_transmit_size(config.gossip_max_transmit_size)
         .validation_mode(ValidationMode::Strict)
         .message_id_fn(message_id_fn)
         .mesh_outbound_min(mesh_outbound_min)
@@ -8,3 +8,4 @@ mod utils;
 
 pub use self::config::*;
 pub use self::service::*;
+pub use self::utils::listen::is_public;
@@ -22,38 +22,47 @@ use ipld_traversal::{selector::RecursionLimit, Selector};
 use libipld::{Cid, DefaultParams};
 use libp2p::{
     autonat::{Event as AutonatEvent, NatStatus},
+    bandwidth::BandwidthSinks,
     gossipsub::{
         error::{PublishError, SubscriptionError},
         IdentTopic as Topic, MessageId, TopicHash,
     },
     identify::Event as IdentifyEvent,
     identity::Keypair,
-    kad::{BootstrapOk, KademliaEvent, QueryResult},
+    kad::{
+        record::Key, BootstrapOk, GetProvidersOk, GetProvidersResult, KademliaEvent, PutRecordOk,
+        QueryId as KadQueryId, QueryResult, Quorum, Record,
+    },
     mdns::Event as MdnsEvent,
     multiaddr::Protocol,
     ping::Event as PingEvent,
     relay::v2::client::Client as RelayClient,
     request_response::{RequestId, RequestResponseEvent, RequestResponseMessage, ResponseChannel},
-    swarm::{ConnectionHandler, IntoConnectionHandler, NetworkBehaviour},
-    swarm::{ConnectionLimits, SwarmBuilder, SwarmEvent},
+    swarm::{dial_opts::DialOpts, ConnectionHandler, IntoConnectionHandler, NetworkBehaviour},
+    swarm::{AddressScore, ConnectionLimits, SwarmBuilder, SwarmEvent},
     Multiaddr, PeerId, Swarm,
 };
 use libp2p_bitswap::{BitswapEvent, QueryId};
+use metrics::{counter, gauge, histogram, increment_counter};
 use rand::prelude::SliceRandom;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
-    fmt::Debug,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{Debug, Display, Formatter},
+    io,
     num::{NonZeroU8, NonZeroUsize},
+    path::PathBuf,
     sync::Arc,
     time::Duration,
 };
 use tokio::{
     select,
     sync::{
+        broadcast,
         mpsc::{unbounded_channel, UnboundedReceiver as Receiver, UnboundedSender as Sender},
         oneshot,
     },
-    time::{sleep, Instant},
+    time::{sleep, sleep_until, Instant},
 };
 use tracing::{debug, error, info, trace, warn};
 use ursa_metrics::Recorder;
@@ -61,17 +70,31 @@ use ursa_store::{BitswapStorage, GraphSyncStorage, UrsaStore};
 
 use crate::behaviour::KAD_PROTOCOL;
 use crate::codec::protocol::{RequestType, ResponseType};
-use crate::transport::build_transport;
-use crate::utils::cache_summary::CacheSummary;
+use crate::transport::{build_transport, substream_limit_reached};
+use crate::utils::{
+    activity::PeerActivity,
+    bootstrap::{self, BootstrapDials},
+    cache_summary::CacheSummary,
+    dial_cooldown::DialCooldown,
+    listen::dedup_listen_addrs,
+    reputation::ReputationTable,
+    routing_table,
+};
 use crate::{
     behaviour::{Behaviour, BehaviourEvent},
     codec::protocol::{UrsaExchangeRequest, UrsaExchangeResponse},
-    config::NetworkConfig,
+    config::{AdvertisePolicy, NetworkConfig},
 };
 
 pub const URSA_GLOBAL: &str = "/ursa/global";
 pub const MESSAGE_PROTOCOL: &[u8] = b"/ursa/message/0.0.1";
 
+/// Routing table peers we aren't connected to asked for a block, the most reliable first.
+const MAX_UNCONNECTED_PROVIDERS: usize = 8;
+
+/// Delay of the first kademlia bootstrap once it can start, jittered like the walks.
+const INITIAL_BOOTSTRAP_DELAY: Duration = Duration::from_secs(1);
+
 type BlockOneShotSender<T> = oneshot::Sender<Result<T, Error>>;
 type SwarmEventType<S> = SwarmEvent<
 <Behaviour<DefaultParams, S> as NetworkBehaviour>::OutEvent,
@@ -98,13 +121,36 @@ pub enum GossipsubMessage {
         sender: oneshot::Sender<Result<bool, PublishError>>,
     },
     /// Publish a message to a specific topic.
+    /// Fails with [`PublishError::InsufficientPeers`] when no peer is subscribed to the topic,
+    /// and with a [`io::ErrorKind::WouldBlock`] [`PublishError::TransformFailed`] while the
+    /// gossipsub outbound queue is full.
     Publish {
         topic: TopicHash,
         data: Bytes,
-        sender: oneshot::Sender<Result<MessageId, PublishError>>,
+        sender: oneshot::Sender<Result<PublishAck, PublishError>>,
     },
 }
 
+/// A published gossipsub message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishAck {
+    pub message_id: MessageId,
+    /// Peers the message was sent to.
+    pub peers: usize,
+}
+
+/// A gossipsub topic the node is subscribed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicStats {
+    pub topic: TopicHash,
+    /// Peers in the mesh of the topic.
+    pub mesh_peers: usize,
+    /// Messages published by the node.
+    pub published: u64,
+    /// Messages received from peers.
+    pub received: u64,
+}
+
 #[derive(Debug)]
 pub enum GossipsubEvent {
     /// A message has been received.
@@ -141,6 +187,13 @@ pub enum NetworkEvent {
     PeerConnected(PeerId),
     /// An event trigger when remote peer disconnects.
     PeerDisconnected(PeerId),
+    /// All the mDNS addresses of a local peer expired.
+    PeerExpired(PeerId),
+    /// A provider lookup completed with the providers found.
+    Providers {
+        key: Vec<u8>,
+        providers: HashSet<PeerId>,
+    },
     /// A Gossip message request was received from a peer.
     Gossipsub(GossipsubEvent),
     /// A message request was received from a peer.
@@ -151,6 +204,88 @@ pub enum NetworkEvent {
     BitswapWant { cid: Cid, query_id: QueryId },
 }
 
+/// What a peer reported about itself through identify.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerIdentity {
+    pub agent_version: String,
+    pub protocol_version: String,
+    pub protocols: Vec<String>,
+    /// Address the peer observed us at.
+    pub observed_addr: Multiaddr,
+}
+
+/// Connections and identity of a connected peer, see [`UrsaService::peer_info`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub peer_id: PeerId,
+    /// Open connections the peer dialed.
+    pub inbound: usize,
+    /// Open connections we dialed.
+    pub outbound: usize,
+    /// Identify info, once received.
+    pub identity: Option<PeerIdentity>,
+}
+
+/// Liveness of the node, see [`UrsaService::node_info`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub peer_id: PeerId,
+    pub listen_addresses: Vec<Multiaddr>,
+    /// Connected peers.
+    pub peers: usize,
+    /// Bootstrap nodes configured.
+    pub bootstrap_nodes: usize,
+    /// Whether a kademlia bootstrap completed.
+    pub bootstrapped: bool,
+}
+
+/// Peer connectivity changes and discoveries, broadcast to the subscribers of
+/// [`UrsaService::subscribe_peer_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerEvent {
+    Connected(PeerId),
+    Disconnected(PeerId),
+    /// A peer we aren't connected to was discovered through kademlia or mDNS.
+    Discovered(PeerId, Vec<Multiaddr>),
+    /// All the mDNS addresses of a local peer expired.
+    Expired(PeerId),
+    /// A provider lookup completed with the providers of `key` found.
+    Providers {
+        key: Vec<u8>,
+        providers: HashSet<PeerId>,
+    },
+}
+
+/// Peer events buffered per subscriber, slower subscribers skip the oldest events.
+const PEER_EVENTS_CAPACITY: usize = 128;
+
+/// An in-flight kademlia provider lookup.
+struct ProviderLookup {
+    key: Vec<u8>,
+    providers: HashSet<PeerId>,
+    /// Everyone waiting on the lookup of `key`.
+    senders: Vec<oneshot::Sender<Result<HashSet<PeerId>>>>,
+}
+
+/// A record value exceeds the max record size of the kademlia store.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RecordTooLarge {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl Display for RecordTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Record value of {} bytes exceeds the max record size of {} bytes",
+            self.size, self.max
+        )
+    }
+}
+
+impl std::error::Error for RecordTooLarge {}
+
 #[derive(Debug)]
 pub enum NetworkCommand {
     GetBitswap {
@@ -158,6 +293,14 @@ pub enum NetworkCommand {
         sender: BlockOneShotSender<()>,
     },
 
+    /// Sync the dag of `cid` from `peer_id` only, dialing the peer at `address` if not connected.
+    FetchFrom {
+        cid: Cid,
+        peer_id: PeerId,
+        address: Option<Multiaddr>,
+        sender: BlockOneShotSender<()>,
+    },
+
     Put {
         cid: Cid,
         sender: oneshot::Sender<Result<()>>,
@@ -167,10 +310,88 @@ pub enum NetworkCommand {
         sender: oneshot::Sender<HashSet<PeerId>>,
     },
 
+    /// Addresses known for each of `peers`, from the routing table and the connections.
+    GetPeerAddresses {
+        peers: Vec<PeerId>,
+        sender: oneshot::Sender<HashMap<PeerId, Vec<Multiaddr>>>,
+    },
+
     GetListenerAddresses {
         sender: oneshot::Sender<Vec<Multiaddr>>,
     },
 
+    /// Listen addresses allowed by the advertise policy, see [`UrsaService::advertised_addresses`].
+    GetAdvertisedAddresses {
+        sender: oneshot::Sender<Vec<Multiaddr>>,
+    },
+
+    GetPeerReputation {
+        sender: oneshot::Sender<HashMap<PeerId, i64>>,
+    },
+
+    GetNodeInfo {
+        sender: oneshot::Sender<NodeInfo>,
+    },
+
+    /// Resolves with `None` if the peer isn't connected.
+    GetPeerInfo {
+        peer_id: PeerId,
+        sender: oneshot::Sender<Option<PeerInfo>>,
+    },
+
+    /// Resolves with the gossipsub topics the node is subscribed to.
+    ListSubscriptions {
+        sender: oneshot::Sender<Vec<TopicStats>>,
+    },
+
+    /// Store a kademlia record, resolves once the record is replicated to at least one peer.
+    /// Values larger than `kad_max_record_size` fail with [`RecordTooLarge`].
+    PutRecord {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        sender: oneshot::Sender<Result<()>>,
+    },
+
+    /// Announce the node as a provider of `key` in the dht.
+    StartProviding {
+        key: Vec<u8>,
+        sender: oneshot::Sender<Result<()>>,
+    },
+
+    /// Resolves with the providers of `key` found in the dht.
+    GetProviders {
+        key: Vec<u8>,
+        sender: oneshot::Sender<Result<HashSet<PeerId>>>,
+    },
+
+    /// Disconnect from every connected peer, resolves with the number of peers dropped.
+    DisconnectAll {
+        sender: oneshot::Sender<usize>,
+    },
+
+    /// Refuse connections from `peer_id` and drop it from the routing table, see [`UrsaService::block_peer`].
+    BlockPeer {
+        peer_id: PeerId,
+        sender: oneshot::Sender<()>,
+    },
+
+    /// Accept connections from `peer_id` again, see [`UrsaService::unblock_peer`].
+    UnblockPeer {
+        peer_id: PeerId,
+        sender: oneshot::Sender<()>,
+    },
+
+    /// Resolves with a new subscription to the [`PeerEvent`]s.
+    SubscribePeerEvents {
+        sender: oneshot::Sender<broadcast::Receiver<PeerEvent>>,
+    },
+
+    /// Resolves with the number of peers once at least `min` peers are connected.
+    AwaitPeers {
+        min: usize,
+        sender: oneshot::Sender<usize>,
+    },
+
     SendRequest {
         peer_id: PeerId,
         request: Box<UrsaExchangeRequest>,
@@ -182,12 +403,25 @@ pub enum NetworkCommand {
         message: GossipsubMessage,
     },
 
+    /// Stop the service once in-flight transfers completed, or the shutdown grace period passed.
+    Shutdown,
+
     #[cfg(test)]
     GetPeerContent {
         sender: oneshot::Sender<HashMap<PeerId, CacheSummary>>,
     },
 }
 
+/// Block, providers and fallback providers of a bitswap query waiting for a
+/// free provider, and whether it only asks connected peers.
+type QueuedQuery = (Cid, Vec<PeerId>, Vec<PeerId>, bool);
+
+type QueuedPublish = (
+    TopicHash,
+    Bytes,
+    oneshot::Sender<Result<PublishAck, PublishError>>,
+);
+
 pub struct UrsaService<S>
 where
     S: Blockstore + Clone + Store + Send + Sync + 'static,
@@ -204,24 +438,112 @@ where
     event_sender: Sender<NetworkEvent>,
     /// Handles events received by the ursa network.
     _event_receiver: Receiver<NetworkEvent>,
+    /// Broadcasts peer connectivity changes to subscribers.
+    peer_events: broadcast::Sender<PeerEvent>,
     /// Bitswap pending queries.
     bitswap_queries: FnvHashMap<QueryId, Cid>,
+    /// Providers left to ask if a bitswap query fails.
+    bitswap_fallbacks: FnvHashMap<QueryId, Vec<PeerId>>,
+    /// Bitswap queries asking only connected peers.
+    connected_queries: HashSet<QueryId>,
+    /// Ask the connected peers for blocks before the rest of the routing table.
+    prefer_connected_providers: bool,
+    /// Peers a bitswap fetch asks at once, 0 is unbounded.
+    max_providers_per_fetch: usize,
     /// hashmap for keeping track of rpc response channels.
     response_channels: FnvHashMap<Cid, Vec<BlockOneShotSender<()>>>,
     /// Pending requests.
     _pending_requests: HashMap<RequestId, ResponseChannel<UrsaExchangeResponse>>,
     /// Pending responses.
-    pending_responses: HashMap<RequestId, oneshot::Sender<Result<UrsaExchangeResponse>>>,
+    pending_responses: HashMap<RequestId, (PeerId, oneshot::Sender<Result<UrsaExchangeResponse>>)>,
+    /// Outstanding bitswap queries per provider.
+    outstanding_queries: HashMap<PeerId, usize>,
+    /// Providers asked by each outstanding bitswap query.
+    query_providers: FnvHashMap<QueryId, Vec<PeerId>>,
+    /// Bitswap queries waiting for the provider to finish one of its queries.
+    queued_queries: HashMap<PeerId, VecDeque<QueuedQuery>>,
+    /// Max outstanding bitswap queries per provider, 0 is unbounded.
+    max_requests_per_peer: usize,
+    /// Max gossipsub messages waiting to be published.
+    gossip_outbound_limit: usize,
+    /// Max size in bytes of a published gossipsub message.
+    gossip_max_transmit_size: usize,
+    /// Gossipsub messages waiting to be published, one per turn of the event loop.
+    gossip_outbound: VecDeque<QueuedPublish>,
     /// Connected peers.
     peers: HashSet<PeerId>,
+    /// Last activity of connected peers.
+    peer_activity: PeerActivity,
+    /// Peer ids of the bootstrap nodes, never closed for being idle.
+    bootstrap_peers: HashSet<PeerId>,
     /// Bootstrap multiaddrs.
     bootstraps: Vec<Multiaddr>,
+    /// Bootstrap nodes left to dial at startup.
+    bootstrap_dials: BootstrapDials,
+    /// Whether a kademlia bootstrap completed.
+    bootstrapped: bool,
+    /// Whether the first kademlia bootstrap waits for a listen address.
+    pending_bootstrap: bool,
+    /// Seconds the first bootstrap waits for a listen address.
+    bootstrap_listen_grace: u64,
+    /// When the scheduled first bootstrap runs.
+    bootstrap_at: Option<Instant>,
     /// Summarizes the cached content.
     cached_content: CacheSummary,
     /// Content summaries from other nodes.
     peer_cached_content: HashMap<PeerId, CacheSummary>,
     /// Interval for random Kademlia walks.
     kad_walk_interval: u64,
+    /// Percentage of `kad_walk_interval` the walks are randomly moved by.
+    bootstrap_jitter_percent: u8,
+    /// Peers refused connections and kept out of the routing table.
+    blocked_peers: HashSet<PeerId>,
+    /// Which listen addresses are advertised.
+    advertise_addresses: AdvertisePolicy,
+    /// Where the routing table is persisted.
+    routing_table_path: Option<PathBuf>,
+    /// Interval for persisting the routing table.
+    routing_table_interval: u64,
+    /// Known peers from which random walks stop, 0 is unbounded.
+    bootstrap_peer_threshold: usize,
+    /// Stop dialing bootstrap nodes once mDNS found enough local peers.
+    prefer_local_peers: bool,
+    /// Reputation of peers based on past interactions.
+    reputation: ReputationTable,
+    /// Peers not dialed again since their dial failed.
+    dial_cooldown: DialCooldown,
+    /// Identify info of the connected peers.
+    peer_identities: HashMap<PeerId, PeerIdentity>,
+    /// Open connections of the connected peers, inbound and outbound.
+    peer_connections: HashMap<PeerId, (usize, usize)>,
+    /// Addresses of the peers discovered by mDNS.
+    mdns_addresses: HashMap<PeerId, HashSet<Multiaddr>>,
+    /// Gossipsub messages published and received per topic.
+    topic_messages: HashMap<TopicHash, (u64, u64)>,
+    /// Pending waiters for a minimum number of connected peers.
+    peer_waiters: Vec<(usize, oneshot::Sender<usize>)>,
+    /// Pending kademlia record puts.
+    pending_records: HashMap<KadQueryId, oneshot::Sender<Result<()>>>,
+    /// Pending provider announcements, with whom to tell once complete.
+    pending_provides: HashMap<KadQueryId, Option<oneshot::Sender<Result<()>>>>,
+    /// Pending provider lookups.
+    provider_lookups: HashMap<KadQueryId, ProviderLookup>,
+    /// Max size of a kademlia record value.
+    max_record_size: usize,
+    /// Interval for closing idle connections.
+    idle_reap_interval: u64,
+    /// Duration without activity after which a connection is idle.
+    idle_connection_timeout: Duration,
+    /// Connection count idle connections are closed down to.
+    target_connections: usize,
+    /// Time given to in-flight transfers to complete on shutdown.
+    shutdown_grace_period: Duration,
+    /// Set by [`NetworkCommand::Shutdown`], stops the event loop.
+    shutting_down: bool,
+    /// Responses to inbound requests not written out yet.
+    inbound_responses: HashSet<RequestId>,
+    /// Bytes sent and received over all connections.
+    bandwidth: Arc<BandwidthSinks>,
 }
 
 impl<S> UrsaService<S>
@@ -233,8 +555,8 @@ where
     /// For ursa `keypair` we use ed25519 either
     /// checking for a local store or creating a new keypair.
     ///
-    /// For ursa `transport` we build a default QUIC layer and
-    /// fail over to tcp.
+    /// For ursa `transport` we build the QUIC and tcp layers selected by
+    /// [`NetworkConfig`] `transport`, by default QUIC failing over to tcp.
     ///
     /// For ursa behaviour we use [`Behaviour`].
     ///
@@ -243,6 +565,10 @@ where
     ///
     pub fn new(keypair: Keypair, config: &NetworkConfig, store: Arc<UrsaStore<S>>) -> Result<Self> {
         let local_peer_id = PeerId::from(keypair.public());
+        let config = &NetworkConfig {
+            bootstrap_nodes: bootstrap::without_local(&config.bootstrap_nodes, &local_peer_id),
+            ..config.clone()
+        };
 
         let (relay_transport, relay_client) = if config.relay_client {
             if !config.autonat {
@@ -256,9 +582,22 @@ where
             (None, None)
         };
 
+        if config.kad_replication_factor == 0 {
+            return Err(anyhow!("kad_replication_factor must be greater than 0"));
+        }
+        if config.gossip_outbound_limit == 0 {
+            return Err(anyhow!("gossip_outbound_limit must be greater than 0"));
+        }
+        if config.gossip_max_transmit_size == 0 {
+            return Err(anyhow!("gossip_max_transmit_size must be greater than 0"));
+        }
+        if config.routing_table_interval == 0 {
+            return Err(anyhow!("routing_table_interval must be greater than 0"));
+        }
+
         let bitswap_store = BitswapStorage(store.clone());
         let graphsync_store = GraphSyncStorage(store.clone());
-        let transport = build_transport(&keypair, config, relay_transport);
+        let (transport, bandwidth) = build_transport(&keypair, config, relay_transport)?;
         let mut peers = HashSet::new();
         let behaviour = Behaviour::new(
             &keypair,
@@ -279,16 +618,41 @@ where
         let mut swarm = SwarmBuilder::with_tokio_executor(transport, behaviour, local_peer_id)
             .notify_handler_buffer_size(NonZeroUsize::new(2 << 7).unwrap())
             .connection_event_buffer_size(2 << 7)
-            .dial_concurrency_factor(NonZeroU8::new(8).unwrap())
+            .dial_concurrency_factor(
+                NonZeroU8::new(config.dial_concurrency_factor)
+                    .ok_or_else(|| anyhow!("dial_concurrency_factor must be greater than 0"))?,
+            )
             .connection_limits(limits)
             .build();
 
-        for to_dial in &config.bootstrap_nodes {
-            swarm.dial(to_dial.clone())?;
+        if config.swarm_addrs.is_empty() {
+            if !config.allow_dial_only {
+                return Err(anyhow!(
+                    "No swarm addresses to listen on and allow_dial_only is disabled"
+                ));
+            }
+            warn!("No swarm addresses to listen on, the node won't accept inbound connections");
         }
-
-        for addr in &config.swarm_addrs {
-            Swarm::listen_on(&mut swarm, addr.clone())
+        let listen_addrs = dedup_listen_addrs(&config.swarm_addrs)?;
+        let unsupported = listen_addrs
+            .iter()
+            .filter(|addr| !config.transport.supports(addr))
+            .count();
+        if unsupported > 0 && unsupported == listen_addrs.len() {
+            return Err(anyhow!(
+                "None of the swarm addresses are supported by the {:?} transport",
+                config.transport
+            ));
+        }
+        for addr in listen_addrs {
+            if !config.transport.supports(&addr) {
+                warn!(
+                    "Skipping listen address {addr}, not supported by the {:?} transport",
+                    config.transport
+                );
+                continue;
+            }
+            Swarm::listen_on(&mut swarm, addr)
                 .map_err(|err| anyhow!("{}", err))
                 .unwrap();
         }
@@ -302,23 +666,83 @@ where
         let (event_sender, _event_receiver) = unbounded_channel();
         let (command_sender, command_receiver) = unbounded_channel();
 
-        Ok(UrsaService {
+        let mut service = UrsaService {
             swarm,
             store,
             command_sender,
             command_receiver,
             event_sender,
             _event_receiver,
+            peer_events: broadcast::channel(PEER_EVENTS_CAPACITY).0,
             response_channels: Default::default(),
             bitswap_queries: Default::default(),
+            bitswap_fallbacks: Default::default(),
+            connected_queries: Default::default(),
+            prefer_connected_providers: config.prefer_connected_providers,
+            max_providers_per_fetch: config.max_providers_per_fetch,
             _pending_requests: HashMap::default(),
             pending_responses: HashMap::default(),
+            outstanding_queries: HashMap::default(),
+            query_providers: Default::default(),
+            queued_queries: HashMap::default(),
+            max_requests_per_peer: config.max_requests_per_peer,
+            gossip_outbound_limit: config.gossip_outbound_limit,
+            gossip_max_transmit_size: config.gossip_max_transmit_size,
+            gossip_outbound: VecDeque::new(),
             peers,
             bootstraps: config.bootstrap_nodes.clone(),
+            bootstrap_dials: BootstrapDials::new(
+                config.bootstrap_nodes.clone(),
+                config.bootstrap_dial_concurrency,
+            ),
+            peer_activity: PeerActivity::default(),
+            bootstrapped: false,
+            pending_bootstrap: !config.bootstrapper && !config.bootstrap_nodes.is_empty(),
+            bootstrap_listen_grace: config.bootstrap_listen_grace,
+            bootstrap_at: None,
+            bootstrap_peers: config
+                .bootstrap_nodes
+                .iter()
+                .filter_map(bootstrap::peer_id)
+                .collect(),
             cached_content: CacheSummary::default(),
             peer_cached_content: HashMap::default(),
             kad_walk_interval: config.kad_walk_interval,
-        })
+            bootstrap_jitter_percent: config.bootstrap_jitter_percent,
+            blocked_peers: config.blocklist.iter().copied().collect(),
+            advertise_addresses: config.advertise_addresses.clone(),
+            routing_table_path: config.routing_table_path.clone(),
+            routing_table_interval: config.routing_table_interval,
+            bootstrap_peer_threshold: config.bootstrap_peer_threshold,
+            prefer_local_peers: config.prefer_local_peers,
+            reputation: ReputationTable::default(),
+            dial_cooldown: DialCooldown::new(Duration::from_secs(config.dial_cooldown)),
+            peer_identities: HashMap::default(),
+            peer_connections: HashMap::default(),
+            mdns_addresses: HashMap::default(),
+            topic_messages: HashMap::default(),
+            peer_waiters: Vec::new(),
+            pending_records: HashMap::default(),
+            pending_provides: HashMap::default(),
+            provider_lookups: HashMap::default(),
+            max_record_size: config.kad_max_record_size,
+            idle_reap_interval: config.idle_reap_interval,
+            idle_connection_timeout: Duration::from_secs(config.idle_connection_timeout),
+            target_connections: config.target_connections,
+            shutdown_grace_period: Duration::from_secs(config.shutdown_grace_period),
+            shutting_down: false,
+            inbound_responses: HashSet::new(),
+            bandwidth,
+        };
+        service.dial_bootstraps();
+        if !service.pending_bootstrap {
+            warn!("Skipping bootstrap");
+        } else if config.swarm_addrs.is_empty() || config.bootstrap_listen_grace == 0 {
+            // dial only nodes never get a listen address
+            service.schedule_bootstrap();
+        }
+
+        Ok(service)
     }
 
     pub fn close_command_receiver(&mut self) {
@@ -329,7 +753,511 @@ where
         self.command_sender.clone()
     }
 
+    /// Identify info of a connected peer, once received.
+    pub fn peer_identity(&self, peer_id: &PeerId) -> Option<&PeerIdentity> {
+        self.peer_identities.get(peer_id)
+    }
+
+    /// Running totals of the bytes sent and received over all connections, kept updated
+    /// once the service is started.
+    pub fn bandwidth(&self) -> Arc<BandwidthSinks> {
+        Arc::clone(&self.bandwidth)
+    }
+
+    /// Subscribe to peer connects, disconnects and discoveries. Events are broadcast without
+    /// waiting on subscribers, a subscriber falling behind by more than
+    /// `PEER_EVENTS_CAPACITY` events receives [`broadcast::error::RecvError::Lagged`].
+    pub fn subscribe_peer_events(&self) -> broadcast::Receiver<PeerEvent> {
+        self.peer_events.subscribe()
+    }
+
+    /// Schedule the first kademlia bootstrap after a jittered delay, so nodes started
+    /// together don't query the bootstrap nodes in lockstep.
+    fn schedule_bootstrap(&mut self) {
+        self.pending_bootstrap = false;
+        let delay = bootstrap::jittered(
+            INITIAL_BOOTSTRAP_DELAY,
+            self.bootstrap_jitter_percent,
+            &mut rand::thread_rng(),
+        );
+        self.bootstrap_at = Some(Instant::now() + delay);
+    }
+
+    /// Start the first kademlia bootstrap.
+    fn bootstrap(&mut self) {
+        self.bootstrap_at = None;
+        if let Err(e) = self.swarm.behaviour_mut().kad.bootstrap() {
+            warn!("Failed to bootstrap: {}", e);
+        } else {
+            info!("Bootstrapping into the network...");
+        }
+    }
+
+    /// Dial bootstrap nodes until the bootstrap dial concurrency is reached.
+    fn dial_bootstraps(&mut self) {
+        if self.local_peers_suffice() {
+            let skipped = self.bootstrap_dials.pause();
+            if skipped != 0 {
+                info!(
+                    "Found {} local peers, skipping {skipped} bootstrap nodes",
+                    self.mdns_addresses.len()
+                );
+            }
+            return;
+        }
+        let resumed = self.bootstrap_dials.resume();
+        if resumed != 0 {
+            info!(
+                "Only {} local peers left, dialing {resumed} bootstrap nodes",
+                self.mdns_addresses.len()
+            );
+        }
+        while let Some(addr) = self.bootstrap_dials.next_dial() {
+            info!("Dialing bootstrap node: {addr}");
+            if let Err(e) = self.swarm.dial(addr.clone()) {
+                warn!("Failed to dial bootstrap node {addr}: {e}");
+                if let Some(peer) = bootstrap::peer_id(&addr) {
+                    self.bootstrap_dials.complete(&peer);
+                }
+            }
+        }
+        gauge!(
+            "network_bootstrap_peak_dials",
+            self.bootstrap_dials.peak() as f64
+        );
+    }
+
+    /// Whether mDNS found enough local peers to skip the remote bootstrap.
+    fn local_peers_suffice(&self) -> bool {
+        self.prefer_local_peers
+            && self.bootstrap_peer_threshold != 0
+            && self.mdns_addresses.len() >= self.bootstrap_peer_threshold
+    }
+
+    /// Resolve the waiters whose minimum of connected peers has been reached.
+    fn notify_peer_waiters(&mut self) {
+        let connected = self.peers.len();
+        let mut pending = Vec::new();
+        for (min, sender) in self.peer_waiters.drain(..) {
+            if connected >= min {
+                if sender.send(connected).is_err() {
+                    debug!("[notify_peer_waiters] - waiter for {min} peers dropped");
+                }
+            } else if !sender.is_closed() {
+                pending.push((min, sender));
+            }
+        }
+        self.peer_waiters = pending;
+    }
+
+    /// Refuse connections from `peer` and drop it from the routing table.
+    pub fn block_peer(&mut self, peer: PeerId) {
+        if self.blocked_peers.insert(peer) {
+            info!("Blocking peer {peer}");
+            self.swarm.behaviour_mut().kad.remove_peer(&peer);
+            let _ = self.swarm.disconnect_peer_id(peer);
+        }
+    }
+
+    /// Accept `peer` again, it is added back to the routing table once rediscovered.
+    pub fn unblock_peer(&mut self, peer: PeerId) {
+        if self.blocked_peers.remove(&peer) {
+            info!("Unblocking peer {peer}");
+        }
+    }
+
+    /// Identity, listen addresses, peer count and bootstrap status of the node.
+    pub fn node_info(&self) -> NodeInfo {
+        NodeInfo {
+            peer_id: *self.swarm.local_peer_id(),
+            listen_addresses: self.swarm.listeners().cloned().collect(),
+            peers: self.swarm.connected_peers().count(),
+            bootstrap_nodes: self.bootstraps.len(),
+            bootstrapped: self.bootstrapped,
+        }
+    }
+
+    /// Connection directions and identity of `peer_id`, `None` if not connected.
+    pub fn peer_info(&self, peer_id: &PeerId) -> Option<PeerInfo> {
+        let (inbound, outbound) = *self.peer_connections.get(peer_id)?;
+        Some(PeerInfo {
+            peer_id: *peer_id,
+            inbound,
+            outbound,
+            identity: self.peer_identities.get(peer_id).cloned(),
+        })
+    }
+
+    /// Listen addresses, and the public address found by autonat, allowed by the advertise policy.
+    pub fn advertised_addresses(&self) -> Vec<Multiaddr> {
+        let mut addresses: Vec<Multiaddr> = vec![];
+        let public = self.swarm.behaviour().public_address();
+        for address in self.swarm.listeners().chain(public) {
+            if self.advertise_addresses.allows(address) && !addresses.contains(address) {
+                addresses.push(address.clone());
+            }
+        }
+        addresses
+    }
+
+    /// Peers of the kademlia routing table, with their addresses.
+    fn routing_table_entries(&mut self) -> Vec<(PeerId, Multiaddr)> {
+        self.swarm
+            .behaviour_mut()
+            .kad
+            .kbuckets()
+            .flat_map(|bucket| {
+                bucket
+                    .iter()
+                    .flat_map(|entry| {
+                        let peer = *entry.node.key.preimage();
+                        entry
+                            .node
+                            .value
+                            .iter()
+                            .map(move |addr| (peer, addr.clone()))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Fetch `cid` over bitswap from the first `max_providers_per_fetch` of `providers`,
+    /// the other providers and then `fallback` being asked in turn if they don't have it.
+    fn sync_block(
+        &mut self,
+        cid: Cid,
+        mut providers: Vec<PeerId>,
+        fallback: Vec<PeerId>,
+    ) -> Result<QueryId> {
+        let mut rest = match self.max_providers_per_fetch {
+            0 => vec![],
+            max => providers.split_off(max.min(providers.len())),
+        };
+        rest.extend(fallback);
+        histogram!(
+            "network_bitswap_providers_per_fetch",
+            providers.len() as f64
+        );
+        let query_id = self
+            .swarm
+            .behaviour_mut()
+            .sync_block(cid, providers.clone())?;
+        if !rest.is_empty() {
+            self.bitswap_fallbacks.insert(query_id, rest);
+        }
+        for provider in &providers {
+            let outstanding = self.outstanding_queries.entry(*provider).or_default();
+            *outstanding += 1;
+            if self.max_requests_per_peer != 0 {
+                histogram!(
+                    "network_peer_substream_utilization",
+                    *outstanding as f64 / self.max_requests_per_peer as f64
+                );
+            }
+        }
+        self.query_providers.insert(query_id, providers);
+        Ok(query_id)
+    }
+
+    /// Fetch `cid` from the providers with less than `max_requests_per_peer` outstanding
+    /// queries, each fetching blocks on its own substreams of the peer connection. The
+    /// busy providers are asked if the others don't have it, and the query is queued
+    /// while every provider is busy. Providers cooling down after a failed dial are skipped.
+    fn want_block(
+        &mut self,
+        cid: Cid,
+        providers: Vec<PeerId>,
+        fallback: Vec<PeerId>,
+        connected: bool,
+    ) {
+        // bitswap dials the providers it isn't connected to
+        let mut dialable = |provider: &PeerId| {
+            self.swarm.is_connected(provider) || !self.dial_cooldown.is_cooling_down(provider)
+        };
+        let providers: Vec<_> = providers.into_iter().filter(&mut dialable).collect();
+        let fallback: Vec<_> = fallback.into_iter().filter(&mut dialable).collect();
+        let (free, busy): (Vec<_>, Vec<_>) = providers.into_iter().partition(|provider| {
+            self.max_requests_per_peer == 0
+                || self.outstanding_queries.get(provider).copied().unwrap_or(0)
+                    < self.max_requests_per_peer
+        });
+        if free.is_empty() && !busy.is_empty() {
+            trace!("[want_block] - queueing the query of {cid} to {}", busy[0]);
+            self.queued_queries
+                .entry(busy[0])
+                .or_default()
+                .push_back((cid, busy, fallback, connected));
+            return;
+        }
+        let fallback = busy.into_iter().chain(fallback).collect();
+
+        match self.sync_block(cid, free, fallback) {
+            Ok(query_id) => {
+                self.bitswap_queries.insert(query_id, cid);
+                if connected {
+                    self.connected_queries.insert(query_id);
+                }
+                self.emit_event(NetworkEvent::BitswapWant { cid, query_id });
+            }
+            Err(e) => {
+                error!("[want_block] - failed to query {cid}: {e:?}");
+                for chan in self.response_channels.remove(&cid).unwrap_or_default() {
+                    if chan
+                        .send(Err(anyhow!("Failed to query {cid}: {e}")))
+                        .is_err()
+                    {
+                        error!("[want_block] - Bitswap response channel send failed");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record bitswap activity with the providers of a query, bitswap doesn't tell
+    /// which of them answered.
+    fn touch_query_providers(&mut self, query_id: &QueryId) {
+        for provider in self.query_providers.get(query_id).into_iter().flatten() {
+            self.peer_activity.touch(*provider);
+        }
+    }
+
+    /// Free the providers of a finished bitswap query, starting the next query queued on them.
+    fn complete_query(&mut self, query_id: &QueryId) {
+        for provider in self.query_providers.remove(query_id).unwrap_or_default() {
+            if let Some(outstanding) = self.outstanding_queries.get_mut(&provider) {
+                *outstanding = outstanding.saturating_sub(1);
+                if *outstanding == 0 {
+                    self.outstanding_queries.remove(&provider);
+                }
+            }
+            let next = match self.queued_queries.get_mut(&provider) {
+                Some(queue) => {
+                    let next = queue.pop_front();
+                    if queue.is_empty() {
+                        self.queued_queries.remove(&provider);
+                    }
+                    next
+                }
+                None => None,
+            };
+            if let Some((cid, providers, fallback, connected)) = next {
+                self.want_block(cid, providers, fallback, connected);
+            }
+        }
+    }
+
+    /// Publish the oldest queued gossipsub message. The swarm is polled between
+    /// publishes, letting the connections write out what was handed to them.
+    fn publish_queued(&mut self) {
+        let Some((topic, data, sender)) = self.gossip_outbound.pop_front() else {
+            return;
+        };
+
+        // flood publishing sends the message to every peer subscribed to the topic
+        let peers = self
+            .swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .filter(|(_, topics)| topics.contains(&&topic))
+            .count();
+        let publish = self
+            .swarm
+            .behaviour_mut()
+            .publish(Topic::new(topic.to_string()), data.to_vec())
+            .map(|message_id| PublishAck { message_id, peers });
+
+        match &publish {
+            Err(PublishError::InsufficientPeers) => {
+                warn!("No peers in the mesh of topic {topic}, the message was not sent")
+            }
+            Err(e) => warn!("Publish error: {e:?}"),
+            Ok(_) => self.topic_messages.entry(topic).or_default().0 += 1,
+        }
+
+        if sender.send(publish).is_err() {
+            warn!("Failed to send the publish result, the receiver was dropped");
+        }
+    }
+
+    /// The most reliable `MAX_UNCONNECTED_PROVIDERS` peers of the routing table we
+    /// aren't connected to. Bitswap learns their addresses as they enter the routing table.
+    fn unconnected_providers(&mut self) -> Vec<PeerId> {
+        let mut providers = Vec::new();
+        for (peer, _) in self.routing_table_entries() {
+            if self.peers.contains(&peer) || self.blocked_peers.contains(&peer) {
+                continue;
+            }
+            if !providers.contains(&peer) {
+                providers.push(peer);
+            }
+        }
+        providers.sort_by_key(|peer| std::cmp::Reverse(self.reputation.score(peer)));
+        providers.truncate(MAX_UNCONNECTED_PROVIDERS);
+        providers
+    }
+
+    /// Write the routing table to `routing_table_path`, to be restored on startup.
+    fn persist_routing_table(&mut self) {
+        let Some(path) = self.routing_table_path.clone() else {
+            return;
+        };
+        let entries = self.routing_table_entries();
+        match routing_table::save(&path, entries.into_iter()) {
+            Ok(count) => debug!("Persisted {count} routing table entries to {path:?}"),
+            Err(e) => warn!("Failed to persist the routing table: {e:?}"),
+        }
+    }
+
+    /// Delay until the next random kademlia walk.
+    fn kad_walk_delay(&self) -> Duration {
+        bootstrap::jittered(
+            Duration::from_secs(self.kad_walk_interval),
+            self.bootstrap_jitter_percent,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    /// Start a random kademlia walk, unless enough peers are known already.
+    /// Returns whether a walk was started.
+    fn kad_walk(&mut self) -> bool {
+        if self.bootstrap_peer_threshold != 0 && self.peers.len() >= self.bootstrap_peer_threshold {
+            debug!(
+                "Skipping random kademlia walk with {} known peers",
+                self.peers.len()
+            );
+            return false;
+        }
+        info!("Starting random kademlia walk");
+        self.swarm
+            .behaviour_mut()
+            .kad
+            .get_closest_peers(PeerId::random());
+        true
+    }
+
+    /// Close connections idle beyond the timeout, longest idle first, until
+    /// the target connection count is reached. Bootstrap nodes and peers with
+    /// pending requests or bitswap queries are kept.
+    fn reap_idle_connections(&mut self) {
+        let mut excess = self.peers.len().saturating_sub(self.target_connections);
+        if excess == 0 {
+            return;
+        }
+        let busy: HashSet<PeerId> = self
+            .pending_responses
+            .values()
+            .map(|(peer, _)| *peer)
+            .chain(self.outstanding_queries.keys().copied())
+            .collect();
+        let idle = self
+            .peer_activity
+            .idle(Instant::now(), self.idle_connection_timeout);
+        for peer in idle {
+            if excess == 0 {
+                break;
+            }
+            if self.bootstrap_peers.contains(&peer) || busy.contains(&peer) {
+                continue;
+            }
+            if self.swarm.disconnect_peer_id(peer).is_ok() {
+                debug!("[reap_idle_connections] - closed idle connection to {peer}");
+                increment_counter!("network_idle_connections_reaped");
+                excess -= 1;
+            }
+        }
+    }
+
+    /// Pending bitswap queries and outbound requests, queued ones included,
+    /// and the responses to inbound requests being sent.
+    fn in_flight_transfers(&self) -> usize {
+        self.bitswap_queries.len()
+            + self.pending_responses.len()
+            + self.inbound_responses.len()
+            + self
+                .queued_queries
+                .values()
+                .map(VecDeque::len)
+                .sum::<usize>()
+    }
+
+    /// Keep driving the swarm until the in-flight transfers complete or the grace period passed,
+    /// so peers don't get their transfers reset. Commands are no longer handled.
+    async fn drain(&mut self) {
+        let in_flight = self.in_flight_transfers();
+        let queued = self.gossip_outbound.len();
+        if in_flight == 0 && queued == 0 {
+            return;
+        }
+        info!(
+            "Waiting up to {:?} for {in_flight} in-flight transfers and {queued} queued publishes",
+            self.shutdown_grace_period
+        );
+
+        let deadline = sleep(self.shutdown_grace_period);
+        tokio::pin!(deadline);
+        while self.in_flight_transfers() > 0 || !self.gossip_outbound.is_empty() {
+            select! {
+                event = self.swarm.next() => {
+                    let Some(event) = event else { break };
+                    if let Err(e) = self.handle_swarm_event(event) {
+                        warn!("[drain] - failed to handle swarm event: {e:?}");
+                    }
+                },
+                _ = async {}, if !self.gossip_outbound.is_empty() => self.publish_queued(),
+                _ = &mut deadline => break,
+            }
+        }
+        self.fail_queued_publishes();
+
+        let aborted = self.in_flight_transfers();
+        counter!(
+            "network_drain_transfers_completed",
+            in_flight.saturating_sub(aborted) as u64
+        );
+        counter!("network_drain_transfers_aborted", aborted as u64);
+        if aborted > 0 {
+            warn!("Aborting {aborted} in-flight transfers");
+        }
+    }
+
+    /// Fail the publishes still queued, the node is shutting down before sending them.
+    fn fail_queued_publishes(&mut self) {
+        if self.gossip_outbound.is_empty() {
+            return;
+        }
+        warn!(
+            "Dropping {} queued gossipsub messages",
+            self.gossip_outbound.len()
+        );
+        counter!(
+            "network_drain_publishes_dropped",
+            self.gossip_outbound.len() as u64
+        );
+        for (_, _, sender) in self.gossip_outbound.drain(..) {
+            let _ = sender.send(Err(PublishError::TransformFailed(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "node shutting down",
+            ))));
+        }
+    }
+
     fn emit_event(&mut self, event: NetworkEvent) {
+        let peer_event = match &event {
+            NetworkEvent::PeerConnected(peer) => Some(PeerEvent::Connected(*peer)),
+            NetworkEvent::PeerDisconnected(peer) => Some(PeerEvent::Disconnected(*peer)),
+            NetworkEvent::PeerExpired(peer) => Some(PeerEvent::Expired(*peer)),
+            NetworkEvent::Providers { key, providers } => Some(PeerEvent::Providers {
+                key: key.clone(),
+                providers: providers.clone(),
+            }),
+            _ => None,
+        };
+        if let Some(peer_event) = peer_event {
+            self.emit_peer_event(peer_event);
+        }
+
         let sender = self.event_sender.clone();
         tokio::task::spawn(async move {
             if let Err(error) = sender.send(event) {
@@ -338,9 +1266,15 @@ where
         });
     }
 
+    fn emit_peer_event(&self, event: PeerEvent) {
+        // only fails without subscribers
+        let _ = self.peer_events.send(event);
+    }
+
     fn handle_ping(&mut self, ping_event: PingEvent) -> Result<()> {
         match ping_event.result {
             Ok(libp2p::ping::Success::Ping { rtt }) => {
+                self.reputation.record_success(ping_event.peer);
                 trace!(
                     "[PingSuccess::Ping] - with rtt {} from {} in ms",
                     rtt.as_millis(),
@@ -354,6 +1288,7 @@ where
                 );
             }
             Err(libp2p::ping::Failure::Other { error }) => {
+                self.reputation.record_failure(ping_event.peer);
                 debug!(
                     "[PingFailure::Other] - the ping failed with {} for reasons {}",
                     ping_event.peer.to_base58(),
@@ -361,6 +1296,7 @@ where
                 );
             }
             Err(libp2p::ping::Failure::Timeout) => {
+                self.reputation.record_failure(ping_event.peer);
                 warn!(
                     "[PingFailure::Timeout] - no response was received from {}",
                     ping_event.peer.to_base58()
@@ -393,7 +1329,9 @@ where
                 }
 
                 // check if received identify is from a peer on the same network
-                if info
+                if self.blocked_peers.contains(&peer_id) {
+                    debug!("[IdentifyEvent::Received] - ignoring blocked peer {peer_id}");
+                } else if info
                     .protocols
                     .iter()
                     .any(|name| name.as_bytes() == KAD_PROTOCOL)
@@ -405,7 +1343,23 @@ where
                     for address in info.listen_addrs {
                         behaviour.add_address(&peer_id, address);
                     }
+                } else {
+                    debug!(
+                        "Peer {peer_id} ({}) doesn't speak {}",
+                        info.agent_version,
+                        String::from_utf8_lossy(KAD_PROTOCOL)
+                    );
                 }
+
+                self.peer_identities.insert(
+                    peer_id,
+                    PeerIdentity {
+                        agent_version: info.agent_version,
+                        protocol_version: info.protocol_version,
+                        protocols: info.protocols,
+                        observed_addr: info.observed_addr,
+                    },
+                );
             }
             IdentifyEvent::Sent { .. }
             | IdentifyEvent::Pushed { .. }
@@ -452,13 +1406,32 @@ where
     fn handle_bitswap(&mut self, bitswap_event: BitswapEvent) -> Result<()> {
         match bitswap_event {
             BitswapEvent::Progress(query_id, _) => {
+                self.touch_query_providers(&query_id);
                 trace!(
                     "[BitswapEvent::Progress] - bitswap request in progress with, id: {}",
                     query_id
                 );
             }
             BitswapEvent::Complete(query_id, result) => {
+                self.touch_query_providers(&query_id);
+                self.complete_query(&query_id);
+                let fallback = self.bitswap_fallbacks.remove(&query_id);
+                let connected = self.connected_queries.remove(&query_id);
                 if let Some(cid) = self.bitswap_queries.remove(&query_id) {
+                    match (&result, fallback) {
+                        (Ok(()), _) if connected => {
+                            increment_counter!("network_bitswap_connected_provider_fetches")
+                        }
+                        (Err(_), Some(providers)) => {
+                            debug!(
+                                "[BitswapEvent::Complete] - peers asked don't have {cid}, asking {} more peers",
+                                providers.len()
+                            );
+                            self.want_block(cid, providers, vec![], false);
+                            return Ok(());
+                        }
+                        _ => (),
+                    }
                     if let Some(chans) = self.response_channels.remove(&cid) {
                         for chan in chans.into_iter() {
                             match result {
@@ -492,6 +1465,11 @@ where
                 message_id,
                 message,
             } => {
+                self.peer_activity.touch(propagation_source);
+                self.topic_messages
+                    .entry(message.topic.clone())
+                    .or_default()
+                    .1 += 1;
                 self.emit_event(NetworkEvent::Gossipsub(GossipsubEvent::Message {
                     peer_id: propagation_source,
                     message_id,
@@ -517,12 +1495,17 @@ where
 
     pub fn handle_kad(&mut self, event: KademliaEvent) -> Result<()> {
         match event {
-            KademliaEvent::OutboundQueryProgressed { id, result, .. } => match result {
+            KademliaEvent::OutboundQueryProgressed {
+                id, result, step, ..
+            } => match result {
                 QueryResult::Bootstrap(result) => match result {
                     Ok(BootstrapOk {
                         peer,
                         num_remaining,
                     }) => {
+                        if num_remaining == 0 {
+                            self.bootstrapped = true;
+                        }
                         debug!(
                             "[KademliaEvent::Bootstrap] - Received peer: {peer:?}, {}",
                             match num_remaining {
@@ -535,38 +1518,251 @@ where
                         warn!("[KademliaEvent::Bootstrap] - Bootstrap failed: {e:?}");
                     }
                 },
+                QueryResult::PutRecord(result) => {
+                    if let Some(sender) = self.pending_records.remove(&id) {
+                        let result = result
+                            .map(|PutRecordOk { .. }| ())
+                            .map_err(|e| anyhow!("Failed to put record: {e:?}"));
+                        if sender.send(result).is_err() {
+                            debug!("[KademliaEvent::PutRecord] - receiver dropped");
+                        }
+                    }
+                }
+                QueryResult::StartProviding(result) => {
+                    if let Some(sender) = self.pending_provides.remove(&id) {
+                        let result = result
+                            .map(|_| ())
+                            .map_err(|e| anyhow!("Failed to announce provider record: {e:?}"));
+                        match sender {
+                            Some(sender) => {
+                                if sender.send(result).is_err() {
+                                    debug!("[KademliaEvent::StartProviding] - receiver dropped");
+                                }
+                            }
+                            None => debug!("[KademliaEvent::StartProviding] - {result:?}"),
+                        }
+                    }
+                }
+                QueryResult::GetProviders(result) => {
+                    self.provider_lookup_progressed(id, result, step.last)
+                }
                 other => debug!("[KademliaEvent::OutboundQueryProgressed] - {id:?}: {other:?}"),
             },
+            KademliaEvent::PendingRoutablePeer { peer, address } => {
+                if !self.blocked_peers.contains(&peer) {
+                    self.evict_for(peer, address);
+                }
+            }
+            KademliaEvent::RoutingUpdated { peer, .. } if self.blocked_peers.contains(&peer) => {
+                // kademlia adds the peers it learns of on its own
+                self.swarm.behaviour_mut().kad.remove_peer(&peer);
+            }
+            KademliaEvent::RoutingUpdated {
+                peer,
+                is_new_peer: true,
+                addresses,
+                ..
+            } => {
+                // bitswap may dial them for blocks the connected peers lack
+                for address in addresses.iter() {
+                    self.swarm
+                        .behaviour_mut()
+                        .bitswap
+                        .add_address(&peer, address.clone());
+                }
+                if !self.peers.contains(&peer) {
+                    self.emit_peer_event(PeerEvent::Discovered(peer, addresses.into_vec()));
+                }
+            }
             _ => debug!("[KademliaEvent] - {event:?}"),
         }
         Ok(())
     }
 
+    /// Announce the node as a provider of `key` in the dht.
+    pub fn start_providing(&mut self, key: Vec<u8>) -> Result<KadQueryId> {
+        let query_id = self
+            .swarm
+            .behaviour_mut()
+            .kad
+            .start_providing(Key::new(&key))
+            .map_err(|e| anyhow!("Failed to store provider record: {e:?}"))?;
+        self.pending_provides.insert(query_id, None);
+        Ok(query_id)
+    }
+
+    /// Look up the providers of `key` in the dht, emitting [`NetworkEvent::Providers`] and
+    /// answering the senders of the lookup once done.
+    /// A lookup of `key` already in flight is joined rather than querying the dht again.
+    pub fn get_providers(&mut self, key: Vec<u8>) -> KadQueryId {
+        if let Some((query_id, _)) = self
+            .provider_lookups
+            .iter()
+            .find(|(_, lookup)| lookup.key == key)
+        {
+            increment_counter!("network_provider_lookups_deduplicated");
+            return *query_id;
+        }
+        let query_id = self.swarm.behaviour_mut().kad.get_providers(Key::new(&key));
+        self.provider_lookups.insert(
+            query_id,
+            ProviderLookup {
+                key,
+                providers: HashSet::new(),
+                senders: Vec::new(),
+            },
+        );
+        query_id
+    }
+
+    fn provider_lookup_progressed(
+        &mut self,
+        id: KadQueryId,
+        result: GetProvidersResult,
+        last: bool,
+    ) {
+        let Some(lookup) = self.provider_lookups.get_mut(&id) else {
+            return;
+        };
+        let error = match result {
+            Ok(GetProvidersOk::FoundProviders { providers, .. }) => {
+                lookup.providers.extend(providers);
+                None
+            }
+            Ok(GetProvidersOk::FinishedWithNoAdditionalRecord { .. }) => None,
+            Err(e) => Some(e),
+        };
+        if !last && error.is_none() {
+            return;
+        }
+
+        let lookup = self.provider_lookups.remove(&id).unwrap();
+        for sender in lookup.senders {
+            let result = match &error {
+                // a timed out lookup still found providers
+                Some(e) if lookup.providers.is_empty() => {
+                    Err(anyhow!("Failed to get providers: {e:?}"))
+                }
+                _ => Ok(lookup.providers.clone()),
+            };
+            if sender.send(result).is_err() {
+                debug!("[KademliaEvent::GetProviders] - receiver dropped");
+            }
+        }
+        self.emit_event(NetworkEvent::Providers {
+            key: lookup.key,
+            providers: lookup.providers,
+        });
+    }
+
+    /// Make room for `peer` in its full routing bucket by evicting the least
+    /// reliable peer of the bucket, if it has a worse reputation than `peer`.
+    fn evict_for(&mut self, peer: PeerId, address: Multiaddr) {
+        let kad = &mut self.swarm.behaviour_mut().kad;
+        let candidates: Vec<PeerId> = kad
+            .kbucket(peer)
+            .map(|bucket| {
+                bucket
+                    .iter()
+                    .map(|entry| *entry.node.key.preimage())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if let Some(worst) = self.reputation.least_reliable(candidates.into_iter()) {
+            if self.reputation.score(&worst) < self.reputation.score(&peer) {
+                debug!(
+                    "[KademliaEvent::PendingRoutablePeer] - evicting {worst} in favor of {peer}"
+                );
+                kad.remove_peer(&worst);
+                kad.add_address(&peer, address.clone());
+                self.swarm
+                    .behaviour_mut()
+                    .bitswap
+                    .add_address(&peer, address);
+            }
+        }
+    }
+
     pub fn handle_mdns(&mut self, event: MdnsEvent) -> Result<()> {
         match event {
-            MdnsEvent::Discovered(discovered_peers) => {
-                for (peer_id, address) in discovered_peers {
-                    self.swarm
-                        .behaviour_mut()
-                        .add_address(&peer_id, address.clone());
+            MdnsEvent::Discovered(discovered_peers) => self.mdns_discovered(discovered_peers),
+            MdnsEvent::Expired(expired_peers) => self.mdns_expired(expired_peers),
+        }
+        Ok(())
+    }
 
-                    if self.peers.insert(peer_id) {
-                        match self.swarm.dial(address) {
-                            Ok(_) => info!("Dialed new local peer: {peer_id:?}"),
-                            Err(e) => error!("Failed to dial new local peer: {e:?}"),
-                        }
-                    }
+    fn mdns_discovered(&mut self, discovered_peers: impl Iterator<Item = (PeerId, Multiaddr)>) {
+        let mut new_peers: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+        for (peer_id, address) in discovered_peers {
+            if self.blocked_peers.contains(&peer_id) {
+                continue;
+            }
+            if !self.peers.contains(&peer_id) || new_peers.contains_key(&peer_id) {
+                new_peers.entry(peer_id).or_default().push(address.clone());
+            }
+            self.swarm
+                .behaviour_mut()
+                .add_address(&peer_id, address.clone());
+            self.mdns_addresses
+                .entry(peer_id)
+                .or_default()
+                .insert(address.clone());
+
+            if self.dial_cooldown.is_cooling_down(&peer_id) {
+                debug!("Skipping dial of local peer {peer_id}, its last dial failed");
+            } else if self.peers.insert(peer_id) {
+                let opts = DialOpts::peer_id(peer_id).addresses(vec![address]).build();
+                match self.swarm.dial(opts) {
+                    Ok(_) => info!("Dialed new local peer: {peer_id:?}"),
+                    Err(e) => error!("Failed to dial new local peer: {e:?}"),
                 }
             }
-            MdnsEvent::Expired(_) => {}
         }
-        Ok(())
+        for (peer_id, addresses) in new_peers {
+            self.emit_peer_event(PeerEvent::Discovered(peer_id, addresses));
+        }
+        if self.local_peers_suffice() {
+            self.dial_bootstraps();
+        }
+    }
+
+    /// Forget the addresses of local peers that left the network, and the
+    /// peers themselves once all their addresses expired. The bootstrap nodes
+    /// skipped for them are dialed once too few local peers are left.
+    fn mdns_expired(&mut self, expired_peers: impl Iterator<Item = (PeerId, Multiaddr)>) {
+        let mut expired = false;
+        for (peer_id, address) in expired_peers {
+            self.swarm
+                .behaviour_mut()
+                .kad
+                .remove_address(&peer_id, &address);
+            let Some(addresses) = self.mdns_addresses.get_mut(&peer_id) else {
+                continue;
+            };
+            addresses.remove(&address);
+            if addresses.is_empty() {
+                self.mdns_addresses.remove(&peer_id);
+                if !self.swarm.is_connected(&peer_id) {
+                    self.peers.remove(&peer_id);
+                }
+                debug!("Local peer {peer_id} expired");
+                self.emit_event(NetworkEvent::PeerExpired(peer_id));
+                expired = true;
+            }
+        }
+        if expired && !self.local_peers_suffice() {
+            self.dial_bootstraps();
+        }
     }
 
     fn handle_req_res(
         &mut self,
         req_res_event: RequestResponseEvent<UrsaExchangeRequest, UrsaExchangeResponse>,
     ) -> Result<()> {
+        if let RequestResponseEvent::Message { peer, .. } = &req_res_event {
+            self.peer_activity.touch(*peer);
+        }
         match req_res_event {
             RequestResponseEvent::Message { peer, message } => match message {
                 RequestResponseMessage::Request {
@@ -603,6 +1799,8 @@ where
                                 .is_err()
                             {
                                 error!("[BehaviourEvent::RequestMessage] failed to send response")
+                            } else {
+                                self.inbound_responses.insert(request_id);
                             }
                         }
                         RequestType::StoreSummary(cache_summary) => {
@@ -620,6 +1818,8 @@ where
                                 error!(
                                         "[BehaviourEvent::RequestMessage] failed to send StoreSummaryRequest response"
                                     )
+                            } else {
+                                self.inbound_responses.insert(request_id);
                             }
                         }
                     }
@@ -637,7 +1837,9 @@ where
                         response
                     );
 
-                    if let Some(request) = self.pending_responses.remove(&request_id) {
+                    self.reputation.record_success(peer);
+
+                    if let Some((_, request)) = self.pending_responses.remove(&request_id) {
                         if request.send(Ok(response)).is_err() {
                             warn!("[RequestResponseMessage::Response] - failed to send request: {request_id:?}");
                         }
@@ -646,9 +1848,22 @@ where
                     debug!("[RequestResponseMessage::Response] - failed to remove channel for: {request_id:?}");
                 }
             },
-            RequestResponseEvent::OutboundFailure { .. }
-            | RequestResponseEvent::InboundFailure { .. }
-            | RequestResponseEvent::ResponseSent { .. } => (),
+            RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                self.reputation.record_failure(peer);
+                if let Some((_, request)) = self.pending_responses.remove(&request_id) {
+                    if request.send(Err(anyhow!("{error}"))).is_err() {
+                        warn!("[RequestResponseEvent::OutboundFailure] - failed to send error: {request_id:?}");
+                    }
+                }
+            }
+            RequestResponseEvent::InboundFailure { request_id, .. }
+            | RequestResponseEvent::ResponseSent { request_id, .. } => {
+                self.inbound_responses.remove(&request_id);
+            }
         }
         Ok(())
     }
@@ -661,6 +1876,7 @@ where
                 received,
             } => {
                 info!("[GraphSyncEvent::Completed]: {id} {peer_id} {received}");
+                self.peer_activity.touch(peer_id);
                 Ok(())
             }
             event => {
@@ -675,6 +1891,7 @@ where
         // record basic swarm metrics
 
         event.record();
+        self.bandwidth.record();
         match event {
             SwarmEvent::Behaviour(event) => match event {
                 BehaviourEvent::Identify(identify_event) => {
@@ -711,25 +1928,105 @@ where
                 BehaviourEvent::Dcutr(_) => Ok(()),
                 BehaviourEvent::Graphsync(event) => self.handle_graphsync(event),
             },
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+            SwarmEvent::ConnectionEstablished { peer_id, .. }
+                if self.blocked_peers.contains(&peer_id) =>
+            {
+                debug!("Closing connection of blocked peer {peer_id}");
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+                Ok(())
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id, endpoint, ..
+            } => {
+                let (inbound, outbound) = self.peer_connections.entry(peer_id).or_default();
+                if endpoint.is_dialer() {
+                    *outbound += 1;
+                } else {
+                    *inbound += 1;
+                }
+                if self.bootstrap_dials.complete(&peer_id) {
+                    self.dial_bootstraps();
+                }
+                self.peer_activity.touch(peer_id);
+                self.dial_cooldown.clear(&peer_id);
                 if self.peers.insert(peer_id) {
                     debug!("Peer connected: {peer_id}");
                     self.emit_event(NetworkEvent::PeerConnected(peer_id));
+                    self.notify_peer_waiters();
                 };
                 Ok(())
             }
             SwarmEvent::ConnectionClosed {
                 peer_id,
+                endpoint,
                 num_established,
-                ..
+                cause,
             } => {
+                if let Some((inbound, outbound)) = self.peer_connections.get_mut(&peer_id) {
+                    let count = if endpoint.is_dialer() {
+                        outbound
+                    } else {
+                        inbound
+                    };
+                    *count = count.saturating_sub(1);
+                }
+                if num_established == 0 {
+                    self.peer_connections.remove(&peer_id);
+                    self.peer_identities.remove(&peer_id);
+                }
+                if cause
+                    .as_ref()
+                    .map_or(false, |cause| substream_limit_reached(cause))
+                {
+                    warn!("Closed connection to {peer_id}: too many substreams");
+                    increment_counter!("network_substream_limit_closures");
+                }
                 if num_established == 0 && self.peers.remove(&peer_id) {
                     self.peer_cached_content.remove(&peer_id);
+                    self.peer_activity.remove(&peer_id);
                     debug!("Peer disconnected: {peer_id}");
                     self.emit_event(NetworkEvent::PeerDisconnected(peer_id));
                 }
                 Ok(())
             }
+            // the external addresses are advertised in the provider records and identify
+            SwarmEvent::NewListenAddr { address, .. } => {
+                if self.pending_bootstrap {
+                    debug!("Listening on {address}, bootstrapping");
+                    self.schedule_bootstrap();
+                }
+                if self.advertise_addresses.allows(&address) {
+                    debug!("Advertising listen address {address}");
+                    self.swarm
+                        .add_external_address(address, AddressScore::Infinite);
+                }
+                Ok(())
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                self.swarm.remove_external_address(&address);
+                Ok(())
+            }
+            SwarmEvent::OutgoingConnectionError {
+                peer_id: Some(peer_id),
+                ..
+            } => {
+                if self.bootstrap_dials.complete(&peer_id) {
+                    self.dial_bootstraps();
+                }
+                self.reputation.record_failure(peer_id);
+                // whether it was dialed as a bootstrap, local or known peer, or by
+                // kademlia or bitswap
+                if !self.swarm.is_connected(&peer_id) {
+                    self.peers.remove(&peer_id);
+                    self.dial_cooldown.record_failure(peer_id);
+                    // kademlia dials its routing table on its own, the bootstrap nodes
+                    // are kept to bootstrap again
+                    if !self.bootstrap_peers.contains(&peer_id) {
+                        self.swarm.behaviour_mut().kad.remove_peer(&peer_id);
+                    }
+                }
+                Ok(())
+            }
             _ => Ok(()),
         }
     }
@@ -741,8 +2038,9 @@ where
                 info!("Getting cid {cid} via bitswap");
 
                 let peers = self.peers.clone();
+                let unconnected = self.unconnected_providers();
 
-                if peers.is_empty() {
+                if peers.is_empty() && unconnected.is_empty() {
                     error!(
                         "There were no peers provided and the block does not exist in local store"
                     );
@@ -758,7 +2056,7 @@ where
                         self.response_channels.insert(cid, vec![sender]);
                     }
 
-                    let peers = peers
+                    let peers: Vec<PeerId> = peers
                         .iter()
                         .filter(|peer| {
                             if let Some(cache_summary) = self.peer_cached_content.get(*peer) {
@@ -769,19 +2067,44 @@ where
                         .copied()
                         .collect();
 
-                    let query = self.swarm.behaviour_mut().sync_block(cid, peers);
-
-                    if let Ok(query_id) = query {
-                        self.bitswap_queries.insert(query_id, cid);
-                        self.emit_event(NetworkEvent::BitswapWant { cid, query_id });
+                    // the routing table peers are only dialed if the connected peers don't have it
+                    if self.prefer_connected_providers && !peers.is_empty() {
+                        self.want_block(cid, peers, unconnected, true);
                     } else {
-                        error!(
-                            "[NetworkCommand::BitswapWant] - no block found for cid {:?}.",
-                            cid
-                        )
+                        let providers = peers.into_iter().chain(unconnected).collect();
+                        self.want_block(cid, providers, vec![], false);
                     }
                 }
             }
+            NetworkCommand::FetchFrom {
+                cid,
+                peer_id,
+                address,
+                sender,
+            } => {
+                info!("Getting cid {cid} via bitswap from {peer_id}");
+
+                if !self.peers.contains(&peer_id) {
+                    if self.dial_cooldown.is_cooling_down(&peer_id) {
+                        return sender
+                            .send(Err(anyhow!(
+                                "Peer {peer_id} is cooling down after a failed dial"
+                            )))
+                            .map_err(|_| anyhow!("Failed to get a bitswap block!"));
+                    }
+                    let opts = DialOpts::peer_id(peer_id)
+                        .addresses(address.into_iter().collect())
+                        .build();
+                    if let Err(e) = self.swarm.dial(opts) {
+                        return sender
+                            .send(Err(anyhow!("Failed to dial {peer_id}: {e}")))
+                            .map_err(|_| anyhow!("Failed to get a bitswap block!"));
+                    }
+                }
+
+                self.response_channels.entry(cid).or_default().push(sender);
+                self.want_block(cid, vec![peer_id], vec![], false);
+            }
             NetworkCommand::Put { cid, sender } => {
                 // replicate content
                 let swarm = self.swarm.behaviour_mut();
@@ -810,6 +2133,24 @@ where
                     .send(self.peers.clone())
                     .map_err(|_| anyhow!("Failed to get Libp2p peers!"))?;
             }
+            NetworkCommand::GetPeerAddresses { peers, sender } => {
+                let swarm = self.swarm.behaviour_mut();
+                let addresses = peers
+                    .into_iter()
+                    .map(|peer| {
+                        let mut addresses: Vec<Multiaddr> = vec![];
+                        for address in swarm.addresses_of_peer(&peer) {
+                            if !addresses.contains(&address) {
+                                addresses.push(address);
+                            }
+                        }
+                        (peer, addresses)
+                    })
+                    .collect();
+                sender
+                    .send(addresses)
+                    .map_err(|_| anyhow!("Failed to get peer addresses from network"))?;
+            }
             NetworkCommand::GetListenerAddresses { sender } => {
                 let mut addresses: Vec<&Multiaddr> = self.swarm.listeners().collect();
                 if let Some(value) = self.swarm.behaviour().public_address() {
@@ -819,6 +2160,128 @@ where
                     .send(addresses.into_iter().cloned().collect())
                     .map_err(|_| anyhow!("Failed to get listener addresses from network"))?;
             }
+            NetworkCommand::GetAdvertisedAddresses { sender } => {
+                sender
+                    .send(self.advertised_addresses())
+                    .map_err(|_| anyhow!("Failed to get advertised addresses from network"))?;
+            }
+            NetworkCommand::PutRecord { key, value, sender } => {
+                if value.len() > self.max_record_size {
+                    let error = RecordTooLarge {
+                        size: value.len(),
+                        max: self.max_record_size,
+                    };
+                    return sender
+                        .send(Err(error.into()))
+                        .map_err(|_| anyhow!("Failed to send put record response"));
+                }
+                match self
+                    .swarm
+                    .behaviour_mut()
+                    .kad
+                    .put_record(Record::new(key, value), Quorum::One)
+                {
+                    Ok(query_id) => {
+                        self.pending_records.insert(query_id, sender);
+                    }
+                    Err(e) => {
+                        return sender
+                            .send(Err(anyhow!("Failed to store record: {e:?}")))
+                            .map_err(|_| anyhow!("Failed to send put record response"));
+                    }
+                }
+            }
+            NetworkCommand::StartProviding { key, sender } => match self.start_providing(key) {
+                Ok(query_id) => {
+                    self.pending_provides.insert(query_id, Some(sender));
+                }
+                Err(e) => {
+                    return sender
+                        .send(Err(e))
+                        .map_err(|_| anyhow!("Failed to send start providing response"));
+                }
+            },
+            NetworkCommand::GetProviders { key, sender } => {
+                let query_id = self.get_providers(key);
+                if let Some(lookup) = self.provider_lookups.get_mut(&query_id) {
+                    lookup.senders.push(sender);
+                }
+            }
+            NetworkCommand::DisconnectAll { sender } => {
+                let mut dropped = 0;
+                for peer in self.peers.clone() {
+                    if self.swarm.disconnect_peer_id(peer).is_err() {
+                        warn!("[NetworkCommand::DisconnectAll] - {peer} is not connected");
+                    } else {
+                        dropped += 1;
+                    }
+                    // forget the peer now, the connections close in the background
+                    self.peers.remove(&peer);
+                    self.peer_cached_content.remove(&peer);
+                    self.peer_activity.remove(&peer);
+                    self.peer_identities.remove(&peer);
+                    self.emit_event(NetworkEvent::PeerDisconnected(peer));
+                }
+                info!("Disconnected from {dropped} peers");
+                sender
+                    .send(dropped)
+                    .map_err(|_| anyhow!("Failed to send disconnect all response"))?;
+            }
+            NetworkCommand::BlockPeer { peer_id, sender } => {
+                self.block_peer(peer_id);
+                sender
+                    .send(())
+                    .map_err(|_| anyhow!("Failed to send block peer response"))?;
+            }
+            NetworkCommand::UnblockPeer { peer_id, sender } => {
+                self.unblock_peer(peer_id);
+                sender
+                    .send(())
+                    .map_err(|_| anyhow!("Failed to send unblock peer response"))?;
+            }
+            NetworkCommand::SubscribePeerEvents { sender } => {
+                sender
+                    .send(self.subscribe_peer_events())
+                    .map_err(|_| anyhow!("Failed to send peer events subscription"))?;
+            }
+            NetworkCommand::AwaitPeers { min, sender } => {
+                self.peer_waiters.push((min, sender));
+                self.notify_peer_waiters();
+            }
+            NetworkCommand::GetPeerReputation { sender } => {
+                sender
+                    .send(self.reputation.scores())
+                    .map_err(|_| anyhow!("Failed to get peer reputation!"))?;
+            }
+            NetworkCommand::GetNodeInfo { sender } => {
+                sender
+                    .send(self.node_info())
+                    .map_err(|_| anyhow!("Failed to get node info!"))?;
+            }
+            NetworkCommand::GetPeerInfo { peer_id, sender } => {
+                sender
+                    .send(self.peer_info(&peer_id))
+                    .map_err(|_| anyhow!("Failed to get peer info!"))?;
+            }
+            NetworkCommand::ListSubscriptions { sender } => {
+                let gossipsub = &self.swarm.behaviour().gossipsub;
+                let topics = gossipsub
+                    .topics()
+                    .map(|topic| {
+                        let (published, received) =
+                            self.topic_messages.get(topic).copied().unwrap_or_default();
+                        TopicStats {
+                            topic: topic.clone(),
+                            mesh_peers: gossipsub.mesh_peers(topic).count(),
+                            published,
+                            received,
+                        }
+                    })
+                    .collect();
+                sender
+                    .send(topics)
+                    .map_err(|_| anyhow!("Failed to list subscriptions!"))?;
+            }
             NetworkCommand::SendRequest {
                 peer_id,
                 request,
@@ -829,7 +2292,8 @@ where
                     .behaviour_mut()
                     .request_response
                     .send_request(&peer_id, *request);
-                self.pending_responses.insert(request_id, channel);
+                self.pending_responses
+                    .insert(request_id, (peer_id, channel));
 
                 self.emit_event(NetworkEvent::RequestMessage { request_id });
             }
@@ -872,20 +2336,29 @@ where
                     data,
                     sender,
                 } => {
-                    let publish = self
-                        .swarm
-                        .behaviour_mut()
-                        .publish(Topic::new(topic.into_string()), data.to_vec());
-
-                    if let Err(e) = &publish {
-                        warn!("Publish error: {e:?}");
+                    if data.len() > self.gossip_max_transmit_size {
+                        warn!(
+                            "Gossipsub message of {} bytes to {topic} exceeds the max transmit size",
+                            data.len()
+                        );
+                        return sender
+                            .send(Err(PublishError::MessageTooLarge))
+                            .map_err(|_| anyhow!("Failed to publish message!"));
                     }
-
-                    sender
-                        .send(publish)
-                        .map_err(|_| anyhow!("Failed to publish message!"))?;
+                    if self.gossip_outbound.len() >= self.gossip_outbound_limit {
+                        warn!("Gossipsub outbound queue full, the message to {topic} was dropped");
+                        increment_counter!("network_gossip_outbound_dropped");
+                        return sender
+                            .send(Err(PublishError::TransformFailed(io::Error::new(
+                                io::ErrorKind::WouldBlock,
+                                "gossipsub outbound queue full",
+                            ))))
+                            .map_err(|_| anyhow!("Failed to publish message!"));
+                    }
+                    self.gossip_outbound.push_back((topic, data, sender));
                 }
             },
+            NetworkCommand::Shutdown => self.shutting_down = true,
             #[cfg(test)]
             NetworkCommand::GetPeerContent { sender } => {
                 sender
@@ -904,8 +2377,23 @@ where
         response: oneshot::Sender<Result<()>>,
     ) -> Result<()> {
         trace!("dial peer ({peer_id}) at address {address}");
+        if self.blocked_peers.contains(&peer_id) {
+            return response
+                .send(Err(anyhow!("Peer {peer_id} is blocked")))
+                .map_err(|_| anyhow!("{}", "Channel Dropped"));
+        }
+        if self.dial_cooldown.is_cooling_down(&peer_id) {
+            return response
+                .send(Err(anyhow!(
+                    "Peer {peer_id} is cooling down after a failed dial"
+                )))
+                .map_err(|_| anyhow!("{}", "Channel Dropped"));
+        }
 
-        match self.swarm.dial(address.clone()) {
+        let opts = DialOpts::peer_id(peer_id)
+            .addresses(vec![address.clone()])
+            .build();
+        match self.swarm.dial(opts) {
             Ok(_) => {
                 self.swarm
                     .behaviour_mut()
@@ -932,8 +2420,14 @@ where
             self.swarm.local_peer_id()
         );
 
-        let kad_walk_delay = sleep(Duration::from_secs(self.kad_walk_interval));
+        let kad_walk_delay = sleep(self.kad_walk_delay());
         tokio::pin!(kad_walk_delay);
+        let idle_reap_delay = sleep(Duration::from_secs(self.idle_reap_interval));
+        tokio::pin!(idle_reap_delay);
+        let routing_table_delay = sleep(Duration::from_secs(self.routing_table_interval));
+        tokio::pin!(routing_table_delay);
+        let bootstrap_grace = sleep(Duration::from_secs(self.bootstrap_listen_grace));
+        tokio::pin!(bootstrap_grace);
 
         loop {
             select! {
@@ -944,14 +2438,38 @@ where
                 command = self.command_receiver.recv() => {
                     let command = command.ok_or_else(|| anyhow!("Command invalid!"))?;
                     self.handle_command(command).expect("Handle rpc command.");
+                    if self.shutting_down {
+                        break;
+                    }
                 },
+                _ = async {}, if !self.gossip_outbound.is_empty() => self.publish_queued(),
                 _ = &mut kad_walk_delay => {
-                    info!("Starting random kademlia walk");
-                    self.swarm.behaviour_mut().kad.get_closest_peers(PeerId::random());
-                    kad_walk_delay.as_mut().reset(Instant::now() + Duration::from_secs(self.kad_walk_interval));
+                    self.kad_walk();
+                    kad_walk_delay.as_mut().reset(Instant::now() + self.kad_walk_delay());
+                }
+                _ = &mut idle_reap_delay => {
+                    self.reap_idle_connections();
+                    self.reputation.prune(|peer| self.swarm.is_connected(peer));
+                    idle_reap_delay.as_mut().reset(Instant::now() + Duration::from_secs(self.idle_reap_interval));
+                }
+                _ = &mut bootstrap_grace, if self.pending_bootstrap => {
+                    warn!("No listen address after {}s, bootstrapping without one", self.bootstrap_listen_grace);
+                    self.schedule_bootstrap();
+                }
+                _ = sleep_until(self.bootstrap_at.unwrap_or_else(Instant::now)), if self.bootstrap_at.is_some() => {
+                    self.bootstrap();
+                }
+                _ = &mut routing_table_delay, if self.routing_table_path.is_some() => {
+                    self.persist_routing_table();
+                    routing_table_delay.as_mut().reset(Instant::now() + Duration::from_secs(self.routing_table_interval));
                 }
             }
         }
+
+        self.drain().await;
+        self.persist_routing_table();
+        info!("Node shut down");
+        Ok(())
     }
 }
 
@@ -1,8 +1,10 @@
-use crate::behaviour::BehaviourEvent;
-use crate::utils::cache_summary::CacheSummary;
+use super::MAX_UNCONNECTED_PROVIDERS;
+use crate::behaviour::{kad_query_timeout, BehaviourEvent, KAD_PROTOCOL};
+use crate::utils::{cache_summary::CacheSummary, routing_table};
 use crate::{
     codec::protocol::{RequestType, UrsaExchangeRequest},
-    NetworkCommand, NetworkConfig, UrsaService, URSA_GLOBAL,
+    AdvertisePolicy, GossipsubMessage, NetworkCommand, NetworkConfig, PeerEvent, RecordTooLarge,
+    TopicStats, TransportKind, UrsaService, URSA_GLOBAL,
 };
 use anyhow::Result;
 use async_fs::File;
@@ -13,17 +15,27 @@ use futures::StreamExt;
 use fvm_ipld_car::{load_car, CarReader};
 use ipld_traversal::blockstore::Blockstore;
 use libipld::{cbor::DagCborCodec, ipld, multihash::Code, Block, Cid, DefaultParams, Ipld};
-use libp2p::kad::{BootstrapOk, KademliaEvent, QueryResult};
+use libp2p::kad::{
+    kbucket::Distance, Addresses, BootstrapOk, GetClosestPeersError, KademliaEvent, QueryResult,
+};
 use libp2p::request_response::RequestResponseEvent;
 use libp2p::{
-    gossipsub::IdentTopic as Topic, identity::Keypair, multiaddr::Protocol, swarm::SwarmEvent,
+    bandwidth::BandwidthSinks,
+    gossipsub::{error::PublishError, IdentTopic as Topic},
+    identity::Keypair,
+    multiaddr::Protocol,
+    swarm::SwarmEvent,
     Multiaddr, PeerId,
 };
-use libp2p_bitswap::BitswapStore;
+use libp2p_bitswap::{BitswapEvent, BitswapStore};
 use simple_logger::SimpleLogger;
 use std::path::Path;
-use std::{sync::Arc, time::Duration, vec};
-use tokio::{select, sync::oneshot, time::timeout};
+use std::{io, sync::Arc, time::Duration, vec};
+use tokio::{
+    select,
+    sync::{broadcast, oneshot},
+    time::{timeout, Instant},
+};
 use tracing::warn;
 use tracing::{error, info, log::LevelFilter};
 use ursa_store::{BitswapStorage, GraphSyncStorage, UrsaStore};
@@ -100,12 +112,17 @@ async fn network_init(
 
     let node_addrs = async {
         loop {
-            if let SwarmEvent::NewListenAddr { mut address, .. } =
-                timeout(Duration::from_secs(5), service.swarm.select_next_some())
-                    .await
-                    .expect("received some event")
-            {
+            let event = timeout(Duration::from_secs(5), service.swarm.select_next_some())
+                .await
+                .expect("received some event");
+            if let SwarmEvent::NewListenAddr { address, .. } = &event {
+                let mut address = address.clone();
                 address.push(Protocol::P2p(peer_id.into()));
+                // schedules the bootstrap, started right away as the swarm is driven by hand
+                service.handle_swarm_event(event).unwrap();
+                if service.bootstrap_at.is_some() {
+                    service.bootstrap();
+                }
                 return address;
             }
         }
@@ -115,6 +132,27 @@ async fn network_init(
     Ok((service, node_addrs, peer_id, store))
 }
 
+/// The peers discovered since the last call.
+fn discovered(peer_events: &mut broadcast::Receiver<PeerEvent>) -> Vec<(PeerId, Vec<Multiaddr>)> {
+    let mut discovered = vec![];
+    while let Ok(event) = peer_events.try_recv() {
+        if let PeerEvent::Discovered(peer, addresses) = event {
+            discovered.push((peer, addresses));
+        }
+    }
+    discovered
+}
+
+fn expired(peer_events: &mut broadcast::Receiver<PeerEvent>) -> Vec<PeerId> {
+    let mut expired = vec![];
+    while let Ok(event) = peer_events.try_recv() {
+        if let PeerEvent::Expired(peer) = event {
+            expired.push(peer);
+        }
+    }
+    expired
+}
+
 #[tokio::test]
 async fn test_network_start() -> Result<()> {
     setup_logger(LevelFilter::Info);
@@ -135,6 +173,828 @@ async fn test_network_start() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_network_shutdown() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+
+    let mut config = NetworkConfig::default();
+    let (service, ..) = network_init(&mut config, None, None).await?;
+    let sender = service.command_sender();
+    let service_task = tokio::task::spawn(service.start());
+
+    sender.send(NetworkCommand::Shutdown)?;
+    timeout(Duration::from_secs(5), service_task).await???;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_duplicate_listen_addrs() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let addr: Multiaddr = "/ip4/127.0.0.1/tcp/0".parse()?;
+    let mut config = NetworkConfig {
+        swarm_addrs: vec![addr.clone(), addr],
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+
+    let (mut service, ..) =
+        network_init(&mut config, None, Some(Keypair::generate_ed25519())).await?;
+
+    // the first listener was awaited by network_init, a duplicate would bind another port
+    let second = timeout(Duration::from_secs(1), async {
+        loop {
+            if let SwarmEvent::NewListenAddr { address, .. } =
+                service.swarm.select_next_some().await
+            {
+                return address;
+            }
+        }
+    })
+    .await;
+    assert!(second.is_err(), "duplicate address bound {second:?}");
+
+    config.swarm_addrs = vec![
+        "/ip4/0.0.0.0/tcp/6019".parse()?,
+        "/ip4/127.0.0.1/tcp/6019".parse()?,
+    ];
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dial_only() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        swarm_addrs: vec![],
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_ok());
+
+    config.allow_dial_only = false;
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_advertise_addresses() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        swarm_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse()?],
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+
+    for policy in [AdvertisePolicy::Public, AdvertisePolicy::All] {
+        config.advertise_addresses = policy.clone();
+        let mut service = UrsaService::new(Keypair::generate_ed25519(), &config, get_store())?;
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let event = service.swarm.select_next_some().await;
+                let listening = matches!(event, SwarmEvent::NewListenAddr { .. });
+                service.handle_swarm_event(event).unwrap();
+                if listening {
+                    break;
+                }
+            }
+        })
+        .await?;
+
+        let listener = service.swarm.listeners().next().cloned().unwrap();
+        let advertised = service.advertised_addresses();
+        let external: Vec<_> = service
+            .swarm
+            .external_addresses()
+            .map(|record| record.addr.clone())
+            .collect();
+        if policy == AdvertisePolicy::Public {
+            // loopback addresses are never advertised by default
+            assert!(advertised.is_empty(), "advertised {advertised:?}");
+            assert!(external.is_empty(), "advertised {external:?}");
+        } else {
+            assert_eq!(advertised, vec![listener.clone()]);
+            assert_eq!(external, vec![listener]);
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_self_bootstrap() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let keypair = Keypair::generate_ed25519();
+    let local: Multiaddr = format!(
+        "/ip4/127.0.0.1/tcp/6009/p2p/{}",
+        PeerId::from(keypair.public())
+    )
+    .parse()
+    .unwrap();
+    let other: Multiaddr = format!("/ip4/127.0.0.1/tcp/6010/p2p/{}", PeerId::random())
+        .parse()
+        .unwrap();
+    let config = NetworkConfig {
+        swarm_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+        bootstrap_nodes: vec![local, other.clone()],
+        ..Default::default()
+    };
+
+    let service = UrsaService::new(keypair, &config, get_store())?;
+    assert_eq!(service.bootstraps, vec![other]);
+    assert_eq!(service.bootstrap_peers.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bootstrap_waits_for_listen_address() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let bootstrap: Multiaddr = format!("/ip4/127.0.0.1/tcp/6010/p2p/{}", PeerId::random())
+        .parse()
+        .unwrap();
+    let mut config = NetworkConfig {
+        swarm_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+        bootstrap_nodes: vec![bootstrap],
+        bootstrap_listen_grace: 60,
+        ..Default::default()
+    };
+
+    let mut service = UrsaService::new(Keypair::generate_ed25519(), &config, get_store())?;
+    assert!(service.pending_bootstrap);
+    assert!(service.bootstrap_at.is_none());
+    let start = Instant::now();
+    loop {
+        let event = timeout(Duration::from_secs(5), service.swarm.select_next_some())
+            .await
+            .expect("received some event");
+        let listening = matches!(event, SwarmEvent::NewListenAddr { .. });
+        service.handle_swarm_event(event)?;
+        if listening {
+            break;
+        }
+    }
+    // the bootstrap runs a jittered second later
+    assert!(!service.pending_bootstrap);
+    let bootstrap_at = service.bootstrap_at.expect("a scheduled bootstrap");
+    assert!(bootstrap_at >= start + Duration::from_millis(900));
+    assert!(bootstrap_at <= Instant::now() + Duration::from_millis(1100));
+    assert_eq!(service.swarm.behaviour_mut().kad.iter_queries().count(), 0);
+    service.bootstrap();
+    assert!(service.bootstrap_at.is_none());
+    assert_eq!(service.swarm.behaviour_mut().kad.iter_queries().count(), 1);
+
+    // dial only nodes don't wait for a listen address
+    config.swarm_addrs = vec![];
+    let service = UrsaService::new(Keypair::generate_ed25519(), &config, get_store())?;
+    assert!(!service.pending_bootstrap);
+    assert!(service.bootstrap_at.is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bootstrap_peer_threshold() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        swarm_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+        bootstrap_nodes: (0..2)
+            .map(|port| {
+                format!(
+                    "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+                    6010 + port,
+                    PeerId::random()
+                )
+                .parse()
+                .unwrap()
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    for (threshold, walks) in [(1, false), (2, false), (3, true), (0, true)] {
+        config.bootstrap_peer_threshold = threshold;
+        let mut service = UrsaService::new(Keypair::generate_ed25519(), &config, get_store())?;
+        assert_eq!(service.kad_walk(), walks);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_kad_replication_factor() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        swarm_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+        kad_replication_factor: 0,
+        ..Default::default()
+    };
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_err());
+
+    config.kad_replication_factor = 3;
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_ok());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_kad_query_timeout() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        swarm_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+        bootstrap_nodes: vec![],
+        kad_query_timeout_secs: 1,
+        ..Default::default()
+    };
+    assert_eq!(kad_query_timeout(&config), Duration::from_secs(1));
+    assert_eq!(
+        kad_query_timeout(&NetworkConfig::default()),
+        Duration::from_secs(60)
+    );
+
+    // a peer accepting connections but never answering, its dial outlasts the query
+    let silent = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let silent_addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", silent.local_addr()?.port())
+        .parse()
+        .unwrap();
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+    node.swarm
+        .behaviour_mut()
+        .kad
+        .add_address(&PeerId::random(), silent_addr);
+    let query = node
+        .swarm
+        .behaviour_mut()
+        .kad
+        .get_closest_peers(PeerId::random());
+
+    let start = Instant::now();
+    let result = timeout(Duration::from_secs(5), async {
+        loop {
+            if let SwarmEvent::Behaviour(BehaviourEvent::Kad(
+                KademliaEvent::OutboundQueryProgressed {
+                    id,
+                    result: QueryResult::GetClosestPeers(result),
+                    ..
+                },
+            )) = node.swarm.select_next_some().await
+            {
+                if id == query {
+                    return result;
+                }
+            }
+        }
+    })
+    .await
+    .expect("the query to time out within the configured timeout");
+    assert!(matches!(result, Err(GetClosestPeersError::Timeout { .. })));
+    assert!(start.elapsed() >= Duration::from_secs(1));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zero_routing_table_interval() {
+    let config = NetworkConfig {
+        routing_table_interval: 0,
+        ..Default::default()
+    };
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_err());
+}
+
+#[tokio::test]
+async fn test_routing_table_restore() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let path =
+        std::env::temp_dir().join(format!("ursa-routing-table-restore-{}", std::process::id()));
+    let peer = PeerId::random();
+    let addr: Multiaddr = "/ip4/127.0.0.1/tcp/6009".parse().unwrap();
+    routing_table::save(&path, [(peer, addr.clone())].into_iter())?;
+
+    let mut config = NetworkConfig {
+        routing_table_path: Some(path.clone()),
+        ..Default::default()
+    };
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+    std::fs::remove_file(&path)?;
+    assert!(node
+        .routing_table_entries()
+        .contains(&(peer, addr.with(Protocol::P2p(peer.into())))));
+
+    // a restarted node restores what the previous one persisted
+    node.persist_routing_table();
+    let restored = routing_table::load(&path)?;
+    std::fs::remove_file(&path)?;
+    assert!(restored.iter().any(|(p, _)| *p == peer));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_blocked_peer_address() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let blocked = PeerId::random();
+    let mut config = NetworkConfig {
+        blocklist: vec![blocked],
+        ..Default::default()
+    };
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let addr: Multiaddr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+    node.mdns_discovered([(blocked, addr.clone())].into_iter());
+    assert!(!node.peers.contains(&blocked));
+    assert!(node.routing_table_entries().is_empty());
+
+    node.unblock_peer(blocked);
+    node.mdns_discovered([(blocked, addr.clone())].into_iter());
+    assert!(node.peers.contains(&blocked));
+    assert!(node.routing_table_entries().contains(&(blocked, addr)));
+
+    node.block_peer(blocked);
+    assert!(node
+        .routing_table_entries()
+        .iter()
+        .all(|(peer, _)| *peer != blocked));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_block_peer_command() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (mut node_1, node_1_addrs, node_1_id, _) = network_init(&mut config, None, None).await?;
+    let (mut node_2, ..) = network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    timeout(Duration::from_secs(10), async {
+        while !node_2.peers.contains(&node_1_id) {
+            select! {
+                event = node_1.swarm.select_next_some() => node_1.handle_swarm_event(event)?,
+                event = node_2.swarm.select_next_some() => node_2.handle_swarm_event(event)?,
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+
+    let (sender, receiver) = oneshot::channel();
+    node_2.handle_command(NetworkCommand::BlockPeer {
+        peer_id: node_1_id,
+        sender,
+    })?;
+    receiver.await?;
+    assert!(node_2.blocked_peers.contains(&node_1_id));
+    assert!(node_2
+        .routing_table_entries()
+        .iter()
+        .all(|(peer, _)| *peer != node_1_id));
+
+    // the connection to the blocked peer is closed
+    timeout(Duration::from_secs(10), async {
+        while node_2.peers.contains(&node_1_id) {
+            select! {
+                event = node_1.swarm.select_next_some() => node_1.handle_swarm_event(event)?,
+                event = node_2.swarm.select_next_some() => node_2.handle_swarm_event(event)?,
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+
+    let (sender, receiver) = oneshot::channel();
+    node_2.handle_command(NetworkCommand::UnblockPeer {
+        peer_id: node_1_id,
+        sender,
+    })?;
+    receiver.await?;
+    assert!(!node_2.blocked_peers.contains(&node_1_id));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dial_cooldown() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let (peer, addr) = (PeerId::random(), "/ip4/127.0.0.1/tcp/1".parse().unwrap());
+    let (sender, receiver) = oneshot::channel();
+    node.dial(peer, addr, sender)?;
+    receiver.await??;
+
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let event = node.swarm.select_next_some().await;
+            let failed = matches!(
+                event,
+                SwarmEvent::OutgoingConnectionError { peer_id: Some(p), .. } if p == peer
+            );
+            node.handle_swarm_event(event).unwrap();
+            if failed {
+                return;
+            }
+        }
+    })
+    .await?;
+
+    // the peer that just failed isn't dialed again
+    let (sender, receiver) = oneshot::channel();
+    node.dial(peer, "/ip4/127.0.0.1/tcp/2".parse().unwrap(), sender)?;
+    assert!(receiver.await?.is_err());
+    assert_eq!(node.dial_cooldown.len(), 1);
+
+    node.mdns_discovered([(peer, "/ip4/127.0.0.1/tcp/3".parse().unwrap())].into_iter());
+    assert!(!node.peers.contains(&peer));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dial_cooldown_kad_and_bitswap() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    // dialed by a kademlia query
+    let peer = PeerId::random();
+    node.swarm
+        .behaviour_mut()
+        .kad
+        .add_address(&peer, "/ip4/127.0.0.1/tcp/1".parse().unwrap());
+    node.swarm
+        .behaviour_mut()
+        .kad
+        .get_closest_peers(PeerId::random());
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let event = node.swarm.select_next_some().await;
+            let failed = matches!(
+                event,
+                SwarmEvent::OutgoingConnectionError { peer_id: Some(p), .. } if p == peer
+            );
+            node.handle_swarm_event(event).unwrap();
+            if failed {
+                return;
+            }
+        }
+    })
+    .await?;
+
+    assert!(node.dial_cooldown.is_cooling_down(&peer));
+    assert!(!node
+        .routing_table_entries()
+        .iter()
+        .any(|(entry, _)| *entry == peer));
+
+    // not asked for blocks over bitswap either
+    let cid = *get_block(b"cooling down").cid();
+    let other = PeerId::random();
+    node.want_block(cid, vec![peer, other], vec![peer], false);
+    assert!(node
+        .query_providers
+        .values()
+        .all(|providers| providers == &vec![other]));
+    assert!(node
+        .bitswap_fallbacks
+        .values()
+        .all(|fallback| !fallback.contains(&peer)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unconnected_providers_bounded() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let peers: Vec<PeerId> = (0..MAX_UNCONNECTED_PROVIDERS * 4)
+        .map(|_| PeerId::random())
+        .collect();
+    for (port, peer) in peers.iter().enumerate() {
+        let addr: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", port + 1).parse().unwrap();
+        node.swarm.behaviour_mut().kad.add_address(peer, addr);
+    }
+    let reliable = peers[peers.len() - 1];
+    node.reputation.record_success(reliable);
+
+    let providers = node.unconnected_providers();
+    assert_eq!(providers.len(), MAX_UNCONNECTED_PROVIDERS);
+    assert_eq!(providers[0], reliable);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dial_cooldown_local_peer() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let peer = PeerId::random();
+    node.mdns_discovered([(peer, "/ip4/127.0.0.1/tcp/1".parse().unwrap())].into_iter());
+    assert!(node.peers.contains(&peer));
+
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let event = node.swarm.select_next_some().await;
+            let failed = matches!(
+                event,
+                SwarmEvent::OutgoingConnectionError { peer_id: Some(p), .. } if p == peer
+            );
+            node.handle_swarm_event(event).unwrap();
+            if failed {
+                return;
+            }
+        }
+    })
+    .await?;
+
+    // the failed local peer cools down like any other
+    assert!(!node.peers.contains(&peer));
+    assert!(node.dial_cooldown.is_cooling_down(&peer));
+    node.mdns_discovered([(peer, "/ip4/127.0.0.1/tcp/2".parse().unwrap())].into_iter());
+    assert!(!node.peers.contains(&peer));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_blocked_peer_connection() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+    let (mut node_1, node_1_addrs, ..) = network_init(&mut config, None, None).await?;
+    let (mut node_2, _, node_2_id, _) = network_init(&mut config, Some(node_1_addrs), None).await?;
+    node_1.block_peer(node_2_id);
+
+    timeout(Duration::from_secs(10), async {
+        loop {
+            select! {
+                event = node_1.swarm.select_next_some() => {
+                    if let SwarmEvent::ConnectionClosed { peer_id, .. } = event {
+                        assert_eq!(peer_id, node_2_id);
+                        return;
+                    }
+                    node_1.handle_swarm_event(event).unwrap();
+                },
+                _ = node_2.swarm.select_next_some() => {},
+            }
+        }
+    })
+    .await?;
+    assert!(!node_1.peers.contains(&node_2_id));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mdns_expired() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+    let mut peer_events = node.subscribe_peer_events();
+
+    let peer = PeerId::random();
+    let addrs: Vec<Multiaddr> = vec![
+        "/ip4/127.0.0.1/tcp/1".parse().unwrap(),
+        "/ip4/127.0.0.1/tcp/2".parse().unwrap(),
+    ];
+    node.mdns_discovered(addrs.iter().map(|addr| (peer, addr.clone())));
+    assert_eq!(node.mdns_addresses[&peer].len(), 2);
+    assert!(node.peers.contains(&peer));
+
+    node.mdns_expired([(peer, addrs[0].clone())].into_iter());
+    assert_eq!(node.mdns_addresses[&peer].len(), 1);
+    assert!(node.peers.contains(&peer));
+    assert!(expired(&mut peer_events).is_empty());
+
+    // subscribers learn of the peer once all its addresses expired
+    node.mdns_expired([(peer, addrs[1].clone())].into_iter());
+    assert!(node.mdns_addresses.is_empty());
+    assert!(!node.peers.contains(&peer));
+    assert_eq!(expired(&mut peer_events), vec![peer]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_discovered() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+    let mut peer_events = node.subscribe_peer_events();
+
+    let peer = PeerId::random();
+    let addr: Multiaddr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+    let routing_updated = |is_new_peer| KademliaEvent::RoutingUpdated {
+        peer,
+        is_new_peer,
+        addresses: Addresses::new(addr.clone()),
+        bucket_range: (Distance::default(), Distance::default()),
+        old_peer: None,
+    };
+    node.handle_kad(routing_updated(true))?;
+    node.handle_kad(routing_updated(false))?;
+    assert_eq!(
+        discovered(&mut peer_events),
+        vec![(peer, vec![addr.clone()])]
+    );
+
+    // peers found by mdns are dialed right away, and not discovered again
+    node.mdns_discovered([(peer, addr.clone())].into_iter());
+    node.mdns_discovered([(peer, addr.clone())].into_iter());
+    node.handle_kad(routing_updated(true))?;
+    assert_eq!(discovered(&mut peer_events), vec![(peer, vec![addr])]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_prefer_local_peers() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let bootstraps: Vec<PeerId> = (0..3).map(|_| PeerId::random()).collect();
+    let mut config = NetworkConfig {
+        swarm_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+        bootstrap_nodes: bootstraps
+            .iter()
+            .map(|peer| format!("/ip4/127.0.0.1/tcp/1/p2p/{peer}").parse().unwrap())
+            .collect(),
+        bootstrap_dial_concurrency: 1,
+        bootstrap_peer_threshold: 2,
+        prefer_local_peers: true,
+        ..Default::default()
+    };
+    let local: Multiaddr = "/ip4/127.0.0.1/tcp/2".parse().unwrap();
+
+    for (prefer_local_peers, skipped) in [(true, true), (false, false)] {
+        config.prefer_local_peers = prefer_local_peers;
+        let mut node = UrsaService::new(Keypair::generate_ed25519(), &config, get_store())?;
+
+        // the first bootstrap node is dialed before any local peer is found
+        let locals: Vec<PeerId> = (0..2).map(|_| PeerId::random()).collect();
+        node.mdns_discovered(locals.iter().map(|peer| (*peer, local.clone())));
+        assert!(node.bootstrap_dials.complete(&bootstraps[0]));
+        assert_eq!(node.bootstrap_dials.next_dial().is_none(), skipped);
+
+        // the skipped ones are dialed once a local peer leaves
+        if skipped {
+            node.mdns_expired([(locals[0], local.clone())].into_iter());
+            assert!(node.bootstrap_dials.complete(&bootstraps[1]));
+            assert!(node.bootstrap_dials.next_dial().is_some());
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zero_upgrade_timeout() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let config = NetworkConfig {
+        transport_upgrade_timeout: 0,
+        ..Default::default()
+    };
+
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_quic_only() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        transport: TransportKind::Quic,
+        relay_client: false,
+        swarm_addrs: vec![
+            "/ip4/127.0.0.1/tcp/0".parse()?,
+            "/ip4/127.0.0.1/udp/0/quic-v1".parse()?,
+        ],
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+
+    // the tcp address is skipped
+    let (mut node_1, node_1_addr, node_1_id, _) =
+        network_init(&mut config, None, Some(Keypair::generate_ed25519())).await?;
+    assert!(node_1_addr.iter().any(|p| p == Protocol::QuicV1));
+    let (mut node_2, ..) = network_init(
+        &mut config,
+        Some(node_1_addr),
+        Some(Keypair::generate_ed25519()),
+    )
+    .await?;
+
+    let endpoint = timeout(Duration::from_secs(10), async {
+        loop {
+            select! {
+                _ = node_1.swarm.select_next_some() => {},
+                event = node_2.swarm.select_next_some() => {
+                    if let SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } = event {
+                        if peer_id == node_1_id {
+                            return endpoint;
+                        }
+                    }
+                }
+            }
+        }
+    })
+    .await?;
+    assert!(endpoint
+        .get_remote_address()
+        .iter()
+        .any(|p| p == Protocol::QuicV1));
+
+    // without an address of the transport, or with the tcp relay client
+    config.swarm_addrs = vec!["/ip4/127.0.0.1/tcp/0".parse()?];
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_err());
+    config.swarm_addrs = vec!["/ip4/127.0.0.1/udp/0/quic-v1".parse()?];
+    config.relay_client = true;
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_identity() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (mut node_1, node_1_addrs, node_1_id, _) = network_init(&mut config, None, None).await?;
+    let (mut node_2, ..) = network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    let identity = timeout(Duration::from_secs(10), async {
+        loop {
+            select! {
+                _ = node_1.swarm.select_next_some() => {},
+                event = node_2.swarm.select_next_some() => {
+                    node_2.handle_swarm_event(event)?;
+                    if let Some(identity) = node_2.peer_identity(&node_1_id) {
+                        return Ok::<_, anyhow::Error>(identity.clone());
+                    }
+                }
+            }
+        }
+    })
+    .await??;
+    assert!(identity.agent_version.starts_with("ursa/"));
+    assert!(identity
+        .protocols
+        .iter()
+        .any(|name| name.as_bytes() == KAD_PROTOCOL));
+
+    // forgotten with the peer, before its connections are closed
+    let (sender, receiver) = oneshot::channel();
+    node_2.handle_command(NetworkCommand::DisconnectAll { sender })?;
+    assert_eq!(receiver.await?, 1);
+    assert!(node_2.peer_identity(&node_1_id).is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_connection_directions() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (mut node_1, node_1_addrs, node_1_id, _) = network_init(&mut config, None, None).await?;
+    let (mut node_2, _, node_2_id, _) = network_init(&mut config, Some(node_1_addrs), None).await?;
+    assert_eq!(node_1.peer_info(&node_2_id), None);
+
+    // node 2 dials node 1
+    timeout(Duration::from_secs(10), async {
+        while node_1.peer_info(&node_2_id).is_none() || node_2.peer_info(&node_1_id).is_none() {
+            select! {
+                event = node_1.swarm.select_next_some() => node_1.handle_swarm_event(event)?,
+                event = node_2.swarm.select_next_some() => node_2.handle_swarm_event(event)?,
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+    let info_1 = node_1.peer_info(&node_2_id).unwrap();
+    assert_eq!((info_1.inbound, info_1.outbound), (1, 0));
+    let info_2 = node_2.peer_info(&node_1_id).unwrap();
+    assert_eq!((info_2.inbound, info_2.outbound), (0, 1));
+
+    node_1.swarm.disconnect_peer_id(node_2_id).unwrap();
+    timeout(Duration::from_secs(10), async {
+        while node_1.peer_info(&node_2_id).is_some() {
+            select! {
+                event = node_1.swarm.select_next_some() => node_1.handle_swarm_event(event)?,
+                event = node_2.swarm.select_next_some() => node_2.handle_swarm_event(event)?,
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_network_gossip() -> Result<()> {
     setup_logger(LevelFilter::Info);
@@ -175,6 +1035,307 @@ async fn test_network_gossip() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_publish_empty_mesh() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (node, ..) = network_init(&mut config, None, None).await?;
+    let sender = node.command_sender();
+    tokio::task::spawn(async move { node.start().await.unwrap() });
+
+    let (publish_sender, receiver) = oneshot::channel();
+    sender.send(NetworkCommand::GossipsubMessage {
+        peer_id: PeerId::random(),
+        message: GossipsubMessage::Publish {
+            topic: Topic::new(URSA_GLOBAL).hash(),
+            data: Bytes::from_static(b"hello world!"),
+            sender: publish_sender,
+        },
+    })?;
+    assert!(matches!(
+        timeout(Duration::from_secs(5), receiver).await??,
+        Err(PublishError::InsufficientPeers)
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gossip_outbound_limit() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        swarm_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+        gossip_outbound_limit: 0,
+        ..Default::default()
+    };
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_err());
+
+    config.gossip_outbound_limit = 2;
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+    let mut receivers = Vec::new();
+    for _ in 0..3 {
+        let (sender, receiver) = oneshot::channel();
+        node.handle_command(NetworkCommand::GossipsubMessage {
+            peer_id: PeerId::random(),
+            message: GossipsubMessage::Publish {
+                topic: Topic::new(URSA_GLOBAL).hash(),
+                data: Bytes::from_static(b"hello world!"),
+                sender,
+            },
+        })?;
+        receivers.push(receiver);
+    }
+    assert_eq!(node.gossip_outbound.len(), 2);
+    assert!(matches!(
+        receivers.pop().unwrap().await?,
+        Err(PublishError::TransformFailed(e)) if e.kind() == io::ErrorKind::WouldBlock
+    ));
+
+    // publishing frees the queue
+    node.publish_queued();
+    assert_eq!(node.gossip_outbound.len(), 1);
+    assert!(matches!(
+        receivers.remove(0).await?,
+        Err(PublishError::InsufficientPeers)
+    ));
+
+    // messages above the max transmit size aren't queued
+    let (sender, receiver) = oneshot::channel();
+    node.handle_command(NetworkCommand::GossipsubMessage {
+        peer_id: PeerId::random(),
+        message: GossipsubMessage::Publish {
+            topic: Topic::new(URSA_GLOBAL).hash(),
+            data: Bytes::from(vec![0; config.gossip_max_transmit_size + 1]),
+            sender,
+        },
+    })?;
+    assert_eq!(node.gossip_outbound.len(), 1);
+    assert!(matches!(
+        receiver.await?,
+        Err(PublishError::MessageTooLarge)
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_drain_queued_publishes() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+
+    let publish = |node: &mut UrsaService<MemoryDB>| {
+        let (sender, receiver) = oneshot::channel();
+        node.handle_command(NetworkCommand::GossipsubMessage {
+            peer_id: PeerId::random(),
+            message: GossipsubMessage::Publish {
+                topic: Topic::new(URSA_GLOBAL).hash(),
+                data: Bytes::from_static(b"hello world!"),
+                sender,
+            },
+        })
+        .map(|_| receiver)
+    };
+
+    // queued publishes are sent within the grace period
+    let receivers = vec![publish(&mut node)?, publish(&mut node)?];
+    timeout(Duration::from_secs(5), node.drain()).await?;
+    assert!(node.gossip_outbound.is_empty());
+    for receiver in receivers {
+        assert!(matches!(
+            receiver.await?,
+            Err(PublishError::InsufficientPeers)
+        ));
+    }
+
+    // and failed once it is over
+    let receiver = publish(&mut node)?;
+    node.fail_queued_publishes();
+    assert!(node.gossip_outbound.is_empty());
+    assert!(matches!(
+        receiver.await?,
+        Err(PublishError::TransformFailed(e)) if e.kind() == io::ErrorKind::Interrupted
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list_subscriptions() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (node, ..) = network_init(&mut config, None, None).await?;
+    let sender = node.command_sender();
+    tokio::task::spawn(async move { node.start().await.unwrap() });
+
+    let (list_sender, receiver) = oneshot::channel();
+    sender.send(NetworkCommand::ListSubscriptions {
+        sender: list_sender,
+    })?;
+    assert_eq!(
+        timeout(Duration::from_secs(5), receiver).await??,
+        vec![TopicStats {
+            topic: Topic::new(URSA_GLOBAL).hash(),
+            mesh_peers: 0,
+            published: 0,
+            received: 0,
+        }]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_await_peers() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (node_1, node_1_addrs, ..) = network_init(&mut config, None, None).await?;
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+
+    let (node_2, ..) = network_init(&mut config, Some(node_1_addrs), None).await?;
+    let node_2_sender = node_2.command_sender();
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    let (sender, receiver) = oneshot::channel();
+    node_2_sender.send(NetworkCommand::AwaitPeers { min: 1, sender })?;
+    let peers = timeout(Duration::from_secs(10), receiver).await??;
+    assert!(peers >= 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_put_record_too_large() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        bootstrap_nodes: vec![],
+        kad_max_record_size: 16,
+        ..Default::default()
+    };
+
+    let (node, ..) = network_init(&mut config, None, None).await?;
+    let sender = node.command_sender();
+    tokio::task::spawn(async move { node.start().await.unwrap() });
+
+    let (tx, rx) = oneshot::channel();
+    sender.send(NetworkCommand::PutRecord {
+        key: b"key".to_vec(),
+        value: vec![0; 17],
+        sender: tx,
+    })?;
+    let error = rx.await?.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<RecordTooLarge>(),
+        Some(&RecordTooLarge { size: 17, max: 16 })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_providers() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (mut node_1, node_1_addrs, peer_id_1, ..) = network_init(&mut config, None, None).await?;
+    let (mut node_2, _, peer_id_2, ..) =
+        network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    timeout(Duration::from_secs(10), async {
+        while !node_1.peers.contains(&peer_id_2) || !node_2.peers.contains(&peer_id_1) {
+            select! {
+                event = node_1.swarm.select_next_some() => node_1.handle_swarm_event(event).unwrap(),
+                event = node_2.swarm.select_next_some() => node_2.handle_swarm_event(event).unwrap(),
+            }
+        }
+    })
+    .await?;
+
+    let node_1_sender = node_1.command_sender();
+    let node_2_sender = node_2.command_sender();
+    let mut peer_events = node_2.subscribe_peer_events();
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    let (sender, receiver) = oneshot::channel();
+    node_1_sender.send(NetworkCommand::StartProviding {
+        key: b"key".to_vec(),
+        sender,
+    })?;
+    timeout(Duration::from_secs(20), receiver).await???;
+
+    let (sender, receiver) = oneshot::channel();
+    node_2_sender.send(NetworkCommand::GetProviders {
+        key: b"key".to_vec(),
+        sender,
+    })?;
+    let providers = timeout(Duration::from_secs(20), receiver).await???;
+    assert!(providers.contains(&peer_id_1));
+
+    // the lookup is also broadcast to the peer event subscribers
+    let event = timeout(Duration::from_secs(5), async {
+        loop {
+            if let PeerEvent::Providers { key, providers } = peer_events.recv().await? {
+                return Ok::<_, broadcast::error::RecvError>((key, providers));
+            }
+        }
+    })
+    .await??;
+    assert_eq!(event, (b"key".to_vec(), providers));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrent_provider_lookups() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (mut node_1, node_1_addrs, peer_id_1, ..) = network_init(&mut config, None, None).await?;
+    let (mut node_2, _, peer_id_2, ..) =
+        network_init(&mut config, Some(node_1_addrs), None).await?;
+
+    timeout(Duration::from_secs(10), async {
+        while !node_1.peers.contains(&peer_id_2) || !node_2.peers.contains(&peer_id_1) {
+            select! {
+                event = node_1.swarm.select_next_some() => node_1.handle_swarm_event(event).unwrap(),
+                event = node_2.swarm.select_next_some() => node_2.handle_swarm_event(event).unwrap(),
+            }
+        }
+    })
+    .await?;
+
+    // the provider record is stored locally right away
+    node_1.start_providing(b"popular".to_vec())?;
+    let mut receivers = vec![];
+    for _ in 0..3 {
+        let (sender, receiver) = oneshot::channel();
+        node_2.handle_command(NetworkCommand::GetProviders {
+            key: b"popular".to_vec(),
+            sender,
+        })?;
+        receivers.push(receiver);
+    }
+    assert_eq!(node_2.provider_lookups.len(), 1);
+    let other = node_2.get_providers(b"other".to_vec());
+    assert_eq!(node_2.provider_lookups.len(), 2);
+    assert_eq!(node_2.get_providers(b"other".to_vec()), other);
+
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    // every lookup is answered by the shared query
+    for receiver in receivers {
+        let providers = timeout(Duration::from_secs(20), receiver).await???;
+        assert!(providers.contains(&peer_id_1));
+    }
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_network_mdns() -> Result<()> {
     setup_logger(LevelFilter::Info);
@@ -297,6 +1458,173 @@ async fn test_network_req_res() -> Result<()> {
     Ok(())
 }
 
+/// Send a request carrying `payload_len` bytes from a node with `config` to another,
+/// returning the bandwidth of both and how long until the request was received.
+async fn send_payload(
+    mut config: NetworkConfig,
+    payload_len: usize,
+) -> Result<(Arc<BandwidthSinks>, Arc<BandwidthSinks>, Duration)> {
+    let (node_1, node_1_addrs, ..) = network_init(&mut config, None, None).await?;
+    let (mut node_2, _, peer_id_2, ..) =
+        network_init(&mut NetworkConfig::default(), Some(node_1_addrs), None).await?;
+    let (bandwidth_1, bandwidth_2) = (node_1.bandwidth(), node_2.bandwidth());
+    let mut peer_events = node_1.subscribe_peer_events();
+    let node_1_sender = node_1.command_sender();
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+
+    loop {
+        select! {
+            event = peer_events.recv() => if event? == PeerEvent::Connected(peer_id_2) {
+                break;
+            },
+            event = node_2.swarm.select_next_some() => node_2.handle_swarm_event(event)?,
+        }
+    }
+
+    let sent = Instant::now();
+    let request = UrsaExchangeRequest(RequestType::CarRequest("a".repeat(payload_len)));
+    node_1_sender.send(NetworkCommand::SendRequest {
+        peer_id: peer_id_2,
+        request: Box::new(request),
+        channel: oneshot::channel().0,
+    })?;
+    loop {
+        if let SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+            RequestResponseEvent::Message { .. },
+        )) = timeout(Duration::from_secs(10), node_2.swarm.select_next_some()).await?
+        {
+            return Ok((bandwidth_1, bandwidth_2, sent.elapsed()));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_bandwidth_metering() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let payload_len = 256 * 1024;
+    let (bandwidth_1, bandwidth_2, _) = send_payload(NetworkConfig::default(), payload_len).await?;
+
+    assert!(bandwidth_1.total_outbound() >= payload_len as u64);
+    assert!(bandwidth_2.total_inbound() >= payload_len as u64);
+    assert!(bandwidth_1.total_inbound() < payload_len as u64);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_max_upload_bytes_per_sec() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let config = NetworkConfig {
+        max_upload_bytes_per_sec: Some(0),
+        ..Default::default()
+    };
+    assert!(UrsaService::new(Keypair::generate_ed25519(), &config, get_store()).is_err());
+
+    // a second of burst, the rest of the payload waits for another second
+    let config = NetworkConfig {
+        max_upload_bytes_per_sec: Some(100_000),
+        ..Default::default()
+    };
+    let (bandwidth_1, bandwidth_2, elapsed) = send_payload(config, 200_000).await?;
+    assert!(elapsed >= Duration::from_millis(900), "{elapsed:?}");
+    assert!(bandwidth_1.total_outbound() >= 200_000);
+    assert!(bandwidth_2.total_inbound() >= 200_000);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_events() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (node_1, node_1_addrs, ..) = network_init(&mut config, None, None).await?;
+    let (node_2, _, peer_id_2, ..) = network_init(&mut config, Some(node_1_addrs), None).await?;
+    let mut peer_events = node_1.subscribe_peer_events();
+    let node_2_sender = node_2.command_sender();
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    assert_eq!(
+        timeout(Duration::from_secs(10), peer_events.recv()).await??,
+        PeerEvent::Connected(peer_id_2)
+    );
+
+    let (sender, receiver) = oneshot::channel();
+    node_2_sender.send(NetworkCommand::DisconnectAll { sender })?;
+    timeout(Duration::from_secs(10), receiver).await??;
+    assert_eq!(
+        timeout(Duration::from_secs(10), peer_events.recv()).await??,
+        PeerEvent::Disconnected(peer_id_2)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_queued_queries_per_provider() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        max_requests_per_peer: 1,
+        ..Default::default()
+    };
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+    let busy = PeerId::random();
+    let free = PeerId::random();
+    let data: [&[u8]; 3] = [b"first", b"second", b"third"];
+    let [first, second, third] = data.map(|data| *get_block(data).cid());
+    let query_of = |node: &UrsaService<MemoryDB>, cid: Cid| {
+        node.bitswap_queries
+            .iter()
+            .find(|(_, query_cid)| **query_cid == cid)
+            .map(|(query_id, _)| *query_id)
+    };
+
+    node.want_block(first, vec![busy], vec![], false);
+    let first_query = query_of(&node, first).unwrap();
+
+    // queries beyond the limit are queued until the provider is free
+    node.want_block(second, vec![busy], vec![], false);
+    assert!(query_of(&node, second).is_none());
+    assert_eq!(node.queued_queries[&busy].len(), 1);
+
+    // or skip the busy provider, asking it only if the others don't have the block
+    node.want_block(third, vec![busy, free], vec![], false);
+    let third_query = query_of(&node, third).unwrap();
+    assert_eq!(node.query_providers[&third_query], vec![free]);
+    assert_eq!(node.bitswap_fallbacks[&third_query], vec![busy]);
+
+    node.handle_bitswap(BitswapEvent::Complete(first_query, Ok(())))?;
+    assert!(query_of(&node, second).is_some());
+    assert!(node.queued_queries.is_empty());
+    assert_eq!(node.outstanding_queries[&busy], 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bitswap_activity() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+    let provider = PeerId::random();
+    let cid = *get_block(&b"hello world"[..]).cid();
+
+    node.want_block(cid, vec![provider], vec![], false);
+    let query_id = *node.bitswap_queries.keys().next().unwrap();
+    assert!(node
+        .peer_activity
+        .idle(Instant::now(), Duration::ZERO)
+        .is_empty());
+
+    // the providers asked by the query were active
+    node.handle_bitswap(BitswapEvent::Complete(query_id, Ok(())))?;
+    assert_eq!(
+        node.peer_activity.idle(Instant::now(), Duration::ZERO),
+        vec![provider]
+    );
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_bitswap_get() -> Result<()> {
     setup_logger(LevelFilter::Info);
@@ -363,6 +1691,128 @@ async fn test_bitswap_get() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_bitswap_get_unconnected_provider() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig::default();
+
+    let (node_1, node_1_addrs, peer_id_1, store_1) = network_init(&mut config, None, None).await?;
+    let (mut node_3, node_3_addrs, peer_id_3, _) = network_init(&mut config, None, None).await?;
+    let (mut node_2, _, _, store_2) = network_init(&mut config, Some(node_3_addrs), None).await?;
+
+    let block = get_block(&b"hello world"[..]);
+    insert_block(BitswapStorage(store_1.clone()), &block);
+
+    // node 2 is connected to node 3, which doesn't have the block, and only knows of node 1
+    timeout(Duration::from_secs(10), async {
+        while !node_2.peers.contains(&peer_id_3) {
+            select! {
+                event = node_2.swarm.select_next_some() => node_2.handle_swarm_event(event).unwrap(),
+                _ = node_3.swarm.select_next_some() => {},
+            }
+        }
+    })
+    .await?;
+    node_2
+        .swarm
+        .behaviour_mut()
+        .kad
+        .add_address(&peer_id_1, node_1_addrs);
+
+    let node_2_sender = node_2.command_sender();
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+    tokio::task::spawn(async move { node_3.start().await.unwrap() });
+
+    let (sender, receiver) = oneshot::channel();
+    let msg = NetworkCommand::GetBitswap {
+        cid: *block.cid(),
+        sender,
+    };
+    assert!(node_2_sender.send(msg).is_ok());
+    timeout(Duration::from_secs(20), receiver).await???;
+
+    let mut bitswap_store_2 = BitswapStorage(store_2);
+    assert_eq!(
+        bitswap_store_2.get(block.cid())?,
+        Some(block.data().to_vec())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_max_providers_per_fetch() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        max_providers_per_fetch: 2,
+        ..Default::default()
+    };
+    let (mut node, ..) = network_init(&mut config, None, None).await?;
+    let cid = *get_block(&b"hello world"[..]).cid();
+    let providers: Vec<PeerId> = (0..3).map(|_| PeerId::random()).collect();
+    let fallback = vec![PeerId::random()];
+
+    // the providers beyond the limit are asked once the first ones don't have it
+    let query_id = node.sync_block(cid, providers.clone(), fallback.clone())?;
+    assert_eq!(
+        node.bitswap_fallbacks[&query_id],
+        vec![providers[2], fallback[0]]
+    );
+
+    node.max_providers_per_fetch = 0;
+    let query_id = node.sync_block(cid, providers, vec![])?;
+    assert!(!node.bitswap_fallbacks.contains_key(&query_id));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fetch_from() -> Result<()> {
+    setup_logger(LevelFilter::Info);
+    let mut config = NetworkConfig {
+        bootstrap_nodes: vec![],
+        ..Default::default()
+    };
+
+    let (node_1, node_1_addrs, peer_id_1, store_1) = network_init(&mut config, None, None).await?;
+    let (node_2, _, _, store_2) = network_init(&mut config, None, None).await?;
+
+    let block = get_block(&b"hello world"[..]);
+    insert_block(BitswapStorage(store_1.clone()), &block);
+
+    let node_2_sender = node_2.command_sender();
+    tokio::task::spawn(async move { node_1.start().await.unwrap() });
+    tokio::task::spawn(async move { node_2.start().await.unwrap() });
+
+    // not connected, the peer is dialed at the given address
+    let (sender, receiver) = oneshot::channel();
+    node_2_sender.send(NetworkCommand::FetchFrom {
+        cid: *block.cid(),
+        peer_id: peer_id_1,
+        address: Some(node_1_addrs),
+        sender,
+    })?;
+    timeout(Duration::from_secs(10), receiver).await???;
+    assert_eq!(
+        BitswapStorage(store_2.clone()).get(block.cid())?,
+        Some(block.data().to_vec())
+    );
+
+    // the peer doesn't have the content
+    let missing = get_block(&b"missing"[..]);
+    let (sender, receiver) = oneshot::channel();
+    node_2_sender.send(NetworkCommand::FetchFrom {
+        cid: *missing.cid(),
+        peer_id: peer_id_1,
+        address: None,
+        sender,
+    })?;
+    assert!(timeout(Duration::from_secs(10), receiver).await??.is_err());
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_bitswap_sync() -> Result<()> {
     setup_logger(LevelFilter::Info);
@@ -1,32 +1,80 @@
 //! Ursa Transport implementation.
+use std::{error::Error, io, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
 use libp2p::{
+    bandwidth::BandwidthSinks,
     core::{
         muxing::StreamMuxerBox,
-        transport::{upgrade, Boxed, OrTransport},
+        transport::{timeout::TransportTimeoutError, upgrade, Boxed, OrTransport},
         upgrade::SelectUpgrade,
     },
     identity::Keypair,
     mplex, noise, quic,
     relay::v2::client::transport::ClientTransport,
     swarm::derive_prelude::EitherOutput,
-    tcp, yamux, PeerId, Transport,
+    tcp, yamux, PeerId, Transport, TransportExt,
 };
 
-use crate::config::NetworkConfig;
+use metrics::increment_counter;
+
+use crate::config::{NetworkConfig, TransportKind};
+use crate::utils::bandwidth::{ThrottledMuxer, UploadLimiter};
+
+/// Count the connections dropped for not completing the upgrade in time.
+fn upgrade_timeout<E>(err: TransportTimeoutError<E>) -> TransportTimeoutError<E> {
+    if let TransportTimeoutError::Timeout = err {
+        increment_counter!("network_transport_upgrade_timeouts");
+    }
+    err
+}
+
+/// Whether a connection was closed by yamux for exceeding `max_substreams_per_connection`.
+/// The muxer error is surfaced wrapped in an io error, which hides it from the sources.
+pub(crate) fn substream_limit_reached(cause: &(dyn Error + 'static)) -> bool {
+    let mut error = Some(cause);
+    while let Some(e) = error {
+        let muxer_error = e
+            .downcast_ref::<io::Error>()
+            .and_then(|e| e.get_ref())
+            .map_or(e, |inner| inner as &(dyn Error + 'static));
+        if let Some(::yamux::ConnectionError::TooManyStreams) = muxer_error.downcast_ref() {
+            return true;
+        }
+        error = e.source();
+    }
+    false
+}
 
-/// Creates a new [`UrsaTransport`].
+/// Creates a new [`UrsaTransport`] of the `transport` of the config.
 ///
 /// Defaults to QUIC transport over TCP.
 /// If QUIC fails to establish a connection, we fail over to TCP.
+///
+/// The connections are metered into the returned [`BandwidthSinks`], and their uploads
+/// throttled to `max_upload_bytes_per_sec` when set.
 pub(crate) fn build_transport(
     keypair: &Keypair,
     // todo(botch): make some of the transport options configurable
-    _config: &NetworkConfig,
+    config: &NetworkConfig,
     relay_transport: Option<ClientTransport>,
-) -> Boxed<(PeerId, StreamMuxerBox)> {
+) -> Result<(Boxed<(PeerId, StreamMuxerBox)>, Arc<BandwidthSinks>)> {
     let id_keys = keypair;
+    if config.transport_upgrade_timeout == 0 {
+        bail!("transport_upgrade_timeout must be greater than 0");
+    }
+    let upgrade_timeout_duration = Duration::from_secs(config.transport_upgrade_timeout);
+    if config.max_substreams_per_connection == 0 {
+        bail!("max_substreams_per_connection must be greater than 0");
+    }
+    if config.max_upload_bytes_per_sec == Some(0) {
+        bail!("max_upload_bytes_per_sec must be greater than 0");
+    }
+    if config.transport == TransportKind::Quic && relay_transport.is_some() {
+        bail!("relay_client requires the tcp transport");
+    }
 
-    let tcp = {
+    let tcp = || {
         let tcp_config = tcp::Config::default().port_reuse(true);
         let tcp_transport = tcp::tokio::Transport::new(tcp_config);
 
@@ -42,9 +90,11 @@ pub(crate) fn build_transport(
             let mut mplex_config = mplex::MplexConfig::new();
             mplex_config.set_max_buffer_behaviour(mplex::MaxBufferBehaviour::Block);
             mplex_config.set_max_buffer_size(usize::MAX);
+            mplex_config.set_max_num_streams(config.max_substreams_per_connection);
 
             let mut yamux_config = yamux::YamuxConfig::default();
             yamux_config.set_window_update_mode(yamux::WindowUpdateMode::on_read());
+            yamux_config.set_max_num_streams(config.max_substreams_per_connection);
 
             SelectUpgrade::new(yamux_config, mplex_config)
         };
@@ -55,12 +105,18 @@ pub(crate) fn build_transport(
                 .upgrade(upgrade::Version::V1)
                 .authenticate(noise)
                 .multiplex(mplex)
+                .timeout(upgrade_timeout_duration)
+                .map_err(upgrade_timeout)
+                .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
                 .boxed()
         } else {
             tcp_transport
                 .upgrade(upgrade::Version::V1)
                 .authenticate(noise)
                 .multiplex(mplex)
+                .timeout(upgrade_timeout_duration)
+                .map_err(upgrade_timeout)
+                .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
                 .boxed()
         }
     };
@@ -70,10 +126,60 @@ pub(crate) fn build_transport(
         quic::tokio::Transport::new(quic_config)
     };
 
-    OrTransport::new(quic, tcp)
-        .map(|either_output, _| match either_output {
-            EitherOutput::First((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
-            EitherOutput::Second((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
-        })
-        .boxed()
+    let transport = match config.transport {
+        TransportKind::Tcp => tcp(),
+        TransportKind::Quic => quic
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .boxed(),
+        TransportKind::Both => OrTransport::new(quic, tcp())
+            .map(|either_output, _| match either_output {
+                EitherOutput::First((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
+                EitherOutput::Second((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
+            })
+            .boxed(),
+    };
+
+    let transport = match config.max_upload_bytes_per_sec {
+        Some(rate) => {
+            let limiter = Arc::new(UploadLimiter::new(rate));
+            transport
+                .map(move |(peer_id, muxer), _| {
+                    let muxer = ThrottledMuxer::new(muxer, Arc::clone(&limiter));
+                    (peer_id, StreamMuxerBox::new(muxer))
+                })
+                .boxed()
+        }
+        None => transport,
+    };
+    Ok(transport.with_bandwidth_logging())
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::swarm::ConnectionError;
+
+    use super::*;
+
+    #[test]
+    fn test_substream_limit_reached() {
+        let closed = |error: io::Error| ConnectionError::<io::Error>::IO(error);
+
+        let too_many = io::Error::new(
+            io::ErrorKind::Other,
+            ::yamux::ConnectionError::TooManyStreams,
+        );
+        assert!(substream_limit_reached(&closed(too_many)));
+        assert!(substream_limit_reached(
+            &::yamux::ConnectionError::TooManyStreams
+        ));
+
+        // other muxer errors, and errors only reading like the muxer one
+        let closed_muxer = io::Error::new(io::ErrorKind::Other, ::yamux::ConnectionError::Closed);
+        assert!(!substream_limit_reached(&closed(closed_muxer)));
+        let lookalike = io::Error::new(io::ErrorKind::Other, "maximum number of streams reached");
+        assert!(!substream_limit_reached(&closed(lookalike)));
+        assert!(!substream_limit_reached(
+            &ConnectionError::<io::Error>::KeepAliveTimeout
+        ));
+    }
 }
@@ -0,0 +1,59 @@
+use libp2p::PeerId;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+
+/// Last time each connected peer was seen doing something other than keep-alive.
+#[derive(Debug, Default)]
+pub struct PeerActivity {
+    last_seen: HashMap<PeerId, Instant>,
+}
+
+impl PeerActivity {
+    pub fn touch(&mut self, peer: PeerId) {
+        self.last_seen.insert(peer, Instant::now());
+    }
+
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.last_seen.remove(peer);
+    }
+
+    /// Peers idle for at least `threshold`, longest idle first.
+    /// Peers without recorded activity are never considered idle.
+    pub fn idle(&self, now: Instant, threshold: Duration) -> Vec<PeerId> {
+        let mut idle: Vec<_> = self
+            .last_seen
+            .iter()
+            .filter(|(_, last)| now.saturating_duration_since(**last) >= threshold)
+            .map(|(peer, last)| (*peer, *last))
+            .collect();
+        idle.sort_by_key(|(_, last)| *last);
+        idle.into_iter().map(|(peer, _)| peer).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle() {
+        let mut activity = PeerActivity::default();
+        let (old, recent, removed) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        activity
+            .last_seen
+            .insert(old, now - Duration::from_secs(15));
+        activity
+            .last_seen
+            .insert(recent, now - Duration::from_secs(5));
+        activity.touch(removed);
+        activity.remove(&removed);
+
+        assert_eq!(activity.idle(now, Duration::from_secs(10)), vec![old]);
+        assert_eq!(
+            activity.idle(now, Duration::from_secs(5)),
+            vec![old, recent]
+        );
+        assert!(activity.idle(now, Duration::from_secs(20)).is_empty());
+    }
+}
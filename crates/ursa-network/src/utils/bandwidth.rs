@@ -0,0 +1,211 @@
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    ready,
+};
+use libp2p::core::muxing::{
+    StreamMuxer, StreamMuxerBox, StreamMuxerEvent, StreamMuxerExt, SubstreamBox,
+};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::{sleep, Instant, Sleep};
+
+/// Token bucket shared by the substreams of every connection, holding at most a second
+/// of uploads.
+#[derive(Debug)]
+pub struct UploadLimiter {
+    /// bytes per second
+    rate: f64,
+    /// fewest bytes a throttled write waits for, so slow rates don't wake per byte
+    chunk: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    bytes: f64,
+    refilled: Instant,
+}
+
+impl UploadLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec as f64;
+        Self {
+            rate,
+            chunk: (rate / 20.0).max(1.0),
+            bucket: Mutex::new(Bucket {
+                bytes: rate,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take up to `len` bytes of the bucket, or how long to wait before asking again.
+    fn take(&self, len: usize) -> Result<usize, Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.rate;
+        bucket.bytes = (bucket.bytes + refill).min(self.rate);
+        bucket.refilled = now;
+
+        let wanted = (len as f64).min(self.chunk);
+        if bucket.bytes < wanted {
+            return Err(Duration::from_secs_f64((wanted - bucket.bytes) / self.rate));
+        }
+        let taken = len.min(bucket.bytes as usize);
+        bucket.bytes -= taken as f64;
+        Ok(taken)
+    }
+
+    /// Give back bytes taken but not written.
+    fn refund(&self, len: usize) {
+        if len > 0 {
+            let mut bucket = self.bucket.lock().unwrap();
+            bucket.bytes = (bucket.bytes + len as f64).min(self.rate);
+        }
+    }
+}
+
+/// Muxer whose substreams write through an [`UploadLimiter`].
+pub struct ThrottledMuxer {
+    inner: StreamMuxerBox,
+    limiter: Arc<UploadLimiter>,
+}
+
+impl ThrottledMuxer {
+    pub fn new(inner: StreamMuxerBox, limiter: Arc<UploadLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+
+    fn throttle(&self, substream: SubstreamBox) -> ThrottledStream<SubstreamBox> {
+        ThrottledStream {
+            inner: substream,
+            limiter: Arc::clone(&self.limiter),
+            delay: None,
+        }
+    }
+}
+
+impl StreamMuxer for ThrottledMuxer {
+    type Substream = ThrottledStream<SubstreamBox>;
+    type Error = io::Error;
+
+    fn poll_inbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let substream = ready!(self.inner.poll_inbound_unpin(cx))?;
+        Poll::Ready(Ok(self.throttle(substream)))
+    }
+
+    fn poll_outbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let substream = ready!(self.inner.poll_outbound_unpin(cx))?;
+        Poll::Ready(Ok(self.throttle(substream)))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        self.inner.poll_unpin(cx)
+    }
+}
+
+/// Substream waiting for the [`UploadLimiter`] before each write.
+pub struct ThrottledStream<S> {
+    inner: S,
+    limiter: Arc<UploadLimiter>,
+    /// pending wait for the bucket to refill
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ThrottledStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        }
+        loop {
+            if let Some(delay) = self.delay.as_mut() {
+                ready!(delay.as_mut().poll(cx));
+                self.delay = None;
+            }
+            match self.limiter.take(buf.len()) {
+                Ok(taken) => {
+                    let written = Pin::new(&mut self.inner).poll_write(cx, &buf[..taken]);
+                    let unused = match &written {
+                        Poll::Ready(Ok(len)) => taken - len,
+                        _ => taken,
+                    };
+                    self.limiter.refund(unused);
+                    return written;
+                }
+                Err(wait) => self.delay = Some(Box::pin(sleep(wait))),
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elapse(limiter: &UploadLimiter, duration: Duration) {
+        limiter.bucket.lock().unwrap().refilled -= duration;
+    }
+
+    #[test]
+    fn test_upload_limiter() {
+        let limiter = UploadLimiter::new(1000);
+
+        // a second of burst, then writes wait for the refill
+        assert_eq!(limiter.take(600), Ok(600));
+        assert!(matches!(limiter.take(600), Ok(400..=401)));
+        let wait = limiter.take(10).unwrap_err();
+        assert!(wait <= Duration::from_millis(10), "{wait:?}");
+        let wait = limiter.take(600).unwrap_err();
+        assert!(wait > Duration::from_millis(40), "{wait:?}");
+
+        elapse(&limiter, Duration::from_millis(100));
+        assert!(matches!(limiter.take(600), Ok(100..=101)));
+        limiter.refund(40);
+        assert_eq!(limiter.take(40), Ok(40));
+
+        // never more than a second in the bucket
+        elapse(&limiter, Duration::from_secs(10));
+        assert_eq!(limiter.take(5000), Ok(1000));
+    }
+}
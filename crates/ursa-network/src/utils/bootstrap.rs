@@ -0,0 +1,193 @@
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use rand::Rng;
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
+use tracing::warn;
+
+/// Peer id of a `/p2p/` terminated multiaddr.
+pub fn peer_id(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last() {
+        Some(Protocol::P2p(mh)) => PeerId::from_multihash(mh).ok(),
+        _ => None,
+    }
+}
+
+/// Drop the bootstrap addresses of the local peer, dialing ourselves only fails.
+pub fn without_local(addrs: &[Multiaddr], local: &PeerId) -> Vec<Multiaddr> {
+    addrs
+        .iter()
+        .filter(|addr| {
+            let is_local = peer_id(addr).as_ref() == Some(local);
+            if is_local {
+                warn!("Ignoring bootstrap node {addr} with the local peer id");
+            }
+            !is_local
+        })
+        .cloned()
+        .collect()
+}
+
+/// `interval` randomly moved by up to `percent` of it either way, so nodes started
+/// together don't query the bootstrap nodes in lockstep.
+pub fn jittered<R: Rng>(interval: Duration, percent: u8, rng: &mut R) -> Duration {
+    let spread = interval.mul_f64(f64::from(percent.min(100)) / 100.0);
+    if spread.is_zero() {
+        return interval;
+    }
+    interval - spread + spread.mul_f64(rng.gen_range(0.0..=2.0))
+}
+
+/// Bootstrap addresses dialed in waves of at most `limit` pending dials.
+/// A limit of 0 dials every address at once.
+///
+/// Dials are tracked by the peer id of the address, addresses without one
+/// are dialed without taking a slot.
+#[derive(Debug, Default)]
+pub struct BootstrapDials {
+    queue: VecDeque<Multiaddr>,
+    pending: HashSet<PeerId>,
+    limit: usize,
+    peak: usize,
+    paused: bool,
+}
+
+impl BootstrapDials {
+    pub fn new(addrs: impl IntoIterator<Item = Multiaddr>, limit: usize) -> Self {
+        Self {
+            queue: addrs.into_iter().collect(),
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Next address to dial, if a slot is free.
+    pub fn next_dial(&mut self) -> Option<Multiaddr> {
+        if self.paused || self.limit != 0 && self.pending.len() >= self.limit {
+            return None;
+        }
+        let addr = self.queue.pop_front()?;
+        if let Some(peer) = peer_id(&addr) {
+            self.pending.insert(peer);
+            self.peak = self.peak.max(self.pending.len());
+        }
+        Some(addr)
+    }
+
+    /// Hold back the addresses left to dial until [`Self::resume`], returns how many
+    /// were held back, 0 if the dials were already paused.
+    pub fn pause(&mut self) -> usize {
+        if self.paused {
+            return 0;
+        }
+        self.paused = true;
+        self.queue.len()
+    }
+
+    /// Dial the addresses held back by [`Self::pause`] again, returns how many are left.
+    pub fn resume(&mut self) -> usize {
+        if !self.paused {
+            return 0;
+        }
+        self.paused = false;
+        self.queue.len()
+    }
+
+    /// Free the slot of a finished dial, returns false for non bootstrap dials.
+    pub fn complete(&mut self, peer: &PeerId) -> bool {
+        self.pending.remove(peer)
+    }
+
+    /// Highest number of simultaneously pending dials.
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jittered() {
+        let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(7);
+        let interval = Duration::from_secs(100);
+        let delays: Vec<Duration> = (0..100).map(|_| jittered(interval, 10, &mut rng)).collect();
+        assert!(delays
+            .iter()
+            .all(|delay| (Duration::from_secs(90)..=Duration::from_secs(110)).contains(delay)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+
+        assert_eq!(jittered(interval, 0, &mut rng), interval);
+        assert!(jittered(interval, 255, &mut rng) <= Duration::from_secs(200));
+    }
+
+    fn addr(peer: PeerId) -> Multiaddr {
+        format!("/ip4/127.0.0.1/tcp/6009/p2p/{peer}")
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_without_local() {
+        let local = PeerId::random();
+        let other = PeerId::random();
+        let anonymous: Multiaddr = "/ip4/127.0.0.1/tcp/6009".parse().unwrap();
+        assert_eq!(
+            without_local(&[addr(local), addr(other), anonymous.clone()], &local),
+            vec![addr(other), anonymous]
+        );
+    }
+
+    #[test]
+    fn test_waves() {
+        let peers: Vec<_> = (0..3).map(|_| PeerId::random()).collect();
+        let mut dials = BootstrapDials::new(peers.iter().copied().map(addr), 2);
+
+        assert_eq!(dials.next_dial(), Some(addr(peers[0])));
+        assert_eq!(dials.next_dial(), Some(addr(peers[1])));
+        assert_eq!(dials.next_dial(), None);
+
+        assert!(!dials.complete(&PeerId::random()));
+        assert_eq!(dials.next_dial(), None);
+
+        assert!(dials.complete(&peers[0]));
+        assert_eq!(dials.next_dial(), Some(addr(peers[2])));
+        assert_eq!(dials.next_dial(), None);
+        assert_eq!(dials.peak(), 2);
+    }
+
+    #[test]
+    fn test_pause() {
+        let peers: Vec<_> = (0..3).map(|_| PeerId::random()).collect();
+        let mut dials = BootstrapDials::new(peers.iter().copied().map(addr), 1);
+
+        assert_eq!(dials.next_dial(), Some(addr(peers[0])));
+        assert_eq!(dials.pause(), 2);
+        assert_eq!(dials.pause(), 0);
+        assert!(dials.complete(&peers[0]));
+        assert_eq!(dials.next_dial(), None);
+
+        assert_eq!(dials.resume(), 2);
+        assert_eq!(dials.resume(), 0);
+        assert_eq!(dials.next_dial(), Some(addr(peers[1])));
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let anonymous: Multiaddr = "/ip4/127.0.0.1/tcp/6009".parse().unwrap();
+        let peers: Vec<_> = (0..3).map(|_| PeerId::random()).collect();
+        let mut dials = BootstrapDials::new(
+            peers.iter().copied().map(addr).chain([anonymous.clone()]),
+            0,
+        );
+
+        for peer in &peers {
+            assert_eq!(dials.next_dial(), Some(addr(*peer)));
+        }
+        assert_eq!(dials.next_dial(), Some(anonymous));
+        assert_eq!(dials.next_dial(), None);
+        assert_eq!(dials.peak(), 3);
+    }
+}
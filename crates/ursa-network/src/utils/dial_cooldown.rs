@@ -0,0 +1,93 @@
+use libp2p::PeerId;
+use metrics::gauge;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+
+/// Peers whose last dial failed, not dialed again until `cooldown` elapsed.
+/// The `network_dial_cooldown_peers` gauge follows every change of the peers.
+#[derive(Debug)]
+pub struct DialCooldown {
+    cooldown: Duration,
+    failed: HashMap<PeerId, Instant>,
+}
+
+impl DialCooldown {
+    /// A zero `cooldown` never skips a dial.
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            failed: HashMap::new(),
+        }
+    }
+
+    pub fn record_failure(&mut self, peer: PeerId) {
+        if self.cooldown.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        self.failed
+            .retain(|_, failed| now.saturating_duration_since(*failed) < self.cooldown);
+        self.failed.insert(peer, now);
+        self.record();
+    }
+
+    /// Forget the failure of a peer, e.g. once connected.
+    pub fn clear(&mut self, peer: &PeerId) {
+        if self.failed.remove(peer).is_some() {
+            self.record();
+        }
+    }
+
+    /// Whether the last dial of the peer failed less than `cooldown` ago.
+    pub fn is_cooling_down(&mut self, peer: &PeerId) -> bool {
+        match self.failed.get(peer) {
+            Some(failed) if failed.elapsed() < self.cooldown => true,
+            Some(_) => {
+                self.failed.remove(peer);
+                self.record();
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.failed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    fn record(&self) {
+        gauge!("network_dial_cooldown_peers", self.failed.len() as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cooldown() {
+        let mut cooldown = DialCooldown::new(Duration::from_secs(10));
+        let (failed, expired) = (PeerId::random(), PeerId::random());
+        cooldown.record_failure(failed);
+        cooldown
+            .failed
+            .insert(expired, Instant::now() - Duration::from_secs(10));
+
+        assert!(cooldown.is_cooling_down(&failed));
+        assert!(!cooldown.is_cooling_down(&expired));
+        assert!(!cooldown.is_cooling_down(&PeerId::random()));
+        assert_eq!(cooldown.len(), 1);
+
+        cooldown.clear(&failed);
+        assert!(!cooldown.is_cooling_down(&failed));
+        assert!(cooldown.is_empty());
+
+        let mut disabled = DialCooldown::new(Duration::ZERO);
+        disabled.record_failure(failed);
+        assert!(!disabled.is_cooling_down(&failed));
+    }
+}
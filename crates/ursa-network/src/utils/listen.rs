@@ -0,0 +1,161 @@
+use anyhow::{bail, Result};
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tracing::warn;
+
+#[derive(Debug, PartialEq, Eq)]
+enum SocketKind {
+    Tcp,
+    Udp,
+}
+
+/// Socket bound when listening on `addr`, `None` for addresses not binding a socket.
+fn socket(addr: &Multiaddr) -> Option<(SocketKind, IpAddr, u16)> {
+    let mut iter = addr.iter();
+    let ip = match iter.next()? {
+        Protocol::Ip4(ip) => IpAddr::V4(ip),
+        Protocol::Ip6(ip) => IpAddr::V6(ip),
+        _ => return None,
+    };
+    match iter.next()? {
+        Protocol::Tcp(port) => Some((SocketKind::Tcp, ip, port)),
+        Protocol::Udp(port) => Some((SocketKind::Udp, ip, port)),
+        _ => None,
+    }
+}
+
+/// Whether binding both sockets would conflict. Ephemeral ports never do.
+fn overlaps(a: &(SocketKind, IpAddr, u16), b: &(SocketKind, IpAddr, u16)) -> bool {
+    let ((kind_a, ip_a, port_a), (kind_b, ip_b, port_b)) = (a, b);
+    kind_a == kind_b
+        && port_a == port_b
+        && *port_a != 0
+        && ip_a.is_ipv4() == ip_b.is_ipv4()
+        && (ip_a == ip_b || ip_a.is_unspecified() || ip_b.is_unspecified())
+}
+
+/// Whether `addr` can be reached from other networks. Loopback, private, link-local
+/// and unspecified ips are not, addresses without an ip, e.g. dns, are assumed to be.
+pub fn is_public(addr: &Multiaddr) -> bool {
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => is_public_ip4(ip),
+        Some(Protocol::Ip6(ip)) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip4(ip),
+            None => is_public_ip6(ip),
+        },
+        _ => true,
+    }
+}
+
+fn is_public_ip4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    // carrier grade nat range 100.64.0.0/10
+    let shared = a == 100 && (b & 0xc0) == 64;
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || shared)
+}
+
+fn is_public_ip6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    // unique local fc00::/7 and link-local fe80::/10
+    let unique_local = (first & 0xfe00) == 0xfc00;
+    let link_local = (first & 0xffc0) == 0xfe80;
+    !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local)
+}
+
+/// Drop exact duplicates of the listen addresses, failing if two different
+/// addresses would bind the same socket.
+pub fn dedup_listen_addrs(addrs: &[Multiaddr]) -> Result<Vec<Multiaddr>> {
+    let mut unique: Vec<Multiaddr> = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        if unique.contains(addr) {
+            warn!("Ignoring duplicate listen address {addr}");
+            continue;
+        }
+        if let Some(bound) = socket(addr) {
+            if let Some(other) = unique
+                .iter()
+                .find(|other| socket(other).map_or(false, |s| overlaps(&bound, &s)))
+            {
+                bail!("Listen addresses {other} and {addr} would bind the same socket");
+            }
+        }
+        unique.push(addr.clone());
+    }
+    Ok(unique)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(addrs: &[&str]) -> Vec<Multiaddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_dedup() {
+        let deduped = dedup_listen_addrs(&addrs(&[
+            "/ip4/0.0.0.0/tcp/6009",
+            "/ip4/0.0.0.0/udp/6009/quic-v1",
+            "/ip4/0.0.0.0/tcp/6009",
+            "/ip4/127.0.0.1/tcp/0",
+            "/ip4/127.0.0.1/tcp/0",
+            "/ip6/::/tcp/6009",
+        ]))
+        .unwrap();
+        assert_eq!(
+            deduped,
+            addrs(&[
+                "/ip4/0.0.0.0/tcp/6009",
+                "/ip4/0.0.0.0/udp/6009/quic-v1",
+                "/ip4/127.0.0.1/tcp/0",
+                "/ip6/::/tcp/6009",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_is_public() {
+        for addr in [
+            "/ip4/127.0.0.1/tcp/6009",
+            "/ip4/192.168.1.2/tcp/6009",
+            "/ip4/10.0.0.1/udp/4890/quic-v1",
+            "/ip4/100.64.0.1/tcp/6009",
+            "/ip4/169.254.0.1/tcp/6009",
+            "/ip4/0.0.0.0/tcp/6009",
+            "/ip6/::1/tcp/6009",
+            "/ip6/fe80::1/tcp/6009",
+            "/ip6/fd00::1/tcp/6009",
+            "/ip6/::ffff:127.0.0.1/tcp/6009",
+        ] {
+            assert!(!is_public(&addr.parse().unwrap()), "{addr}");
+        }
+        for addr in [
+            "/ip4/159.223.211.234/tcp/6009",
+            "/ip6/2001:4860::8888/tcp/6009",
+            "/dns4/node.ursa.earth/tcp/6009",
+        ] {
+            assert!(is_public(&addr.parse().unwrap()), "{addr}");
+        }
+    }
+
+    #[test]
+    fn test_same_socket() {
+        for conflicting in [
+            ["/ip4/0.0.0.0/tcp/6009", "/ip4/127.0.0.1/tcp/6009"],
+            ["/ip4/127.0.0.1/tcp/6009", "/ip4/127.0.0.1/tcp/6009/ws"],
+            [
+                "/ip4/0.0.0.0/udp/4890/quic-v1",
+                "/ip4/0.0.0.0/udp/4890/quic",
+            ],
+        ] {
+            assert!(dedup_listen_addrs(&addrs(&conflicting)).is_err());
+        }
+    }
+}
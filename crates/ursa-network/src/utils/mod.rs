@@ -1 +1,8 @@
+pub mod activity;
+pub mod bandwidth;
+pub mod bootstrap;
 pub mod cache_summary;
+pub mod dial_cooldown;
+pub mod listen;
+pub mod reputation;
+pub mod routing_table;
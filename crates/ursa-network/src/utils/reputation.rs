@@ -0,0 +1,152 @@
+use libp2p::PeerId;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+
+/// Disconnected peers not interacted with for this long are forgotten.
+const REPUTATION_TTL: Duration = Duration::from_secs(60 * 60);
+/// Disconnected peers kept at most, the least recently updated are forgotten first.
+const MAX_DISCONNECTED: usize = 1024;
+
+/// Interaction stats for a single peer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Reputation {
+    pub successes: u64,
+    pub failures: u64,
+}
+
+impl Reputation {
+    pub fn score(&self) -> i64 {
+        self.successes as i64 - self.failures as i64
+    }
+}
+
+/// Tracks successful and failed interactions with peers.
+/// Used to prefer unreliable peers when evicting from full routing buckets.
+#[derive(Debug, Default)]
+pub struct ReputationTable {
+    /// reputation of each peer, with when it was last updated
+    peers: HashMap<PeerId, (Reputation, Instant)>,
+}
+
+impl ReputationTable {
+    pub fn record_success(&mut self, peer: PeerId) {
+        self.update(peer).successes += 1;
+    }
+
+    pub fn record_failure(&mut self, peer: PeerId) {
+        self.update(peer).failures += 1;
+    }
+
+    fn update(&mut self, peer: PeerId) -> &mut Reputation {
+        let (reputation, updated) = self
+            .peers
+            .entry(peer)
+            .or_insert_with(|| (Reputation::default(), Instant::now()));
+        *updated = Instant::now();
+        reputation
+    }
+
+    /// Score of a peer, unknown peers are neutral.
+    pub fn score(&self, peer: &PeerId) -> i64 {
+        self.peers
+            .get(peer)
+            .map(|(reputation, _)| reputation.score())
+            .unwrap_or(0)
+    }
+
+    /// The candidate with the lowest score, if any.
+    pub fn least_reliable(&self, candidates: impl Iterator<Item = PeerId>) -> Option<PeerId> {
+        candidates.min_by_key(|peer| self.score(peer))
+    }
+
+    pub fn scores(&self) -> HashMap<PeerId, i64> {
+        self.peers
+            .iter()
+            .map(|(peer, (reputation, _))| (*peer, reputation.score()))
+            .collect()
+    }
+
+    /// Forget the disconnected peers not updated within [`REPUTATION_TTL`], then the least
+    /// recently updated ones beyond [`MAX_DISCONNECTED`].
+    pub fn prune(&mut self, is_connected: impl Fn(&PeerId) -> bool) {
+        let now = Instant::now();
+        self.peers.retain(|peer, (_, updated)| {
+            is_connected(peer) || now.saturating_duration_since(*updated) < REPUTATION_TTL
+        });
+
+        let mut disconnected: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(peer, _)| !is_connected(peer))
+            .map(|(peer, (_, updated))| (*peer, *updated))
+            .collect();
+        if disconnected.len() > MAX_DISCONNECTED {
+            disconnected.sort_by_key(|(_, updated)| *updated);
+            for (peer, _) in &disconnected[..disconnected.len() - MAX_DISCONNECTED] {
+                self.peers.remove(peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score() {
+        let mut table = ReputationTable::default();
+        let peer = PeerId::random();
+        assert_eq!(table.score(&peer), 0);
+
+        table.record_success(peer);
+        table.record_success(peer);
+        table.record_failure(peer);
+        assert_eq!(table.score(&peer), 1);
+        assert_eq!(table.scores().get(&peer), Some(&1));
+    }
+
+    #[test]
+    fn test_least_reliable() {
+        let mut table = ReputationTable::default();
+        let (good, bad, unknown) = (PeerId::random(), PeerId::random(), PeerId::random());
+        table.record_success(good);
+        table.record_failure(bad);
+
+        let candidates = vec![good, bad, unknown];
+        assert_eq!(table.least_reliable(candidates.into_iter()), Some(bad));
+        assert_eq!(
+            table.least_reliable(vec![good, unknown].into_iter()),
+            Some(unknown)
+        );
+        assert_eq!(table.least_reliable(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut table = ReputationTable::default();
+        let (connected, stale, recent) = (PeerId::random(), PeerId::random(), PeerId::random());
+        for peer in [connected, stale, recent] {
+            table.record_success(peer);
+        }
+        for peer in [connected, stale] {
+            table.peers.get_mut(&peer).unwrap().1 -= REPUTATION_TTL;
+        }
+
+        // only the disconnected peers age out
+        table.prune(|peer| *peer == connected);
+        assert_eq!(table.score(&connected), 1);
+        assert_eq!(table.score(&stale), 0);
+        assert_eq!(table.score(&recent), 1);
+
+        // beyond the bound, the least recently updated disconnected peers are forgotten
+        table.peers.get_mut(&recent).unwrap().1 -= Duration::from_secs(1);
+        for _ in 0..MAX_DISCONNECTED {
+            table.record_failure(PeerId::random());
+        }
+        table.prune(|peer| *peer == connected);
+        assert_eq!(table.peers.len(), MAX_DISCONNECTED + 1);
+        assert_eq!(table.score(&connected), 1);
+        assert_eq!(table.score(&recent), 0);
+    }
+}
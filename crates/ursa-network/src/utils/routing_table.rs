@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::{fs, path::Path};
+use tracing::warn;
+
+use super::bootstrap::peer_id;
+
+/// Routing table entries persisted at `path`, one `/p2p/` terminated multiaddr per line.
+/// Malformed lines are skipped.
+pub fn load(path: &Path) -> Result<Vec<(PeerId, Multiaddr)>> {
+    let table = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read routing table from {path:?}"))?;
+    Ok(table
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let entry = line
+                .parse::<Multiaddr>()
+                .ok()
+                .and_then(|addr| Some((peer_id(&addr)?, addr)));
+            if entry.is_none() {
+                warn!("Skipping malformed routing table entry: {line}");
+            }
+            entry
+        })
+        .collect())
+}
+
+/// Write the routing table entries to `path`, replacing the previous table.
+pub fn save(path: &Path, entries: impl Iterator<Item = (PeerId, Multiaddr)>) -> Result<usize> {
+    let mut table = String::new();
+    let mut count = 0;
+    for (peer, mut addr) in entries {
+        if peer_id(&addr).is_none() {
+            addr.push(Protocol::P2p(peer.into()));
+        }
+        table.push_str(&addr.to_string());
+        table.push('\n');
+        count += 1;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, table).with_context(|| format!("Failed to write routing table to {path:?}"))?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join(format!("ursa-routing-table-{}", std::process::id()));
+        let peers: Vec<_> = (0..2).map(|_| PeerId::random()).collect();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/6009".parse().unwrap();
+
+        let entries = peers.iter().map(|peer| (*peer, addr.clone()));
+        assert_eq!(save(&path, entries).unwrap(), 2);
+        let mut table = fs::read_to_string(&path).unwrap();
+        table.push_str("not a multiaddr\n/ip4/127.0.0.1/tcp/6009\n");
+        fs::write(&path, table).unwrap();
+
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            loaded,
+            peers
+                .iter()
+                .map(|peer| (*peer, addr.clone().with(Protocol::P2p((*peer).into()))))
+                .collect::<Vec<_>>()
+        );
+    }
+}
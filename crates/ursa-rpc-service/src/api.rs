@@ -1,41 +1,72 @@
 use anyhow::{anyhow, Result};
-use async_fs::{create_dir_all, File};
+use async_fs::{create_dir_all, remove_file, File};
 use async_trait::async_trait;
 use axum::body::StreamBody;
+use bytes::Bytes;
 use db::Store;
-use futures::channel::mpsc::unbounded;
+use futures::channel::mpsc::channel;
 use futures::io::BufReader;
-use futures::{AsyncRead, AsyncWriteExt, SinkExt};
+use futures::stream::{self, BoxStream};
+use futures::{AsyncRead, AsyncReadExt, FutureExt, SinkExt, StreamExt};
 use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_car::{load_car, CarHeader, CarReader};
-use libipld::Cid;
-use libp2p::{Multiaddr, PeerId};
+use libipld::{
+    cbor::DagCborCodec,
+    codec::Codec,
+    multihash::{Code, Hasher, Multihash, MultihashDigest, Sha2_256},
+    Cid, Ipld,
+};
+use libp2p::{gossipsub::TopicHash, Multiaddr, PeerId};
+use metrics::{decrement_gauge, increment_gauge};
 use serde::{Deserialize, Serialize};
 use std::collections::{
     hash_map::{Entry, HashMap},
     HashSet,
 };
+use std::fmt::{Display, Formatter};
+use std::io;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use surf::{http::Method, Client, RequestBuilder};
 use tokio::sync::{
+    broadcast,
     mpsc::{unbounded_channel, UnboundedSender as Sender},
-    oneshot, RwLock,
+    oneshot, OwnedSemaphorePermit, RwLock, Semaphore,
 };
 use tokio::task;
 use tokio_util::{compat::TokioAsyncWriteCompatExt, io::ReaderStream};
 use tracing::{debug, error, info};
 use ursa_index_provider::engine::ProviderCommand;
-use ursa_network::NetworkCommand;
-use ursa_store::UrsaStore;
+use ursa_network::{
+    is_public, GossipsubMessage, NetworkCommand, NodeInfo, PeerEvent, PeerInfo, PublishAck,
+    TopicStats,
+};
+use ursa_store::{DagLimits, UrsaStore};
 
-use crate::config::OriginConfig;
+use crate::{
+    config::{ImportLimits, OriginConfig, ProgressiveConfig},
+    reorder::progressive_walk,
+};
 
 pub const MAX_BLOCK_SIZE: usize = 1048576;
 pub const MAX_CHUNK_SIZE: usize = 104857600;
 pub const DEFAULT_CHUNK_SIZE: usize = 10 * 1024 * 1024; // chunk to ~10MB CARs
+pub const DEFAULT_FILE_STREAM_PAGE: usize = 4 * 1024 * 1024;
+pub const MAX_FILE_STREAM_PAGE: usize = 16 * 1024 * 1024;
+/// Blocks buffered between the dag traversal and the car writer
+const CAR_EXPORT_BUFFER: usize = 16;
+/// Bytes read at once from a file hashed while it is read.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Car file streamed as a response body
+pub type CarStream = StreamBody<BoxStream<'static, io::Result<Bytes>>>;
+
+/// Blocks of a dag streamed in traversal order
+pub type BlockStream = BoxStream<'static, Result<(Cid, Vec<u8>)>>;
 
 /// Network Api
 #[derive(Deserialize, Serialize)]
@@ -46,20 +77,233 @@ pub struct NetworkGetParams {
 pub type NetworkGetResult = Vec<u8>;
 pub const NETWORK_GET: &str = "ursa_get_cid";
 
+#[derive(Deserialize, Serialize)]
+pub struct NetworkGetBlockByHashParams {
+    /// Multibase encoded multihash, e.g. `zQm...` for a base58btc sha2-256
+    pub multihash: String,
+    /// Multicodec of the block
+    pub codec: u64,
+}
+pub const NETWORK_GET_BLOCK_BY_HASH: &str = "ursa_get_block_by_hash";
+
 #[derive(Deserialize, Serialize)]
 pub struct NetworkPutFileParams {
     pub path: String,
+    /// Hash function used for the cids of non-car files.
+    #[serde(default)]
+    pub hash: HashFunction,
+}
+
+/// Hash functions supported for content addressing on put
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum HashFunction {
+    #[default]
+    #[serde(rename = "sha2-256")]
+    Sha256,
+    #[serde(rename = "sha2-512")]
+    Sha512,
+    #[serde(rename = "blake2b-256")]
+    Blake2b256,
+    #[serde(rename = "blake3")]
+    Blake3,
+}
+
+impl From<HashFunction> for Code {
+    fn from(hash: HashFunction) -> Self {
+        match hash {
+            HashFunction::Sha256 => Code::Sha2_256,
+            HashFunction::Sha512 => Code::Sha2_512,
+            HashFunction::Blake2b256 => Code::Blake2b256,
+            HashFunction::Blake3 => Code::Blake3_256,
+        }
+    }
+}
+
+impl FromStr for HashFunction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| anyhow!("Unsupported hash function {s}"))
+    }
 }
 
 pub type NetworkPutFileResult = String;
 pub const NETWORK_PUT_FILE: &str = "ursa_put_file";
 
-pub type NetworkGetPeers = HashSet<PeerId>;
+/// Page of the connected peers, ordered by peer id. Every peer without params.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy)]
+#[serde(default)]
+pub struct NetworkGetPeersParams {
+    /// Peers skipped before the page
+    pub offset: usize,
+    /// Peers of the page, all the remaining peers if unset
+    pub limit: Option<usize>,
+    /// Return [`PeerEntry`]s with the known addresses rather than the peer ids
+    pub with_addresses: bool,
+}
+
+impl NetworkGetPeersParams {
+    /// The page of `peers`, in peer id order.
+    pub fn page(&self, peers: impl IntoIterator<Item = PeerId>) -> Vec<PeerId> {
+        let mut peers: Vec<PeerId> = peers.into_iter().collect();
+        peers.sort_by_cached_key(PeerId::to_bytes);
+        peers
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct PeerEntry {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+}
+
+/// Peer ids, or peer entries if requested `with_addresses`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum NetworkGetPeers {
+    Peers(Vec<PeerId>),
+    WithAddresses(Vec<PeerEntry>),
+}
 pub const NETWORK_GET_PEERS: &str = "ursa_get_peers";
 
+/// Filters of the listener addresses, every address without params.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy)]
+#[serde(default)]
+pub struct NetworkGetListenerAddressesParams {
+    /// Skip the loopback, private and link-local addresses
+    pub public_only: bool,
+    /// Skip the repeated addresses
+    pub dedup: bool,
+    /// Addresses returned at most, all if unset
+    pub limit: Option<usize>,
+}
+
+impl NetworkGetListenerAddressesParams {
+    pub fn apply(&self, addresses: Vec<Multiaddr>) -> Vec<Multiaddr> {
+        let mut filtered: Vec<Multiaddr> = vec![];
+        for address in addresses {
+            if self.limit.map_or(false, |limit| filtered.len() >= limit) {
+                break;
+            }
+            if (self.public_only && !is_public(&address))
+                || (self.dedup && filtered.contains(&address))
+            {
+                continue;
+            }
+            filtered.push(address);
+        }
+        filtered
+    }
+}
+
 pub type NetworkGetListenerAddresses = Vec<Multiaddr>;
 pub const NETWORK_LISTENER_ADDRESSES: &str = "ursa_listener_addresses";
 
+pub type NetworkNodeInfoResult = NodeInfo;
+pub const NETWORK_NODE_INFO: &str = "ursa_node_info";
+
+#[derive(Deserialize, Serialize)]
+pub struct NetworkPeerInfoParams {
+    pub peer_id: PeerId,
+}
+
+/// `null` if the peer isn't connected.
+pub type NetworkPeerInfoResult = Option<PeerInfo>;
+pub const NETWORK_PEER_INFO: &str = "ursa_peer_info";
+
+#[derive(Deserialize, Serialize)]
+pub struct NetworkAwaitPeersParams {
+    pub min: usize,
+    /// Timeout in milliseconds
+    pub timeout: u64,
+}
+
+pub type NetworkAwaitPeersResult = usize;
+pub const NETWORK_AWAIT_PEERS: &str = "ursa_await_peers";
+
+#[derive(Deserialize, Serialize)]
+pub struct NetworkDisconnectAllParams {
+    /// Admin token of the node
+    pub token: String,
+}
+
+pub type NetworkDisconnectAllResult = usize;
+pub const NETWORK_DISCONNECT_ALL: &str = "ursa_disconnect_all";
+
+#[derive(Deserialize, Serialize)]
+pub struct NetworkBlockPeerParams {
+    pub peer_id: PeerId,
+    /// Admin token of the node
+    pub token: String,
+}
+
+pub type NetworkBlockPeerResult = ();
+pub const NETWORK_BLOCK_PEER: &str = "ursa_block_peer";
+pub const NETWORK_UNBLOCK_PEER: &str = "ursa_unblock_peer";
+
+#[derive(Deserialize, Serialize)]
+pub struct NetworkPutRecordParams {
+    pub key: String,
+    /// Limited to the `kad_max_record_size` of the node
+    pub value: String,
+}
+pub const NETWORK_PUT_RECORD: &str = "ursa_put_record";
+
+#[derive(Deserialize, Serialize)]
+pub struct NetworkPublishParams {
+    pub topic: String,
+    pub data: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct NetworkPublishResult {
+    pub message_id: String,
+    /// Peers the message was sent to
+    pub peers: usize,
+}
+
+pub const NETWORK_PUBLISH: &str = "ursa_publish";
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct NetworkSubscription {
+    pub topic: String,
+    /// Peers in the mesh of the topic
+    pub mesh_peers: usize,
+    /// Messages published by the node
+    pub published: u64,
+    /// Messages received from peers
+    pub received: u64,
+}
+
+pub type NetworkListSubscriptionsResult = Vec<NetworkSubscription>;
+pub const NETWORK_LIST_SUBSCRIPTIONS: &str = "ursa_list_subscriptions";
+
+#[derive(Deserialize, Serialize)]
+pub struct NetworkFetchFromParams {
+    pub cid: String,
+    /// Peer id, or multiaddr ending with `/p2p/<peer id>` to dial the peer at
+    pub peer: String,
+    /// Announce the content once fetched, like content fetched on request
+    #[serde(default)]
+    pub provide: bool,
+}
+pub const NETWORK_FETCH_FROM: &str = "ursa_fetch_from";
+
+/// Effective config of the node.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct NodeConfigResult {
+    /// Config after cli overrides, with secrets redacted
+    pub config: serde_json::Value,
+    /// Dotted paths of the values differing from the defaults, e.g. `server_config.port`
+    pub overridden: Vec<String>,
+}
+pub const NODE_CONFIG: &str = "ursa_config";
+
 #[derive(Deserialize, Serialize)]
 pub struct NetworkGetFileParams {
     pub path: String,
@@ -67,12 +311,45 @@ pub struct NetworkGetFileParams {
 }
 pub const NETWORK_GET_FILE: &str = "ursa_get_file";
 
+#[derive(Deserialize, Serialize)]
+pub struct NetworkGetFileStreamParams {
+    pub cid: String,
+    /// Cid of the last block received, the page starts after it
+    #[serde(default)]
+    pub after: Option<String>,
+    /// Bytes of the blocks of a page, at least one block is returned. Defaults to 4MB,
+    /// capped to 16MB
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+}
+
+/// Page of the blocks of a dag, in traversal order.
+#[derive(Deserialize, Serialize)]
+pub struct NetworkGetFileStreamResult {
+    /// Base64 encoded blocks
+    pub blocks: Vec<String>,
+    /// Cid of the last block of the page to request the next page `after`, `None` once
+    /// the whole dag was returned
+    pub next: Option<String>,
+}
+pub const NETWORK_GET_FILE_STREAM: &str = "ursa_get_file_stream";
+
 /// Abstraction of Ursa's server commands
 #[async_trait]
 pub trait NetworkInterface: Sync + Send + 'static {
     /// Get a bitswap block from the network
     async fn get(&self, cid: Cid) -> Result<Vec<u8>>;
 
+    /// Fetch the dag of a cid from a specific peer, without looking up providers.
+    /// Fails if the peer doesn't have the content.
+    async fn fetch_from(
+        &self,
+        cid: Cid,
+        peer_id: PeerId,
+        address: Option<Multiaddr>,
+        provide: bool,
+    ) -> Result<()>;
+
     /// Get content under a cid
     async fn get_data(&self, root_cid: Cid) -> Result<Vec<(Cid, Vec<u8>)>>;
 
@@ -80,26 +357,69 @@ pub trait NetworkInterface: Sync + Send + 'static {
     async fn get_file(&self, path: String, cid: Cid) -> Result<()>;
 
     /// Stream the car file from server
-    async fn stream(
-        &self,
-        root_cid: Cid,
-    ) -> Result<StreamBody<ReaderStream<tokio::io::DuplexStream>>>;
+    async fn stream(&self, root_cid: Cid) -> Result<CarStream>;
+
+    /// Blocks of the dag in traversal order as they arrive, ending with an
+    /// error instead if the dag can't be completed
+    async fn stream_blocks(&self, root_cid: Cid) -> Result<BlockStream>;
 
     /// Put a car file and start providing to the network
     async fn put_car<R: AsyncRead + Send + Unpin>(&self, file: Car<R>) -> Result<Vec<Cid>>;
 
-    /// Put a file using a local path
-    async fn put_file(&self, path: String) -> Result<Vec<Cid>>;
+    /// Put a file using a local path.
+    /// Car files, told apart by their header, are imported as is, other files are chunked and hashed with `hash`,
+    /// once for the identical files put concurrently if coalescing is enabled.
+    async fn put_file(&self, path: String, hash: HashFunction) -> Result<Vec<Cid>>;
 
     /// Get peers from the network
     async fn get_peers(&self) -> Result<HashSet<PeerId>>;
 
+    /// Addresses known for each of `peers`
+    async fn get_peer_addresses(
+        &self,
+        peers: Vec<PeerId>,
+    ) -> Result<HashMap<PeerId, Vec<Multiaddr>>>;
+
     /// Get the addresses that p2p node is listening on
     async fn get_listener_addresses(&self) -> Result<Vec<Multiaddr>>;
+
+    /// Peer id, listen addresses, peer count and bootstrap status of the node
+    async fn node_info(&self) -> Result<NodeInfo>;
+
+    /// Inbound and outbound connections and identity of a connected peer
+    async fn peer_info(&self, peer_id: PeerId) -> Result<Option<PeerInfo>>;
+
+    /// Wait until at least `min` peers are connected, returning the number of peers
+    async fn await_peers(&self, min: usize, timeout: Duration) -> Result<usize>;
+
+    /// Disconnect from every connected peer, returning the number of peers dropped
+    async fn disconnect_all(&self) -> Result<usize>;
+
+    /// Refuse connections from `peer_id`, disconnecting it and dropping it from the routing table
+    async fn block_peer(&self, peer_id: PeerId) -> Result<()>;
+
+    /// Accept connections from `peer_id` again
+    async fn unblock_peer(&self, peer_id: PeerId) -> Result<()>;
+
+    /// Subscribe to peers connecting and disconnecting
+    async fn peer_events(&self) -> Result<broadcast::Receiver<PeerEvent>>;
+
+    /// Store a record in the dht, failing with [`ursa_network::RecordTooLarge`] on values above the store limit
+    async fn put_record(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
+
+    /// Publish a gossipsub message, failing with a [`libp2p::gossipsub::error::PublishError`]
+    async fn publish(&self, topic: String, data: Vec<u8>) -> Result<PublishAck>;
+
+    /// Gossipsub topics the node is subscribed to
+    async fn list_subscriptions(&self) -> Result<Vec<TopicStats>>;
 }
 
 type PendingRequests = Arc<RwLock<HashMap<Cid, Vec<Sender<Result<u64>>>>>>;
 
+/// Puts of files in progress by content fingerprint, with whom to tell the cids.
+type PendingPuts =
+    Arc<RwLock<HashMap<(Multihash, HashFunction), Vec<oneshot::Sender<Result<Vec<Cid>>>>>>>;
+
 #[derive(Clone)]
 pub struct NodeNetworkInterface<S>
 where
@@ -109,8 +429,15 @@ where
     pub network_send: Sender<NetworkCommand>,
     pub provider_send: Sender<ProviderCommand>,
     pending_requests: PendingRequests,
+    pending_puts: PendingPuts,
+    coalesce_puts: bool,
     client: Arc<Client>,
     origin_config: OriginConfig,
+    car_limits: DagLimits,
+    import_limits: ImportLimits,
+    imports: Arc<Semaphore>,
+    hash_workers: usize,
+    progressive: Option<ProgressiveConfig>,
 }
 
 #[async_trait]
@@ -137,52 +464,67 @@ where
     /// Used through CLI
     async fn get_file(&self, path: String, root_cid: Cid) -> Result<()> {
         info!("getting and storing the file at: {path}");
+        self.sync_content(root_cid).await?;
 
         let header = CarHeader {
             roots: vec![root_cid],
             version: 1,
         };
 
-        let buffer: Arc<RwLock<Vec<u8>>> = Default::default();
-        let (mut tx, mut rx) = unbounded();
+        let file_path = PathBuf::from(path).join(format!("{root_cid}.car"));
+        create_dir_all(file_path.parent().unwrap()).await?;
+        let mut file = File::create(&file_path).await?;
 
-        let buffer_cloned = buffer.clone();
+        let (mut tx, mut rx) = channel(CAR_EXPORT_BUFFER);
         let write_task = tokio::task::spawn(async move {
             header
-                .write_stream_async(&mut *buffer_cloned.write().await, &mut rx)
+                .write_stream_async(&mut file, &mut rx)
                 .await
-                .unwrap()
+                .map_err(|e| anyhow!("Error while writing the car file {e:?}"))?;
+            file.sync_all().await?;
+            Ok::<_, anyhow::Error>(())
         });
-        let dag = self.get_data(root_cid).await?;
 
-        for (cid, data) in dag {
-            tx.send((cid, data)).await?;
+        let mut export = Ok(());
+        for block in self.store.walk(&root_cid, self.car_limits) {
+            match block {
+                Ok(block) => {
+                    if tx.send(block).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    export = Err(e);
+                    break;
+                }
+            }
         }
         drop(tx);
-        write_task.await?;
+        let written = write_task.await?;
 
-        let buffer: Vec<_> = buffer.read().await.clone();
-        let file_path = PathBuf::from(path).join(format!("{root_cid}.car"));
-        create_dir_all(file_path.parent().unwrap()).await?;
-        let mut file = File::create(file_path).await?;
-        file.write_all(&buffer).await?;
-        file.sync_all().await?;
+        if let Err(e) = export.and(written) {
+            remove_file(&file_path).await?;
+            return Err(e);
+        }
         Ok(())
     }
 
-    async fn stream(
-        &self,
-        root_cid: Cid,
-    ) -> Result<StreamBody<ReaderStream<tokio::io::DuplexStream>>> {
+    async fn stream(&self, root_cid: Cid) -> Result<CarStream> {
+        let mut blocks = self.stream_blocks(root_cid).await?;
+
         let header = CarHeader {
             roots: vec![root_cid],
             version: 1,
         };
 
-        let (mut tx, mut rx) = unbounded();
+        let (mut tx, mut rx) = channel(CAR_EXPORT_BUFFER);
         let (writer, reader) = tokio::io::duplex(1024 * 100);
+        let (abort_tx, abort_rx) = oneshot::channel();
 
-        let body = StreamBody::new(ReaderStream::new(reader));
+        // a limit error ends the body with an error instead of a clean eof,
+        // so the truncated car can not be mistaken for a complete one
+        let abort = stream::once(abort_rx).filter_map(|res| async move { res.ok().map(Err) });
+        let body = StreamBody::new(ReaderStream::new(reader).chain(abort).boxed());
 
         task::spawn(async move {
             if let Err(err) = header
@@ -192,28 +534,144 @@ where
                 error!("Error while streaming the car file {err:?}");
             }
         });
-        let dag = self.get_data(root_cid).await?;
 
-        for (cid, data) in dag {
-            tx.send((cid, data)).await?;
-        }
-        drop(tx);
+        task::spawn(async move {
+            while let Some(block) = blocks.next().await {
+                match block {
+                    Ok(block) => {
+                        if tx.send(block).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => {
+                        error!("Aborting car export: {err:?}");
+                        abort_tx
+                            .send(io::Error::new(io::ErrorKind::Other, err.to_string()))
+                            .ok();
+                        return;
+                    }
+                }
+            }
+        });
 
         Ok(body)
     }
 
-    async fn put_car<R: AsyncRead + Send + Unpin>(&self, car: Car<R>) -> Result<Vec<Cid>> {
-        let size = car.size;
-        let cids = load_car(self.store.blockstore(), car).await?;
+    async fn stream_blocks(&self, root_cid: Cid) -> Result<BlockStream> {
+        let progressive = match self.progressive {
+            Some(config) if !self.store.blockstore().has(&root_cid)? => Some(config),
+            _ => {
+                self.sync_content(root_cid).await?;
+                None
+            }
+        };
+
+        let (mut tx, rx) = channel(CAR_EXPORT_BUFFER);
+        let (error_tx, error_rx) = oneshot::channel();
+        // the walk error, if any, follows the blocks walked before it
+        let error = stream::once(error_rx).filter_map(|res| async move { res.ok().map(Err) });
+        let blocks = rx.map(Ok).chain(error).boxed();
+
+        let store = Arc::clone(&self.store);
+        let limits = self.car_limits;
+        let interface = self.clone();
+        task::spawn(async move {
+            let walk = match progressive {
+                Some(config) => {
+                    let fetch = task::spawn(async move { interface.sync_content(root_cid).await })
+                        .map(|res| res.map_err(anyhow::Error::from).and_then(|res| res));
+                    progressive_walk(store, root_cid, config, limits, fetch, tx).await
+                }
+                None => {
+                    async move {
+                        for block in store.walk(&root_cid, limits) {
+                            if tx.send(block?).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(())
+                    }
+                    .await
+                }
+            };
+            if let Err(err) = walk {
+                error!("Aborting dag walk of {root_cid}: {err:?}");
+                error_tx.send(err).ok();
+            }
+        });
+
+        Ok(blocks)
+    }
+
+    async fn put_car<R: AsyncRead + Send + Unpin>(&self, mut car: Car<R>) -> Result<Vec<Cid>> {
+        let cids = match load_car(self.store.blockstore(), &mut car).await {
+            Ok(cids) => cids,
+            Err(_) if car.exceeded() => {
+                return Err(CarTooLarge {
+                    max: car.max_size.unwrap_or_default(),
+                }
+                .into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let size = car.read;
         let root_cid = cids[0];
         info!("The inserted cids are: {cids:?}");
         self.provide_cid(root_cid, size).await.map(|_| cids)
     }
 
     /// Used through CLI
-    async fn put_file(&self, path: String) -> Result<Vec<Cid>> {
+    async fn put_file(&self, path: String, hash: HashFunction) -> Result<Vec<Cid>> {
         info!("Putting the file on network: {path}");
-        self.put_car(Car::from_file(path).await?).await
+        if is_car(&path).await? {
+            return self.put_car(Car::from_file(path).await?).await;
+        }
+
+        if !self.coalesce_puts {
+            return self.put_bytes(async_fs::read(&path).await?, hash).await;
+        }
+
+        // files differing anywhere have different fingerprints and are put separately
+        let (bytes, fingerprint) = read_hashed(&path).await?;
+        let key = (fingerprint, hash);
+        let (sender, receiver) = oneshot::channel();
+        let running = match self.pending_puts.write().await.entry(key) {
+            Entry::Occupied(mut e) => {
+                e.get_mut().push(sender);
+                true
+            }
+            Entry::Vacant(e) => {
+                e.insert(vec![sender]);
+                false
+            }
+        };
+        if running {
+            debug!("Joining the put of an identical file for {path}");
+        } else {
+            // completes even if the first caller goes away, the others wait for it
+            let interface = self.clone();
+            task::spawn(async move {
+                let result = interface.put_bytes(bytes, hash).await;
+                let waiters = interface
+                    .pending_puts
+                    .write()
+                    .await
+                    .remove(&key)
+                    .unwrap_or_default();
+                for waiter in waiters {
+                    let result = match &result {
+                        Ok(cids) => Ok(cids.clone()),
+                        Err(e) => Err(anyhow!("{e:#}")),
+                    };
+                    if waiter.send(result).is_err() {
+                        debug!("Failed to send put result to channel");
+                    }
+                }
+            });
+        }
+        receiver
+            .await
+            .map_err(|_| anyhow!("Failed to receive put result from channel"))?
     }
 
     async fn get_peers(&self) -> Result<HashSet<PeerId>> {
@@ -227,6 +685,19 @@ where
         }
     }
 
+    async fn get_peer_addresses(
+        &self,
+        peers: Vec<PeerId>,
+    ) -> Result<HashMap<PeerId, Vec<Multiaddr>>> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::GetPeerAddresses { peers, sender };
+
+        self.network_send.send(request)?;
+        receiver
+            .await
+            .map_err(|e| anyhow!("GetPeerAddresses NetworkCommand failed {e:?}"))
+    }
+
     async fn get_listener_addresses(&self) -> Result<Vec<Multiaddr>> {
         let (sender, receiver) = oneshot::channel();
         let request = NetworkCommand::GetListenerAddresses { sender };
@@ -239,6 +710,154 @@ where
             ))),
         }
     }
+
+    async fn node_info(&self) -> Result<NodeInfo> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::GetNodeInfo { sender };
+
+        self.network_send.send(request)?;
+        match receiver.await {
+            Ok(info) => Ok(info),
+            Err(e) => Err(anyhow!(format!("GetNodeInfo NetworkCommand failed {e:?}"))),
+        }
+    }
+
+    async fn peer_info(&self, peer_id: PeerId) -> Result<Option<PeerInfo>> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::GetPeerInfo { peer_id, sender };
+
+        self.network_send.send(request)?;
+        match receiver.await {
+            Ok(info) => Ok(info),
+            Err(e) => Err(anyhow!(format!("GetPeerInfo NetworkCommand failed {e:?}"))),
+        }
+    }
+
+    async fn list_subscriptions(&self) -> Result<Vec<TopicStats>> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::ListSubscriptions { sender };
+
+        self.network_send.send(request)?;
+        match receiver.await {
+            Ok(topics) => Ok(topics),
+            Err(e) => Err(anyhow!(format!(
+                "ListSubscriptions NetworkCommand failed {e:?}"
+            ))),
+        }
+    }
+
+    async fn await_peers(&self, min: usize, timeout: Duration) -> Result<usize> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::AwaitPeers { min, sender };
+
+        self.network_send.send(request)?;
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(peers)) => Ok(peers),
+            Ok(Err(e)) => Err(anyhow!(format!("AwaitPeers NetworkCommand failed {e:?}"))),
+            Err(_) => Err(anyhow!(
+                "Timed out waiting for {min} peers after {timeout:?}"
+            )),
+        }
+    }
+
+    async fn disconnect_all(&self) -> Result<usize> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::DisconnectAll { sender };
+
+        self.network_send.send(request)?;
+        match receiver.await {
+            Ok(dropped) => Ok(dropped),
+            Err(e) => Err(anyhow!(format!(
+                "DisconnectAll NetworkCommand failed {e:?}"
+            ))),
+        }
+    }
+
+    async fn block_peer(&self, peer_id: PeerId) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::BlockPeer { peer_id, sender };
+
+        self.network_send.send(request)?;
+        receiver
+            .await
+            .map_err(|e| anyhow!(format!("BlockPeer NetworkCommand failed {e:?}")))
+    }
+
+    async fn unblock_peer(&self, peer_id: PeerId) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::UnblockPeer { peer_id, sender };
+
+        self.network_send.send(request)?;
+        receiver
+            .await
+            .map_err(|e| anyhow!(format!("UnblockPeer NetworkCommand failed {e:?}")))
+    }
+
+    async fn peer_events(&self) -> Result<broadcast::Receiver<PeerEvent>> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::SubscribePeerEvents { sender };
+
+        self.network_send.send(request)?;
+        match receiver.await {
+            Ok(events) => Ok(events),
+            Err(e) => Err(anyhow!(format!(
+                "SubscribePeerEvents NetworkCommand failed {e:?}"
+            ))),
+        }
+    }
+
+    async fn fetch_from(
+        &self,
+        cid: Cid,
+        peer_id: PeerId,
+        address: Option<Multiaddr>,
+        provide: bool,
+    ) -> Result<()> {
+        info!("Fetching cid {cid} from {peer_id}");
+        let (sender, receiver) = oneshot::channel();
+        self.network_send.send(NetworkCommand::FetchFrom {
+            cid,
+            peer_id,
+            address,
+            sender,
+        })?;
+        receiver.await??;
+
+        if provide {
+            let size = self.store.car_size(&cid)?;
+            self.provide_cid(cid, size).await
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn put_record(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::PutRecord { key, value, sender };
+
+        self.network_send.send(request)?;
+        receiver
+            .await
+            .map_err(|e| anyhow!(format!("PutRecord NetworkCommand failed {e:?}")))?
+    }
+
+    async fn publish(&self, topic: String, data: Vec<u8>) -> Result<PublishAck> {
+        let (sender, receiver) = oneshot::channel();
+        let request = NetworkCommand::GossipsubMessage {
+            // only used by subscriptions
+            peer_id: PeerId::random(),
+            message: GossipsubMessage::Publish {
+                topic: TopicHash::from_raw(topic),
+                data: data.into(),
+                sender,
+            },
+        };
+
+        self.network_send.send(request)?;
+        Ok(receiver
+            .await
+            .map_err(|e| anyhow!(format!("Publish NetworkCommand failed {e:?}")))??)
+    }
 }
 
 impl<S> NodeNetworkInterface<S>
@@ -250,17 +869,82 @@ where
         network_send: Sender<NetworkCommand>,
         provider_send: Sender<ProviderCommand>,
         origin_config: OriginConfig,
+        car_limits: DagLimits,
     ) -> Self {
         Self {
             store,
             network_send,
             provider_send,
             origin_config,
+            car_limits,
+            import_limits: ImportLimits::default(),
+            imports: Arc::new(Semaphore::new(ImportLimits::default().max_concurrent)),
+            hash_workers: 1,
+            progressive: None,
             pending_requests: Arc::new(RwLock::new(HashMap::new())),
+            pending_puts: Arc::new(RwLock::new(HashMap::new())),
+            coalesce_puts: false,
             client: Arc::new(Client::new()),
         }
     }
 
+    /// Bound the car files imported over http, see [`ImportLimits`].
+    pub fn with_import_limits(self, import_limits: ImportLimits) -> Self {
+        Self {
+            import_limits,
+            imports: Arc::new(Semaphore::new(import_limits.max_concurrent)),
+            ..self
+        }
+    }
+
+    /// Hash the chunks of the files put on up to `hash_workers` threads.
+    pub fn with_hash_workers(self, hash_workers: usize) -> Self {
+        Self {
+            hash_workers,
+            ..self
+        }
+    }
+
+    /// Hash and store once the identical files put concurrently, see [`NetworkInterface::put_file`].
+    pub fn with_coalesce_puts(self, coalesce_puts: bool) -> Self {
+        Self {
+            coalesce_puts,
+            ..self
+        }
+    }
+
+    /// Stream the car exports of missing content while it is being fetched.
+    pub fn with_progressive(self, progressive: Option<ProgressiveConfig>) -> Self {
+        Self {
+            progressive,
+            ..self
+        }
+    }
+
+    pub fn import_limits(&self) -> ImportLimits {
+        self.import_limits
+    }
+
+    /// Slot for a car import, `None` while `max_concurrent` imports are running.
+    pub fn try_start_import(&self) -> Option<ImportPermit> {
+        let permit = Arc::clone(&self.imports).try_acquire_owned().ok()?;
+        increment_gauge!(ACTIVE_IMPORTS_GAUGE, 1.0);
+        Some(ImportPermit { _permit: permit })
+    }
+
+    /// Chunk, hash and store a file, then provide its root cid.
+    async fn put_bytes(&self, bytes: Vec<u8>, hash: HashFunction) -> Result<Vec<Cid>> {
+        let store = Arc::clone(&self.store);
+        let workers = self.hash_workers;
+        let cids =
+            task::spawn_blocking(move || store.put_bytes_parallel(&bytes, hash.into(), workers))
+                .await??;
+        let root_cid = cids[0];
+        info!("The inserted cids are: {cids:?}");
+        let size = self.store.car_size(&root_cid)?;
+        self.provide_cid(root_cid, size).await.map(|_| cids)
+    }
+
     /// Ensure a root cid is synced to the blockstore
     async fn sync_content(&self, cid: Cid) -> Result<()> {
         if !self.store.blockstore().has(&cid)? {
@@ -419,8 +1103,75 @@ where
     }
 }
 
+/// Largest car header sniffed, files announcing a larger one are put as they are.
+const MAX_CAR_HEADER_SIZE: u64 = 1024 * 1024;
+
+/// Whether the file at `path` starts with a car header: a length prefixed dag-cbor
+/// map of the car version and roots. Cars are imported whatever their file name.
+pub(crate) async fn is_car(path: &str) -> Result<bool> {
+    let mut file = File::open(path).await?;
+    let mut len = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0];
+        if shift > 63 || file.read(&mut byte).await? == 0 {
+            return Ok(false);
+        }
+        len |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    if len == 0 || len > MAX_CAR_HEADER_SIZE {
+        return Ok(false);
+    }
+    let mut header = vec![0; len as usize];
+    if file.read_exact(&mut header).await.is_err() {
+        return Ok(false);
+    }
+    let Ok(Ipld::Map(header)) = DagCborCodec.decode::<Ipld>(&header) else {
+        return Ok(false);
+    };
+    Ok(matches!(header.get("version"), Some(Ipld::Integer(1)))
+        && matches!(header.get("roots"), Some(Ipld::List(_))))
+}
+
+/// Read the file at `path`, hashing it incrementally as it is read.
+async fn read_hashed(path: &str) -> Result<(Vec<u8>, Multihash)> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha2_256::default();
+    let mut bytes = Vec::new();
+    let mut chunk = vec![0; READ_CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&chunk[..read]);
+        bytes.extend_from_slice(&chunk[..read]);
+    }
+    Ok((bytes, Code::Sha2_256.wrap(hasher.finalize())?))
+}
+
+const ACTIVE_IMPORTS_GAUGE: &str = "rpc_car_imports_active";
+
+/// Running car import, frees its slot once dropped.
+pub struct ImportPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for ImportPermit {
+    fn drop(&mut self) {
+        decrement_gauge!(ACTIVE_IMPORTS_GAUGE, 1.0);
+    }
+}
+
+/// Car file read as a stream, its size is counted while reading.
 pub struct Car<R> {
-    pub size: u64,
+    /// Bytes read so far
+    pub read: u64,
+    max_size: Option<u64>,
     reader: R,
 }
 
@@ -428,20 +1179,52 @@ impl<R> Car<R>
 where
     R: AsyncRead + Send + Unpin,
 {
-    pub fn new(size: u64, reader: R) -> Self {
-        Self { size, reader }
+    pub fn new(reader: R) -> Self {
+        Self {
+            read: 0,
+            max_size: None,
+            reader,
+        }
+    }
+
+    /// Fail reading past `max_size` bytes, the import then fails with [`CarTooLarge`].
+    pub fn with_max_size(self, max_size: u64) -> Self {
+        Self {
+            max_size: Some(max_size),
+            ..self
+        }
+    }
+
+    fn exceeded(&self) -> bool {
+        self.max_size.map_or(false, |max| self.read > max)
     }
 }
 
 impl Car<BufReader<File>> {
     pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = File::open(path).await?;
-        let size = file.metadata().await?.len();
-        let reader = BufReader::new(file);
-        Ok(Self::new(size, reader))
+        Ok(Self::new(BufReader::new(file)))
+    }
+}
+
+/// An imported car file exceeds the max import size.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CarTooLarge {
+    pub max: u64,
+}
+
+impl Display for CarTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Car file exceeds the max import size of {} bytes",
+            self.max
+        )
     }
 }
 
+impl std::error::Error for CarTooLarge {}
+
 impl<R> AsyncRead for Car<R>
 where
     R: AsyncRead + Send + Unpin,
@@ -451,6 +1234,22 @@ where
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<std::io::Result<usize>> {
-        Pin::new(&mut self.reader).poll_read(cx, buf)
+        if self.exceeded() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Other,
+                "car file exceeds the max import size",
+            )));
+        }
+        let poll = Pin::new(&mut self.reader).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = poll {
+            self.read += read as u64;
+            if self.exceeded() {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "car file exceeds the max import size",
+                )));
+            }
+        }
+        poll
     }
 }
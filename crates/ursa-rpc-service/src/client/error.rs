@@ -0,0 +1,42 @@
+use jsonrpc_v2::Error;
+use thiserror::Error;
+
+/// Failure kinds of an rpc client call.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    /// The request could not be sent or the server answered with a non 200 status.
+    #[error("Transport error: {0}")]
+    Transport(String),
+    #[error("Request timed out")]
+    Timeout,
+    /// The response body exceeded the client's maximum response size.
+    #[error("Response larger than {limit} bytes")]
+    ResponseTooLarge { limit: usize },
+    /// The server answered with a json-rpc error object.
+    #[error("Rpc error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("Failed to serialize request: {0}")]
+    Serialize(String),
+    #[error("Failed to deserialize response: {0}")]
+    Deserialize(String),
+}
+
+impl From<surf::Error> for ClientError {
+    fn from(e: surf::Error) -> Self {
+        ClientError::Transport(e.to_string())
+    }
+}
+
+impl From<ClientError> for Error {
+    fn from(e: ClientError) -> Self {
+        let code = match &e {
+            ClientError::Rpc { code, .. } => *code,
+            _ => 200,
+        };
+        Error::Full {
+            code,
+            message: e.to_string(),
+            data: None,
+        }
+    }
+}
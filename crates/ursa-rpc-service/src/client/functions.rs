@@ -1,25 +1,106 @@
-use jsonrpc_v2::Error;
-
 use crate::api::{
-    NetworkGetFileParams, NetworkGetParams, NetworkGetResult, NetworkPutFileParams,
-    NetworkPutFileResult, NETWORK_GET, NETWORK_GET_FILE, NETWORK_PUT_FILE,
+    NetworkAwaitPeersParams, NetworkAwaitPeersResult, NetworkBlockPeerParams,
+    NetworkBlockPeerResult, NetworkDisconnectAllParams, NetworkDisconnectAllResult,
+    NetworkFetchFromParams, NetworkGetBlockByHashParams, NetworkGetFileParams,
+    NetworkGetFileStreamParams, NetworkGetFileStreamResult, NetworkGetParams, NetworkGetResult,
+    NetworkListSubscriptionsResult, NetworkPublishParams, NetworkPublishResult,
+    NetworkPutFileParams, NetworkPutFileResult, NetworkPutRecordParams, NodeConfigResult,
+    NETWORK_AWAIT_PEERS, NETWORK_BLOCK_PEER, NETWORK_DISCONNECT_ALL, NETWORK_FETCH_FROM,
+    NETWORK_GET, NETWORK_GET_BLOCK_BY_HASH, NETWORK_GET_FILE, NETWORK_GET_FILE_STREAM,
+    NETWORK_LIST_SUBSCRIPTIONS, NETWORK_PUBLISH, NETWORK_PUT_FILE, NETWORK_PUT_RECORD,
+    NETWORK_UNBLOCK_PEER, NODE_CONFIG,
 };
 
+use bytes::Bytes;
+use futures::{stream, Stream, TryStreamExt};
+
 use super::{
-    call,
+    call, call_idempotent, ClientError,
     RpcMethod::{Post, Put},
 };
 
-pub type Result<T> = anyhow::Result<T, Error>;
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+// the calls without side effects are retried as configured by `set_client_config`,
+// the others never are
 
 pub async fn get_block(params: NetworkGetParams) -> Result<NetworkGetResult> {
-    call(NETWORK_GET, params, Post).await
+    call_idempotent(NETWORK_GET, params, Post).await
+}
+
+pub async fn get_block_by_hash(params: NetworkGetBlockByHashParams) -> Result<NetworkGetResult> {
+    call_idempotent(NETWORK_GET_BLOCK_BY_HASH, params, Post).await
 }
 
 pub async fn get_file(params: NetworkGetFileParams) -> Result<()> {
-    call(NETWORK_GET_FILE, params, Put).await
+    call_idempotent(NETWORK_GET_FILE, params, Put).await
+}
+
+/// Blocks of the file in dag order, requested a page at a time so the file is never buffered
+/// in full. A failed page ends the stream with its error.
+pub fn get_file_stream(cid: String) -> impl Stream<Item = Result<Bytes>> {
+    stream::try_unfold(Some(None), move |after| {
+        let cid = cid.clone();
+        async move {
+            let Some(after) = after else {
+                return Ok(None);
+            };
+            let params = NetworkGetFileStreamParams {
+                cid,
+                after,
+                max_bytes: None,
+            };
+            let page: NetworkGetFileStreamResult =
+                call_idempotent(NETWORK_GET_FILE_STREAM, params, Post).await?;
+            let blocks = page.blocks.into_iter().map(|block| {
+                base64::decode(block)
+                    .map(Bytes::from)
+                    .map_err(|e| ClientError::Deserialize(e.to_string()))
+            });
+            Ok::<_, ClientError>(Some((stream::iter(blocks), page.next.map(Some))))
+        }
+    })
+    .try_flatten()
 }
 
 pub async fn put_file(params: NetworkPutFileParams) -> Result<NetworkPutFileResult> {
     call(NETWORK_PUT_FILE, params, Put).await
 }
+
+pub async fn await_peers(params: NetworkAwaitPeersParams) -> Result<NetworkAwaitPeersResult> {
+    call_idempotent(NETWORK_AWAIT_PEERS, params, Post).await
+}
+
+pub async fn fetch_from(params: NetworkFetchFromParams) -> Result<()> {
+    call(NETWORK_FETCH_FROM, params, Post).await
+}
+
+pub async fn put_record(params: NetworkPutRecordParams) -> Result<()> {
+    call(NETWORK_PUT_RECORD, params, Post).await
+}
+
+pub async fn publish(params: NetworkPublishParams) -> Result<NetworkPublishResult> {
+    call(NETWORK_PUBLISH, params, Post).await
+}
+
+pub async fn list_subscriptions() -> Result<NetworkListSubscriptionsResult> {
+    call_idempotent(NETWORK_LIST_SUBSCRIPTIONS, (), Post).await
+}
+
+pub async fn config() -> Result<NodeConfigResult> {
+    call_idempotent(NODE_CONFIG, (), Post).await
+}
+
+pub async fn disconnect_all(
+    params: NetworkDisconnectAllParams,
+) -> Result<NetworkDisconnectAllResult> {
+    call(NETWORK_DISCONNECT_ALL, params, Post).await
+}
+
+pub async fn block_peer(params: NetworkBlockPeerParams) -> Result<NetworkBlockPeerResult> {
+    call(NETWORK_BLOCK_PEER, params, Post).await
+}
+
+pub async fn unblock_peer(params: NetworkBlockPeerParams) -> Result<NetworkBlockPeerResult> {
+    call(NETWORK_UNBLOCK_PEER, params, Post).await
+}
@@ -1,4 +1,6 @@
+mod error;
 pub mod functions;
 mod rpc;
 
+pub use error::ClientError;
 pub use rpc::*;
@@ -1,10 +1,112 @@
-use anyhow::Result;
-use jsonrpc_v2::{Error, Id, RequestObject, V2};
+use std::{sync::RwLock, time::Duration};
 
-use crate::config::ServerConfig;
+use jsonrpc_v2::{Id, RequestObject, V2};
+
+use super::ClientError;
+use crate::{
+    api::{NETWORK_GET_FILE, NETWORK_PUT_FILE},
+    config::ServerConfig,
+};
+use futures::AsyncReadExt;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error, info};
+use tokio::time::{sleep, timeout};
+use tracing::{debug, error, info, warn};
+
+/// Methods moving whole files, bounded by `transfer_timeout` rather than `timeout`.
+const TRANSFER_METHODS: [&str; 2] = [NETWORK_PUT_FILE, NETWORK_GET_FILE];
+
+/// Where and how patiently the client calls the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcClientConfig {
+    /// Rpc endpoint. Defaults to the endpoint of the default server config
+    pub url: String,
+    /// Upper bound on each attempt of a call. Defaults to 30s
+    pub timeout: Duration,
+    /// Upper bound on each attempt of a file transfer, `ursa_put_file` and `ursa_get_file`.
+    /// Defaults to 300s
+    pub transfer_timeout: Duration,
+    /// Largest response body, in bytes, read before failing a call with
+    /// [`ClientError::ResponseTooLarge`]. Defaults to 256MB
+    pub max_response_size: usize,
+    /// Retries of the idempotent calls failing on a transport error or timeout,
+    /// other calls are never retried. Defaults to 0
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on each further retry. Defaults to 100ms
+    pub retry_backoff: Duration,
+}
+
+impl Default for RpcClientConfig {
+    fn default() -> Self {
+        let ServerConfig { port, addr, .. } = ServerConfig::default();
+        Self {
+            url: format!("http://{addr}:{port}/rpc/v0"),
+            timeout: Duration::from_secs(30),
+            transfer_timeout: Duration::from_secs(300),
+            max_response_size: 256 * 1024 * 1024,
+            max_retries: 0,
+            retry_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RpcClientConfig {
+    pub fn with_url(self, url: String) -> Self {
+        Self { url, ..self }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    pub fn with_transfer_timeout(self, transfer_timeout: Duration) -> Self {
+        Self {
+            transfer_timeout,
+            ..self
+        }
+    }
+
+    pub fn with_max_response_size(self, max_response_size: usize) -> Self {
+        Self {
+            max_response_size,
+            ..self
+        }
+    }
+
+    pub fn with_max_retries(self, max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..self
+        }
+    }
+
+    pub fn with_retry_backoff(self, retry_backoff: Duration) -> Self {
+        Self {
+            retry_backoff,
+            ..self
+        }
+    }
+
+    /// Upper bound on each attempt of `method_name`.
+    pub fn timeout_of(&self, method_name: &str) -> Duration {
+        if TRANSFER_METHODS.contains(&method_name) {
+            self.transfer_timeout
+        } else {
+            self.timeout
+        }
+    }
+}
+
+static CLIENT_CONFIG: RwLock<Option<RpcClientConfig>> = RwLock::new(None);
+
+/// Set the config of the calls made through [`super::functions`].
+pub fn set_client_config(config: RpcClientConfig) {
+    *CLIENT_CONFIG.write().unwrap() = Some(config);
+}
+
+pub fn client_config() -> RpcClientConfig {
+    CLIENT_CONFIG.read().unwrap().clone().unwrap_or_default()
+}
 
 /// Error object in a response
 #[derive(Deserialize)]
@@ -33,89 +135,136 @@ pub enum RpcMethod {
     Post,
 }
 
-/// Utility method for sending RPC requests over HTTP
-pub(crate) async fn call<P, R>(method_name: &str, params: P, method: RpcMethod) -> Result<R, Error>
+/// Utility method for sending RPC requests over HTTP, never retried
+pub(crate) async fn call<P, R>(
+    method_name: &str,
+    params: P,
+    method: RpcMethod,
+) -> Result<R, ClientError>
 where
     P: Serialize,
     R: DeserializeOwned,
 {
-    if let Ok(value) = serde_json::to_value(params) {
-        let rpc_req = RequestObject::request()
-            .with_method(method_name)
-            .with_params(value)
-            .with_id(1)
-            .finish();
+    let config = client_config();
+    call_with(&config, method_name, params, method, 0).await
+}
 
-        let ServerConfig { port, addr, .. } = ServerConfig::default();
-        let api_url = format!("http://{addr}:{port}/rpc/v0");
-
-        info!("Using JSON-RPC v2 HTTP URL: {api_url}");
-        debug!("rpc_req {:?}", rpc_req);
-
-        // TODO(arslan): Add authentication
-        if let Ok(from_json) = surf::Body::from_json(&rpc_req) {
-            let mut http_res = match method {
-                RpcMethod::Post => surf::post(api_url)
-                    .content_type("application/json")
-                    .body(from_json)
-                    .await
-                    .unwrap(),
-                RpcMethod::Put => surf::put(api_url)
-                    .content_type("application/json")
-                    .body(from_json)
-                    .await
-                    .unwrap(),
-            };
-            let res = http_res.body_string().await.unwrap();
-
-            let code = http_res.status() as i64;
-
-            if code != 200 {
-                error!("[RPCClient] - server responded with http error code {code:?} - {res}");
-                return Err(Error::Full {
-                    message: format!("Error code from HTTP Response: {code}"),
-                    code,
-                    data: None,
-                });
-            }
+/// [`call`] for methods safe to repeat, retried up to `max_retries` times
+pub(crate) async fn call_idempotent<P, R>(
+    method_name: &str,
+    params: P,
+    method: RpcMethod,
+) -> Result<R, ClientError>
+where
+    P: Serialize,
+    R: DeserializeOwned,
+{
+    let config = client_config();
+    call_with(&config, method_name, params, method, config.max_retries).await
+}
 
-            // Return the parsed RPC result
-            let rpc_res: JsonRpcResponse<R> = match serde_json::from_str(&res) {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(Error::Full {
-                        data: None,
-                        code: 200,
-                        message: format!("Parse Error: {e}\nData: {res}"),
-                    })
-                }
-            };
-
-            match rpc_res {
-                JsonRpcResponse::Result { result, .. } => Ok(result),
-                JsonRpcResponse::Error { error, .. } => Err(Error::Full {
-                    data: None,
-                    code: error.code,
-                    message: error.message,
-                }),
+/// Call `method_name`, retrying transport errors and timeouts up to `retries` times.
+pub(crate) async fn call_with<P, R>(
+    config: &RpcClientConfig,
+    method_name: &str,
+    params: P,
+    method: RpcMethod,
+    retries: u32,
+) -> Result<R, ClientError>
+where
+    P: Serialize,
+    R: DeserializeOwned,
+{
+    let mut attempt = 0;
+    loop {
+        match call_once(config, method_name, &params, &method).await {
+            Err(e @ (ClientError::Transport(_) | ClientError::Timeout)) if attempt < retries => {
+                let backoff = config.retry_backoff * 2u32.saturating_pow(attempt);
+                warn!("[RPCClient] - {method_name} failed: {e}, retrying in {backoff:?}");
+                sleep(backoff).await;
+                attempt += 1;
             }
-        } else {
-            error!("[RPCClient] - There was an while serializing the rpc request");
-            Err(Error::Full {
-                data: None,
-                code: 200,
-                message: "There was an error while serializing the rpc request".to_string(),
-            })
+            res => return res,
         }
-    } else {
+    }
+}
+
+async fn call_once<P, R>(
+    config: &RpcClientConfig,
+    method_name: &str,
+    params: &P,
+    method: &RpcMethod,
+) -> Result<R, ClientError>
+where
+    P: Serialize,
+    R: DeserializeOwned,
+{
+    let value = serde_json::to_value(params).map_err(|e| {
         error!(
             "[RPCClient] - There was an error while converting the params to serializable value"
         );
-        Err(Error::Full {
-            data: None,
-            code: 200,
-            message: "There was an error while converting the params to serializable value"
-                .to_string(),
-        })
+        ClientError::Serialize(e.to_string())
+    })?;
+    let rpc_req = RequestObject::request()
+        .with_method(method_name)
+        .with_params(value)
+        .with_id(1)
+        .finish();
+
+    let api_url = &config.url;
+
+    info!("Using JSON-RPC v2 HTTP URL: {api_url}");
+    debug!("rpc_req {:?}", rpc_req);
+
+    // TODO(arslan): Add authentication
+    let body = surf::Body::from_json(&rpc_req).map_err(|e| {
+        error!("[RPCClient] - There was an while serializing the rpc request");
+        ClientError::Serialize(e.to_string())
+    })?;
+    let request = match method {
+        RpcMethod::Post => surf::post(api_url),
+        RpcMethod::Put => surf::put(api_url),
+    }
+    .content_type("application/json")
+    .body(body);
+
+    let limit = config.max_response_size;
+    let res = timeout(config.timeout_of(method_name), async {
+        let mut http_res = request.await?;
+        if http_res.len().map_or(false, |len| len > limit) {
+            return Err(ClientError::ResponseTooLarge { limit });
+        }
+        // read one byte past the limit to tell a body of exactly `limit` bytes from a larger one
+        let mut body = Vec::new();
+        (&mut http_res)
+            .take(limit as u64 + 1)
+            .read_to_end(&mut body)
+            .await
+            .map_err(|e| ClientError::Transport(e.to_string()))?;
+        if body.len() > limit {
+            return Err(ClientError::ResponseTooLarge { limit });
+        }
+        let res = String::from_utf8(body).map_err(|e| ClientError::Deserialize(e.to_string()))?;
+        Ok::<_, ClientError>((http_res.status() as i64, res))
+    });
+    let (code, res) = res.await.map_err(|_| ClientError::Timeout)??;
+
+    if code != 200 {
+        error!("[RPCClient] - server responded with http error code {code:?} - {res}");
+        return Err(ClientError::Transport(format!(
+            "Error code from HTTP Response: {code}"
+        )));
+    }
+
+    // Return the parsed RPC result
+    let rpc_res: JsonRpcResponse<R> = serde_json::from_str(&res)
+        .map_err(|e| ClientError::Deserialize(format!("Parse Error: {e}\nData: {res}")))?;
+
+    match rpc_res {
+        JsonRpcResponse::Result { result, .. } => Ok(result),
+        JsonRpcResponse::Error { error, .. } => Err(ClientError::Rpc {
+            code: error.code,
+            message: error.message,
+        }),
     }
 }
@@ -1,5 +1,7 @@
+use anyhow::{bail, Result};
 use libp2p::Multiaddr;
 use serde::{Deserialize, Serialize};
+use ursa_store::DagLimits;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ServerConfig {
@@ -14,6 +16,44 @@ pub struct ServerConfig {
     pub addr: String,
     #[serde(default)]
     pub origin: OriginConfig,
+    /// Serve the prometheus metrics on `/metrics`
+    #[serde(default = "ServerConfig::default_metrics")]
+    pub metrics: bool,
+    /// Limits on the dags exported as car files
+    #[serde(default)]
+    pub car_export: DagLimits,
+    /// Stream the car exports of content not in the store while it is being fetched,
+    /// instead of once fully fetched. Disabled if unset
+    #[serde(default)]
+    pub progressive: Option<ProgressiveConfig>,
+    /// Limits on the car files imported over http
+    #[serde(default)]
+    pub car_import: ImportLimits,
+    /// Disable Nagle's algorithm on accepted connections. Small rpc requests and
+    /// responses are written immediately instead of waiting to coalesce with
+    /// more data, which otherwise can add up to ~40ms with delayed acks.
+    #[serde(default = "ServerConfig::default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    /// Socket send buffer size in bytes, OS default if unset
+    #[serde(default)]
+    pub send_buffer_size: Option<u32>,
+    /// Socket receive buffer size in bytes, OS default if unset
+    #[serde(default)]
+    pub recv_buffer_size: Option<u32>,
+    /// Token required by the admin rpc methods, which are disabled if unset
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Seconds after startup during which `/readyz` reports the node as starting
+    /// instead of unready while it has no peers. Defaults to 30
+    #[serde(default = "ServerConfig::default_startup_grace_period")]
+    pub startup_grace_period: u64,
+    /// Threads hashing the chunks of a file put with `ursa_put_file`. Defaults to 4
+    #[serde(default = "ServerConfig::default_hash_workers")]
+    pub hash_workers: usize,
+    /// Share the hashing and storing of files put with `ursa_put_file` while an
+    /// identical file is being put. Defaults to true
+    #[serde(default = "ServerConfig::default_coalesce_puts")]
+    pub coalesce_puts: bool,
 }
 
 impl ServerConfig {
@@ -26,6 +66,21 @@ impl ServerConfig {
     fn default_addr() -> String {
         "0.0.0.0".to_string()
     }
+    fn default_tcp_nodelay() -> bool {
+        true
+    }
+    fn default_metrics() -> bool {
+        true
+    }
+    fn default_startup_grace_period() -> u64 {
+        30
+    }
+    fn default_hash_workers() -> usize {
+        4
+    }
+    fn default_coalesce_puts() -> bool {
+        true
+    }
 }
 
 impl Default for ServerConfig {
@@ -35,6 +90,91 @@ impl Default for ServerConfig {
             port: Self::default_port(),
             addr: Self::default_addr(),
             origin: Default::default(),
+            metrics: Self::default_metrics(),
+            car_export: Default::default(),
+            progressive: None,
+            car_import: Default::default(),
+            tcp_nodelay: Self::default_tcp_nodelay(),
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            admin_token: None,
+            startup_grace_period: Self::default_startup_grace_period(),
+            hash_workers: Self::default_hash_workers(),
+            coalesce_puts: Self::default_coalesce_puts(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct ImportLimits {
+    /// Imports running at once, further imports are rejected. Must be greater than 0,
+    /// checked on load. Defaults to 4
+    #[serde(default = "ImportLimits::default_max_concurrent")]
+    pub max_concurrent: usize,
+    /// Max size of an imported car file in bytes. Defaults to 250 MiB
+    #[serde(default = "ImportLimits::default_max_size")]
+    pub max_size: u64,
+    /// Answer uploads sent with `Expect: 100-continue` before their body: uploads announcing
+    /// a size above `max_size` or beyond `max_concurrent` get their final status right away,
+    /// the others `100 Continue`. If disabled they are refused with 417. Defaults to true
+    #[serde(default = "ImportLimits::default_expect_continue")]
+    pub expect_continue: bool,
+}
+
+impl ImportLimits {
+    /// Reject the limits no import could run under.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_concurrent == 0 {
+            bail!("car_import.max_concurrent must be greater than 0");
+        }
+        Ok(())
+    }
+
+    fn default_max_concurrent() -> usize {
+        4
+    }
+    fn default_max_size() -> u64 {
+        250 * 1024 * 1024
+    }
+    fn default_expect_continue() -> bool {
+        true
+    }
+}
+
+impl Default for ImportLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: Self::default_max_concurrent(),
+            max_size: Self::default_max_size(),
+            expect_continue: Self::default_expect_continue(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct ProgressiveConfig {
+    /// Blocks arrived ahead of the next block to stream held in memory. Defaults to 64
+    #[serde(default = "ProgressiveConfig::default_max_buffered_blocks")]
+    pub max_buffered_blocks: usize,
+    /// Seconds to wait for the next block to stream before aborting the export. Defaults to 30
+    #[serde(default = "ProgressiveConfig::default_gap_timeout")]
+    pub gap_timeout: u64,
+}
+
+impl ProgressiveConfig {
+    fn default_max_buffered_blocks() -> usize {
+        64
+    }
+    fn default_gap_timeout() -> u64 {
+        30
+    }
+}
+
+impl Default for ProgressiveConfig {
+    fn default() -> Self {
+        Self {
+            max_buffered_blocks: Self::default_max_buffered_blocks(),
+            gap_timeout: Self::default_gap_timeout(),
         }
     }
 }
@@ -0,0 +1,100 @@
+use crate::api::{NetworkInterface, NodeNetworkInterface};
+use axum::{response::IntoResponse, routing::get, Extension, Json, Router};
+use db::Store;
+use fvm_ipld_blockstore::Blockstore;
+use hyper::StatusCode;
+use serde_json::json;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Upper bound on asking the network for its peers.
+const PEERS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Startup grace period, during which not being ready is expected, and whether
+/// the node is shutting down.
+#[derive(Clone, Debug)]
+pub struct Readiness {
+    started: Instant,
+    grace_period: Duration,
+    draining: Arc<AtomicBool>,
+}
+
+impl Readiness {
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            started: Instant::now(),
+            grace_period,
+            draining: Default::default(),
+        }
+    }
+
+    /// Report the node as draining from now on, while it shuts down.
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    fn warming_up(&self) -> bool {
+        self.started.elapsed() < self.grace_period
+    }
+}
+
+pub fn init<S: Blockstore + Store + Send + Sync + 'static>() -> Router {
+    Router::new().route("/readyz", get(readyz_handler::<S>))
+}
+
+/// Ready once connected to at least one peer. Until then the node is reported
+/// `starting` during the startup grace period, and `unready` after it. Once it
+/// is shutting down, the node is reported `draining` whatever its peers.
+pub async fn readyz_handler<S>(
+    Extension(interface): Extension<Arc<NodeNetworkInterface<S>>>,
+    Extension(readiness): Extension<Readiness>,
+) -> impl IntoResponse
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    if readiness.draining.load(Ordering::Relaxed) {
+        debug!("Shutting down, draining in-flight transfers");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "draining" })),
+        );
+    }
+    let peers = match tokio::time::timeout(PEERS_TIMEOUT, interface.get_peers()).await {
+        Ok(Ok(peers)) => peers.len(),
+        Ok(Err(e)) => {
+            warn!("Failed to get the peers for readiness: {e:?}");
+            0
+        }
+        Err(_) => {
+            warn!("Timed out getting the peers for readiness");
+            0
+        }
+    };
+    if peers > 0 {
+        return (
+            StatusCode::OK,
+            Json(json!({ "status": "ready", "peers": peers })),
+        );
+    }
+
+    let status = if readiness.warming_up() {
+        debug!("Not connected to any peer yet, still in the startup grace period");
+        "starting"
+    } else {
+        warn!(
+            "Not connected to any peer {:?} after startup",
+            readiness.started.elapsed()
+        );
+        "unready"
+    };
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "status": status, "peers": 0 })),
+    )
+}
@@ -1,36 +1,50 @@
 pub const BASE_PATH: &str = "./car_files";
 
-use crate::api::{Car, NetworkInterface, NodeNetworkInterface};
+/// Bytes of the multipart framing around an uploaded car file tolerated above the
+/// import size limit before rejecting an upload on its `Content-Length` alone.
+const MULTIPART_OVERHEAD: u64 = 64 * 1024;
+
+use crate::api::{Car, CarTooLarge, NetworkInterface, NodeNetworkInterface};
 use axum::{
     extract::{DefaultBodyLimit, Multipart, Path},
-    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
-    response::{IntoResponse, Response},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, EXPECT},
+        HeaderMap,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::{get, post},
     Extension, Json, Router,
 };
 use db::Store;
-use futures::io::Cursor;
+use futures::{stream, TryStreamExt};
 use fvm_ipld_blockstore::Blockstore;
 use hyper::StatusCode;
 use libipld::Cid;
-use std::{str::FromStr, sync::Arc};
-use tokio::task;
-use tower_http::limit::RequestBodyLimitLayer;
-use tracing::{error, info};
+use std::{convert::Infallible, io, str::FromStr, sync::Arc};
+use tokio::{sync::broadcast::error::RecvError, task};
+use tracing::{error, info, warn};
+use ursa_network::PeerEvent;
 
 pub fn init<S: Blockstore + Store + Send + Sync + 'static>() -> Router {
     Router::new()
         .route("/ursa/v0/", post(upload_handler::<S>))
         .route("/ursa/v0/:cid", get(get_handler::<S>))
+        .route("/ursa/v0/peers/events", get(peer_events_handler::<S>))
         .route("/ping", get(|| async { "pong" })) // to be used for TLS verification
+        // uploads are bounded by the car import limits
         .layer(DefaultBodyLimit::disable())
-        .layer(RequestBodyLimitLayer::new(250 * 1024 * 1024)) // 250mb
 }
 
 pub enum NetworkError {
     NotFoundError(String),
     InternalError(String),
     BadRequest(String),
+    PayloadTooLarge(String),
+    Unavailable(String),
+    ExpectationFailed(String),
 }
 impl IntoResponse for NetworkError {
     fn into_response(self) -> Response {
@@ -40,18 +54,49 @@ impl IntoResponse for NetworkError {
                 (StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
             }
             NetworkError::BadRequest(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+            NetworkError::PayloadTooLarge(e) => (StatusCode::PAYLOAD_TOO_LARGE, e).into_response(),
+            NetworkError::Unavailable(e) => (StatusCode::SERVICE_UNAVAILABLE, e).into_response(),
+            NetworkError::ExpectationFailed(e) => {
+                (StatusCode::EXPECTATION_FAILED, e).into_response()
+            }
         }
     }
 }
 
+/// Uploads are rejected before their body is read when possible, hyper only
+/// sends `100 Continue` to a client expecting it once the body is read.
 pub async fn upload_handler<S>(
     Extension(interface): Extension<Arc<NodeNetworkInterface<S>>>,
+    headers: HeaderMap,
     mut buf: Multipart,
 ) -> Result<impl IntoResponse, NetworkError>
 where
     S: Blockstore + Store + Send + Sync + 'static,
 {
+    let limits = interface.import_limits();
+    let expects_continue = headers.get(EXPECT).map_or(false, |expect| {
+        expect.as_bytes().eq_ignore_ascii_case(b"100-continue")
+    });
+    if expects_continue && !limits.expect_continue {
+        return Err(NetworkError::ExpectationFailed(
+            "Expect: 100-continue is not supported, send the body right away".to_string(),
+        ));
+    }
+    let content_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<u64>().ok());
+    if let Some(len) = content_length.filter(|len| *len > limits.max_size + MULTIPART_OVERHEAD) {
+        return Err(NetworkError::PayloadTooLarge(format!(
+            "Upload of {len} bytes exceeds the car size limit of {} bytes",
+            limits.max_size
+        )));
+    }
+    let permit = interface.try_start_import().ok_or_else(|| {
+        NetworkError::Unavailable("Too many car imports in progress, retry later".to_string())
+    })?;
+    let max_size = limits.max_size;
     let upload_task = task::spawn(async move {
+        let _permit = permit;
         info!("uploading file via http");
         if let Some(field) = buf
             .next_field()
@@ -60,17 +105,17 @@ where
         {
             let content_type = field.content_type().unwrap().to_string();
             if content_type == *"application/vnd.curl.car".to_string() {
-                let data = field
-                    .bytes()
-                    .await
-                    .map_err(|e| NetworkError::InternalError(e.to_string()))?;
-                let vec_data = data.to_vec();
-                let reader = Cursor::new(&vec_data);
+                // stream the field instead of buffering the whole car file
+                let reader = Box::pin(field.map_err(|e| io::Error::new(io::ErrorKind::Other, e)))
+                    .into_async_read();
 
                 match interface
-                    .put_car(Car::new(vec_data.len() as u64, reader))
+                    .put_car(Car::new(reader).with_max_size(max_size))
                     .await
                 {
+                    Err(err) if err.is::<CarTooLarge>() => {
+                        Err(NetworkError::PayloadTooLarge(err.to_string()))
+                    }
                     Err(err) => {
                         error!("{:?}", err);
                         Err(NetworkError::InternalError(err.to_string()))
@@ -128,3 +173,43 @@ where
         )))
     }
 }
+
+/// Server sent `connected`, `disconnected`, `discovered` and `expired` events, with the peer id as data.
+pub async fn peer_events_handler<S>(
+    Extension(interface): Extension<Arc<NodeNetworkInterface<S>>>,
+) -> Result<impl IntoResponse, NetworkError>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    let receiver = interface.peer_events().await.map_err(|err| {
+        error!("{:?}", err);
+        NetworkError::InternalError(err.to_string())
+    })?;
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(PeerEvent::Connected(peer)) => {
+                    Event::default().event("connected").data(peer.to_string())
+                }
+                Ok(PeerEvent::Disconnected(peer)) => Event::default()
+                    .event("disconnected")
+                    .data(peer.to_string()),
+                Ok(PeerEvent::Discovered(peer, _)) => {
+                    Event::default().event("discovered").data(peer.to_string())
+                }
+                Ok(PeerEvent::Expired(peer)) => {
+                    Event::default().event("expired").data(peer.to_string())
+                }
+                // provider lookups are answered to their callers
+                Ok(PeerEvent::Providers { .. }) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Peer events subscriber lagging behind, skipped {skipped} events");
+                    continue;
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((Ok::<_, Infallible>(event), receiver));
+        }
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
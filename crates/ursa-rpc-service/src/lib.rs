@@ -2,6 +2,7 @@ pub mod api;
 pub mod client;
 pub mod config;
 pub mod http;
+mod reorder;
 pub mod rpc;
 pub mod server;
 mod service;
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use db::Store;
+use fnv::FnvHashSet;
+use futures::{channel::mpsc::Sender, Future, FutureExt, SinkExt};
+use fvm_ipld_blockstore::Blockstore;
+use libipld::{store::DefaultParams, Block, Cid};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::{sleep, Instant};
+use ursa_store::{DagLimits, UrsaStore};
+
+use crate::config::ProgressiveConfig;
+
+/// Interval to check the store for the blocks landed since the last check.
+const PROGRESSIVE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Puts the blocks of a dag back in the breadth first order of [`UrsaStore::walk`],
+/// whatever order they arrive in.
+pub struct DagReorder {
+    /// Blocks to yield, in order, the first being the next.
+    queue: VecDeque<Cid>,
+    seen: FnvHashSet<Cid>,
+    /// Blocks arrived ahead of the next block.
+    buffered: HashMap<Cid, Vec<u8>>,
+    max_buffered: usize,
+}
+
+impl DagReorder {
+    pub fn new(root: Cid, max_buffered: usize) -> Self {
+        Self {
+            queue: VecDeque::from([root]),
+            seen: FnvHashSet::from_iter([root]),
+            buffered: HashMap::new(),
+            max_buffered,
+        }
+    }
+
+    /// The block to yield next, `None` once the whole dag was yielded.
+    pub fn next(&self) -> Option<&Cid> {
+        self.queue.front()
+    }
+
+    /// Blocks of the dag known from their parents but not arrived yet, in order.
+    pub fn wanted(&self) -> impl Iterator<Item = &Cid> {
+        self.queue
+            .iter()
+            .filter(|cid| !self.buffered.contains_key(*cid))
+    }
+
+    /// Blocks arriving ahead of the next block that can still be buffered.
+    pub fn room(&self) -> usize {
+        self.max_buffered.saturating_sub(self.buffered.len())
+    }
+
+    /// Take an arrived block, returning the blocks it completed in dag order.
+    pub fn push(&mut self, cid: Cid, data: Vec<u8>) -> Result<Vec<(Cid, Vec<u8>)>> {
+        if !self.queue.contains(&cid) || self.buffered.contains_key(&cid) {
+            return Ok(vec![]);
+        }
+        if self.next() != Some(&cid) && self.room() == 0 {
+            return Err(anyhow!(
+                "Reorder buffer of {} blocks is full, waiting for {:?}",
+                self.max_buffered,
+                self.next()
+            ));
+        }
+        self.buffered.insert(cid, data);
+
+        let mut ready = vec![];
+        while let Some(data) = self.next().and_then(|cid| self.buffered.remove(cid)) {
+            let cid = self.queue.pop_front().unwrap();
+            let mut refs = Vec::new();
+            Block::<DefaultParams>::new(cid, data.clone())?.references(&mut refs)?;
+            for link in refs {
+                if self.seen.insert(link) {
+                    self.queue.push_back(link);
+                }
+            }
+            ready.push((cid, data));
+        }
+        Ok(ready)
+    }
+}
+
+/// Send the blocks of the dag rooted at `root` to `blocks` in dag order as they
+/// land in `store`, while `fetch` brings them in. Only the blocks arrived ahead of
+/// the next block are buffered, up to `config.max_buffered_blocks`. Fails if the
+/// next block doesn't arrive within `config.gap_timeout` seconds.
+pub async fn progressive_walk<S, F>(
+    store: Arc<UrsaStore<S>>,
+    root: Cid,
+    config: ProgressiveConfig,
+    limits: DagLimits,
+    fetch: F,
+    mut blocks: Sender<(Cid, Vec<u8>)>,
+) -> Result<()>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+    F: Future<Output = Result<()>> + Unpin,
+{
+    let gap_timeout = Duration::from_secs(config.gap_timeout);
+    let mut reorder = DagReorder::new(root, config.max_buffered_blocks);
+    let mut fetch = Some(fetch);
+    let mut last_block = Instant::now();
+    let (mut count, mut bytes) = (0, 0);
+
+    while let Some(next) = reorder.next().copied() {
+        let mut arrived = vec![];
+        let mut room = reorder.room();
+        for cid in reorder.wanted() {
+            if *cid != next {
+                if room == 0 {
+                    break;
+                }
+                room -= 1;
+            }
+            match store.blockstore().get(cid)? {
+                Some(data) => arrived.push((*cid, data)),
+                None if *cid != next => room += 1,
+                None => (),
+            }
+        }
+
+        let mut ready = vec![];
+        for (cid, data) in arrived {
+            ready.extend(reorder.push(cid, data)?);
+        }
+        if ready.is_empty() {
+            let Some(pending) = fetch.as_mut() else {
+                // the blocks still missing once fetched will never arrive
+                return Err(anyhow!("The block {next} of the dag {root} is missing"));
+            };
+            if let Some(result) = pending.now_or_never() {
+                result?;
+                // check the blocks landed before the fetch completed
+                fetch = None;
+                continue;
+            }
+            if last_block.elapsed() >= gap_timeout {
+                return Err(anyhow!(
+                    "The block {next} of the dag {root} didn't arrive within {}s",
+                    config.gap_timeout
+                ));
+            }
+            sleep(PROGRESSIVE_POLL_INTERVAL).await;
+            continue;
+        }
+
+        last_block = Instant::now();
+        for block in ready {
+            count += 1;
+            bytes += block.1.len() as u64;
+            if let Some(max) = limits.max_blocks.filter(|max| count > *max) {
+                return Err(anyhow!(
+                    "The dag {root} exceeds the maximum of {max} blocks"
+                ));
+            }
+            if let Some(max) = limits.max_bytes.filter(|max| bytes > *max) {
+                return Err(anyhow!("The dag {root} exceeds the maximum of {max} bytes"));
+            }
+            if blocks.send(block).await.is_err() {
+                // the car export was dropped
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
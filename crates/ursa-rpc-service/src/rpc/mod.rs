@@ -1,4 +1,8 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use axum::{
@@ -6,10 +10,17 @@ use axum::{
     response::{IntoResponse, Response},
     Extension, Json,
 };
-use jsonrpc_v2::{Data, Error, MapRouter, RequestObject, ResponseObject, ResponseObjects, Server};
+use jsonrpc_v2::{
+    Data, Error, Id, MapRouter, RequestObject, ResponseObject, ResponseObjects, Server,
+    ServerBuilder, V2,
+};
+use libipld::Cid;
+use metrics::increment_counter;
+use serde_json::Value;
+use subtle::ConstantTimeEq;
 
 use self::routes::network;
-use crate::api::NetworkInterface;
+use crate::api::{BlockStream, NetworkInterface, NodeConfigResult};
 
 pub mod routes;
 
@@ -18,20 +29,95 @@ pub struct RpcServer(Arc<Server<MapRouter>>);
 
 pub enum ServerErrors {
     ApiError(Error),
+    /// A request rejected as a whole before any call is handled, answered with a 400.
+    BadRequest(Error),
 }
 impl IntoResponse for ServerErrors {
     fn into_response(self) -> Response {
-        let body = match self {
-            ServerErrors::ApiError(e) => Json(e),
-        };
-        (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
+        match self {
+            ServerErrors::ApiError(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e)),
+            ServerErrors::BadRequest(e) => (StatusCode::BAD_REQUEST, Json(e)),
+        }
+        .into_response()
     }
 }
 
+/// Declare the methods served, by name and handler, as the `METHODS` names and the
+/// `with_methods` registering them, so the two can't drift apart.
+macro_rules! methods {
+    ($($name:literal => $handler:expr),* $(,)?) => {
+        /// Methods registered by [`RpcServer::new`].
+        const METHODS: &[&str] = &[$($name),*];
+
+        fn with_methods<I: NetworkInterface>(
+            server: ServerBuilder<MapRouter>,
+        ) -> ServerBuilder<MapRouter> {
+            server$(.with_method($name, $handler))*
+        }
+    };
+}
+
+methods! {
+    "ursa_get_cid" => network::get_cid_handler::<I>,
+    "ursa_get_block_by_hash" => network::get_block_by_hash_handler::<I>,
+    "ursa_get_file" => network::get_file_handler::<I>,
+    "ursa_get_file_stream" => network::get_file_stream_handler::<I>,
+    "ursa_fetch_from" => network::fetch_from_handler::<I>,
+    "ursa_put_file" => network::put_file_handler::<I>,
+    "ursa_get_peers" => network::get_peers::<I>,
+    "ursa_listener_addresses" => network::get_listener_addresses::<I>,
+    "ursa_node_info" => network::node_info::<I>,
+    "ursa_peer_info" => network::peer_info::<I>,
+    "ursa_await_peers" => network::await_peers::<I>,
+    "ursa_put_record" => network::put_record::<I>,
+    "ursa_publish" => network::publish::<I>,
+    "ursa_list_subscriptions" => network::list_subscriptions::<I>,
+    "ursa_config" => network::config,
+    // admin
+    "ursa_disconnect_all" => network::disconnect_all::<I>,
+    "ursa_block_peer" => network::block_peer::<I>,
+    "ursa_unblock_peer" => network::unblock_peer::<I>,
+}
+
+/// Count the failed calls by json-rpc error code and method. The methods not served
+/// share the `unknown` label, so callers can't grow the label set, the batches failing
+/// as a whole are labelled `batch`.
+fn record_error(method: &str, error: &Error) {
+    let code = match error {
+        Error::Full { code, .. } | Error::Provided { code, .. } => *code,
+    };
+    let method = if method == "batch" || METHODS.contains(&method) {
+        method
+    } else {
+        "unknown"
+    };
+    increment_counter!(
+        "rpc_errors",
+        "code" => code.to_string(),
+        "method" => method.to_string()
+    );
+}
+
 pub async fn rpc_handler(
     Extension(server): Extension<RpcServer>,
-    Json(req): Json<RequestObject>,
-) -> Result<Json<ResponseObjects>, ServerErrors> {
+    Json(req): Json<Value>,
+) -> Result<Response, ServerErrors> {
+    if let Value::Array(batch) = req {
+        return batch_handler(&server, batch).await;
+    }
+    // the method is not exposed by the parsed request
+    let method = req
+        .get("method")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown")
+        .to_string();
+    let req: RequestObject = match serde_json::from_value(req) {
+        Ok(req) => req,
+        Err(_) => {
+            record_error(&method, &Error::INVALID_REQUEST);
+            return Err(ServerErrors::ApiError(Error::INVALID_REQUEST));
+        }
+    };
     match server.0.handle(req).await {
         ResponseObjects::One(r) => match r {
             ResponseObject::Result {
@@ -42,33 +128,174 @@ pub async fn rpc_handler(
                 jsonrpc,
                 result,
                 id,
-            }))),
+            }))
+            .into_response()),
             ResponseObject::Error {
                 jsonrpc: _,
                 error,
                 id: _,
-            } => Err(ServerErrors::ApiError(error)),
+            } => {
+                record_error(&method, &error);
+                Err(ServerErrors::ApiError(error))
+            }
         },
-        ResponseObjects::Many(_) => todo!(),
-        ResponseObjects::Empty => todo!(),
+        // a batch is never handled as a single request
+        ResponseObjects::Many(responses) => {
+            Ok(Json(ResponseObjects::Many(responses)).into_response())
+        }
+        // notifications have no response
+        ResponseObjects::Empty => Ok(StatusCode::OK.into_response()),
+    }
+}
+
+/// Most calls in a batch, larger batches are rejected as a whole.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// Handle the calls of a batch, each call succeeds or fails on its own so the batch
+/// responds 200, with an empty body if all the calls are notifications. Batches over
+/// [`MAX_BATCH_SIZE`] calls are answered with a 400 instead.
+async fn batch_handler(server: &RpcServer, batch: Vec<Value>) -> Result<Response, ServerErrors> {
+    if batch.is_empty() {
+        record_error("batch", &Error::INVALID_REQUEST);
+        return Err(ServerErrors::ApiError(Error::INVALID_REQUEST));
+    }
+    if batch.len() > MAX_BATCH_SIZE {
+        let error = Error::Full {
+            code: -32600,
+            message: format!(
+                "Batch of {} calls, at most {MAX_BATCH_SIZE} are allowed",
+                batch.len()
+            ),
+            data: None,
+        };
+        record_error("batch", &error);
+        return Err(ServerErrors::BadRequest(error));
+    }
+    let methods: Vec<(Value, String)> = batch
+        .iter()
+        .map(|req| {
+            let id = req.get("id").cloned().unwrap_or(Value::Null);
+            let method = req
+                .get("method")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown");
+            (id, method.to_string())
+        })
+        .collect();
+
+    let mut responses = vec![];
+    let mut requests = vec![];
+    for req in batch {
+        match serde_json::from_value::<RequestObject>(req) {
+            Ok(req) => requests.push(req),
+            Err(_) => responses.push(ResponseObject::Error {
+                jsonrpc: V2,
+                error: Error::INVALID_REQUEST,
+                id: Id::Null,
+            }),
+        }
+    }
+    if !requests.is_empty() {
+        match server.0.handle(requests).await {
+            ResponseObjects::One(response) => responses.push(response),
+            ResponseObjects::Many(many) => responses.extend(many),
+            ResponseObjects::Empty => (),
+        }
+    }
+
+    for response in &responses {
+        if let ResponseObject::Error { error, id, .. } = response {
+            let id = serde_json::to_value(id).unwrap_or(Value::Null);
+            let method = methods
+                .iter()
+                .find(|(req_id, _)| *req_id == id)
+                .map_or("unknown", |(_, method)| method.as_str());
+            record_error(method, error);
+        }
+    }
+    if responses.is_empty() {
+        return Ok(StatusCode::OK.into_response());
+    }
+    Ok(Json(ResponseObjects::Many(responses)).into_response())
+}
+
+/// Json-rpc error codes of the failures specific to ursa, in the range left to servers.
+/// The admin token of an admin call isn't the configured one.
+pub const INVALID_ADMIN_TOKEN: i64 = -32001;
+/// No peer is in the mesh of the topic of a publish, the message was not sent.
+pub const NO_TOPIC_PEERS: i64 = -32002;
+/// An admin method was called while no admin token is configured.
+pub const ADMIN_DISABLED: i64 = -32003;
+
+/// Token authorizing the admin methods, admin methods are disabled without one.
+pub struct AdminToken(pub Option<String>);
+
+impl AdminToken {
+    pub fn authorize(&self, token: &str) -> Result<(), Error> {
+        match &self.0 {
+            Some(admin_token) if bool::from(admin_token.as_bytes().ct_eq(token.as_bytes())) => {
+                Ok(())
+            }
+            Some(_) => Err(Error::Full {
+                code: INVALID_ADMIN_TOKEN,
+                message: "Invalid admin token".into(),
+                data: None,
+            }),
+            None => Err(Error::Full {
+                code: ADMIN_DISABLED,
+                message: "Admin methods are disabled, no admin token is configured".into(),
+                data: None,
+            }),
+        }
+    }
+}
+
+/// Dag walks kept between the pages of `ursa_get_file_stream`.
+const MAX_FILE_STREAMS: usize = 64;
+/// Dag walks not resumed within this are dropped.
+const FILE_STREAM_TTL: Duration = Duration::from_secs(60);
+
+/// Dag walks paged through `ursa_get_file_stream`, keyed by the root and the last block
+/// returned so the next page resumes the walk instead of walking the dag again.
+#[derive(Default)]
+pub struct FileStreams(Mutex<HashMap<(Cid, Cid), (Instant, BlockStream)>>);
+
+impl FileStreams {
+    /// The walk of `root` past the block `after`, if a page ended there.
+    pub fn take(&self, root: Cid, after: Cid) -> Option<BlockStream> {
+        let (kept, stream) = self.0.lock().unwrap().remove(&(root, after))?;
+        (kept.elapsed() < FILE_STREAM_TTL).then_some(stream)
+    }
+
+    /// Keep the walk of `root` past the block `after` for the next page.
+    pub fn keep(&self, root: Cid, after: Cid, stream: BlockStream) {
+        let mut streams = self.0.lock().unwrap();
+        streams.retain(|_, (kept, _)| kept.elapsed() < FILE_STREAM_TTL);
+        if streams.len() >= MAX_FILE_STREAMS {
+            let oldest = streams
+                .iter()
+                .min_by_key(|(_, (kept, _))| *kept)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                streams.remove(&oldest);
+            }
+        }
+        streams.insert((root, after), (Instant::now(), stream));
     }
 }
 
 impl RpcServer {
-    pub fn new<I>(interface: Arc<I>) -> Self
+    pub fn new<I>(interface: Arc<I>, admin_token: AdminToken, config: NodeConfigResult) -> Self
     where
         I: NetworkInterface,
     {
-        let server = Server::new()
-            .with_data(Data::new(interface))
-            .with_method("ursa_get_cid", network::get_cid_handler::<I>)
-            .with_method("ursa_get_file", network::get_file_handler::<I>)
-            .with_method("ursa_put_file", network::put_file_handler::<I>)
-            .with_method("ursa_get_peers", network::get_peers::<I>)
-            .with_method(
-                "ursa_listener_addresses",
-                network::get_listener_addresses::<I>,
-            );
+        let server = with_methods::<I>(
+            Server::new()
+                .with_data(Data::new(interface))
+                .with_data(Data::new(admin_token))
+                .with_data(Data::new(config))
+                .with_data(Data::new(FileStreams::default())),
+        );
 
         RpcServer(server.finish())
     }
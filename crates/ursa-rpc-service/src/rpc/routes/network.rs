@@ -3,20 +3,31 @@ use axum::{
     routing::{post, put},
     Router,
 };
-use libipld::Cid;
-use std::{str::FromStr, sync::Arc};
+use futures::StreamExt;
+use libipld::{cid::multibase, multihash::Multihash, Cid};
+use std::{str::FromStr, sync::Arc, time::Duration};
 use ursa_metrics::middleware::track_metrics;
 
 use jsonrpc_v2::{Data, Error, Params};
 
 use crate::{
     api::{
-        NetworkGetFileParams, NetworkGetListenerAddresses, NetworkGetParams, NetworkGetPeers,
-        NetworkGetResult, NetworkInterface, NetworkPutFileParams, NetworkPutFileResult,
+        NetworkAwaitPeersParams, NetworkAwaitPeersResult, NetworkBlockPeerParams,
+        NetworkBlockPeerResult, NetworkDisconnectAllParams, NetworkDisconnectAllResult,
+        NetworkFetchFromParams, NetworkGetBlockByHashParams, NetworkGetFileParams,
+        NetworkGetFileStreamParams, NetworkGetFileStreamResult, NetworkGetListenerAddresses,
+        NetworkGetListenerAddressesParams, NetworkGetParams, NetworkGetPeers,
+        NetworkGetPeersParams, NetworkGetResult, NetworkInterface, NetworkListSubscriptionsResult,
+        NetworkNodeInfoResult, NetworkPeerInfoParams, NetworkPeerInfoResult, NetworkPublishParams,
+        NetworkPublishResult, NetworkPutFileParams, NetworkPutFileResult, NetworkPutRecordParams,
+        NetworkSubscription, NodeConfigResult, PeerEntry, DEFAULT_FILE_STREAM_PAGE,
+        MAX_FILE_STREAM_PAGE,
     },
-    rpc::rpc_handler,
+    rpc::{rpc_handler, AdminToken, FileStreams, NO_TOPIC_PEERS},
 };
+use libp2p::{gossipsub::error::PublishError, multiaddr::Protocol, Multiaddr, PeerId};
 use tracing::error;
+use ursa_network::RecordTooLarge;
 
 pub type Result<T> = anyhow::Result<T, Error>;
 
@@ -44,6 +55,64 @@ where
         Err(Error::INVALID_PARAMS)
     }
 }
+
+/// Parse a peer id, or a multiaddr ending with the peer id.
+fn parse_peer(peer: &str) -> Option<(PeerId, Option<Multiaddr>)> {
+    if let Ok(peer_id) = PeerId::from_str(peer) {
+        return Some((peer_id, None));
+    }
+    let addr = Multiaddr::from_str(peer).ok()?;
+    match addr.iter().last()? {
+        Protocol::P2p(hash) => Some((PeerId::from_multihash(hash).ok()?, Some(addr))),
+        _ => None,
+    }
+}
+
+pub async fn fetch_from_handler<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<NetworkFetchFromParams>,
+) -> Result<()>
+where
+    I: NetworkInterface,
+{
+    let (Ok(cid), Some((peer_id, address))) =
+        (Cid::from_str(&params.cid), parse_peer(&params.peer))
+    else {
+        error!(
+            "Invalid fetch from params, cid {} peer {}",
+            params.cid, params.peer
+        );
+        return Err(Error::INVALID_PARAMS);
+    };
+    data.0
+        .fetch_from(cid, peer_id, address, params.provide)
+        .await
+        .map_err(|err| {
+            error!("{:?}", err);
+            Error::internal(err)
+        })
+}
+
+pub async fn get_block_by_hash_handler<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<NetworkGetBlockByHashParams>,
+) -> Result<NetworkGetResult>
+where
+    I: NetworkInterface,
+{
+    let Some(hash) = multibase::decode(&params.multihash)
+        .ok()
+        .and_then(|(_, bytes)| Multihash::from_bytes(&bytes).ok())
+    else {
+        error!("Invalid multihash, cannot parse {}", &params.multihash);
+        return Err(Error::INVALID_PARAMS);
+    };
+    match data.0.get(Cid::new_v1(params.codec, hash)).await {
+        Err(err) => Err(Error::internal(err)),
+        Ok(res) => Ok(res),
+    }
+}
+
 pub async fn get_file_handler<I>(
     data: Data<Arc<I>>,
     Params(params): Params<NetworkGetFileParams>,
@@ -66,6 +135,75 @@ where
     }
 }
 
+/// A page of the blocks of a file, the client pages through them so neither side buffers the file.
+pub async fn get_file_stream_handler<I>(
+    data: Data<Arc<I>>,
+    streams: Data<FileStreams>,
+    Params(params): Params<NetworkGetFileStreamParams>,
+) -> Result<NetworkGetFileStreamResult>
+where
+    I: NetworkInterface,
+{
+    let Ok(cid) = Cid::from_str(&params.cid) else {
+        error!("Invalid Cid String, Cannot Parse {} to CID", &params.cid);
+        return Err(Error::INVALID_PARAMS);
+    };
+    let Ok(after) = params.after.as_deref().map(Cid::from_str).transpose() else {
+        error!(
+            "Invalid Cid String, Cannot Parse {:?} to CID",
+            &params.after
+        );
+        return Err(Error::INVALID_PARAMS);
+    };
+    let max_bytes = params
+        .max_bytes
+        .unwrap_or(DEFAULT_FILE_STREAM_PAGE)
+        .min(MAX_FILE_STREAM_PAGE);
+    // an error past the blocks already received fails the page, never truncating the file
+    let failed = |err: anyhow::Error| {
+        error!("{:?}", err);
+        Error::internal(err)
+    };
+
+    let mut stream = match after.and_then(|after| streams.take(cid, after)) {
+        Some(stream) => stream,
+        None => {
+            let mut stream = data.0.stream_blocks(cid).await.map_err(failed)?;
+            // the walk of the previous page expired, skip the blocks already returned
+            if let Some(after) = after {
+                loop {
+                    match stream.next().await {
+                        Some(Ok((block_cid, _))) if block_cid == after => break,
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => return Err(failed(err)),
+                        None => {
+                            error!("Block {after} is not part of the dag of {cid}");
+                            return Err(Error::INVALID_PARAMS);
+                        }
+                    }
+                }
+            }
+            stream
+        }
+    };
+
+    let mut bytes = 0;
+    let mut blocks = vec![];
+    while let Some(block) = stream.next().await {
+        let (block_cid, block) = block.map_err(failed)?;
+        bytes += block.len();
+        blocks.push(base64::encode(block));
+        if bytes >= max_bytes {
+            streams.keep(cid, block_cid, stream);
+            return Ok(NetworkGetFileStreamResult {
+                blocks,
+                next: Some(block_cid.to_string()),
+            });
+        }
+    }
+    Ok(NetworkGetFileStreamResult { blocks, next: None })
+}
+
 pub async fn put_file_handler<I>(
     data: Data<Arc<I>>,
     Params(params): Params<NetworkPutFileParams>,
@@ -75,7 +213,7 @@ where
 {
     let path = params.path;
 
-    match data.0.put_file(path).await {
+    match data.0.put_file(path, params.hash).await {
         Err(err) => {
             error!("{:?}", err);
             Err(Error::internal(err))
@@ -84,33 +222,246 @@ where
     }
 }
 
-pub async fn get_peers<I>(data: Data<Arc<I>>) -> Result<NetworkGetPeers>
+pub async fn get_peers<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<Option<NetworkGetPeersParams>>,
+) -> Result<NetworkGetPeers>
 where
     I: NetworkInterface,
 {
-    match data.0.get_peers().await {
+    let params = params.unwrap_or_default();
+    let peers = match data.0.get_peers().await {
+        Err(err) => {
+            error!("{:?}", err);
+            return Err(Error::internal(err));
+        }
+        Ok(res) => params.page(res),
+    };
+    if !params.with_addresses {
+        return Ok(NetworkGetPeers::Peers(peers));
+    }
+
+    match data.0.get_peer_addresses(peers.clone()).await {
         Err(err) => {
             error!("{:?}", err);
             Err(Error::internal(err))
         }
-        Ok(res) => Ok(res),
+        Ok(mut addresses) => Ok(NetworkGetPeers::WithAddresses(
+            peers
+                .into_iter()
+                .map(|peer_id| PeerEntry {
+                    addresses: addresses.remove(&peer_id).unwrap_or_default(),
+                    peer_id,
+                })
+                .collect(),
+        )),
     }
 }
 
-pub async fn get_listener_addresses<I>(data: Data<Arc<I>>) -> Result<NetworkGetListenerAddresses>
+pub async fn get_listener_addresses<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<Option<NetworkGetListenerAddressesParams>>,
+) -> Result<NetworkGetListenerAddresses>
 where
     I: NetworkInterface,
 {
+    let params = params.unwrap_or_default();
     if cfg!(test) {
         // for rpc server unit test
-        Ok(Vec::from(["/ip4/127.0.0.1/tcp/6009".parse().unwrap()]))
+        Ok(params.apply(Vec::from(["/ip4/127.0.0.1/tcp/6009".parse().unwrap()])))
     } else {
         match data.0.get_listener_addresses().await {
             Err(err) => {
                 error!("{:?}", err);
                 Err(Error::internal(err))
             }
-            Ok(res) => Ok(res),
+            Ok(res) => Ok(params.apply(res)),
+        }
+    }
+}
+
+pub async fn node_info<I>(data: Data<Arc<I>>) -> Result<NetworkNodeInfoResult>
+where
+    I: NetworkInterface,
+{
+    match data.0.node_info().await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
+    }
+}
+
+pub async fn peer_info<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<NetworkPeerInfoParams>,
+) -> Result<NetworkPeerInfoResult>
+where
+    I: NetworkInterface,
+{
+    match data.0.peer_info(params.peer_id).await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
+    }
+}
+
+pub async fn await_peers<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<NetworkAwaitPeersParams>,
+) -> Result<NetworkAwaitPeersResult>
+where
+    I: NetworkInterface,
+{
+    match data
+        .0
+        .await_peers(params.min, Duration::from_millis(params.timeout))
+        .await
+    {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
+    }
+}
+
+pub async fn put_record<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<NetworkPutRecordParams>,
+) -> Result<()>
+where
+    I: NetworkInterface,
+{
+    match data
+        .0
+        .put_record(params.key.into_bytes(), params.value.into_bytes())
+        .await
+    {
+        Err(err) if err.is::<RecordTooLarge>() => Err(Error::Full {
+            code: -32602,
+            message: err.to_string(),
+            data: None,
+        }),
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
+    }
+}
+
+pub async fn publish<I>(
+    data: Data<Arc<I>>,
+    Params(params): Params<NetworkPublishParams>,
+) -> Result<NetworkPublishResult>
+where
+    I: NetworkInterface,
+{
+    match data
+        .0
+        .publish(params.topic.clone(), params.data.into_bytes())
+        .await
+    {
+        Ok(ack) => Ok(NetworkPublishResult {
+            message_id: ack.message_id.to_string(),
+            peers: ack.peers,
+        }),
+        Err(err) => match err.downcast_ref::<PublishError>() {
+            Some(PublishError::InsufficientPeers) => Err(Error::Full {
+                code: NO_TOPIC_PEERS,
+                message: format!(
+                    "No peers in the mesh of topic {}, the message was not sent",
+                    params.topic
+                ),
+                data: None,
+            }),
+            _ => {
+                error!("{:?}", err);
+                Err(Error::internal(err))
+            }
+        },
+    }
+}
+
+pub async fn list_subscriptions<I>(data: Data<Arc<I>>) -> Result<NetworkListSubscriptionsResult>
+where
+    I: NetworkInterface,
+{
+    match data.0.list_subscriptions().await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
         }
+        Ok(topics) => Ok(topics
+            .into_iter()
+            .map(|stats| NetworkSubscription {
+                topic: stats.topic.into_string(),
+                mesh_peers: stats.mesh_peers,
+                published: stats.published,
+                received: stats.received,
+            })
+            .collect()),
+    }
+}
+
+pub async fn config(config: Data<NodeConfigResult>) -> Result<NodeConfigResult> {
+    Ok(config.0.as_ref().clone())
+}
+
+pub async fn disconnect_all<I>(
+    data: Data<Arc<I>>,
+    admin_token: Data<AdminToken>,
+    Params(params): Params<NetworkDisconnectAllParams>,
+) -> Result<NetworkDisconnectAllResult>
+where
+    I: NetworkInterface,
+{
+    admin_token.authorize(&params.token)?;
+    match data.0.disconnect_all().await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
+    }
+}
+
+pub async fn block_peer<I>(
+    data: Data<Arc<I>>,
+    admin_token: Data<AdminToken>,
+    Params(params): Params<NetworkBlockPeerParams>,
+) -> Result<NetworkBlockPeerResult>
+where
+    I: NetworkInterface,
+{
+    admin_token.authorize(&params.token)?;
+    match data.0.block_peer(params.peer_id).await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
+    }
+}
+
+pub async fn unblock_peer<I>(
+    data: Data<Arc<I>>,
+    admin_token: Data<AdminToken>,
+    Params(params): Params<NetworkBlockPeerParams>,
+) -> Result<NetworkBlockPeerResult>
+where
+    I: NetworkInterface,
+{
+    admin_token.authorize(&params.token)?;
+    match data.0.unblock_peer(params.peer_id).await {
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::internal(err))
+        }
+        Ok(res) => Ok(res),
     }
 }
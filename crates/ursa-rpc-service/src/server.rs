@@ -2,13 +2,14 @@ use anyhow::Result;
 use axum::{Extension, Router};
 use db::Store;
 use fvm_ipld_blockstore::Blockstore;
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::net::TcpSocket;
 
 use crate::{
-    api::NodeNetworkInterface,
+    api::{NodeConfigResult, NodeNetworkInterface},
     config::ServerConfig,
-    http,
-    rpc::{routes, RpcServer},
+    http::{self, routes::health::Readiness},
+    rpc::{routes, AdminToken, RpcServer},
     service::MultiplexService,
 };
 use tracing::info;
@@ -19,6 +20,9 @@ where
 {
     rpc_server: RpcServer,
     interface: Arc<NodeNetworkInterface<S>>,
+    readiness: Readiness,
+    admin_token: Option<String>,
+    config: NodeConfigResult,
 }
 
 impl<S> Server<S>
@@ -27,11 +31,56 @@ where
 {
     pub fn new(interface: Arc<NodeNetworkInterface<S>>) -> Self {
         Self {
-            rpc_server: RpcServer::new(Arc::clone(&interface)),
+            rpc_server: RpcServer::new(
+                Arc::clone(&interface),
+                AdminToken(None),
+                NodeConfigResult::default(),
+            ),
             interface: interface.clone(),
+            readiness: Readiness::new(Duration::ZERO),
+            admin_token: None,
+            config: NodeConfigResult::default(),
         }
     }
 
+    fn with_rpc_server(self) -> Self {
+        Self {
+            rpc_server: RpcServer::new(
+                Arc::clone(&self.interface),
+                AdminToken(self.admin_token.clone()),
+                self.config.clone(),
+            ),
+            ..self
+        }
+    }
+
+    /// Report the node as starting rather than unready for `grace_period` after startup.
+    pub fn with_startup_grace_period(self, grace_period: Duration) -> Self {
+        Self {
+            readiness: Readiness::new(grace_period),
+            ..self
+        }
+    }
+
+    /// Readiness reported by `/readyz`, to mark the node as draining on shutdown.
+    pub fn readiness(&self) -> Readiness {
+        self.readiness.clone()
+    }
+
+    /// Enable the admin rpc methods, authorized by `token`.
+    pub fn with_admin_token(self, token: Option<String>) -> Self {
+        Self {
+            admin_token: token,
+            ..self
+        }
+        .with_rpc_server()
+    }
+
+    /// Effective config of the node, reported by `ursa_config`.
+    pub fn with_config(self, config: NodeConfigResult) -> Self {
+        Self { config, ..self }.with_rpc_server()
+    }
+
     pub async fn start(
         &self,
         config: &ServerConfig,
@@ -47,7 +96,8 @@ where
 
         let http_address = SocketAddr::from(([0, 0, 0, 0], config.port));
         info!("listening on {}", http_address);
-        axum::Server::bind(&http_address)
+        axum::Server::from_tcp(bind_listener(config, http_address)?)?
+            .tcp_nodelay(config.tcp_nodelay)
             .serve(tower::make::Shared::new(service))
             .await?;
 
@@ -63,8 +113,25 @@ where
     pub fn http_app(&self, index_provider: Router, metrics: Option<Router>) -> Router {
         Router::new()
             .merge(http::routes::network::init::<S>())
+            .merge(http::routes::health::init::<S>())
             .merge(index_provider)
             .merge(metrics.unwrap_or_else(Router::new))
             .layer(Extension(self.interface.clone()))
+            .layer(Extension(self.readiness.clone()))
+    }
+}
+
+/// Bind the listener with the configured socket buffer sizes,
+/// accepted connections inherit them from the listening socket.
+fn bind_listener(config: &ServerConfig, address: SocketAddr) -> Result<std::net::TcpListener> {
+    let socket = TcpSocket::new_v4()?;
+    socket.set_reuseaddr(true)?;
+    if let Some(size) = config.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = config.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
     }
+    socket.bind(address)?;
+    Ok(socket.listen(1024)?.into_std()?)
 }
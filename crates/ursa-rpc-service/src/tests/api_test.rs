@@ -1,16 +1,55 @@
 #[cfg(test)]
 mod tests {
-    use crate::api::{NetworkInterface, NodeNetworkInterface};
-    use crate::config::OriginConfig;
-    use crate::tests::{dummy_ipfs, init, setup_logger};
+    use crate::api::{
+        is_car, Car, CarTooLarge, NetworkGetBlockByHashParams, NetworkGetFileStreamParams,
+        NetworkGetListenerAddressesParams, NetworkGetPeers, NetworkGetPeersParams,
+        NetworkInterface, NodeNetworkInterface,
+    };
+    use crate::config::{ImportLimits, OriginConfig};
+    use crate::rpc::{
+        routes::network::{get_block_by_hash_handler, get_file_stream_handler},
+        FileStreams,
+    };
+    use crate::tests::{dummy_ipfs, get_store, init, setup_logger};
     use anyhow::Result;
-    use async_fs::{remove_file, File};
+    use async_fs::{remove_dir_all, remove_file, File};
     use futures::io::BufReader;
+    use fvm_ipld_blockstore::Blockstore;
     use fvm_ipld_car::load_car;
+    use jsonrpc_v2::{Data, Params};
+    use libipld::cid::multibase::{self, Base};
+    use libp2p::{Multiaddr, PeerId};
+    use serde_json::json;
     use std::path::Path;
     use std::sync::Arc;
-    use tokio::task;
+    use std::time::Duration;
+    use tokio::{sync::mpsc::unbounded_channel, task, time::timeout};
     use tracing::error;
+    use ursa_network::NetworkCommand;
+    use ursa_store::DagLimits;
+
+    #[tokio::test]
+    async fn test_is_car() -> Result<()> {
+        assert!(is_car("../../test_files/test.car").await?);
+
+        // sniffed from the content, not the file name
+        let dir = std::env::temp_dir().join(format!("ursa-is-car-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let file = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let car = std::fs::read("../../test_files/test.car")?;
+        assert!(is_car(&file("car", &car)).await?);
+        assert!(!is_car(&file("text.car", b"not a car")).await?);
+        assert!(!is_car(&file("empty.car", b"")).await?);
+        // a header length above the sniffed bound
+        assert!(!is_car(&file("large.car", &[0xff, 0xff, 0xff, 0x7f])).await?);
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
 
     #[tokio::test]
     async fn test_put_and_get() -> Result<()> {
@@ -22,6 +61,7 @@ mod tests {
             ursa_service.command_sender(),
             provider_engine.command_sender(),
             Default::default(),
+            Default::default(),
         ));
 
         // the test case does not start the provider engine, so the best way
@@ -30,7 +70,7 @@ mod tests {
         ursa_service.close_command_receiver();
 
         let put_file = interface
-            .put_file("../../test_files/test.car".to_string())
+            .put_file("../../test_files/test.car".to_string(), Default::default())
             .await?;
         let root_cid = put_file[0];
 
@@ -50,6 +90,331 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_get_file_limits() -> Result<()> {
+        setup_logger();
+        let (mut ursa_service, mut provider_engine, store) = init()?;
+        provider_engine.command_receiver().close();
+        ursa_service.close_command_receiver();
+
+        let interface = |car_limits| {
+            NodeNetworkInterface::new(
+                Arc::clone(&store),
+                ursa_service.command_sender(),
+                provider_engine.command_sender(),
+                Default::default(),
+                car_limits,
+            )
+        };
+
+        let root_cid = interface(Default::default())
+            .put_file("../../test_files/test.car".to_string(), Default::default())
+            .await?[0];
+        let blocks = store.dag_traversal(&root_cid)?.len();
+        let dir = "../../test_files/limits".to_string();
+        let path = format!("{dir}/{root_cid}.car");
+
+        // exceeding the limits mid export leaves no partial file behind
+        let bounded = interface(DagLimits {
+            max_blocks: Some(blocks - 1),
+            ..Default::default()
+        });
+        assert!(bounded.get_file(dir.clone(), root_cid).await.is_err());
+        assert!(!Path::new(&path).exists());
+
+        let bounded = interface(DagLimits {
+            max_blocks: Some(blocks),
+            ..Default::default()
+        });
+        bounded.get_file(dir.clone(), root_cid).await?;
+        let reader = BufReader::new(File::open(&path).await?);
+        let cids = load_car(store.blockstore(), reader).await?;
+        assert_eq!(cids[0], root_cid);
+        remove_dir_all(dir).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_limits() -> Result<()> {
+        setup_logger();
+        let (mut ursa_service, mut provider_engine, store) = init()?;
+        provider_engine.command_receiver().close();
+        ursa_service.close_command_receiver();
+
+        let interface = NodeNetworkInterface::new(
+            Arc::clone(&store),
+            ursa_service.command_sender(),
+            provider_engine.command_sender(),
+            Default::default(),
+            Default::default(),
+        )
+        .with_import_limits(ImportLimits {
+            max_concurrent: 1,
+            max_size: 1024,
+            ..Default::default()
+        });
+
+        let permit = interface.try_start_import();
+        assert!(permit.is_some());
+        assert!(interface.try_start_import().is_none());
+        drop(permit);
+        assert!(interface.try_start_import().is_some());
+
+        let car = Car::from_file("../../test_files/test.car").await?;
+        let err = interface
+            .put_car(car.with_max_size(1024))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CarTooLarge>(),
+            Some(&CarTooLarge { max: 1024 })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_puts() -> Result<()> {
+        setup_logger();
+        let (network_send, mut network_commands) = unbounded_channel();
+        let (provider_send, provider_commands) = unbounded_channel();
+        // no provider engine to announce the content
+        drop(provider_commands);
+        let interface = Arc::new(
+            NodeNetworkInterface::new(
+                get_store(),
+                network_send,
+                provider_send,
+                Default::default(),
+                Default::default(),
+            )
+            .with_coalesce_puts(true),
+        );
+
+        let dir = std::env::temp_dir().join(format!("ursa-coalesce-puts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let data = vec![7; 1024 * 1024];
+        let mut diverging = data.clone();
+        *diverging.last_mut().unwrap() = 8;
+        let put = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            let interface = Arc::clone(&interface);
+            task::spawn(async move {
+                interface
+                    .put_file(path.to_string_lossy().to_string(), Default::default())
+                    .await
+            })
+        };
+        let puts = [put("a", &data), put("b", &data), put("c", &diverging)];
+
+        // the network puts are held until no other put comes, so the puts overlap
+        let mut provided = vec![];
+        while let Ok(Some(command)) = timeout(Duration::from_secs(1), network_commands.recv()).await
+        {
+            if let NetworkCommand::Put { cid, sender } = command {
+                provided.push((cid, sender));
+            }
+        }
+        let (provided, senders): (Vec<_>, Vec<_>) = provided.into_iter().unzip();
+        for sender in senders {
+            sender.send(Ok(())).unwrap();
+        }
+
+        let mut roots = vec![];
+        for put in puts {
+            roots.push(put.await??[0]);
+        }
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(roots[0], roots[1]);
+        assert_ne!(roots[0], roots[2]);
+        // the identical files were stored and provided once
+        assert_eq!(provided.len(), 2, "provided {provided:?}");
+        assert!(provided.contains(&roots[0]) && provided.contains(&roots[2]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_listener_addresses_filter() {
+        let addresses: Vec<Multiaddr> = [
+            "/ip4/127.0.0.1/tcp/6009",
+            "/ip4/1.2.3.4/tcp/6009",
+            "/ip4/192.168.1.2/tcp/6009",
+            "/ip4/1.2.3.4/tcp/6009",
+            "/ip6/::1/udp/4890/quic-v1",
+            "/ip4/5.6.7.8/udp/4890/quic-v1",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+        let filter = |public_only, dedup, limit| {
+            NetworkGetListenerAddressesParams {
+                public_only,
+                dedup,
+                limit,
+            }
+            .apply(addresses.clone())
+        };
+        let pick = |indices: &[usize]| -> Vec<Multiaddr> {
+            indices.iter().map(|i| addresses[*i].clone()).collect()
+        };
+
+        assert_eq!(filter(false, false, None), addresses);
+        // loopback and private addresses are removed
+        assert_eq!(filter(true, false, None), pick(&[1, 3, 5]));
+        assert_eq!(filter(true, true, None), pick(&[1, 5]));
+        assert_eq!(filter(true, true, Some(1)), pick(&[1]));
+        assert_eq!(filter(false, false, Some(0)), vec![]);
+    }
+
+    #[test]
+    fn test_get_peers_page() {
+        let peers: Vec<_> = (0..5).map(|_| PeerId::random()).collect();
+        let page = |offset, limit| {
+            NetworkGetPeersParams {
+                offset,
+                limit,
+                with_addresses: false,
+            }
+            .page(peers.clone())
+        };
+        let all = page(0, None);
+        assert_eq!(all.len(), 5);
+        assert_eq!(page(0, None), all, "unstable peer order");
+
+        assert_eq!(page(0, Some(2)), all[..2]);
+        assert_eq!(page(4, Some(2)), all[4..]);
+        assert_eq!(page(3, None), all[3..]);
+        assert_eq!(page(0, Some(0)), vec![]);
+        assert_eq!(page(5, Some(2)), vec![]);
+        assert_eq!(page(usize::MAX, Some(usize::MAX)), vec![]);
+        let pages: Vec<_> = (0..3).flat_map(|i| page(i * 2, Some(2))).collect();
+        assert_eq!(pages, all);
+
+        // no params or empty params request every peer id, as before paging
+        let params = |value| serde_json::from_value::<Option<NetworkGetPeersParams>>(value);
+        assert!(params(json!(null)).unwrap().is_none());
+        let params = params(json!([])).unwrap().unwrap();
+        assert_eq!((params.offset, params.limit), (0, None));
+        assert!(!params.with_addresses);
+        assert_eq!(
+            serde_json::to_value(NetworkGetPeers::Peers(all.clone())).unwrap(),
+            json!(all.iter().map(PeerId::to_string).collect::<Vec<_>>())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_block_by_hash() -> Result<()> {
+        setup_logger();
+        let (mut ursa_service, mut provider_engine, store) = init()?;
+        provider_engine.command_receiver().close();
+        ursa_service.close_command_receiver();
+
+        let interface = Arc::new(NodeNetworkInterface::new(
+            Arc::clone(&store),
+            ursa_service.command_sender(),
+            provider_engine.command_sender(),
+            Default::default(),
+            Default::default(),
+        ));
+        let root_cid = interface
+            .put_file("../../test_files/test.car".to_string(), Default::default())
+            .await?[0];
+        let get = |multihash: String| {
+            get_block_by_hash_handler(
+                Data::new(Arc::clone(&interface)),
+                Params(NetworkGetBlockByHashParams {
+                    multihash,
+                    codec: root_cid.codec(),
+                }),
+            )
+        };
+
+        let multihash = multibase::encode(Base::Base58Btc, root_cid.hash().to_bytes());
+        let Ok(block) = get(multihash).await else {
+            panic!("failed to get block by hash");
+        };
+        assert_eq!(Some(block), store.blockstore().get(&root_cid)?);
+
+        assert!(get("not a multihash".to_string()).await.is_err());
+        // valid multibase but not a multihash
+        let invalid = multibase::encode(Base::Base58Btc, [0xff]);
+        assert!(get(invalid).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_file_stream() -> Result<()> {
+        setup_logger();
+        let (mut ursa_service, mut provider_engine, store) = init()?;
+        provider_engine.command_receiver().close();
+        ursa_service.close_command_receiver();
+
+        let interface = Arc::new(NodeNetworkInterface::new(
+            Arc::clone(&store),
+            ursa_service.command_sender(),
+            provider_engine.command_sender(),
+            Default::default(),
+            Default::default(),
+        ));
+        let root_cid = interface
+            .put_file("../../test_files/test.car".to_string(), Default::default())
+            .await?[0];
+        let dag = store.dag_traversal(&root_cid)?;
+        assert!(dag.len() > 1);
+
+        let page = |streams: Data<FileStreams>, after: Option<String>| {
+            get_file_stream_handler(
+                Data::new(Arc::clone(&interface)),
+                streams,
+                Params(NetworkGetFileStreamParams {
+                    cid: root_cid.to_string(),
+                    after,
+                    max_bytes: Some(1),
+                }),
+            )
+        };
+
+        // a page per block, each resuming the walk of the previous one
+        let streams = Arc::new(FileStreams::default());
+        let (mut after, mut pages, mut content) = (Some(None), 0, vec![]);
+        while let Some(page_after) = after {
+            let Ok(page) = page(Data(Arc::clone(&streams)), page_after).await else {
+                panic!("failed to get a page of the file");
+            };
+            for block in page.blocks {
+                content.extend(base64::decode(block)?);
+            }
+            after = page.next.map(Some);
+            pages += 1;
+        }
+
+        assert_eq!(pages, dag.len() + 1);
+        assert_eq!(
+            content,
+            dag.iter()
+                .flat_map(|(_, data)| data.clone())
+                .collect::<Vec<_>>()
+        );
+
+        // without the walk of the previous page, the dag is walked again past `after`
+        let Ok(resumed) = page(
+            Data::new(FileStreams::default()),
+            Some(dag[0].0.to_string()),
+        )
+        .await
+        else {
+            panic!("failed to get a page of the file");
+        };
+        assert_eq!(base64::decode(&resumed.blocks[0])?, dag[1].1);
+        assert_eq!(resumed.next, Some(dag[1].0.to_string()));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_origin_fallback() -> Result<()> {
         setup_logger();
@@ -77,6 +442,7 @@ mod tests {
                 ipfs_gateway: "127.0.0.1:9682".to_string(),
                 use_https: Some(false),
             },
+            Default::default(),
         ));
 
         // since we have no peers, get will fallback to origin
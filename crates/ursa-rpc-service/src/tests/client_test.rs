@@ -0,0 +1,115 @@
+#[cfg(test)]
+mod tests {
+    use crate::client::{call_with, ClientError, RpcClientConfig, RpcMethod};
+    use axum::{http::StatusCode, routing::post, Json, Router, Server};
+    use serde_json::{json, Value};
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+    use std::time::Duration;
+
+    /// Rpc server failing the first `failures` requests with a 503, answering
+    /// after `delay` otherwise. Returns its config and request counter.
+    fn mock_server(failures: u32, delay: Duration) -> (RpcClientConfig, Arc<AtomicU32>) {
+        let requests = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&requests);
+        let app = Router::new().route(
+            "/rpc/v0",
+            post(move || {
+                let attempt = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if attempt < failures {
+                        return Err(StatusCode::SERVICE_UNAVAILABLE);
+                    }
+                    tokio::time::sleep(delay).await;
+                    Ok(Json(json!({ "jsonrpc": "2.0", "result": "ok", "id": 1 })))
+                }
+            }),
+        );
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let url = format!("http://{}/rpc/v0", server.local_addr());
+        tokio::spawn(server);
+        let config = RpcClientConfig::default()
+            .with_url(url)
+            .with_retry_backoff(Duration::from_millis(10));
+        (config, requests)
+    }
+
+    #[tokio::test]
+    async fn test_client_timeout() {
+        let (config, requests) = mock_server(0, Duration::from_secs(5));
+        let config = config.with_timeout(Duration::from_millis(100));
+
+        let res: Result<Value, _> = call_with(&config, "ursa_config", (), RpcMethod::Post, 0).await;
+        assert!(matches!(res, Err(ClientError::Timeout)), "{res:?}");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // each attempt is bounded separately
+        let res: Result<Value, _> = call_with(&config, "ursa_config", (), RpcMethod::Post, 2).await;
+        assert!(matches!(res, Err(ClientError::Timeout)), "{res:?}");
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_client_transfer_timeout() {
+        let (config, _) = mock_server(0, Duration::from_millis(300));
+        let config = config
+            .with_timeout(Duration::from_millis(100))
+            .with_transfer_timeout(Duration::from_secs(5));
+
+        let res: Result<Value, _> = call_with(&config, "ursa_config", (), RpcMethod::Post, 0).await;
+        assert!(matches!(res, Err(ClientError::Timeout)), "{res:?}");
+        // file transfers get longer
+        let res: Value = call_with(&config, "ursa_put_file", (), RpcMethod::Put, 0)
+            .await
+            .unwrap();
+        assert_eq!(res, json!("ok"));
+    }
+
+    #[tokio::test]
+    async fn test_client_max_response_size() {
+        let (config, _) = mock_server(0, Duration::ZERO);
+
+        let res: Result<Value, _> = call_with(
+            &config.clone().with_max_response_size(8),
+            "ursa_config",
+            (),
+            RpcMethod::Post,
+            0,
+        )
+        .await;
+        assert!(
+            matches!(res, Err(ClientError::ResponseTooLarge { limit: 8 })),
+            "{res:?}"
+        );
+        let res: Value = call_with(&config, "ursa_config", (), RpcMethod::Post, 0)
+            .await
+            .unwrap();
+        assert_eq!(res, json!("ok"));
+    }
+
+    #[tokio::test]
+    async fn test_client_retries() {
+        let (config, requests) = mock_server(2, Duration::ZERO);
+
+        // calls not safe to repeat fail on the first transient error
+        let res: Result<Value, _> =
+            call_with(&config, "ursa_put_file", (), RpcMethod::Put, 0).await;
+        assert!(matches!(res, Err(ClientError::Transport(_))), "{res:?}");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let res: Value = call_with(&config, "ursa_config", (), RpcMethod::Post, 2)
+            .await
+            .unwrap();
+        assert_eq!(res, json!("ok"));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        assert_eq!(RpcClientConfig::default().max_retries, 0);
+        assert_eq!(RpcClientConfig::default().timeout, Duration::from_secs(30));
+        assert_eq!(
+            RpcClientConfig::default().transfer_timeout,
+            Duration::from_secs(300)
+        );
+    }
+}
@@ -1,4 +1,6 @@
 mod api_test;
+mod client_test;
+mod reorder_test;
 mod server_test;
 
 use anyhow::Result;
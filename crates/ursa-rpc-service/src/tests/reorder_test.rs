@@ -0,0 +1,112 @@
+#[cfg(test)]
+mod tests {
+    use crate::config::ProgressiveConfig;
+    use crate::reorder::{progressive_walk, DagReorder};
+    use crate::tests::{get_store, setup_logger};
+    use anyhow::Result;
+    use futures::{channel::mpsc::channel, future, StreamExt};
+    use fvm_ipld_blockstore::Blockstore;
+    use libipld::{multihash::Code, Cid};
+    use std::time::Duration;
+    use tokio::{task, time::sleep};
+    use ursa_store::{DagLimits, CHUNK_SIZE};
+
+    /// Content spanning a few chunks, and its dag in walk order.
+    fn dag() -> Result<(Vec<u8>, Vec<(Cid, Vec<u8>)>)> {
+        // chunks differ so none of the leaves are deduplicated
+        let content: Vec<u8> = (0..3 * CHUNK_SIZE + 1)
+            .map(|i| ((i + i / CHUNK_SIZE) % 251) as u8)
+            .collect();
+        let store = get_store();
+        let cids = store.put_bytes(&content, Code::Sha2_256)?;
+        let blocks = store
+            .walk(&cids[0], DagLimits::default())
+            .collect::<Result<_>>()?;
+        Ok((content, blocks))
+    }
+
+    #[test]
+    fn test_reorder_out_of_order() -> Result<()> {
+        let (content, blocks) = dag()?;
+        let (root, leaves) = blocks.split_first().unwrap();
+
+        let mut reorder = DagReorder::new(root.0, leaves.len());
+        let mut ordered = reorder.push(root.0, root.1.clone())?;
+        for (cid, data) in leaves.iter().rev() {
+            ordered.extend(reorder.push(*cid, data.clone())?);
+        }
+        assert!(reorder.next().is_none());
+        assert_eq!(ordered, blocks);
+        let bytes: Vec<u8> = ordered[1..]
+            .iter()
+            .flat_map(|(_, data)| data.clone())
+            .collect();
+        assert_eq!(bytes, content);
+
+        // blocks arriving ahead of the next one are bounded
+        let mut reorder = DagReorder::new(root.0, 1);
+        reorder.push(root.0, root.1.clone())?;
+        assert!(reorder.push(leaves[2].0, leaves[2].1.clone())?.is_empty());
+        assert!(reorder.push(leaves[1].0, leaves[1].1.clone()).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_progressive_walk() -> Result<()> {
+        setup_logger();
+        let (_, blocks) = dag()?;
+        let store = get_store();
+        let config = ProgressiveConfig {
+            max_buffered_blocks: 2,
+            gap_timeout: 5,
+        };
+
+        // blocks land in the store in any order while the walk runs
+        let (tx, rx) = channel(16);
+        let walk = task::spawn(progressive_walk(
+            store.clone(),
+            blocks[0].0,
+            config,
+            DagLimits::default(),
+            future::pending::<Result<()>>(),
+            tx,
+        ));
+        for (cid, data) in blocks.iter().rev() {
+            sleep(Duration::from_millis(50)).await;
+            store.blockstore().put_keyed(cid, data)?;
+        }
+        assert_eq!(rx.collect::<Vec<_>>().await, blocks);
+        walk.await??;
+
+        // gaps never filled abort the walk
+        let (tx, _rx) = channel(16);
+        let config = ProgressiveConfig {
+            max_buffered_blocks: 2,
+            gap_timeout: 1,
+        };
+        let missing = blocks[0].0;
+        assert!(progressive_walk(
+            get_store(),
+            missing,
+            config,
+            DagLimits::default(),
+            future::pending::<Result<()>>(),
+            tx.clone()
+        )
+        .await
+        .is_err());
+        assert!(progressive_walk(
+            get_store(),
+            missing,
+            config,
+            DagLimits::default(),
+            future::ready(Ok(())),
+            tx
+        )
+        .await
+        .is_err());
+
+        Ok(())
+    }
+}
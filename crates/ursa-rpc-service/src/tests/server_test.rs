@@ -1,7 +1,9 @@
 #[cfg(test)]
 mod tests {
     use crate::{
-        api::NodeNetworkInterface,
+        api::{NodeConfigResult, NodeNetworkInterface},
+        config::ImportLimits,
+        rpc::{AdminToken, ADMIN_DISABLED, INVALID_ADMIN_TOKEN, MAX_BATCH_SIZE, NO_TOPIC_PEERS},
         server::Server,
         tests::{init, setup_logger},
     };
@@ -11,10 +13,29 @@ mod tests {
         http::{self, Request, StatusCode},
     };
 
+    use jsonrpc_v2::Error;
     use serde_json::{json, Value};
-    use std::sync::Arc;
+    use std::{sync::Arc, time::Duration};
     use tower::ServiceExt;
 
+    #[test]
+    fn test_error_codes() {
+        let code = |result: Result<(), Error>| match result {
+            Err(Error::Full { code, .. } | Error::Provided { code, .. }) => code,
+            Ok(()) => panic!("authorized"),
+        };
+        let token = AdminToken(Some("token".into()));
+        assert!(token.authorize("token").is_ok());
+        assert_eq!(code(token.authorize("other")), INVALID_ADMIN_TOKEN);
+        assert_eq!(code(AdminToken(None).authorize("token")), ADMIN_DISABLED);
+
+        // each failure has its own code
+        let mut codes = vec![INVALID_ADMIN_TOKEN, NO_TOPIC_PEERS, ADMIN_DISABLED];
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), 3);
+    }
+
     #[tokio::test]
     async fn test_http_server() -> Result<()> {
         setup_logger();
@@ -25,11 +46,58 @@ mod tests {
             ursa_service.command_sender(),
             provider_engine.command_sender(),
             Default::default(),
+            Default::default(),
         ));
         let server = Server::new(interface);
         let metrics = ursa_metrics::routes::init();
         let http_app = server.http_app(provider_engine.router(), Some(metrics));
 
+        // the recorder is global, so rpc errors are counted here
+        for method in ["ursa_get_cid", "ursa_not_a_method"] {
+            let req = serde_json::to_vec(&json!({
+                "jsonrpc": "2.0",
+                "method": method,
+                "params": {"cid": "invalid"},
+                "id": 1,
+            }))
+            .unwrap();
+            server
+                .rpc_app()
+                .oneshot(
+                    Request::builder()
+                        .method(http::Method::POST)
+                        .uri("/rpc/v0")
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(req))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let response = http_app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.lines().any(|line| line.starts_with("rpc_errors{")
+            && line.contains(r#"code="-32602""#)
+            && line.contains(r#"method="ursa_get_cid""#)
+            && line.ends_with(" 1")));
+        // methods not served don't get their own label
+        assert!(body.lines().any(|line| line.starts_with("rpc_errors{")
+            && line.contains(r#"code="-32601""#)
+            && line.contains(r#"method="unknown""#)));
+        assert!(!body.contains("ursa_not_a_method"));
+
         let response = http_app
             .oneshot(Request::builder().uri("/ping").body(Body::empty()).unwrap())
             .await
@@ -42,6 +110,109 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_upload_expect_continue() -> Result<()> {
+        setup_logger();
+        let (ursa_service, provider_engine, store) = init()?;
+
+        let upload = |expect_continue| {
+            let interface = NodeNetworkInterface::new(
+                Arc::clone(&store),
+                ursa_service.command_sender(),
+                provider_engine.command_sender(),
+                Default::default(),
+                Default::default(),
+            )
+            .with_import_limits(ImportLimits {
+                max_size: 1024,
+                expect_continue,
+                ..Default::default()
+            });
+            let http_app =
+                Server::new(Arc::new(interface)).http_app(provider_engine.router(), None);
+            async move {
+                // the body never comes, reading it would hang the upload
+                let (_sender, body) = Body::channel();
+                let request = Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/ursa/v0/")
+                    .header(
+                        http::header::CONTENT_TYPE,
+                        "multipart/form-data; boundary=boundary",
+                    )
+                    .header(http::header::CONTENT_LENGTH, 1024 * 1024)
+                    .header(http::header::EXPECT, "100-continue")
+                    .body(body)
+                    .unwrap();
+                tokio::time::timeout(Duration::from_secs(5), http_app.oneshot(request))
+                    .await
+                    .expect("the upload to be rejected before its body is read")
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        assert_eq!(upload(true).await, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(upload(false).await, StatusCode::EXPECTATION_FAILED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_readyz_grace_period() -> Result<()> {
+        setup_logger();
+        let (mut ursa_service, provider_engine, store) = init()?;
+        // no peers can be reported without a running network
+        ursa_service.close_command_receiver();
+
+        let interface = Arc::new(NodeNetworkInterface::new(
+            Arc::clone(&store),
+            ursa_service.command_sender(),
+            provider_engine.command_sender(),
+            Default::default(),
+            Default::default(),
+        ));
+        let readyz = |grace_period, draining| {
+            let server =
+                Server::new(Arc::clone(&interface)).with_startup_grace_period(grace_period);
+            if draining {
+                server.readiness().drain();
+            }
+            let http_app = server.http_app(provider_engine.router(), None);
+            async move {
+                let response = http_app
+                    .oneshot(
+                        Request::builder()
+                            .uri("/readyz")
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                let body: Value = serde_json::from_slice(&body).unwrap();
+                (status, body["status"].clone())
+            }
+        };
+
+        assert_eq!(
+            readyz(Duration::from_secs(60), false).await,
+            (StatusCode::SERVICE_UNAVAILABLE, json!("starting"))
+        );
+        assert_eq!(
+            readyz(Duration::ZERO, false).await,
+            (StatusCode::SERVICE_UNAVAILABLE, json!("unready"))
+        );
+        // told apart from not being ready yet while shutting down
+        for grace_period in [Duration::from_secs(60), Duration::ZERO] {
+            assert_eq!(
+                readyz(grace_period, true).await,
+                (StatusCode::SERVICE_UNAVAILABLE, json!("draining"))
+            );
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_rpc_server() -> Result<()> {
         setup_logger();
@@ -52,6 +223,7 @@ mod tests {
             ursa_service.command_sender(),
             provider_engine.command_sender(),
             Default::default(),
+            Default::default(),
         ));
         let server = Server::new(interface);
         let rpc_app = server.rpc_app();
@@ -82,6 +254,203 @@ mod tests {
             *value.get("result").unwrap(),
             json!(["/ip4/127.0.0.1/tcp/6009".to_string()])
         );
+
+        // loopback addresses are filtered when only public addresses are requested
+        let req = serde_json::to_vec(&json!({
+            "jsonrpc": "2.0",
+            "method":"ursa_listener_addresses",
+            "params": { "public_only": true },
+            "id":1,
+        }))
+        .unwrap();
+        let response = server
+            .rpc_app()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/rpc/v0")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(req))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let value: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value["result"], json!([]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rpc_config() -> Result<()> {
+        setup_logger();
+        let (ursa_service, provider_engine, store) = init()?;
+
+        let interface = Arc::new(NodeNetworkInterface::new(
+            Arc::clone(&store),
+            ursa_service.command_sender(),
+            provider_engine.command_sender(),
+            Default::default(),
+            Default::default(),
+        ));
+        let config = NodeConfigResult {
+            config: json!({ "server_config": { "port": 4070 } }),
+            overridden: vec!["server_config.port".to_string()],
+        };
+        let server = Server::new(interface)
+            .with_config(config)
+            .with_admin_token(Some("token".to_string()));
+
+        let req = serde_json::to_vec(&json!({
+            "jsonrpc": "2.0",
+            "method": "ursa_config",
+            "id": 1,
+        }))
+        .unwrap();
+
+        let response = server
+            .rpc_app()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/rpc/v0")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(req))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let value: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            value["result"],
+            json!({
+                "config": { "server_config": { "port": 4070 } },
+                "overridden": ["server_config.port"],
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rpc_node_info() -> Result<()> {
+        setup_logger();
+        let (ursa_service, provider_engine, store) = init()?;
+        let peer_id = ursa_service.node_info().peer_id;
+
+        let interface = Arc::new(NodeNetworkInterface::new(
+            Arc::clone(&store),
+            ursa_service.command_sender(),
+            provider_engine.command_sender(),
+            Default::default(),
+            Default::default(),
+        ));
+        tokio::task::spawn(async move { ursa_service.start().await.unwrap() });
+        let rpc_app = Server::new(interface).rpc_app();
+
+        let req = serde_json::to_vec(&json!({
+            "jsonrpc": "2.0",
+            "method": "ursa_node_info",
+            "id": 1,
+        }))
+        .unwrap();
+        // the listener is bound once the service started
+        let info = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let response = rpc_app
+                    .clone()
+                    .oneshot(
+                        Request::builder()
+                            .method(http::Method::POST)
+                            .uri("/rpc/v0")
+                            .header(http::header::CONTENT_TYPE, "application/json")
+                            .body(Body::from(req.clone()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                let value: Value = serde_json::from_slice(&body).unwrap();
+                if !value["result"]["listen_addresses"]
+                    .as_array()
+                    .unwrap()
+                    .is_empty()
+                {
+                    return value["result"].clone();
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+
+        assert_eq!(info["peer_id"], json!(peer_id.to_string()));
+        assert_eq!(info["peers"], json!(0));
+        assert_eq!(info["bootstrap_nodes"], json!(0));
+        assert_eq!(info["bootstrapped"], json!(false));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rpc_batch() -> Result<()> {
+        setup_logger();
+        let (ursa_service, provider_engine, store) = init()?;
+
+        let interface = Arc::new(NodeNetworkInterface::new(
+            Arc::clone(&store),
+            ursa_service.command_sender(),
+            provider_engine.command_sender(),
+            Default::default(),
+            Default::default(),
+        ));
+        let rpc_app = Server::new(interface).rpc_app();
+        let post = |req: Value| {
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/rpc/v0")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&req).unwrap()))
+                .unwrap()
+        };
+
+        let response = rpc_app
+            .clone()
+            .oneshot(post(json!([
+                { "jsonrpc": "2.0", "method": "ursa_config", "id": 1 },
+                { "jsonrpc": "2.0", "method": "ursa_unknown", "id": 2 },
+            ])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let value: Value = serde_json::from_slice(&body).unwrap();
+        let responses = value.as_array().unwrap();
+        assert_eq!(responses.len(), 2);
+        let response = |id| responses.iter().find(|r| r["id"] == json!(id)).unwrap();
+        assert!(response(1).get("result").is_some());
+        assert_eq!(response(2)["error"]["code"], json!(-32601));
+
+        // a batch of notifications has no response
+        let response = rpc_app
+            .clone()
+            .oneshot(post(json!([
+                { "jsonrpc": "2.0", "method": "ursa_config" },
+            ])))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        // oversized batches are rejected before any call is handled
+        let calls = (0..=MAX_BATCH_SIZE)
+            .map(|id| json!({ "jsonrpc": "2.0", "method": "ursa_config", "id": id }))
+            .collect();
+        let response = rpc_app.oneshot(post(Value::Array(calls))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let value: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value["code"], json!(-32600));
         Ok(())
     }
 }
@@ -0,0 +1,91 @@
+//! CPU and disk cost of the block compression, run with `cargo bench -p ursa-store`.
+
+use std::time::{Duration, Instant};
+
+use db::MemoryDB;
+use fvm_ipld_blockstore::Blockstore;
+use libipld::{
+    multihash::{Code, MultihashDigest},
+    Cid,
+};
+use ursa_store::{CompressedStore, CHUNK_SIZE, RAW};
+
+const CHUNKS: usize = 64;
+
+/// Chunks of json like text, compressing well.
+fn text() -> Vec<Vec<u8>> {
+    (0..CHUNKS)
+        .map(|chunk| {
+            let mut bytes = Vec::with_capacity(CHUNK_SIZE);
+            let mut i = 0;
+            while bytes.len() < CHUNK_SIZE {
+                bytes.extend(format!("{{\"id\":{chunk}{i},\"name\":\"block {i}\"}},").bytes());
+                i += 1;
+            }
+            bytes.truncate(CHUNK_SIZE);
+            bytes
+        })
+        .collect()
+}
+
+/// Chunks of random bytes, such as already compressed media.
+fn noise() -> Vec<Vec<u8>> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..CHUNKS)
+        .map(|_| {
+            (0..CHUNK_SIZE)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn throughput(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / elapsed.as_secs_f64() / 1_000_000.0
+}
+
+fn bench(name: &str, chunks: &[Vec<u8>], level: Option<i32>) {
+    let blocks: Vec<_> = chunks
+        .iter()
+        .map(|chunk| (Cid::new_v1(RAW, Code::Sha2_256.digest(chunk)), chunk))
+        .collect();
+    let total: usize = chunks.iter().map(Vec::len).sum();
+    let store = CompressedStore::new(MemoryDB::default(), level).unwrap();
+
+    let start = Instant::now();
+    for (cid, chunk) in &blocks {
+        store.put_keyed(cid, chunk).unwrap();
+    }
+    let write = start.elapsed();
+
+    let start = Instant::now();
+    for (cid, _) in &blocks {
+        store.get(cid).unwrap().unwrap();
+    }
+    let read = start.elapsed();
+
+    let stored: usize = blocks
+        .iter()
+        .map(|(cid, _)| store.inner().get(cid).unwrap().unwrap().len())
+        .sum();
+    let level = level.map_or("off".to_string(), |level| level.to_string());
+    println!(
+        "{name:<6} level {level:<3} write {:>8.1} MB/s  read {:>8.1} MB/s  stored {:>5.1}%",
+        throughput(total, write),
+        throughput(total, read),
+        stored as f64 * 100.0 / total as f64,
+    );
+}
+
+fn main() {
+    for (name, chunks) in [("text", text()), ("noise", noise())] {
+        for level in [None, Some(1), Some(3), Some(9), Some(19)] {
+            bench(name, &chunks, level);
+        }
+    }
+}
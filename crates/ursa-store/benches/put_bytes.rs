@@ -0,0 +1,20 @@
+//! Chunking and hashing throughput of the parallel `put_bytes`, run with `cargo bench -p ursa-store`.
+
+use std::{sync::Arc, time::Instant};
+
+use db::MemoryDB;
+use libipld::multihash::Code;
+use ursa_store::UrsaStore;
+
+fn main() {
+    let bytes: Vec<u8> = (0..256 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+    for workers in [1, 2, 4, 8] {
+        let store = UrsaStore::new(Arc::new(MemoryDB::default()));
+        let start = Instant::now();
+        store
+            .put_bytes_parallel(&bytes, Code::Sha2_256, workers)
+            .unwrap();
+        println!("{workers} workers: {:?}", start.elapsed());
+    }
+}
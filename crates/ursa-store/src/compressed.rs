@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Result};
+use db::{Error, Store};
+use fvm_ipld_blockstore::Blockstore;
+use libipld::{
+    multihash::{Code, MultihashDigest},
+    Cid,
+};
+
+/// First bytes of a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Blockstore compressing the blocks at rest with zstd, decompressing them on read.
+///
+/// Blocks are only stored compressed when it makes them smaller and their cid can be
+/// verified, so a decompressed block is always checked against its cid. Blocks stored
+/// before compression was enabled, or after it was disabled, are read as is.
+/// Keys written through [`Store`] are not blocks and are kept uncompressed.
+#[derive(Debug, Clone)]
+pub struct CompressedStore<S> {
+    inner: S,
+    /// zstd level of the stored blocks, `None` stores them as is
+    level: Option<i32>,
+}
+
+impl<S> CompressedStore<S> {
+    /// Fails for a `level` zstd doesn't support, so a misconfigured level is caught at
+    /// startup rather than on the first write.
+    pub fn new(inner: S, level: Option<i32>) -> Result<Self> {
+        if let Some(level) = level {
+            let supported = zstd::compression_level_range();
+            if !supported.contains(&level) {
+                return Err(anyhow!(
+                    "zstd level {level} is out of the supported range {}..={}",
+                    supported.start(),
+                    supported.end()
+                ));
+            }
+        }
+        Ok(Self { inner, level })
+    }
+
+    /// The wrapped store, holding the blocks as stored.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Bytes to store for `block`.
+    fn compress<'a>(&self, k: &Cid, block: &'a [u8]) -> Result<Stored<'a>> {
+        let Some(level) = self.level else {
+            return Ok(Stored::Raw(block));
+        };
+        if Code::try_from(k.hash().code()).is_err() {
+            return Ok(Stored::Raw(block));
+        }
+        let compressed = zstd::encode_all(block, level)?;
+        Ok(if compressed.len() < block.len() {
+            Stored::Compressed(compressed)
+        } else {
+            Stored::Raw(block)
+        })
+    }
+}
+
+enum Stored<'a> {
+    Raw(&'a [u8]),
+    Compressed(Vec<u8>),
+}
+
+impl AsRef<[u8]> for Stored<'_> {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Stored::Raw(block) => block,
+            Stored::Compressed(compressed) => compressed,
+        }
+    }
+}
+
+/// Whether `data` hashes to the multihash of `cid`.
+fn matches(cid: &Cid, data: &[u8]) -> bool {
+    Code::try_from(cid.hash().code()).map_or(false, |code| code.digest(data) == *cid.hash())
+}
+
+/// Block of `cid` from its `stored` bytes.
+fn decompress(cid: &Cid, stored: Vec<u8>) -> Result<Vec<u8>> {
+    if !stored.starts_with(&ZSTD_MAGIC) {
+        return Ok(stored);
+    }
+    match zstd::decode_all(stored.as_slice()) {
+        Ok(block) if matches(cid, &block) => Ok(block),
+        // a raw block happening to start with the magic
+        _ if matches(cid, &stored) => Ok(stored),
+        _ => Err(anyhow!(
+            "The block with cid {cid:?} doesn't match its decompressed content"
+        )),
+    }
+}
+
+impl<S: Blockstore> Blockstore for CompressedStore<S> {
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        match self.inner.get(k)? {
+            Some(stored) => decompress(k, stored).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        self.inner.put_keyed(k, self.compress(k, block)?.as_ref())
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        self.inner.has(k)
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        let blocks: Vec<_> = blocks.into_iter().collect();
+        let stored = blocks
+            .iter()
+            .map(|(k, block)| Ok((*k, self.compress(k, block.as_ref())?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.inner.put_many_keyed(stored)
+    }
+}
+
+impl<S: Store> Store for CompressedStore<S> {
+    fn read<K>(&self, key: K) -> Result<Option<Vec<u8>>, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.read(key)
+    }
+
+    fn write<K, V>(&self, key: K, value: V) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.inner.write(key, value)
+    }
+
+    fn delete<K>(&self, key: K) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.delete(key)
+    }
+
+    fn exists<K>(&self, key: K) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.inner.exists(key)
+    }
+}
+
+#[cfg(test)]
+#[path = "tests/compressed_tests.rs"]
+mod compressed_tests;
@@ -1,5 +1,7 @@
+mod compressed;
 mod store;
 
+pub use self::compressed::*;
 pub use self::store::*;
 #[cfg(test)]
 mod tests;
@@ -7,13 +7,67 @@ use fvm_ipld_encoding::{de::DeserializeOwned, from_slice, ser::Serialize, to_vec
 use integer_encoding::VarInt;
 use ipld_traversal::blockstore::Blockstore as GSBlockstore;
 use libipld::{
+    cbor::DagCborCodec,
     cid,
     multihash::{Code, MultihashDigest},
     store::DefaultParams,
-    Block, Cid, Result,
+    Block, Cid, Ipld, Result,
 };
 use libp2p_bitswap::BitswapStore;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Arc, thread};
+
+/// Multicodec for raw binary leaves.
+pub const RAW: u64 = 0x55;
+/// Size of the raw leaves when chunking content, same as the ipfs default.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+/// Hash functions accepted when importing raw content.
+pub const SUPPORTED_HASH_CODES: [Code; 4] = [
+    Code::Sha2_256,
+    Code::Sha2_512,
+    Code::Blake2b256,
+    Code::Blake3_256,
+];
+
+/// Links traversed by default, enough for dags of tens of gigabytes of chunks or
+/// sites of hundreds of thousands of files.
+pub const DEFAULT_MAX_LINKS: usize = 1_000_000;
+
+/// Bounds on a dag traversal, unset limits are unbounded. Only the links are bounded
+/// by default, to [`DEFAULT_MAX_LINKS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DagLimits {
+    /// Maximum number of blocks
+    #[serde(default)]
+    pub max_blocks: Option<usize>,
+    /// Maximum total size of the blocks in bytes
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Maximum depth of a block below the root, the root being at depth 0
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// Maximum number of links of the blocks traversed, bounding the fetches
+    /// wide nodes such as large directories trigger. Defaults to [`DEFAULT_MAX_LINKS`]
+    #[serde(default = "DagLimits::default_max_links")]
+    pub max_links: Option<usize>,
+}
+
+impl DagLimits {
+    fn default_max_links() -> Option<usize> {
+        Some(DEFAULT_MAX_LINKS)
+    }
+}
+
+impl Default for DagLimits {
+    fn default() -> Self {
+        Self {
+            max_blocks: None,
+            max_bytes: None,
+            max_depth: None,
+            max_links: Self::default_max_links(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct UrsaStore<S> {
@@ -35,35 +89,56 @@ where
 
     /// traverse a dag and get full dag given a root cid
     pub fn dag_traversal(&self, root_cid: &Cid) -> Result<Vec<(Cid, Vec<u8>)>> {
-        let mut res = Vec::new();
-        // get full dag starting with root id
-        let mut current = FnvHashSet::default();
-        let mut refs = FnvHashSet::default();
-        current.insert(*root_cid);
-
-        while let Some(cid) = current.iter().next().copied() {
-            current.remove(&cid);
-            if refs.contains(&cid) {
-                continue;
-            }
-            match self.db.get(&cid)? {
-                Some(data) => {
-                    res.push((cid, data.clone()));
-                    let next_block = Block::<DefaultParams>::new(cid, data)?;
-                    next_block.references(&mut current)?;
-                    refs.insert(cid);
-                }
-                None => {
-                    // TODO: handle the case where parts of the dags are missing
-                    return Err(anyhow!(
-                        "The block with cid {:?} from the dag with the root {:?} is missing ",
-                        cid,
-                        root_cid
-                    ));
-                }
-            }
+        self.walk(root_cid, DagLimits::default()).collect()
+    }
+
+    /// Lazily traverse a dag breadth first, yielding blocks as they are read.
+    /// Yields an error and stops once a block is missing or `limits` are exceeded.
+    pub fn walk(&self, root_cid: &Cid, limits: DagLimits) -> DagWalker<'_, S> {
+        DagWalker {
+            store: self,
+            root: *root_cid,
+            limits,
+            queue: VecDeque::from([(*root_cid, 0)]),
+            seen: FnvHashSet::default(),
+            blocks: 0,
+            bytes: 0,
+            links: 0,
+            done: false,
         }
-        Ok(res)
+    }
+
+    /// Chunk raw bytes into a dag hashed with `code` and insert it into the blockstore.
+    /// Content larger than a single chunk is linked under a dag-cbor root.
+    /// Returns the inserted cids, root first.
+    pub fn put_bytes(&self, bytes: &[u8], code: Code) -> Result<Vec<Cid>> {
+        self.put_bytes_parallel(bytes, code, 1)
+    }
+
+    /// [`Self::put_bytes`], hashing the chunks on up to `workers` threads.
+    /// The cids are the same regardless of the number of workers.
+    pub fn put_bytes_parallel(&self, bytes: &[u8], code: Code, workers: usize) -> Result<Vec<Cid>> {
+        if !SUPPORTED_HASH_CODES.contains(&code) {
+            return Err(anyhow!("Unsupported hash function {code:?}"));
+        }
+
+        let chunks: Vec<&[u8]> = bytes.chunks(CHUNK_SIZE).collect();
+        let leaves = hash_chunks(&chunks, code, workers);
+        for (cid, chunk) in leaves.iter().zip(&chunks) {
+            self.db.put_keyed(cid, chunk)?;
+        }
+
+        if leaves.len() == 1 {
+            return Ok(leaves);
+        }
+
+        let links = leaves.iter().copied().map(Ipld::Link).collect();
+        let root = Block::<DefaultParams>::encode(DagCborCodec, code, &Ipld::List(links))?;
+        self.db.put_keyed(root.cid(), root.data())?;
+
+        let mut cids = vec![*root.cid()];
+        cids.extend(leaves);
+        Ok(cids)
     }
 
     /// Calculate a car file size from a root cid
@@ -86,6 +161,27 @@ where
     }
 }
 
+/// Raw leaf cids of `chunks` in order, each worker hashing a contiguous run of chunks.
+fn hash_chunks(chunks: &[&[u8]], code: Code, workers: usize) -> Vec<Cid> {
+    let leaf = |chunk: &&[u8]| Cid::new_v1(RAW, code.digest(chunk));
+    let workers = workers.clamp(1, chunks.len().max(1));
+    if workers == 1 {
+        return chunks.iter().map(leaf).collect();
+    }
+
+    let per_worker = (chunks.len() + workers - 1) / workers;
+    thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .chunks(per_worker)
+            .map(|run| scope.spawn(move || run.iter().map(leaf).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("hashing thread panicked"))
+            .collect()
+    })
+}
+
 /// Extension methods for inserting and retrieving IPLD data with CIDs
 pub trait BlockstoreExt: Blockstore {
     /// Get typed object from block store by CID
@@ -228,3 +324,88 @@ where
 #[cfg(test)]
 #[path = "tests/store_tests.rs"]
 mod store_tests;
+
+/// Iterator over the blocks of a dag, see [`UrsaStore::walk`].
+pub struct DagWalker<'a, S> {
+    store: &'a UrsaStore<S>,
+    root: Cid,
+    limits: DagLimits,
+    queue: VecDeque<(Cid, usize)>,
+    seen: FnvHashSet<Cid>,
+    blocks: usize,
+    bytes: u64,
+    links: usize,
+    done: bool,
+}
+
+impl<S> DagWalker<'_, S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    fn visit(&mut self, cid: Cid, depth: usize) -> Result<(Cid, Vec<u8>)> {
+        if let Some(max) = self.limits.max_depth.filter(|max| depth > *max) {
+            return Err(anyhow!(
+                "The dag with the root {:?} exceeds the maximum depth of {max}",
+                self.root
+            ));
+        }
+        let data = self.store.db.get(&cid)?.ok_or_else(|| {
+            // TODO: handle the case where parts of the dags are missing
+            anyhow!(
+                "The block with cid {:?} from the dag with the root {:?} is missing ",
+                cid,
+                self.root
+            )
+        })?;
+
+        self.blocks += 1;
+        self.bytes += data.len() as u64;
+        if let Some(max) = self.limits.max_blocks.filter(|max| self.blocks > *max) {
+            return Err(anyhow!(
+                "The dag with the root {:?} exceeds the maximum of {max} blocks",
+                self.root
+            ));
+        }
+        if let Some(max) = self.limits.max_bytes.filter(|max| self.bytes > *max) {
+            return Err(anyhow!(
+                "The dag with the root {:?} exceeds the maximum of {max} bytes",
+                self.root
+            ));
+        }
+
+        let mut refs = Vec::new();
+        Block::<DefaultParams>::new(cid, data.clone())?.references(&mut refs)?;
+        self.links += refs.len();
+        if let Some(max) = self.limits.max_links.filter(|max| self.links > *max) {
+            return Err(anyhow!(
+                "The dag with the root {:?} exceeds the maximum of {max} links",
+                self.root
+            ));
+        }
+        self.queue
+            .extend(refs.into_iter().map(|link| (link, depth + 1)));
+        Ok((cid, data))
+    }
+}
+
+impl<S> Iterator for DagWalker<'_, S>
+where
+    S: Blockstore + Store + Send + Sync + 'static,
+{
+    type Item = Result<(Cid, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        while let Some((cid, depth)) = self.queue.pop_front() {
+            if !self.seen.insert(cid) {
+                continue;
+            }
+            let res = self.visit(cid, depth);
+            self.done = res.is_err();
+            return Some(res);
+        }
+        None
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use db::MemoryDB;
+
+use super::*;
+use crate::{tests::setup_logger, UrsaStore, CHUNK_SIZE, RAW};
+
+fn compressed_store(level: Option<i32>) -> UrsaStore<CompressedStore<MemoryDB>> {
+    UrsaStore::new(Arc::new(
+        CompressedStore::new(MemoryDB::default(), level).unwrap(),
+    ))
+}
+
+/// Bytes that don't compress.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_round_trip() -> anyhow::Result<()> {
+    setup_logger();
+    let store = compressed_store(Some(3));
+    let bytes: Vec<u8> = (0..CHUNK_SIZE * 2 + 1).map(|i| (i % 7) as u8).collect();
+
+    let cids = store.put_bytes(&bytes, Code::Sha2_256)?;
+    let leaves: Vec<u8> = store
+        .dag_traversal(&cids[0])?
+        .into_iter()
+        .skip(1)
+        .flat_map(|(_, block)| block)
+        .collect();
+    assert_eq!(leaves, bytes);
+
+    // stored compressed, served decompressed
+    let stored = store.blockstore().inner.get(&cids[1])?.unwrap();
+    assert!(stored.starts_with(&ZSTD_MAGIC));
+    assert!(stored.len() < CHUNK_SIZE / 10);
+    assert_eq!(store.blockstore().get(&cids[1])?.unwrap().len(), CHUNK_SIZE);
+    assert!(store.blockstore().has(&cids[1])?);
+    Ok(())
+}
+
+#[test]
+fn test_incompressible_blocks_stored_as_is() -> anyhow::Result<()> {
+    let store = compressed_store(Some(3));
+    let bytes = noise(1024);
+    let cid = store.put_bytes(&bytes, Code::Sha2_256)?[0];
+    assert_eq!(store.blockstore().inner.get(&cid)?.unwrap(), bytes);
+    assert_eq!(store.blockstore().get(&cid)?.unwrap(), bytes);
+    Ok(())
+}
+
+#[test]
+fn test_toggling_compression() -> anyhow::Result<()> {
+    let bytes = vec![1; 4096];
+    let cid = Cid::new_v1(RAW, Code::Sha2_256.digest(&bytes));
+
+    // written uncompressed, read once compression is enabled
+    let db = MemoryDB::default();
+    db.put_keyed(&cid, &bytes)?;
+    let store = CompressedStore::new(db, Some(3))?;
+    assert_eq!(store.get(&cid)?.unwrap(), bytes);
+
+    // written compressed, read once compression is disabled
+    let other = vec![2; 4096];
+    let other_cid = Cid::new_v1(RAW, Code::Sha2_256.digest(&other));
+    store.put_keyed(&other_cid, &other)?;
+    let store = CompressedStore::new(store.inner, None)?;
+    assert_eq!(store.get(&other_cid)?.unwrap(), other);
+    Ok(())
+}
+
+#[test]
+fn test_raw_block_with_zstd_magic() -> anyhow::Result<()> {
+    let store = compressed_store(None);
+    let mut bytes = ZSTD_MAGIC.to_vec();
+    bytes.extend(noise(64));
+    let cid = store.put_bytes(&bytes, Code::Sha2_256)?[0];
+    assert_eq!(store.blockstore().get(&cid)?.unwrap(), bytes);
+    Ok(())
+}
+
+#[test]
+fn test_unsupported_level() {
+    let supported = zstd::compression_level_range();
+    for level in [supported.start() - 1, supported.end() + 1] {
+        assert!(CompressedStore::new(MemoryDB::default(), Some(level)).is_err());
+    }
+    for level in [*supported.start(), 0, 3, *supported.end()] {
+        assert!(CompressedStore::new(MemoryDB::default(), Some(level)).is_ok());
+    }
+}
+
+#[test]
+fn test_corrupted_block_rejected() -> anyhow::Result<()> {
+    let store = CompressedStore::new(MemoryDB::default(), Some(3))?;
+    let bytes = vec![1; 4096];
+    let cid = Cid::new_v1(RAW, Code::Sha2_256.digest(&bytes));
+    store
+        .inner
+        .put_keyed(&cid, &zstd::encode_all(&[2; 4096][..], 3)?)?;
+    assert!(store.get(&cid).is_err());
+    Ok(())
+}
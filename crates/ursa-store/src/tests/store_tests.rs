@@ -2,12 +2,14 @@
 mod tests {
     use async_fs::File;
     use futures::io::BufReader;
+    use fvm_ipld_blockstore::Blockstore;
     use fvm_ipld_car::{load_car, CarReader};
-    use libipld::Cid;
+    use libipld::{cbor::DagCborCodec, multihash::Code, store::DefaultParams, Block, Cid, Ipld};
     use std::path::Path;
     use std::sync::Arc;
 
     use crate::tests::{get_store, setup_logger};
+    use crate::{DagLimits, DEFAULT_MAX_LINKS};
 
     #[tokio::test]
     async fn test_dag_traversal() -> anyhow::Result<()> {
@@ -34,4 +36,151 @@ mod tests {
         // todo: check if they both have sam cids
         Ok(())
     }
+
+    #[test]
+    fn test_put_bytes_hash_functions() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let bytes: Vec<u8> = (0..crate::CHUNK_SIZE * 2 + 1)
+            .map(|i| (i % 251) as u8)
+            .collect();
+
+        let sha2 = store.put_bytes(&bytes, Code::Sha2_256)?;
+        let blake3 = store.put_bytes(&bytes, Code::Blake3_256)?;
+
+        assert_ne!(sha2[0], blake3[0]);
+        assert_eq!(sha2[0].hash().code(), u64::from(Code::Sha2_256));
+        assert_eq!(blake3[0].hash().code(), u64::from(Code::Blake3_256));
+
+        // traversal verifies every block against its multihash
+        assert_eq!(store.dag_traversal(&sha2[0])?.len(), sha2.len());
+        assert_eq!(store.dag_traversal(&blake3[0])?.len(), blake3.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_put_bytes_parallel() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let bytes: Vec<u8> = (0..crate::CHUNK_SIZE * 9 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect();
+
+        let sequential = store.put_bytes(&bytes, Code::Sha2_256)?;
+        for workers in [0, 2, 4, 16] {
+            assert_eq!(
+                store.put_bytes_parallel(&bytes, Code::Sha2_256, workers)?,
+                sequential
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_limits() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let bytes: Vec<u8> = (0..crate::CHUNK_SIZE * 2 + 1)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let cids = store.put_bytes(&bytes, Code::Sha2_256)?;
+        let root = cids[0];
+        let size: u64 = store
+            .dag_traversal(&root)?
+            .iter()
+            .map(|(_, data)| data.len() as u64)
+            .sum();
+
+        let walk = |limits: DagLimits| {
+            store
+                .walk(&root, limits)
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+
+        let blocks = walk(DagLimits {
+            max_blocks: Some(cids.len()),
+            max_bytes: Some(size),
+            max_depth: Some(1),
+            max_links: Some(cids.len() - 1),
+        })?;
+        assert_eq!(blocks.len(), cids.len());
+        assert_eq!(blocks[0].0, root);
+
+        assert!(walk(DagLimits {
+            max_blocks: Some(cids.len() - 1),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(walk(DagLimits {
+            max_bytes: Some(size - 1),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(walk(DagLimits {
+            max_depth: Some(0),
+            ..Default::default()
+        })
+        .is_err());
+        // only the links are bounded by default
+        assert_eq!(
+            DagLimits::default(),
+            DagLimits {
+                max_blocks: None,
+                max_bytes: None,
+                max_depth: None,
+                max_links: Some(DEFAULT_MAX_LINKS),
+            }
+        );
+        // the root links to every chunk
+        assert!(walk(DagLimits {
+            max_links: Some(cids.len() - 2),
+            ..Default::default()
+        })
+        .is_err());
+
+        // the first blocks are still yielded before the limit error
+        let mut walker = store.walk(
+            &root,
+            DagLimits {
+                max_blocks: Some(1),
+                ..Default::default()
+            },
+        );
+        assert!(walker.next().unwrap().is_ok());
+        assert!(walker.next().unwrap().is_err());
+        assert!(walker.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_max_links_across_blocks() -> anyhow::Result<()> {
+        setup_logger();
+        let store = get_store();
+        let mut files = vec![];
+        for modulo in [251, 241] {
+            let bytes: Vec<u8> = (0..crate::CHUNK_SIZE * 2 + 1)
+                .map(|i| (i % modulo) as u8)
+                .collect();
+            files.push(Ipld::Link(store.put_bytes(&bytes, Code::Sha2_256)?[0]));
+        }
+        let root =
+            Block::<DefaultParams>::encode(DagCborCodec, Code::Sha2_256, &Ipld::List(files))?;
+        store.blockstore().put_keyed(root.cid(), root.data())?;
+
+        let walk = |max_links| {
+            store
+                .walk(
+                    root.cid(),
+                    DagLimits {
+                        max_links: Some(max_links),
+                        ..Default::default()
+                    },
+                )
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        // the root links to both files, each linking to its 3 chunks
+        assert_eq!(walk(8)?.len(), 9);
+        assert!(walk(7).is_err());
+        Ok(())
+    }
 }
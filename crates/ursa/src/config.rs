@@ -1,6 +1,7 @@
 use anyhow::{Context, Result};
 use imara_diff::{intern::InternedInput, sink::Counter, Algorithm, UnifiedDiffBuilder};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fmt::Display;
 use std::{
     fs::{create_dir_all, File},
@@ -10,10 +11,13 @@ use std::{
 use tracing::{info, warn};
 use ursa_index_provider::config::ProviderConfig;
 use ursa_network::NetworkConfig;
-use ursa_rpc_service::config::ServerConfig;
+use ursa_rpc_service::{api::NodeConfigResult, config::ServerConfig};
 
 pub const DEFAULT_CONFIG_PATH_STR: &str = ".ursa/config.toml";
 
+/// Json pointers of the secret values, redacted when reporting the config.
+const SECRETS: [&str; 1] = ["/server_config/admin_token"];
+
 #[derive(Default, Serialize, Deserialize, Debug)]
 pub struct UrsaConfig {
     #[serde(default)]
@@ -36,6 +40,11 @@ impl UrsaConfig {
 
             let mut config: UrsaConfig =
                 toml::from_str(&raw).context("Failed to parse config file")?;
+            config
+                .server_config
+                .car_import
+                .validate()
+                .context("Invalid server_config")?;
 
             // TEMP: remove this after some time
             if let Some(domain) = config.provider_config.domain.clone() {
@@ -70,6 +79,48 @@ impl UrsaConfig {
             Ok(config)
         }
     }
+
+    /// Report of the effective config, with the secrets redacted
+    /// and the values overridden from the defaults.
+    pub fn report(&self) -> Result<NodeConfigResult> {
+        let mut config = serde_json::to_value(self)?;
+        let mut overridden = Vec::new();
+        diff(
+            &config,
+            &serde_json::to_value(UrsaConfig::default())?,
+            "",
+            &mut overridden,
+        );
+        for secret in SECRETS {
+            if let Some(value) = config.pointer_mut(secret).filter(|v| !v.is_null()) {
+                *value = Value::String("<redacted>".to_string());
+            }
+        }
+        Ok(NodeConfigResult { config, overridden })
+    }
+}
+
+/// Collect the dotted paths of the values differing between `config` and `defaults`.
+fn diff(config: &Value, defaults: &Value, path: &str, overridden: &mut Vec<String>) {
+    match (config, defaults) {
+        (Value::Object(config), Value::Object(defaults)) => {
+            for (key, value) in config {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                diff(
+                    value,
+                    defaults.get(key).unwrap_or(&Value::Null),
+                    &path,
+                    overridden,
+                );
+            }
+        }
+        (config, defaults) if config != defaults => overridden.push(path.to_string()),
+        _ => {}
+    }
 }
 
 pub fn write<S: Display, P: Into<PathBuf>>(str: S, path: P) -> Result<()> {
@@ -79,3 +130,45 @@ pub fn write<S: Display, P: Into<PathBuf>>(str: S, path: P) -> Result<()> {
     file.write_all(str.to_string().as_bytes())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_redacts_secrets() -> Result<()> {
+        let mut config = serde_json::to_value(UrsaConfig::default())?;
+        for (i, secret) in SECRETS.iter().enumerate() {
+            *config.pointer_mut(secret).unwrap() = Value::String(format!("secret-{i}"));
+        }
+        let config: UrsaConfig = serde_json::from_value(config)?;
+
+        let report = config.report()?;
+        let serialized = serde_json::to_string(&report)?;
+        for (i, secret) in SECRETS.iter().enumerate() {
+            assert!(!serialized.contains(&format!("secret-{i}")), "{secret}");
+            assert_eq!(report.config.pointer(secret).unwrap(), "<redacted>");
+        }
+        // overriding a secret is still reported, without its value
+        assert!(report
+            .overridden
+            .contains(&"server_config.admin_token".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn zero_concurrent_imports_rejected() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "ursa-config-zero-imports-{}.toml",
+            std::process::id()
+        ));
+        write(
+            "[server_config]\ncar_import = { max_concurrent = 0 }\n",
+            &path,
+        )?;
+        let loaded = UrsaConfig::load_or_default(&path);
+        std::fs::remove_file(&path)?;
+        assert!(loaded.is_err());
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{config::UrsaConfig, ursa::identity::IdentityManager};
-use anyhow::Result;
+use anyhow::{Context, Result};
 use db::{rocks::RocksDb, rocks_config::RocksDbConfig};
 use dotenv::dotenv;
 use libp2p::multiaddr::Protocol;
@@ -7,14 +7,15 @@ use libp2p::Multiaddr;
 use resolve_path::PathResolveExt;
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::task;
 use tracing::{error, info};
 use ursa::{cli_error_and_die, wait_until_ctrlc, Cli, Subcommand};
 use ursa_index_provider::engine::ProviderEngine;
-use ursa_network::UrsaService;
+use ursa_network::{NetworkCommand, UrsaService};
 use ursa_rpc_service::{api::NodeNetworkInterface, server::Server};
-use ursa_store::UrsaStore;
+use ursa_store::{CompressedStore, UrsaStore};
 use ursa_telemetry::TelemetryConfig;
 use ursa_tracker::TrackerRegistration;
 
@@ -42,6 +43,13 @@ async fn main() -> Result<()> {
                     }
                 }
             } else {
+                let report = match config.report() {
+                    Ok(report) => report,
+                    Err(e) => {
+                        error!("Failed to report the config: {e:?}");
+                        Default::default()
+                    }
+                };
                 let UrsaConfig {
                     network_config,
                     provider_config,
@@ -84,6 +92,8 @@ async fn main() -> Result<()> {
 
                 let db = RocksDb::open(db_path, &RocksDbConfig::default())
                     .expect("Opening blockstore RocksDB must succeed");
+                let db = CompressedStore::new(db, network_config.database_compression)
+                    .context("Invalid database_compression")?;
                 let store = Arc::new(UrsaStore::new(Arc::clone(&Arc::new(db))));
                 let service =
                     UrsaService::new(keypair.clone(), &network_config, Arc::clone(&store))?;
@@ -93,6 +103,9 @@ async fn main() -> Result<()> {
                     &RocksDbConfig::default(),
                 )
                 .expect("Opening provider RocksDB must succeed");
+                let provider_db =
+                    CompressedStore::new(provider_db, provider_config.database_compression)
+                        .context("Invalid provider database_compression")?;
 
                 let server_address = Multiaddr::try_from(format!(
                     "/ip4/{}/tcp/{}",
@@ -113,15 +126,29 @@ async fn main() -> Result<()> {
                 let index_provider_router = index_provider_engine.router();
 
                 // server setup
-                let interface = Arc::new(NodeNetworkInterface::new(
-                    store,
-                    service.command_sender(),
-                    index_provider_engine.command_sender(),
-                    server_config.origin.clone(),
-                ));
-                let server = Server::new(interface);
+                let interface = Arc::new(
+                    NodeNetworkInterface::new(
+                        store,
+                        service.command_sender(),
+                        index_provider_engine.command_sender(),
+                        server_config.origin.clone(),
+                        server_config.car_export,
+                    )
+                    .with_import_limits(server_config.car_import)
+                    .with_hash_workers(server_config.hash_workers)
+                    .with_coalesce_puts(server_config.coalesce_puts)
+                    .with_progressive(server_config.progressive),
+                );
+                let server = Server::new(interface)
+                    .with_admin_token(server_config.admin_token.clone())
+                    .with_config(report)
+                    .with_startup_grace_period(Duration::from_secs(
+                        server_config.startup_grace_period,
+                    ));
+                let readiness = server.readiness();
 
                 // Start libp2p service
+                let network_sender = service.command_sender();
                 let service_task = task::spawn(async {
                     if let Err(err) = service.start().await {
                         error!("[service_task] - {:?}", err);
@@ -129,12 +156,12 @@ async fn main() -> Result<()> {
                 });
 
                 // todo(oz): spawn task to track storage/ram/cpu metrics
-                let metrics = ursa_metrics::routes::init();
+                let metrics = server_config.metrics.then(ursa_metrics::routes::init);
 
                 // Start multiplex server service (rpc, http, and metrics)
                 let rpc_task = task::spawn(async move {
                     if let Err(err) = server
-                        .start(&server_config, index_provider_router, Some(metrics))
+                        .start(&server_config, index_provider_router, metrics)
                         .await
                     {
                         error!("[rpc_task] - {:?}", err);
@@ -160,10 +187,16 @@ async fn main() -> Result<()> {
 
                 wait_until_ctrlc();
 
-                // Gracefully shutdown node & rpc
-                rpc_task.abort();
-                service_task.abort();
+                // Gracefully shutdown node & rpc, giving in-flight transfers time to complete.
+                // The rpc keeps serving meanwhile, reporting the node as draining.
+                readiness.drain();
                 provider_task.abort();
+                if network_sender.send(NetworkCommand::Shutdown).is_ok() {
+                    if let Err(err) = service_task.await {
+                        error!("[service_task] - {:?}", err);
+                    }
+                }
+                rpc_task.abort();
             }
         }
         Err(e) => {
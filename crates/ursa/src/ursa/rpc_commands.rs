@@ -1,7 +1,7 @@
 use structopt::StructOpt;
 use tracing::{error, info};
 use ursa_rpc_service::{
-    api::{NetworkGetFileParams, NetworkPutFileParams},
+    api::{HashFunction, NetworkGetFileParams, NetworkPutFileParams},
     client::functions::{get_file, put_file},
 };
 
@@ -11,6 +11,12 @@ pub enum RpcCommands {
     Put {
         #[structopt(about = "The path to the file")]
         path: String,
+        #[structopt(
+            long,
+            default_value = "sha2-256",
+            help = "Hash function for non-car files: sha2-256, sha2-512, blake2b-256, blake3"
+        )]
+        hash: HashFunction,
     },
     #[structopt(
         about = "get the file from network for a given root cid and store it on given path"
@@ -26,16 +32,17 @@ pub enum RpcCommands {
 impl RpcCommands {
     pub async fn run(&self) {
         match self {
-            Self::Put { path } => {
+            Self::Put { path, hash } => {
                 let params = NetworkPutFileParams {
                     path: path.to_string(),
+                    hash: *hash,
                 };
                 match put_file(params).await {
                     Ok(file) => {
                         info!("Put car file done: {:?}", file);
                     }
-                    Err(_e) => {
-                        error!("There was an error while calling the rpc server. Please Check Server Logs")
+                    Err(e) => {
+                        error!("There was an error while calling the rpc server: {e}. Please Check Server Logs")
                     }
                 };
             }
@@ -48,8 +55,8 @@ impl RpcCommands {
                     Ok(_result) => {
                         info!("file stored at {path:?}");
                     }
-                    Err(_e) => {
-                        error!("There was an error while calling the rpc server. Please Check Server Logs")
+                    Err(e) => {
+                        error!("There was an error while calling the rpc server: {e}. Please Check Server Logs")
                     }
                 };
             }